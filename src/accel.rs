@@ -0,0 +1,1025 @@
+use crate::arena::*;
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::intersections::*;
+use crate::matrix::*;
+use crate::ray::*;
+use crate::shape::*;
+use crate::tuple::*;
+
+// How World::intersect_world finds the shapes a ray might hit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Acceleration
+{
+    // test the ray against every object in turn
+    None,
+    // a kd-tree over every shape in the world, with groups flattened
+    KdTree,
+    // a uniform grid of cells, which is quicker to build than a kd-tree
+    // and works well when the shapes are spread evenly
+    Grid,
+    // a bounding volume hierarchy: nested boxes around halves of the
+    // shapes, which is quick to build and never tests a shape twice
+    Bvh,
+}
+
+// An acceleration structure built for the objects of a world
+#[derive(Clone, Debug)]
+pub enum Accelerator
+{
+    KdTree(KdTree),
+    Grid(UniformGrid),
+    Bvh(Bvh),
+}
+
+impl Accelerator
+{
+    pub fn new(acceleration: Acceleration, objects: &[Shape]) -> Option<Accelerator>
+    {
+        match acceleration
+        {
+            Acceleration::None => None,
+            Acceleration::KdTree => Some(Accelerator::KdTree(KdTree::new(objects))),
+            Acceleration::Grid => Some(Accelerator::Grid(UniformGrid::new(objects))),
+            Acceleration::Bvh => Some(Accelerator::Bvh(Bvh::new(objects))),
+        }
+    }
+
+    pub fn get_acceleration(&self) -> Acceleration
+    {
+        match self
+        {
+            Accelerator::KdTree(_) => Acceleration::KdTree,
+            Accelerator::Grid(_) => Acceleration::Grid,
+            Accelerator::Bvh(_) => Acceleration::Bvh,
+        }
+    }
+
+    pub fn intersect<'a>(&self, objects: &'a [Shape], ray: Ray) -> Vec<Intersection<'a>>
+    {
+        match self
+        {
+            Accelerator::KdTree(t) => t.intersect(objects, ray),
+            Accelerator::Grid(g) => g.intersect(objects, ray),
+            Accelerator::Bvh(b) => b.intersect(objects, ray),
+        }
+    }
+
+    // Whether any intersection along the ray passes the test, stopping at
+    // the first primitive with one instead of finding them all
+    pub fn any_hit<'a, F>(&self, objects: &'a [Shape], ray: Ray, blocks: F) -> bool
+        where F: Fn(&Intersection<'a>) -> bool
+    {
+        let (primitives, candidates) = match self
+        {
+            Accelerator::KdTree(t) => (&t.primitives, t.candidates(ray)),
+            Accelerator::Grid(g) => (&g.primitives, g.candidates(ray)),
+            Accelerator::Bvh(b) => (&b.primitives, b.candidates(ray)),
+        };
+        primitives.all_candidates(candidates).into_iter()
+            .any(|i| primitives.intersect_primitive(objects, i, ray).iter().any(&blocks))
+    }
+
+    // The boxes the structure divides space into that hold any
+    // primitives: the leaves of a kd-tree or BVH, or the filled cells of
+    // a grid
+    pub fn cell_bounds(&self) -> Vec<BoundingBox>
+    {
+        match self
+        {
+            Accelerator::KdTree(t) => t.leaf_bounds(),
+            Accelerator::Grid(g) => g.cell_bounds(),
+            Accelerator::Bvh(b) => b.leaf_bounds(),
+        }
+    }
+
+    fn get_primitives_mut(&mut self) -> &mut Primitives
+    {
+        match self
+        {
+            Accelerator::KdTree(t) => &mut t.primitives,
+            Accelerator::Grid(g) => &mut g.primitives,
+            Accelerator::Bvh(b) => &mut b.primitives,
+        }
+    }
+
+    // Keeps the structure working after the objects at the given indices
+    // have changed, without building it again. Changed objects are taken
+    // out of the cells and tested against every ray, until so many have
+    // changed that building it again is quicker. Objects inside groups
+    // can't be taken out, so changing one builds the structure again.
+    pub fn update(&mut self, objects: &[Shape], changed: &[usize])
+    {
+        let acceleration = self.get_acceleration();
+        let primitives = self.get_primitives_mut();
+        if !primitives.set_aside(objects, changed)
+        {
+            *self = Accelerator::new(acceleration, objects).unwrap();
+        }
+    }
+}
+
+// A shape tested directly by the kd-tree, which is any shape except a
+// group. CSG shapes are kept whole, since the intersections with their
+// children have to be filtered together.
+#[derive(Clone, Debug)]
+struct Primitive
+{
+    // the index of the shape in World.objects, or of the object whose
+    // arena holds the shape
+    object: usize,
+    node: Option<ShapeId>,
+    // takes a ray from world space to the space of the shape's parent
+    parent_inverse: Matrix,
+    bounds: BoundingBox,
+}
+
+#[derive(Clone, Debug)]
+enum KdNode
+{
+    Leaf(Vec<usize>),
+    Interior{axis: usize, split: Scalar, below: usize, above: usize},
+}
+
+// A kd-tree over the primitives of a world, split using the surface area
+// heuristic. Every intersection along the ray is still found (including
+// those behind its origin, which refraction needs), but only the
+// primitives in the cells the ray passes through are tested.
+#[derive(Clone, Debug)]
+pub struct KdTree
+{
+    primitives: Primitives,
+    nodes: Vec<KdNode>,
+}
+
+impl KdTree
+{
+    // relative costs used by the surface area heuristic
+    const TRAVERSAL_COST: Scalar = 1.0;
+    const INTERSECTION_COST: Scalar = 80.0;
+    const EMPTY_BONUS: Scalar = 0.5;
+    const LEAF_SIZE: usize = 4;
+
+    pub fn new(objects: &[Shape]) -> Self
+    {
+        let primitives = Primitives::new(objects);
+        let bounded = primitives.bounded.clone();
+        let bounds = primitives.bounds;
+        let max_depth = (8.0 + 1.3 * (bounded.len().max(1) as Scalar).log2()).round() as usize;
+        let mut tree = KdTree{primitives, nodes: Vec::new()};
+        tree.build(bounded, bounds, max_depth);
+        tree
+    }
+
+    // Adds the node for the primitives within the bounds (and the nodes
+    // below it), returning its index
+    fn build(&mut self, items: Vec<usize>, bounds: BoundingBox, depth: usize) -> usize
+    {
+        let index = self.nodes.len();
+        self.nodes.push(KdNode::Leaf(Vec::new()));
+        let split = if items.len() <= Self::LEAF_SIZE || depth == 0
+        {
+            None
+        }
+        else
+        {
+            self.find_split(&items, &bounds)
+        };
+        match split
+        {
+            Some((axis, position)) =>
+            {
+                let mut below_items = Vec::new();
+                let mut above_items = Vec::new();
+                for &i in &items
+                {
+                    let b = &self.primitives.list[i].bounds;
+                    if b.min.get_vec()[axis] <= position
+                    {
+                        below_items.push(i);
+                    }
+                    if b.max.get_vec()[axis] >= position
+                    {
+                        above_items.push(i);
+                    }
+                }
+                let (below_bounds, above_bounds) = split_box(&bounds, axis, position);
+                let below = self.build(below_items, below_bounds, depth - 1);
+                let above = self.build(above_items, above_bounds, depth - 1);
+                self.nodes[index] = KdNode::Interior{axis, split: position, below, above};
+            },
+            None => self.nodes[index] = KdNode::Leaf(items),
+        }
+        index
+    }
+
+    // The axis and position of the cheapest split according to the surface
+    // area heuristic, or None if leaving the primitives in a leaf is cheaper
+    fn find_split(&self, items: &[usize], bounds: &BoundingBox) -> Option<(usize, Scalar)>
+    {
+        let total_area = bounds.surface_area();
+        if !(total_area > 0.0)
+        {
+            return None;
+        }
+        let min = bounds.min.get_vec();
+        let max = bounds.max.get_vec();
+        let mut best = None;
+        let mut best_cost = Self::INTERSECTION_COST * items.len() as Scalar;
+        for axis in 0..3
+        {
+            // where each primitive starts and ends along the axis, in order,
+            // with starts before ends at the same position
+            let mut edges = Vec::with_capacity(2 * items.len());
+            for &i in items
+            {
+                let b = &self.primitives.list[i].bounds;
+                edges.push((b.min.get_vec()[axis], false));
+                edges.push((b.max.get_vec()[axis], true));
+            }
+            edges.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(a.1.cmp(&b.1)));
+
+            let mut below = 0;
+            let mut above = items.len();
+            for &(position, is_end) in &edges
+            {
+                if is_end
+                {
+                    above = above - 1;
+                }
+                if position > min[axis] && position < max[axis]
+                {
+                    let (below_bounds, above_bounds) = split_box(bounds, axis, position);
+                    let below_chance = below_bounds.surface_area() / total_area;
+                    let above_chance = above_bounds.surface_area() / total_area;
+                    let bonus = if below == 0 || above == 0 { Self::EMPTY_BONUS } else { 0.0 };
+                    let cost = Self::TRAVERSAL_COST + Self::INTERSECTION_COST * (1.0 - bonus) *
+                        (below_chance * below as Scalar + above_chance * above as Scalar);
+                    if cost < best_cost
+                    {
+                        best_cost = cost;
+                        best = Some((axis, position));
+                    }
+                }
+                if !is_end
+                {
+                    below = below + 1;
+                }
+            }
+        }
+        best
+    }
+
+    fn leaf_bounds(&self) -> Vec<BoundingBox>
+    {
+        let mut leaves = Vec::new();
+        if self.primitives.bounds.is_empty()
+        {
+            return leaves;
+        }
+        let mut pending = vec![(0, self.primitives.bounds)];
+        while let Some((index, bounds)) = pending.pop()
+        {
+            match &self.nodes[index]
+            {
+                KdNode::Leaf(items) if !items.is_empty() => leaves.push(bounds),
+                KdNode::Leaf(_) => (),
+                KdNode::Interior{axis, split, below, above} =>
+                {
+                    let (below_bounds, above_bounds) = split_box(&bounds, *axis, *split);
+                    pending.push((*below, below_bounds));
+                    pending.push((*above, above_bounds));
+                },
+            }
+        }
+        leaves
+    }
+
+    // The primitives in the parts of the structure the ray passes through
+    fn candidates(&self, ray: Ray) -> Vec<usize>
+    {
+        let mut candidates = Vec::new();
+        if let Some((tmin, tmax)) = self.primitives.bounds.intersection_range(ray)
+            .filter(|&(tmin, _)| tmin <= ray.max_t)
+        {
+            let tmax = tmax.min(ray.max_t);
+            let origin = ray.origin.get_vec();
+            let direction = ray.direction.get_vec();
+            let mut stack = vec![(0, tmin, tmax)];
+            while let Some((node, tmin, tmax)) = stack.pop()
+            {
+                match &self.nodes[node]
+                {
+                    KdNode::Leaf(items) => candidates.extend_from_slice(items),
+                    KdNode::Interior{axis, split, below, above} =>
+                    {
+                        let o = origin[*axis];
+                        let d = direction[*axis];
+                        if d == 0.0
+                        {
+                            // parallel to the splitting plane
+                            if o <= *split
+                            {
+                                stack.push((*below, tmin, tmax));
+                            }
+                            if o >= *split
+                            {
+                                stack.push((*above, tmin, tmax));
+                            }
+                            continue;
+                        }
+                        // the line is on the first side of the plane before
+                        // it crosses it at t, and on the second side after
+                        let (first, second) = if d > 0.0 { (*below, *above) } else { (*above, *below) };
+                        let t = (*split - o) / d;
+                        if t > tmax
+                        {
+                            stack.push((first, tmin, tmax));
+                        }
+                        else if t < tmin
+                        {
+                            stack.push((second, tmin, tmax));
+                        }
+                        else
+                        {
+                            stack.push((second, t, tmax));
+                            stack.push((first, tmin, t));
+                        }
+                    },
+                }
+            }
+        }
+        candidates
+    }
+
+    pub fn intersect<'a>(&self, objects: &'a [Shape], ray: Ray) -> Vec<Intersection<'a>>
+    {
+        self.primitives.intersect(objects, self.candidates(ray), ray)
+    }
+}
+
+// A grid of equal sized cells over the bounds of the primitives, each
+// listing the primitives overlapping it. Rays step from cell to cell
+// along the line, so only the primitives near it are tested.
+#[derive(Clone, Debug)]
+pub struct UniformGrid
+{
+    primitives: Primitives,
+    resolution: [usize; 3],
+    cells: Vec<Vec<usize>>,
+}
+
+impl UniformGrid
+{
+    // cells per axis are limited, to keep the grid's size reasonable
+    const MAX_RESOLUTION: usize = 64;
+
+    pub fn new(objects: &[Shape]) -> Self
+    {
+        let primitives = Primitives::new(objects);
+        let mut resolution = [1; 3];
+        if !primitives.bounds.is_empty()
+        {
+            // about three cells along the widest axis for each cube root
+            // of the number of primitives, so the cells are roughly cubes
+            let size = primitives.bounds.max.sub(primitives.bounds.min).get_vec();
+            let widest = size[0].max(size[1]).max(size[2]);
+            let cells_per_unit = 3.0 * (primitives.bounded.len() as Scalar).cbrt() / widest;
+            for axis in 0..3
+            {
+                let cells = (size[axis] * cells_per_unit).round() as usize;
+                resolution[axis] = cells.clamp(1, Self::MAX_RESOLUTION);
+            }
+        }
+
+        let mut grid = UniformGrid{primitives, resolution,
+            cells: vec![Vec::new(); resolution[0] * resolution[1] * resolution[2]]};
+        for &i in &grid.primitives.bounded
+        {
+            let bounds = &grid.primitives.list[i].bounds;
+            let first = grid.cell_of(bounds.min);
+            let last = grid.cell_of(bounds.max);
+            for x in first[0]..=last[0]
+            {
+                for y in first[1]..=last[1]
+                {
+                    for z in first[2]..=last[2]
+                    {
+                        let index = grid.index([x, y, z]);
+                        grid.cells[index].push(i);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell_bounds(&self) -> Vec<BoundingBox>
+    {
+        let min = self.primitives.bounds.min.get_vec();
+        let max = self.primitives.bounds.max.get_vec();
+        let size: Vec<Scalar> = (0..3).map(|a| (max[a] - min[a]) / self.resolution[a] as Scalar).collect();
+        let mut boxes = Vec::new();
+        for z in 0..self.resolution[2]
+        {
+            for y in 0..self.resolution[1]
+            {
+                for x in 0..self.resolution[0]
+                {
+                    if self.cells[self.index([x, y, z])].is_empty()
+                    {
+                        continue;
+                    }
+                    let corner = |i: usize, j: usize, k: usize| create_point(min[0] + i as Scalar * size[0],
+                        min[1] + j as Scalar * size[1], min[2] + k as Scalar * size[2]);
+                    boxes.push(BoundingBox::new_with_corners(corner(x, y, z), corner(x + 1, y + 1, z + 1)));
+                }
+            }
+        }
+        boxes
+    }
+
+    fn index(&self, cell: [usize; 3]) -> usize
+    {
+        (cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]
+    }
+
+    // The cell containing a point, or the nearest one if it is outside the grid
+    fn cell_of(&self, point: Tuple) -> [usize; 3]
+    {
+        let p = point.get_vec();
+        let min = self.primitives.bounds.min.get_vec();
+        let max = self.primitives.bounds.max.get_vec();
+        let mut cell = [0; 3];
+        for axis in 0..3
+        {
+            let offset = (p[axis] - min[axis]) / (max[axis] - min[axis]);
+            let position = (offset * self.resolution[axis] as Scalar).floor();
+            cell[axis] = position.clamp(0.0, (self.resolution[axis] - 1) as Scalar) as usize;
+        }
+        cell
+    }
+
+    // The primitives in the parts of the structure the ray passes through
+    fn candidates(&self, ray: Ray) -> Vec<usize>
+    {
+        let mut candidates = Vec::new();
+        if let Some((tmin, tmax)) = self.primitives.bounds.intersection_range(ray)
+            .filter(|&(tmin, _)| tmin <= ray.max_t)
+        {
+            let tmax = tmax.min(ray.max_t);
+            // walk the cells along the line, from where it enters the grid
+            // to where it leaves, always stepping across the nearest wall
+            let origin = ray.origin.get_vec();
+            let direction = ray.direction.get_vec();
+            let min = self.primitives.bounds.min.get_vec();
+            let max = self.primitives.bounds.max.get_vec();
+            let mut cell = self.cell_of(ray.position(tmin));
+            let mut next_crossing = [Scalar::INFINITY; 3];
+            let mut delta = [0.0; 3];
+            for axis in 0..3
+            {
+                let width = (max[axis] - min[axis]) / self.resolution[axis] as Scalar;
+                let d = direction[axis];
+                if d > 0.0
+                {
+                    let wall = min[axis] + (cell[axis] + 1) as Scalar * width;
+                    next_crossing[axis] = (wall - origin[axis]) / d;
+                    delta[axis] = width / d;
+                }
+                else if d < 0.0
+                {
+                    let wall = min[axis] + cell[axis] as Scalar * width;
+                    next_crossing[axis] = (wall - origin[axis]) / d;
+                    delta[axis] = -width / d;
+                }
+            }
+            loop
+            {
+                candidates.extend_from_slice(&self.cells[self.index(cell)]);
+                let mut axis = 0;
+                for i in 1..3
+                {
+                    if next_crossing[i] < next_crossing[axis]
+                    {
+                        axis = i;
+                    }
+                }
+                if next_crossing[axis] > tmax
+                {
+                    break;
+                }
+                if direction[axis] > 0.0
+                {
+                    if cell[axis] + 1 == self.resolution[axis]
+                    {
+                        break;
+                    }
+                    cell[axis] = cell[axis] + 1;
+                }
+                else
+                {
+                    if cell[axis] == 0
+                    {
+                        break;
+                    }
+                    cell[axis] = cell[axis] - 1;
+                }
+                next_crossing[axis] = next_crossing[axis] + delta[axis];
+            }
+        }
+        candidates
+    }
+
+    pub fn intersect<'a>(&self, objects: &'a [Shape], ray: Ray) -> Vec<Intersection<'a>>
+    {
+        self.primitives.intersect(objects, self.candidates(ray), ray)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum BvhNode
+{
+    Leaf{bounds: BoundingBox, items: Vec<usize>},
+    Interior{bounds: BoundingBox, first: usize, second: usize},
+}
+
+// A bounding volume hierarchy over the primitives of a world. Each node's
+// box holds half of its parent's primitives, split at the middle one
+// along the axis their centers are most spread out on, so a ray only
+// tests the primitives in the boxes it passes through.
+#[derive(Clone, Debug)]
+pub struct Bvh
+{
+    primitives: Primitives,
+    nodes: Vec<BvhNode>,
+}
+
+impl Bvh
+{
+    const LEAF_SIZE: usize = 4;
+
+    pub fn new(objects: &[Shape]) -> Self
+    {
+        let primitives = Primitives::new(objects);
+        let bounded = primitives.bounded.clone();
+        let mut bvh = Bvh{primitives, nodes: Vec::new()};
+        if !bounded.is_empty()
+        {
+            bvh.build(bounded);
+        }
+        bvh
+    }
+
+    // Adds the node for the primitives (and the nodes below it),
+    // returning its index
+    fn build(&mut self, mut items: Vec<usize>) -> usize
+    {
+        let mut bounds = BoundingBox::new();
+        let mut centers = BoundingBox::new();
+        for &i in &items
+        {
+            bounds.add_box(&self.primitives.list[i].bounds);
+            centers.add_point(self.primitives.list[i].bounds.center());
+        }
+        let index = self.nodes.len();
+        let spread = centers.max.sub(centers.min).get_vec();
+        let axis = (0..3).fold(0, |a, b| if spread[b] > spread[a] { b } else { a });
+        // primitives all centered on one point can't be split apart
+        if items.len() <= Self::LEAF_SIZE || !(spread[axis] > 0.0)
+        {
+            self.nodes.push(BvhNode::Leaf{bounds, items});
+            return index;
+        }
+        self.nodes.push(BvhNode::Leaf{bounds, items: Vec::new()});
+        let middle = items.len() / 2;
+        let list = &self.primitives.list;
+        items.select_nth_unstable_by(middle, |a, b|
+        {
+            let ca = list[*a].bounds.center().get_vec()[axis];
+            let cb = list[*b].bounds.center().get_vec()[axis];
+            ca.partial_cmp(&cb).unwrap()
+        });
+        let second_items = items.split_off(middle);
+        let first = self.build(items);
+        let second = self.build(second_items);
+        self.nodes[index] = BvhNode::Interior{bounds, first, second};
+        index
+    }
+
+    fn leaf_bounds(&self) -> Vec<BoundingBox>
+    {
+        self.nodes.iter().filter_map(|node| match node
+        {
+            BvhNode::Leaf{bounds, items} if !items.is_empty() => Some(*bounds),
+            _ => None,
+        }).collect()
+    }
+
+    // The primitives in the parts of the structure the ray passes through
+    fn candidates(&self, ray: Ray) -> Vec<usize>
+    {
+        let mut candidates = Vec::new();
+        let slab = SlabRay::new(ray);
+        // boxes behind the ray's origin are still entered, since refraction
+        // needs every intersection along the line
+        let hits = |bounds: &BoundingBox| bounds.slab_range(&slab)
+            .map_or(false, |(tmin, _)| tmin <= ray.max_t + EPSILON);
+        let mut stack = match self.nodes.is_empty()
+        {
+            true => Vec::new(),
+            false => vec![0],
+        };
+        while let Some(node) = stack.pop()
+        {
+            match &self.nodes[node]
+            {
+                BvhNode::Leaf{bounds, items} if hits(bounds) => candidates.extend_from_slice(items),
+                BvhNode::Interior{bounds, first, second} if hits(bounds) =>
+                {
+                    stack.push(*second);
+                    stack.push(*first);
+                },
+                _ => (),
+            }
+        }
+        candidates
+    }
+
+    pub fn intersect<'a>(&self, objects: &'a [Shape], ray: Ray) -> Vec<Intersection<'a>>
+    {
+        self.primitives.intersect(objects, self.candidates(ray), ray)
+    }
+}
+
+// The shapes of a world that acceleration structures test directly
+#[derive(Clone, Debug)]
+struct Primitives
+{
+    list: Vec<Primitive>,
+    // the primitives with finite bounds, and the box enclosing them all
+    bounded: Vec<usize>,
+    bounds: BoundingBox,
+    // primitives with infinite bounds, such as planes, are tested for every
+    // ray, as are the primitives of objects changed since it was built
+    unbounded: Vec<usize>,
+    // the number of primitives set aside because their objects changed
+    set_aside: usize,
+}
+
+impl Primitives
+{
+    fn new(objects: &[Shape]) -> Self
+    {
+        let mut list = Vec::new();
+        for (i, object) in objects.iter().enumerate()
+        {
+            match object.get_arena()
+            {
+                // the primitives of moving groups don't stay where they are
+                // put, so the group is tested as a whole
+                Some(arena) if object.is_group() && object.get_motion().is_none() && !arena.has_moving_groups() =>
+                {
+                    let transform = object.get_transform();
+                    for &root in arena.get_roots()
+                    {
+                        Self::collect(arena, i, root, transform.clone(), &mut list);
+                    }
+                },
+                _ => list.push(Primitive{object: i, node: None,
+                    parent_inverse: Matrix::identity(4), bounds: object.parent_space_bounds()}),
+            }
+        }
+
+        let mut bounds = BoundingBox::new();
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, primitive) in list.iter().enumerate()
+        {
+            if primitive.bounds.is_infinite()
+            {
+                unbounded.push(i);
+            }
+            else if !primitive.bounds.is_empty()
+            {
+                bounds.add_box(&primitive.bounds);
+                bounded.push(i);
+            }
+        }
+        if !bounds.is_empty()
+        {
+            // padded so that rays grazing the outside are not lost to round-off
+            bounds.min = bounds.min.sub(create_vector(EPSILON, EPSILON, EPSILON));
+            bounds.max = bounds.max.add(create_vector(EPSILON, EPSILON, EPSILON));
+        }
+        Primitives{list, bounded, bounds, unbounded, set_aside: 0}
+    }
+
+    // Once more than one in this many primitives have been set aside, the
+    // structure is built again instead
+    const MAX_SET_ASIDE: usize = 8;
+
+    // Sets aside the primitives of the changed objects to be tested for
+    // every ray, returning false if the structure has to be built again
+    fn set_aside(&mut self, objects: &[Shape], changed: &[usize]) -> bool
+    {
+        if changed.iter().any(|&i| i >= objects.len() || objects[i].is_group())
+        {
+            return false;
+        }
+        for (index, primitive) in self.list.iter_mut().enumerate()
+        {
+            if changed.contains(&primitive.object)
+            {
+                if primitive.node.is_some()
+                {
+                    return false;
+                }
+                if !self.unbounded.contains(&index)
+                {
+                    self.unbounded.push(index);
+                    self.set_aside += 1;
+                }
+                primitive.bounds = objects[primitive.object].parent_space_bounds();
+            }
+        }
+        self.set_aside * Self::MAX_SET_ASIDE <= self.list.len()
+    }
+
+    fn collect(arena: &ShapeArena, object: usize, id: ShapeId, parent_transform: Matrix,
+        list: &mut Vec<Primitive>)
+    {
+        let shape = arena.get(id);
+        if shape.is_group()
+        {
+            let transform = parent_transform.multiply(&shape.get_transform());
+            for &child in arena.get_children(id)
+            {
+                Self::collect(arena, object, child, transform.clone(), list);
+            }
+        }
+        else
+        {
+            let bounds = arena.parent_space_bounds(id).transform(&parent_transform);
+            list.push(Primitive{object, node: Some(id),
+                parent_inverse: parent_transform.inverse(), bounds});
+        }
+    }
+
+    // The candidate primitives and the unbounded ones, each once
+    fn all_candidates(&self, candidates: Vec<usize>) -> Vec<usize>
+    {
+        let mut candidates = candidates;
+        candidates.extend_from_slice(&self.unbounded);
+
+        // a primitive can be in more than one of the cells the ray crosses
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+
+    // Intersects the ray with the candidate primitives and the unbounded ones
+    fn intersect<'a>(&self, objects: &'a [Shape], candidates: Vec<usize>, ray: Ray) -> Vec<Intersection<'a>>
+    {
+        self.all_candidates(candidates).into_iter()
+            .flat_map(|i| self.intersect_primitive(objects, i, ray)).collect()
+    }
+
+    fn intersect_primitive<'a>(&self, objects: &'a [Shape], i: usize, ray: Ray) -> Vec<Intersection<'a>>
+    {
+        let primitive = &self.list[i];
+        let object = &objects[primitive.object];
+        let (mut xs, parents) = match (primitive.node, object.get_arena())
+        {
+            (Some(id), Some(arena)) =>
+            {
+                let local_ray = ray.transform(&primitive.parent_inverse);
+                (arena.intersect_node(id, local_ray), object.get_descendant_parents(id))
+            },
+            _ => (object.intersect(ray), Vec::new()),
+        };
+        // the intersections of a CSG shape already list the shapes inside
+        // it, so the groups above it go first
+        if !parents.is_empty()
+        {
+            for x in xs.iter_mut()
+            {
+                let inner = std::mem::take(&mut x.parents);
+                x.parents = parents.iter().copied().chain(inner).collect();
+            }
+        }
+        xs
+    }
+}
+
+// The two halves of a box on either side of a plane across one axis
+fn split_box(bounds: &BoundingBox, axis: usize, position: Scalar) -> (BoundingBox, BoundingBox)
+{
+    let mut below_max = bounds.max.get_vec();
+    let mut above_min = bounds.min.get_vec();
+    below_max[axis] = position;
+    above_min[axis] = position;
+    (BoundingBox::new_with_corners(bounds.min, create_point(below_max[0], below_max[1], below_max[2])),
+        BoundingBox::new_with_corners(create_point(above_min[0], above_min[1], above_min[2]), bounds.max))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::csg::*;
+
+    fn sphere_grid(id: i32) -> Shape
+    {
+        // A group of 125 small spheres on a 5x5x5 grid
+        let mut grid = Shape::new_group(id);
+        let mut next_id = id;
+        for x in 0..5
+        {
+            for y in 0..5
+            {
+                for z in 0..5
+                {
+                    next_id = next_id + 1;
+                    let mut s = Shape::new_sphere(next_id);
+                    s.set_transform(Matrix::translation(x as Scalar * 2.0,
+                        y as Scalar * 2.0, z as Scalar * 2.0)
+                        .multiply(&Matrix::scaling(0.75, 0.75, 0.75)));
+                    grid.add_child(s);
+                }
+            }
+        }
+        grid.set_transform(Matrix::translation(-4.0, -4.0, -4.0));
+        grid
+    }
+
+    fn assert_same_intersections(accelerator: &Accelerator, objects: &[Shape], ray: Ray)
+    {
+        let mut xs: Vec<(Scalar, i32)> = accelerator.intersect(objects, ray).iter()
+            .map(|x| (x.t, x.object.get_id())).collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut expected: Vec<(Scalar, i32)> = objects.iter().flat_map(|o| o.intersect(ray))
+            .map(|x| (x.t, x.object.get_id())).collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(xs.len(), expected.len());
+        for (x, e) in xs.iter().zip(expected)
+        {
+            assert!(fuzzy_equal(x.0, e.0));
+            assert_eq!(x.1, e.1);
+        }
+    }
+
+    #[test]
+    fn test_accel_kd_tree_feature()
+    {
+        // Scenario: A kd-tree finds the same intersections as testing every shape
+        let mut plane = Shape::new_plane(1);
+        plane.set_transform(Matrix::translation(0.0, -6.0, 0.0));
+        let mut csg_left = Shape::new_sphere(2);
+        let mut csg_right = Shape::new_cube(3);
+        csg_right.set_transform(Matrix::translation(0.5, 0.0, 0.0));
+        let mut csg = Shape::new_csg(4, CsgOperation::Difference, csg_left, csg_right);
+        csg.set_transform(Matrix::translation(0.0, 0.0, 8.0));
+        let objects = vec![plane, csg, sphere_grid(10)];
+        let tree = KdTree::new(&objects);
+        assert!(tree.nodes.len() > 1);
+        assert_eq!(tree.primitives.unbounded.len(), 1);
+        let tree = Accelerator::KdTree(tree);
+
+        let origins = [create_point(-0.3, 0.1, -20.0), create_point(-10.0, 1.9, 0.2),
+            create_point(0.0, 20.0, 0.0), create_point(3.0, -3.0, 3.0)];
+        let targets = [create_point(0.0, 0.0, 8.0), create_point(2.0, 2.0, 2.0),
+            create_point(-4.0, -4.0, 4.0), create_point(4.0, 0.0, -4.0)];
+        for origin in origins
+        {
+            for target in targets
+            {
+                let ray = Ray::new(origin, target.sub(origin).normalize());
+                assert_same_intersections(&tree, &objects, ray);
+            }
+        }
+
+        // Scenario: Intersections found by a kd-tree list the groups containing the shape
+        let ray = Ray::new(create_point(-4.0, -4.0, -20.0), create_vector(0.0, 0.0, 1.0));
+        let xs = tree.intersect(&objects, ray);
+        let hit = xs.iter().filter(|x| x.t >= 0.0)
+            .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap()).unwrap();
+        assert_eq!(hit.object.get_id(), 11);
+        assert_eq!(hit.parents.len(), 1);
+        assert_eq!(hit.parents[0].get_id(), 10);
+        assert!(fuzzy_equal(hit.t, 15.25));
+        assert_eq!(hit.normal_at(ray.position(hit.t)), create_vector(0.0, 0.0, -1.0));
+
+        // Scenario: The leaves of a kd-tree holding primitives cover them
+        let leaves = tree.cell_bounds();
+        assert!(leaves.len() > 1);
+        let mut covered = BoundingBox::new();
+        leaves.iter().for_each(|b| covered.add_box(b));
+        assert!(covered.contains_box(&BoundingBox::new_with_corners(create_point(-4.5, -4.5, -0.5),
+            create_point(4.5, 4.5, 0.5))));
+    }
+
+    #[test]
+    fn test_accel_update()
+    {
+        // Scenario: A moved shape is set aside instead of building the kd-tree again
+        let mut objects = vec![sphere_grid(10)];
+        for i in 0..20
+        {
+            let mut s = Shape::new_sphere(200 + i);
+            s.set_transform(Matrix::translation(i as Scalar, 8.0, 0.0));
+            objects.push(s);
+        }
+        let mut tree = Accelerator::new(Acceleration::KdTree, &objects).unwrap();
+        objects[3].set_transform(Matrix::translation(0.0, 0.0, -8.0));
+        tree.update(&objects, &[3]);
+        match &tree
+        {
+            Accelerator::KdTree(t) => assert_eq!(t.primitives.set_aside, 1),
+            _ => panic!("expected a kd-tree"),
+        }
+        let ray = Ray::new(create_point(0.0, 0.0, -20.0), create_vector(0.0, 0.0, 1.0));
+        assert_same_intersections(&tree, &objects, ray);
+        let ray = Ray::new(create_point(2.0, 8.0, -20.0), create_vector(0.0, 0.0, 1.0));
+        assert_same_intersections(&tree, &objects, ray);
+
+        // Scenario: Changing a group builds the kd-tree again
+        objects[0].set_transform(Matrix::translation(0.0, 1.0, 0.0));
+        tree.update(&objects, &[0]);
+        match &tree
+        {
+            Accelerator::KdTree(t) => assert_eq!(t.primitives.set_aside, 0),
+            _ => panic!("expected a kd-tree"),
+        }
+        let ray = Ray::new(create_point(-4.0, -3.0, -20.0), create_vector(0.0, 0.0, 1.0));
+        assert_same_intersections(&tree, &objects, ray);
+    }
+
+    #[test]
+    fn test_accel_bvh_feature()
+    {
+        // Scenario: A BVH finds the same intersections as testing every shape
+        let mut plane = Shape::new_plane(1);
+        plane.set_transform(Matrix::translation(0.0, -6.0, 0.0));
+        let objects = vec![plane, Shape::new_sphere(2), sphere_grid(10)];
+        let bvh = Bvh::new(&objects);
+        assert_eq!(bvh.primitives.unbounded.len(), 1);
+        let leaves = bvh.leaf_bounds();
+        assert!(leaves.len() >= 126 / Bvh::LEAF_SIZE);
+        assert_eq!(leaves.len(), bvh.nodes.iter().filter(|n| matches!(n, BvhNode::Leaf{..})).count());
+        let bvh = Accelerator::Bvh(bvh);
+        let origins = [create_point(-0.3, 0.1, -20.0), create_point(-10.0, 1.9, 0.2),
+            create_point(0.0, 20.0, 0.0), create_point(-4.0, -4.0, 0.0)];
+        let directions = [create_vector(0.1, 0.2, 1.0), create_vector(1.0, 0.0, 0.0),
+            create_vector(-0.3, -1.0, 0.4), create_vector(1.0, -1.0, -1.0)];
+        for origin in origins
+        {
+            for direction in directions
+            {
+                let ray = Ray::new(origin, direction.normalize());
+                assert_same_intersections(&bvh, &objects, ray);
+            }
+        }
+
+        // Scenario: A moved shape is set aside instead of building the BVH again
+        let mut objects = objects;
+        let mut bvh = bvh;
+        objects[1].set_transform(Matrix::translation(0.0, 0.0, -8.0));
+        bvh.update(&objects, &[1]);
+        assert_eq!(bvh.get_acceleration(), Acceleration::Bvh);
+        let ray = Ray::new(create_point(0.0, 0.0, -20.0), create_vector(0.0, 0.0, 1.0));
+        assert_same_intersections(&bvh, &objects, ray);
+
+        // Scenario: A world of nothing but planes has an empty BVH
+        let bvh = Bvh::new(&[Shape::new_plane(1)]);
+        assert!(bvh.nodes.is_empty());
+        let ray = Ray::new(create_point(0.0, 1.0, 0.0), create_vector(0.0, -1.0, 0.0));
+        assert_eq!(Accelerator::Bvh(bvh).intersect(&[Shape::new_plane(1)], ray).len(), 1);
+    }
+
+    #[test]
+    fn test_accel_grid_feature()
+    {
+        // Scenario: A uniform grid finds the same intersections as testing every shape
+        let mut plane = Shape::new_plane(1);
+        plane.set_transform(Matrix::translation(0.0, -6.0, 0.0));
+        let objects = vec![plane, sphere_grid(10)];
+        let grid = UniformGrid::new(&objects);
+        assert_eq!(grid.resolution, [15, 15, 15]);
+        assert!(grid.cells.iter().all(|c| c.len() <= 8));
+        assert_eq!(grid.cell_bounds().len(), grid.cells.iter().filter(|c| !c.is_empty()).count());
+        let grid = Accelerator::Grid(grid);
+        let origins = [create_point(-0.3, 0.1, -20.0), create_point(-10.0, 1.9, 0.2),
+            create_point(0.0, 20.0, 0.0), create_point(-4.0, -4.0, 0.0)];
+        let directions = [create_vector(0.1, 0.2, 1.0), create_vector(1.0, 0.0, 0.0),
+            create_vector(-0.3, -1.0, 0.4), create_vector(1.0, -1.0, -1.0)];
+        for origin in origins
+        {
+            for direction in directions
+            {
+                let ray = Ray::new(origin, direction.normalize());
+                assert_same_intersections(&grid, &objects, ray);
+            }
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use crate::arithmetic::*;
+use crate::camera::*;
+use crate::matrix::*;
+use crate::quaternion::*;
+use crate::transform::*;
+use crate::tuple::*;
+use crate::world::*;
+
+// How values between keyframes are found
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation
+{
+    // straight from one key to the next, changing speed suddenly at keys
+    Linear,
+    // a Catmull-Rom curve through the keys, changing speed smoothly
+    Cubic,
+}
+
+// A value that can be keyed and blended between keys
+pub trait Animatable: Clone
+{
+    fn add(&self, b: &Self) -> Self;
+    fn scale(&self, s: Scalar) -> Self;
+
+    fn interpolate(&self, b: &Self, t: Scalar) -> Self
+    {
+        self.scale(1.0 - t).add(&b.scale(t))
+    }
+
+    // Tidies up a value made by adding and scaling others
+    fn finish(self) -> Self
+    {
+        self
+    }
+}
+
+impl Animatable for Scalar
+{
+    fn add(&self, b: &Self) -> Self
+    {
+        self + b
+    }
+
+    fn scale(&self, s: Scalar) -> Self
+    {
+        self * s
+    }
+}
+
+impl Animatable for Tuple
+{
+    fn add(&self, b: &Self) -> Self
+    {
+        Tuple::add(self, *b)
+    }
+
+    fn scale(&self, s: Scalar) -> Self
+    {
+        self.multiply(s)
+    }
+}
+
+impl Animatable for Quaternion
+{
+    fn add(&self, b: &Self) -> Self
+    {
+        Quaternion::new(self.w + b.w, self.x + b.x, self.y + b.y, self.z + b.z)
+    }
+
+    fn scale(&self, s: Scalar) -> Self
+    {
+        Quaternion::new(self.w * s, self.x * s, self.y * s, self.z * s)
+    }
+
+    fn interpolate(&self, b: &Self, t: Scalar) -> Self
+    {
+        self.slerp(*b, t)
+    }
+
+    fn finish(self) -> Self
+    {
+        self.normalize()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe<T>
+{
+    pub time: Scalar,
+    pub value: T,
+}
+
+// The values a property takes at keyed times, in seconds. Before the first
+// key and after the last the value stays put.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T: Animatable>
+{
+    keys: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T>
+{
+    pub fn new(interpolation: Interpolation) -> Self
+    {
+        Track{keys: Vec::new(), interpolation}
+    }
+
+    // Adds a key, replacing any already at the same time
+    pub fn key(mut self, time: Scalar, value: T) -> Self
+    {
+        self.keys.retain(|k| k.time != time);
+        let index = self.keys.iter().position(|k| k.time > time).unwrap_or(self.keys.len());
+        self.keys.insert(index, Keyframe{time, value});
+        self
+    }
+
+    pub fn get_keys(&self) -> &[Keyframe<T>]
+    {
+        &self.keys
+    }
+
+    pub fn value_at(&self, time: Scalar) -> Option<T>
+    {
+        let last = self.keys.len().checked_sub(1)?;
+        if time <= self.keys[0].time
+        {
+            return Some(self.keys[0].value.clone());
+        }
+        if time >= self.keys[last].time
+        {
+            return Some(self.keys[last].value.clone());
+        }
+        let i = self.keys.iter().rposition(|k| k.time <= time)?;
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let u = (time - a.time) / (b.time - a.time);
+        match self.interpolation
+        {
+            Interpolation::Linear => Some(a.value.interpolate(&b.value, u)),
+            Interpolation::Cubic =>
+            {
+                // the keys either side of the pair shape the curve; at the
+                // ends keys are made up by carrying on in a straight line
+                let p0 = match i
+                {
+                    0 => a.value.scale(2.0).add(&b.value.scale(-1.0)),
+                    _ => self.keys[i - 1].value.clone(),
+                };
+                let p3 = match i + 1 == last
+                {
+                    true => b.value.scale(2.0).add(&a.value.scale(-1.0)),
+                    false => self.keys[i + 2].value.clone(),
+                };
+                let (u2, u3) = (u * u, u * u * u);
+                let weights = [0.5 * (-u + 2.0 * u2 - u3), 0.5 * (2.0 - 5.0 * u2 + 3.0 * u3),
+                    0.5 * (u + 4.0 * u2 - 3.0 * u3), 0.5 * (u3 - u2)];
+                let value = p0.scale(weights[0]).add(&a.value.scale(weights[1]))
+                    .add(&b.value.scale(weights[2])).add(&p3.scale(weights[3]));
+                Some(value.finish())
+            },
+        }
+    }
+}
+
+// A shape's transformation, keyed as separate scaling, rotation and
+// translation so that rotations turn smoothly instead of the matrices
+// being blended
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformTrack
+{
+    pub scale: Track<Tuple>,
+    pub rotation: Track<Quaternion>,
+    pub translation: Track<Tuple>,
+}
+
+impl TransformTrack
+{
+    pub fn new(interpolation: Interpolation) -> Self
+    {
+        TransformTrack{scale: Track::new(interpolation), rotation: Track::new(interpolation),
+            translation: Track::new(interpolation)}
+    }
+
+    // Scales, then rotates, then translates. Parts with no keys are left
+    // out.
+    pub fn matrix_at(&self, time: Scalar) -> Matrix
+    {
+        let mut transform = Transform::identity();
+        if let Some(s) = self.scale.value_at(time).map(|s| s.to_array())
+        {
+            transform = transform.scale(s[0], s[1], s[2]);
+        }
+        if let Some(rotation) = self.rotation.value_at(time)
+        {
+            transform = transform.then(&rotation.to_matrix());
+        }
+        if let Some(t) = self.translation.value_at(time).map(|t| t.to_array())
+        {
+            transform = transform.translate(t[0], t[1], t[2]);
+        }
+        transform.into()
+    }
+}
+
+// A number in a material that can be animated
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MaterialValue
+{
+    Ambient,
+    Diffuse,
+    Specular,
+    Shininess,
+    Reflective,
+    Transparency,
+    RefractiveIndex,
+}
+
+// Something in a scene that changes over time. Shapes are found by the
+// names given to them with World::add_named or set_name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Channel
+{
+    Transform{name: String, track: TransformTrack},
+    Color{name: String, track: Track<Tuple>},
+    Material{name: String, value: MaterialValue, track: Track<Scalar>},
+    LightPosition(Track<Tuple>),
+    LightIntensity(Track<Tuple>),
+    // the camera looks from one point to another, with up as for
+    // Matrix::view_transform
+    CameraView{from: Track<Tuple>, to: Track<Tuple>, up: Tuple},
+    CameraFieldOfView(Track<Scalar>),
+    CameraExposure(Track<Scalar>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation
+{
+    pub frame_rate: Scalar,
+    // in seconds
+    pub duration: Scalar,
+    channels: Vec<Channel>,
+}
+
+impl Animation
+{
+    pub fn new(frame_rate: Scalar, duration: Scalar) -> Self
+    {
+        Animation{frame_rate, duration, channels: Vec::new()}
+    }
+
+    pub fn add_channel(&mut self, channel: Channel)
+    {
+        self.channels.push(channel);
+    }
+
+    pub fn get_channels(&self) -> &[Channel]
+    {
+        &self.channels
+    }
+
+    // The number of frames, including ones at the start and the end
+    pub fn frame_count(&self) -> usize
+    {
+        (self.duration * self.frame_rate + EPSILON).floor() as usize + 1
+    }
+
+    pub fn frame_time(&self, frame: usize) -> Scalar
+    {
+        frame as Scalar / self.frame_rate
+    }
+
+    // Sets everything animated to its value at a time
+    pub fn apply(&self, time: Scalar, world: &mut World, camera: &mut Camera) -> io::Result<()>
+    {
+        for channel in &self.channels
+        {
+            match channel
+            {
+                Channel::Transform{name, track} =>
+                {
+                    let matrix = track.matrix_at(time);
+                    node(world, name)?.set_transform(matrix);
+                },
+                Channel::Color{name, track} =>
+                {
+                    if let Some(color) = track.value_at(time)
+                    {
+                        let shape = node(world, name)?;
+                        let mut material = shape.get_material().clone();
+                        material.color = color;
+                        shape.set_material(material);
+                    }
+                },
+                Channel::Material{name, value, track} =>
+                {
+                    if let Some(v) = track.value_at(time)
+                    {
+                        let shape = node(world, name)?;
+                        let mut material = shape.get_material().clone();
+                        match value
+                        {
+                            MaterialValue::Ambient => material.ambient = v,
+                            MaterialValue::Diffuse => material.diffuse = v,
+                            MaterialValue::Specular => material.specular = v,
+                            MaterialValue::Shininess => material.shininess = v,
+                            MaterialValue::Reflective => material.reflective = v,
+                            MaterialValue::Transparency => material.transparency = v,
+                            MaterialValue::RefractiveIndex => material.refractive_index = v,
+                        }
+                        shape.set_material(material);
+                    }
+                },
+                Channel::LightPosition(track) =>
+                {
+                    if let Some(position) = track.value_at(time)
+                    {
+                        world.light.position = position;
+                    }
+                },
+                Channel::LightIntensity(track) =>
+                {
+                    if let Some(intensity) = track.value_at(time)
+                    {
+                        world.light.intensity = intensity;
+                    }
+                },
+                Channel::CameraView{from, to, up} =>
+                {
+                    if let (Some(from), Some(to)) = (from.value_at(time), to.value_at(time))
+                    {
+                        camera.transform = Matrix::view_transform(from, to, *up);
+                    }
+                },
+                Channel::CameraFieldOfView(track) =>
+                {
+                    if let Some(field_of_view) = track.value_at(time)
+                    {
+                        camera.set_field_of_view(field_of_view);
+                    }
+                },
+                Channel::CameraExposure(track) =>
+                {
+                    if let Some(exposure) = track.value_at(time)
+                    {
+                        camera.exposure = exposure;
+                    }
+                },
+            }
+        }
+        world.update();
+        Ok(())
+    }
+}
+
+fn node<'a>(world: &'a mut World, name: &str) -> io::Result<&'a mut crate::shape::Shape>
+{
+    world.get_node_mut(name).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+        format!("no shape is named '{}'", name)))
+}
+
+// Renders every frame of an animation into a directory, as frame0000.ppm,
+// frame0001.ppm and so on, returning the paths written. The world and
+// camera are left as they are in the last frame.
+pub fn render_animation(animation: &Animation, world: &mut World, camera: &mut Camera,
+    directory: &Path) -> io::Result<Vec<PathBuf>>
+{
+    fs::create_dir_all(directory)?;
+    let mut paths = Vec::new();
+    for frame in 0..animation.frame_count()
+    {
+        animation.apply(animation.frame_time(frame), world, camera)?;
+        let path = directory.join(format!("frame{:04}.ppm", frame));
+        let file = BufWriter::new(fs::File::create(&path)?);
+        camera.render_to(world, file)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+    use crate::canvas::*;
+    use crate::shape::*;
+
+    #[test]
+    fn test_animation_tracks()
+    {
+        // Scenario: A linear track blends between keys and holds at the ends
+        let track1 = Track::new(Interpolation::Linear).key(2.0, 10.0).key(0.0, 0.0).key(3.0, 10.0);
+        assert_eq!(track1.get_keys()[1].time, 2.0);
+        assert_eq!(track1.value_at(-1.0), Some(0.0));
+        assert_eq!(track1.value_at(0.5), Some(2.5));
+        assert_eq!(track1.value_at(2.5), Some(10.0));
+        assert_eq!(track1.value_at(9.0), Some(10.0));
+        assert_eq!(Track::<Scalar>::new(Interpolation::Linear).value_at(1.0), None);
+
+        // Scenario: A cubic track passes through its keys smoothly
+        let track2 = Track::new(Interpolation::Cubic).key(0.0, 0.0).key(1.0, 1.0).key(2.0, 0.0);
+        assert_eq!(track2.value_at(1.0), Some(1.0));
+        let before = track2.value_at(0.99).unwrap();
+        let after = track2.value_at(1.01).unwrap();
+        assert!(before < 1.0 && after < 1.0 && (before - after).abs() < 0.001);
+        // straight keys give a straight line
+        let track3 = Track::new(Interpolation::Cubic).key(0.0, create_point(0.0, 0.0, 0.0))
+            .key(1.0, create_point(1.0, 2.0, 0.0)).key(2.0, create_point(2.0, 4.0, 0.0));
+        assert_eq!(track3.value_at(0.5), Some(create_point(0.5, 1.0, 0.0)));
+
+        // Scenario: Rotations turn evenly between keys
+        let y = create_vector(0.0, 1.0, 0.0);
+        let mut track4 = TransformTrack::new(Interpolation::Linear);
+        track4.rotation = Track::new(Interpolation::Linear).key(0.0, Quaternion::identity())
+            .key(1.0, Quaternion::from_axis_angle(y, PI / 2.0));
+        track4.translation = Track::new(Interpolation::Linear).key(0.0, create_vector(0.0, 0.0, 0.0))
+            .key(1.0, create_vector(2.0, 0.0, 0.0));
+        assert_eq!(track4.matrix_at(0.5), Matrix::translation(1.0, 0.0, 0.0).multiply(&Matrix::rotation_y(PI / 4.0)));
+    }
+
+    #[test]
+    fn test_animation_feature()
+    {
+        let mut world = World::default_world();
+        world.set_name("ball", NodeRef{object: 0, id: None});
+        let mut camera = Camera::new(11, 11, PI / 2.0);
+        let mut animation = Animation::new(2.0, 1.0);
+        assert_eq!(animation.frame_count(), 3);
+        assert_eq!(animation.frame_time(1), 0.5);
+
+        // Scenario: Applying an animation sets the keyed values
+        let mut transform = TransformTrack::new(Interpolation::Linear);
+        transform.translation = Track::new(Interpolation::Linear).key(0.0, create_vector(0.0, 0.0, 0.0))
+            .key(1.0, create_vector(0.0, 4.0, 0.0));
+        animation.add_channel(Channel::Transform{name: String::from("ball"), track: transform});
+        animation.add_channel(Channel::Material{name: String::from("ball"), value: MaterialValue::Diffuse,
+            track: Track::new(Interpolation::Linear).key(0.0, 0.0).key(1.0, 1.0)});
+        animation.add_channel(Channel::LightIntensity(Track::new(Interpolation::Linear)
+            .key(0.0, create_color(0.0, 0.0, 0.0)).key(1.0, create_color(1.0, 0.5, 0.0))));
+        animation.add_channel(Channel::CameraView{
+            from: Track::new(Interpolation::Linear).key(0.0, create_point(0.0, 0.0, -5.0)),
+            to: Track::new(Interpolation::Linear).key(0.0, create_point(0.0, 0.0, 0.0)),
+            up: create_vector(0.0, 1.0, 0.0)});
+        animation.add_channel(Channel::CameraFieldOfView(Track::new(Interpolation::Linear).key(0.0, PI / 3.0)));
+        animation.apply(0.5, &mut world, &mut camera).unwrap();
+        assert_eq!(world.get_node("ball").unwrap().get_transform(), Matrix::translation(0.0, 2.0, 0.0));
+        assert_eq!(world.get_node("ball").unwrap().get_material().diffuse, 0.5);
+        assert_eq!(world.light.intensity, create_color(0.5, 0.25, 0.0));
+        assert_eq!(camera.transform, Matrix::view_transform(create_point(0.0, 0.0, -5.0),
+            create_point(0.0, 0.0, 0.0), create_vector(0.0, 1.0, 0.0)));
+        assert_eq!(camera.field_of_view, PI / 3.0);
+
+        // Scenario: Rendering an animation writes a numbered image per frame
+        let dir = std::env::temp_dir().join("ray_tracer_test_animation");
+        let _ = fs::remove_dir_all(&dir);
+        let paths = render_animation(&animation, &mut world, &mut camera, &dir).unwrap();
+        assert_eq!(paths, vec![dir.join("frame0000.ppm"), dir.join("frame0001.ppm"), dir.join("frame0002.ppm")]);
+        // no light at the start, so the first frame is black
+        let first = Canvas::from_ppm(fs::File::open(&paths[0]).unwrap()).unwrap();
+        assert!(first.pixels.iter().all(|p| *p == create_color(0.0, 0.0, 0.0)));
+        let last = Canvas::from_ppm(fs::File::open(&paths[2]).unwrap()).unwrap();
+        assert!(last.pixels.iter().any(|p| *p != create_color(0.0, 0.0, 0.0)));
+
+        // Scenario: Animating a shape that isn't there fails
+        animation.add_channel(Channel::Color{name: String::from("missing"),
+            track: Track::new(Interpolation::Linear).key(0.0, create_color(1.0, 0.0, 0.0))});
+        assert!(animation.apply(0.0, &mut world, &mut camera).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
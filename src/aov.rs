@@ -0,0 +1,132 @@
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::computations::*;
+use crate::ray::*;
+use crate::tuple::*;
+
+// Arbitrary output variables: what a camera ray first hit, kept apart
+// from its color so that images can be composited and debugged with
+// them. A ray that hits nothing has no depth, normal or object.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aovs
+{
+    // the distance along the ray
+    pub depth: Option<Scalar>,
+    // the surface normal in world space, facing the ray
+    pub normal: Option<Tuple>,
+    // the id of the shape, not of the group it is in
+    pub object_id: Option<i32>,
+}
+
+impl Aovs
+{
+    // For a ray that hits nothing
+    pub fn new() -> Self
+    {
+        Aovs{depth: None, normal: None, object_id: None}
+    }
+
+    pub fn from_hit(comps: &Computations, ray: Ray) -> Self
+    {
+        Aovs{depth: Some(comps.t * ray.direction.magnitude()), normal: Some(comps.normalv),
+            object_id: Some(comps.object.get_id())}
+    }
+}
+
+// The images rendered along with the beauty image, which is the usual
+// render. Each is black where nothing is hit. The depth and normal passes
+// are data rather than colors, so they are written without gamma.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderPasses
+{
+    pub beauty: Canvas,
+    // the distance to the hit in every channel, not scaled to 0..1
+    pub depth: Canvas,
+    // the x, y and z of the normal in the red, green and blue channels,
+    // from -1 to 1
+    pub normal: Canvas,
+    // a color for each object, the same in every render, for picking out
+    // the pixels of one object
+    pub object_id: Canvas,
+}
+
+impl RenderPasses
+{
+    pub fn new(width: usize, height: usize) -> Self
+    {
+        let mut passes = RenderPasses{beauty: Canvas::new(width, height), depth: Canvas::new(width, height),
+            normal: Canvas::new(width, height), object_id: Canvas::new(width, height)};
+        passes.depth.gamma = Gamma::Linear;
+        passes.normal.gamma = Gamma::Linear;
+        passes
+    }
+
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Tuple, aovs: &Aovs)
+    {
+        self.beauty.write_pixel(x, y, color);
+        if let Some(depth) = aovs.depth
+        {
+            self.depth.write_pixel(x, y, create_color(depth, depth, depth));
+        }
+        if let Some(n) = aovs.normal
+        {
+            let n = n.to_array();
+            self.normal.write_pixel(x, y, create_color(n[0], n[1], n[2]));
+        }
+        if let Some(id) = aovs.object_id
+        {
+            self.object_id.write_pixel(x, y, id_color(id));
+        }
+    }
+}
+
+// A bright color picked by scrambling the bits of the id, so that
+// neighbouring ids look different
+pub fn id_color(id: i32) -> Tuple
+{
+    let bits = (id as u32).wrapping_add(1).wrapping_mul(0x9e3779b1);
+    let channel = |shift: u32| 0.25 + 0.75 * Scalar::from((bits >> shift) as u8) / 255.0;
+    create_color(channel(24), channel(16), channel(8))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::world::*;
+
+    #[test]
+    fn test_aov_feature()
+    {
+        // Scenario: The outputs of a hit on the outer sphere
+        let world1 = World::default_world();
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 2.0));
+        let xs1 = world1.intersect_world(ray1);
+        let comps1 = xs1.hit().unwrap().prepare_computations(ray1, xs1.clone());
+        let aovs1 = Aovs::from_hit(&comps1, ray1);
+        assert!(fuzzy_equal(aovs1.depth.unwrap(), 4.0));
+        assert_eq!(aovs1.normal, Some(create_vector(0.0, 0.0, -1.0)));
+        assert_eq!(aovs1.object_id, Some(world1.objects[0].get_id()));
+
+        // Scenario: Shading a ray gives the outputs of its first hit
+        assert_eq!(world1.color_at_with_aovs(ray1, 5), (world1.color_at(ray1, 5), aovs1));
+        assert_eq!(world1.aovs_at(ray1), aovs1);
+        let ray2 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 1.0, 0.0));
+        assert_eq!(world1.color_at_with_aovs(ray2, 5).1, Aovs::new());
+
+        // Scenario: A miss leaves the passes black, except the beauty
+        let mut passes = RenderPasses::new(2, 1);
+        passes.write_pixel(0, 0, create_color(0.5, 0.5, 0.5), &aovs1);
+        passes.write_pixel(1, 0, create_color(0.1, 0.2, 0.3), &Aovs::new());
+        assert_eq!(passes.depth.pixel_at(0, 0), create_color(4.0, 4.0, 4.0));
+        assert_eq!(passes.normal.pixel_at(0, 0), create_color(0.0, 0.0, -1.0));
+        assert_eq!(passes.beauty.pixel_at(1, 0), create_color(0.1, 0.2, 0.3));
+        let black = create_color(0.0, 0.0, 0.0);
+        assert!([&passes.depth, &passes.normal, &passes.object_id].iter().all(|c| c.pixel_at(1, 0) == black));
+
+        // Scenario: Each object has its own color
+        assert_eq!(id_color(7), id_color(7));
+        assert_ne!(id_color(7), id_color(8));
+        assert_ne!(id_color(0), black);
+    }
+}
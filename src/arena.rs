@@ -0,0 +1,346 @@
+use std::sync::Arc;
+
+use crate::bounds::*;
+use crate::csg::*;
+use crate::intersections::*;
+use crate::material::*;
+use crate::matrix::*;
+use crate::ray::*;
+use crate::shape::*;
+use crate::tuple::*;
+
+// A handle to a shape stored in a ShapeArena
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShapeId(usize);
+
+// A shape in an arena, with the handles of its parent and children.
+// Group and CSG shapes inside an arena keep their children here rather
+// than in themselves.
+#[derive(Clone, Debug)]
+pub struct ShapeNode
+{
+    pub shape: Shape,
+    // None for the children of the shape owning the arena
+    pub parent: Option<ShapeId>,
+    pub children: Vec<ShapeId>,
+}
+
+// All the shapes inside a group or CSG shape, stored in one flat list and
+// linked by handles, so that no shape holds copies of its parent or of
+// its children.
+#[derive(Clone, Debug)]
+pub struct ShapeArena
+{
+    nodes: Vec<ShapeNode>,
+    // the children of the shape owning the arena, in order
+    roots: Vec<ShapeId>,
+}
+
+impl ShapeArena
+{
+    pub fn new() -> Self
+    {
+        ShapeArena{nodes: Vec::new(), roots: Vec::new()}
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.nodes.is_empty()
+    }
+
+    pub fn get_roots(&self) -> &[ShapeId]
+    {
+        &self.roots
+    }
+
+    pub fn get(&self, id: ShapeId) -> &Shape
+    {
+        &self.nodes[id.0].shape
+    }
+
+    // The children of a group or CSG shape inside the arena stay in the
+    // arena, so changing the shape returned here does not change them.
+    pub fn get_mut(&mut self, id: ShapeId) -> &mut Shape
+    {
+        &mut self.nodes[id.0].shape
+    }
+
+    // Every shape in the arena, at any depth
+    pub fn shapes(&self) -> impl Iterator<Item = &Shape>
+    {
+        self.nodes.iter().map(|node| &node.shape)
+    }
+
+    pub fn shapes_mut(&mut self) -> impl Iterator<Item = &mut Shape>
+    {
+        self.nodes.iter_mut().map(|node| &mut node.shape)
+    }
+
+    pub fn get_parent(&self, id: ShapeId) -> Option<ShapeId>
+    {
+        self.nodes[id.0].parent
+    }
+
+    pub fn get_children(&self, id: ShapeId) -> &[ShapeId]
+    {
+        &self.nodes[id.0].children
+    }
+
+    // The groups and CSG shapes containing a shape inside the arena,
+    // outermost first
+    pub fn ancestors(&self, id: ShapeId) -> Vec<ShapeId>
+    {
+        let mut ancestors = Vec::new();
+        let mut current = self.nodes[id.0].parent;
+        while let Some(parent) = current
+        {
+            ancestors.push(parent);
+            current = self.nodes[parent.0].parent;
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    // Adds a shape, and all the shapes inside it, as a child of the given
+    // shape (or of the shape owning the arena), returning its handle
+    pub fn insert(&mut self, parent: Option<ShapeId>, shape: Shape) -> ShapeId
+    {
+        let mut shape = shape;
+        let inner = shape.take_arena();
+        let id = ShapeId(self.nodes.len());
+        self.nodes.push(ShapeNode{shape, parent, children: Vec::new()});
+        match parent
+        {
+            Some(p) => self.nodes[p.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        if let Some(inner) = inner
+        {
+            let offset = self.nodes.len();
+            let moved = |child: ShapeId| ShapeId(child.0 + offset);
+            for node in inner.nodes
+            {
+                let parent = Some(node.parent.map_or(id, moved));
+                let children = node.children.into_iter().map(moved).collect();
+                self.nodes.push(ShapeNode{shape: node.shape, parent, children});
+            }
+            self.nodes[id.0].children = inner.roots.into_iter().map(moved).collect();
+        }
+        id
+    }
+
+    // A standalone copy of a shape inside the arena, holding copies of
+    // the shapes inside it
+    pub fn extract(&self, id: ShapeId) -> Shape
+    {
+        let node = &self.nodes[id.0];
+        let mut shape = node.shape.clone();
+        if let Some(arena) = shape.get_arena_mut()
+        {
+            for &child in &node.children
+            {
+                arena.insert(None, self.extract(child));
+            }
+        }
+        shape
+    }
+
+    // Sets the material of a shape and of all the shapes inside it
+    pub fn set_material(&mut self, id: ShapeId, material: &Arc<Material>)
+    {
+        self.nodes[id.0].shape.set_shared_material(Arc::clone(material));
+        for child in self.nodes[id.0].children.clone()
+        {
+            self.set_material(child, material);
+        }
+    }
+
+    // Sets the material of every shape in the arena, and of the shapes
+    // inside those with arenas of their own
+    pub fn set_all_materials(&mut self, material: &Arc<Material>)
+    {
+        for node in self.nodes.iter_mut()
+        {
+            node.shape.set_all_materials(Arc::clone(material));
+        }
+    }
+
+    // Adds the shapes inside a group in the arena to the list, as
+    // Shape::flatten does, with the transform, layer and visibility of the
+    // group's parent
+    pub fn flatten(&self, id: ShapeId, parent_transform: &Matrix, parent_motion: Option<Tuple>,
+        parent_layer: Option<&str>, parent_visibility: Visibility, list: &mut Vec<Shape>)
+    {
+        let node = &self.nodes[id.0];
+        let layer = node.shape.get_layer().or(parent_layer);
+        let visibility = node.shape.get_visibility().and(parent_visibility);
+        if node.shape.is_group()
+        {
+            let transform = parent_transform.multiply(&node.shape.get_transform());
+            // the group's motion, moved into the space of the flattened
+            // shapes, on top of the motion of the groups around it
+            let mut motion = node.shape.get_motion().map(|m| parent_transform.multiply_tuple(m));
+            if let Some(p) = parent_motion
+            {
+                motion = Some(motion.map_or(p, |m| m.add(p)));
+            }
+            for &child in &node.children
+            {
+                self.flatten(child, &transform, motion, layer, visibility, list);
+            }
+        }
+        else
+        {
+            let mut shape = self.extract(id);
+            shape.bake_transform(parent_transform);
+            shape.inherit_motion(parent_motion);
+            shape.inherit_layer(layer);
+            shape.inherit_visibility(parent_visibility);
+            list.push(shape);
+        }
+    }
+
+    // Intersects a shape inside the arena, with the ray in the space of
+    // its parent
+    pub fn intersect_node(&self, id: ShapeId, ray: Ray) -> Vec<Intersection<'_>>
+    {
+        let node = &self.nodes[id.0];
+        if !node.shape.is_composite()
+        {
+            return node.shape.intersect(ray);
+        }
+        let local_ray = node.shape.local_ray(ray);
+        let mut xs = self.intersect_children(&node.children,
+            node.shape.get_csg_operation(), local_ray);
+        for x in xs.iter_mut()
+        {
+            x.parents.insert(0, &node.shape);
+        }
+        xs
+    }
+
+    // Intersects the children of a group, or the left and right operands
+    // of a CSG shape, with the ray in the space of the shape containing
+    // them. The CSG operation filters the combined intersections.
+    pub fn intersect_children(&self, ids: &[ShapeId], operation: Option<CsgOperation>,
+        ray: Ray) -> Vec<Intersection<'_>>
+    {
+        match operation
+        {
+            Some(operation) =>
+            {
+                let mut xs = Vec::new();
+                for (n, &id) in ids.iter().enumerate()
+                {
+                    xs.extend(self.intersect_node(id, ray).into_iter().map(|i| (n == 0, i)));
+                }
+                xs.sort_by(|a, b| a.1.t.partial_cmp(&b.1.t).unwrap());
+                Csg::filter(operation, Csg::merge_mesh_edges(xs, ray))
+            },
+            None =>
+            {
+                let mut xs: Vec<Intersection> = ids.iter()
+                    .flat_map(|&id| self.intersect_node(id, ray)).collect();
+                xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+                xs
+            },
+        }
+    }
+
+    // Whether any group in the arena moves over the shutter
+    pub fn has_moving_groups(&self) -> bool
+    {
+        self.nodes.iter().any(|n| n.shape.is_group() && n.shape.get_motion().is_some())
+    }
+
+    // Bounding box of a shape inside the arena in the space of its parent
+    pub fn parent_space_bounds(&self, id: ShapeId) -> BoundingBox
+    {
+        let node = &self.nodes[id.0];
+        if !node.shape.is_composite()
+        {
+            return node.shape.parent_space_bounds();
+        }
+        node.shape.sweep_bounds(self.children_bounds(&node.children).transform(&node.shape.get_transform()))
+    }
+
+    pub fn children_bounds(&self, ids: &[ShapeId]) -> BoundingBox
+    {
+        let mut bounds = BoundingBox::new();
+        for &id in ids
+        {
+            bounds.add_box(&self.parent_space_bounds(id));
+        }
+        bounds
+    }
+
+    // Bounding sphere of a shape inside the arena in the space of its parent
+    pub fn bounding_sphere(&self, id: ShapeId) -> BoundingSphere
+    {
+        let node = &self.nodes[id.0];
+        if !node.shape.is_composite()
+        {
+            return node.shape.bounding_sphere();
+        }
+        node.shape.sweep_sphere(self.children_bounding_sphere(&node.children).transform(&node.shape.get_transform()))
+    }
+
+    pub fn children_bounding_sphere(&self, ids: &[ShapeId]) -> BoundingSphere
+    {
+        let mut sphere = BoundingSphere::empty();
+        for &id in ids
+        {
+            sphere = sphere.merge(&self.bounding_sphere(id));
+        }
+        sphere
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::tuple::*;
+
+    #[test]
+    fn test_arena_feature()
+    {
+        // Scenario: Inserting a group adds its children to the arena
+        let mut group = Shape::new_group(1);
+        group.add_child(Shape::new_sphere(2));
+        group.add_child(Shape::new_cube(3));
+        let mut arena = ShapeArena::new();
+        let g = arena.insert(None, group);
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.get_roots(), &[g]);
+        assert_eq!(arena.get_children(g).len(), 2);
+        let s = arena.get_children(g)[0];
+        assert_eq!(*arena.get(s), Shape::new_sphere(2));
+        assert_eq!(arena.get_parent(s), Some(g));
+        assert_eq!(arena.ancestors(s), vec![g]);
+        assert!(arena.get(g).get_children().is_empty());
+
+        // Scenario: Extracting a group copies its children back into it
+        let extracted = arena.extract(g);
+        assert_eq!(extracted, Shape::new_group(1));
+        assert_eq!(extracted.get_children(), vec![Shape::new_sphere(2), Shape::new_cube(3)]);
+    }
+
+    #[test]
+    fn test_arena_mutable_after_assembly()
+    {
+        // Scenario: A shape inside a group can be moved after the group is built
+        let mut group = Shape::new_group(1);
+        let s = group.add_child(Shape::new_sphere(2));
+        let r = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(group.intersect(r).len(), 2);
+        group.get_arena_mut().unwrap().get_mut(s)
+            .set_transform(Matrix::translation(5.0, 0.0, 0.0));
+        assert!(group.intersect(r).is_empty());
+    }
+}
@@ -1,7 +1,70 @@
-pub const EPSILON: f64 = 0.00001;
+// The floating point type used for geometry and colors. It is f64 unless
+// the crate is built with the "f32" feature, which trades a little
+// precision for speed and memory.
+#[cfg(not(feature = "f32"))]
+pub type Scalar = f64;
+#[cfg(feature = "f32")]
+pub type Scalar = f32;
 
-pub fn fuzzy_equal(a: f64, b: f64) -> bool
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+
+// f32 can't resolve differences as small as f64 can, so surfaces are
+// offset further and values compared less strictly
+#[cfg(not(feature = "f32"))]
+pub const EPSILON: Scalar = 0.00001;
+#[cfg(feature = "f32")]
+pub const EPSILON: Scalar = 0.0001;
+
+pub fn fuzzy_equal(a: Scalar, b: Scalar) -> bool
 {
     let diff = a - b;
     diff.abs() < EPSILON
 }
+
+// Compares two values, allowing a difference of either tolerance, so that
+// both small values near zero and large values can compare equal
+pub fn fuzzy_equal_relative(a: Scalar, b: Scalar, absolute: Scalar, relative: Scalar) -> bool
+{
+    (a - b).abs() <= absolute.max(relative * a.abs().max(b.abs()))
+}
+
+// How far apart values must be to count as different, and how far off a
+// surface secondary rays start so they don't hit it again. The absolute
+// amount suits scenes about the size of the book's; the relative one
+// takes over far from the origin, where values are less exact.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tolerance
+{
+    pub absolute: Scalar,
+    pub relative: Scalar,
+}
+
+impl Tolerance
+{
+    // a relative amount well above the precision of Scalar
+    #[cfg(not(feature = "f32"))]
+    const RELATIVE: Scalar = 1e-9;
+    #[cfg(feature = "f32")]
+    const RELATIVE: Scalar = 1e-5;
+
+    pub fn new() -> Self
+    {
+        Tolerance{absolute: EPSILON, relative: Self::RELATIVE}
+    }
+
+    pub fn equal(&self, a: Scalar, b: Scalar) -> bool
+    {
+        fuzzy_equal_relative(a, b, self.absolute, self.relative)
+    }
+
+    // The distance to move off a surface at a point, in whatever units
+    // its coordinates are in
+    pub fn offset(&self, coordinates: &[Scalar]) -> Scalar
+    {
+        let largest = coordinates.iter().fold(0.0, |m: Scalar, c| m.max(c.abs()));
+        self.absolute.max(self.relative * largest)
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use crate::arithmetic::consts::PI;
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::tuple::*;
+
+// What rays that miss every object see
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background
+{
+    Color(Tuple),
+    // blends from the bottom color, for rays pointing straight down, to
+    // the top color, for rays pointing straight up
+    Gradient{top: Tuple, bottom: Tuple},
+    // an image wrapped around the scene with a spherical (latitude and
+    // longitude) mapping, with the top row straight up and the middle
+    // looking along +z
+    Environment(Arc<Canvas>),
+}
+
+impl Background
+{
+    pub fn new() -> Self
+    {
+        Background::Color(create_color(0.0, 0.0, 0.0))
+    }
+
+    // The color seen in a direction, which need not be normalized
+    pub fn color_at(&self, direction: Tuple) -> Tuple
+    {
+        match self
+        {
+            Background::Color(c) => *c,
+            Background::Gradient{top, bottom} =>
+            {
+                let v = direction.normalize().get_vec();
+                let t = 0.5 * (v[1] + 1.0);
+                bottom.add(top.sub(*bottom).multiply(t))
+            },
+            // an empty image, such as a PPM of no pixels, has nothing to show
+            Background::Environment(image) if image.width == 0 || image.height == 0 => create_color(0.0, 0.0, 0.0),
+            Background::Environment(image) =>
+            {
+                let v = direction.normalize().get_vec();
+                // as the book's spherical_map, on the unit sphere
+                let theta = v[0].atan2(v[2]);
+                let u = 1.0 - (theta / (2.0 * PI) + 0.5);
+                let phi = v[1].clamp(-1.0, 1.0).acos();
+                let v = 1.0 - phi / PI;
+                let x = (u * (image.width - 1) as Scalar).round() as usize;
+                let y = ((1.0 - v) * (image.height - 1) as Scalar).round() as usize;
+                image.pixel_at(x, y)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_background_feature()
+    {
+        // Scenario: The default background is black
+        let b1 = Background::new();
+        assert_eq!(b1.color_at(create_vector(0.0, 1.0, 0.0)), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: A gradient blends from the bottom to the top color
+        let b2 = Background::Gradient{top: create_color(0.5, 0.7, 1.0), bottom: create_color(1.0, 1.0, 1.0)};
+        assert_eq!(b2.color_at(create_vector(0.0, 2.0, 0.0)), create_color(0.5, 0.7, 1.0));
+        assert_eq!(b2.color_at(create_vector(0.0, -1.0, 0.0)), create_color(1.0, 1.0, 1.0));
+        assert_eq!(b2.color_at(create_vector(1.0, 0.0, 0.0)), create_color(0.75, 0.85, 1.0));
+
+        // Scenario: An environment image is looked up by direction
+        let mut image = Canvas::new(5, 3);
+        image.write_pixel(2, 0, create_color(0.0, 0.0, 1.0));
+        image.write_pixel(2, 1, create_color(1.0, 0.0, 0.0));
+        image.write_pixel(0, 1, create_color(0.0, 1.0, 0.0));
+        image.write_pixel(2, 2, create_color(1.0, 1.0, 0.0));
+        let b3 = Background::Environment(Arc::new(image));
+        assert_eq!(b3.color_at(create_vector(0.0, 1.0, 0.0)), create_color(0.0, 0.0, 1.0));
+        assert_eq!(b3.color_at(create_vector(0.0, 0.0, 1.0)), create_color(1.0, 0.0, 0.0));
+        assert_eq!(b3.color_at(create_vector(0.0, 0.0, -1.0)), create_color(0.0, 1.0, 0.0));
+        assert_eq!(b3.color_at(create_vector(0.0, -3.0, 0.0)), create_color(1.0, 1.0, 0.0));
+
+        // Scenario: An empty environment image is black
+        let b4 = Background::Environment(Arc::new(Canvas::new(0, 0)));
+        assert_eq!(b4.color_at(create_vector(0.0, 1.0, 0.0)), create_color(0.0, 0.0, 0.0));
+        let b5 = Background::Environment(Arc::new(Canvas::new(4, 0)));
+        assert_eq!(b5.color_at(create_vector(1.0, 0.0, 0.0)), create_color(0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,526 @@
+use std::fmt;
+use crate::arithmetic::*;
+use crate::matrix::*;
+use crate::ray::*;
+use crate::tuple::*;
+
+// An axis aligned bounding box, described by its minimum and maximum corners
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingBox
+{
+    pub min: Tuple,
+    pub max: Tuple,
+}
+
+impl BoundingBox
+{
+    // An empty box, which grows as points are added to it
+    pub fn new() -> Self
+    {
+        BoundingBox{min: create_point(Scalar::INFINITY, Scalar::INFINITY, Scalar::INFINITY),
+            max: create_point(Scalar::NEG_INFINITY, Scalar::NEG_INFINITY, Scalar::NEG_INFINITY)}
+    }
+
+    pub fn new_with_corners(min: Tuple, max: Tuple) -> Self
+    {
+        BoundingBox{min, max}
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        let min = self.min.get_vec();
+        let max = self.max.get_vec();
+        min[0] > max[0] || min[1] > max[1] || min[2] > max[2]
+    }
+
+    pub fn is_infinite(&self) -> bool
+    {
+        let min = self.min.get_vec();
+        let max = self.max.get_vec();
+        (0..3).any(|i| min[i].is_infinite() || max[i].is_infinite())
+    }
+
+    pub fn add_point(&mut self, point: Tuple)
+    {
+        let p = point.get_vec();
+        let min = self.min.get_vec();
+        let max = self.max.get_vec();
+        self.min = create_point(min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2]));
+        self.max = create_point(max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2]));
+    }
+
+    pub fn add_box(&mut self, other: &BoundingBox)
+    {
+        if other.is_empty()
+        {
+            return;
+        }
+        self.add_point(other.min);
+        self.add_point(other.max);
+    }
+
+    pub fn contains_point(&self, point: Tuple) -> bool
+    {
+        let p = point.get_vec();
+        let min = self.min.get_vec();
+        let max = self.max.get_vec();
+        (0..3).all(|i| min[i] <= p[i] && p[i] <= max[i])
+    }
+
+    pub fn contains_box(&self, other: &BoundingBox) -> bool
+    {
+        self.contains_point(other.min) && self.contains_point(other.max)
+    }
+
+    pub fn center(&self) -> Tuple
+    {
+        let min = self.min.get_vec();
+        let max = self.max.get_vec();
+        let mut c = vec![0.0; 3];
+        for i in 0..3
+        {
+            // the centre of a half-infinite or infinite axis is taken as zero
+            c[i] = if min[i].is_infinite() || max[i].is_infinite()
+            {
+                0.0
+            }
+            else
+            {
+                (min[i] + max[i]) / 2.0
+            };
+        }
+        create_point(c[0], c[1], c[2])
+    }
+
+    // The box enclosing this box after it has been transformed by a matrix
+    pub fn transform(&self, matrix: &Matrix) -> BoundingBox
+    {
+        let mut transformed = BoundingBox::new();
+        if self.is_empty()
+        {
+            return transformed;
+        }
+        if self.is_infinite()
+        {
+            // Transforming infinite corners produces NaN, so only the
+            // axes that stay finite under a pure translation are kept.
+            if Self::is_translation(matrix)
+            {
+                let offset = matrix.multiply_tuple(create_point(0.0, 0.0, 0.0)).get_vec();
+                let min = self.min.get_vec();
+                let max = self.max.get_vec();
+                return BoundingBox::new_with_corners(
+                    create_point(min[0] + offset[0], min[1] + offset[1], min[2] + offset[2]),
+                    create_point(max[0] + offset[0], max[1] + offset[1], max[2] + offset[2]));
+            }
+            return Self::infinite();
+        }
+        let min = self.min.get_vec();
+        let max = self.max.get_vec();
+        for x in [min[0], max[0]]
+        {
+            for y in [min[1], max[1]]
+            {
+                for z in [min[2], max[2]]
+                {
+                    transformed.add_point(matrix.multiply_tuple(create_point(x, y, z)));
+                }
+            }
+        }
+        transformed
+    }
+
+    pub fn infinite() -> BoundingBox
+    {
+        BoundingBox::new_with_corners(
+            create_point(Scalar::NEG_INFINITY, Scalar::NEG_INFINITY, Scalar::NEG_INFINITY),
+            create_point(Scalar::INFINITY, Scalar::INFINITY, Scalar::INFINITY))
+    }
+
+    fn is_translation(matrix: &Matrix) -> bool
+    {
+        for y in 0..3
+        {
+            for x in 0..3
+            {
+                let expected = if x == y { 1.0 } else { 0.0 };
+                if !fuzzy_equal(matrix.at(y, x), expected)
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // Slab test, like the cube intersection but for an arbitrary box
+    pub fn intersects(&self, ray: Ray) -> bool
+    {
+        match self.intersection_range(ray)
+        {
+            Some((_, tmax)) => tmax >= 0.0,
+            None => false,
+        }
+    }
+
+    // The range of t over which the line along the ray is inside the box,
+    // including any part of it behind the ray's origin
+    pub fn intersection_range(&self, ray: Ray) -> Option<(Scalar, Scalar)>
+    {
+        self.slab_range(&SlabRay::new(ray))
+    }
+
+    // Like intersection_range, for a ray prepared for testing many boxes.
+    // The three slabs are worked out together as the lanes of 4-wide
+    // arrays, so the compiler can use SIMD min and max instructions.
+    pub fn slab_range(&self, ray: &SlabRay) -> Option<(Scalar, Scalar)>
+    {
+        if self.is_empty()
+        {
+            return None;
+        }
+        // the fourth lane is a slab containing the whole line
+        let mut min = self.min.to_array();
+        let mut max = self.max.to_array();
+        min[3] = Scalar::NEG_INFINITY;
+        max[3] = Scalar::INFINITY;
+
+        let mut near = [0.0; 4];
+        let mut far = [0.0; 4];
+        for i in 0..4
+        {
+            let t0 = (min[i] - ray.origin[i]) * ray.inverse_direction[i];
+            let t1 = (max[i] - ray.origin[i]) * ray.inverse_direction[i];
+            // NaN only comes from a ray parallel to a slab and starting on
+            // one of its planes, which counts as inside the slab
+            let parallel_edge = t0.is_nan() || t1.is_nan();
+            near[i] = if parallel_edge { Scalar::NEG_INFINITY } else { t0.min(t1) };
+            far[i] = if parallel_edge { Scalar::INFINITY } else { t0.max(t1) };
+        }
+        let tmin = near[0].max(near[1]).max(near[2].max(near[3]));
+        let tmax = far[0].min(far[1]).min(far[2].min(far[3]));
+        if tmin <= tmax
+        {
+            Some((tmin, tmax))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    pub fn surface_area(&self) -> Scalar
+    {
+        if self.is_empty()
+        {
+            return 0.0;
+        }
+        let size = self.max.sub(self.min).get_vec();
+        2.0 * (size[0] * size[1] + size[1] * size[2] + size[2] * size[0])
+    }
+}
+
+// A ray prepared for slab tests against many boxes, with the reciprocal of
+// its direction worked out once. A direction of zero on an axis gives an
+// infinite reciprocal, so a ray parallel to a slab is inside it everywhere
+// or nowhere.
+#[derive(Copy, Clone, Debug)]
+pub struct SlabRay
+{
+    origin: [Scalar; 4],
+    inverse_direction: [Scalar; 4],
+}
+
+impl SlabRay
+{
+    pub fn new(ray: Ray) -> Self
+    {
+        let mut origin = ray.origin.to_array();
+        let direction = ray.direction.to_array();
+        origin[3] = 0.0;
+        let inverse_direction = [1.0 / direction[0], 1.0 / direction[1],
+            1.0 / direction[2], 1.0];
+        SlabRay{origin, inverse_direction}
+    }
+}
+
+impl fmt::Display for BoundingBox
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "min {} max {}", self.min, self.max)
+    }
+}
+
+impl PartialEq for BoundingBox
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        let a = self.min.get_vec().into_iter().chain(self.max.get_vec());
+        let b = other.min.get_vec().into_iter().chain(other.max.get_vec());
+        a.zip(b).all(|(x, y)| x == y || fuzzy_equal(x, y))
+    }
+}
+
+// A sphere enclosing a shape, cheaper to test against than a box
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingSphere
+{
+    pub center: Tuple,
+    pub radius: Scalar,
+}
+
+impl BoundingSphere
+{
+    pub fn new(center: Tuple, radius: Scalar) -> Self
+    {
+        BoundingSphere{center, radius}
+    }
+
+    // A sphere with no extent, the starting point for merging
+    pub fn empty() -> Self
+    {
+        BoundingSphere{center: create_point(0.0, 0.0, 0.0), radius: -1.0}
+    }
+
+    pub fn infinite() -> Self
+    {
+        BoundingSphere{center: create_point(0.0, 0.0, 0.0), radius: Scalar::INFINITY}
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.radius < 0.0
+    }
+
+    pub fn is_infinite(&self) -> bool
+    {
+        self.radius.is_infinite()
+    }
+
+    // The smallest sphere enclosing a box
+    pub fn from_box(bounds: &BoundingBox) -> Self
+    {
+        if bounds.is_empty()
+        {
+            return Self::empty();
+        }
+        if bounds.is_infinite()
+        {
+            return Self::infinite();
+        }
+        let center = bounds.center();
+        BoundingSphere{center, radius: bounds.max.sub(center).magnitude()}
+    }
+
+    pub fn contains_point(&self, point: Tuple) -> bool
+    {
+        point.sub(self.center).magnitude() <= self.radius + EPSILON
+    }
+
+    // The smallest sphere enclosing both this sphere and another one
+    pub fn merge(&self, other: &BoundingSphere) -> BoundingSphere
+    {
+        if other.is_empty()
+        {
+            return *self;
+        }
+        if self.is_empty()
+        {
+            return *other;
+        }
+        if self.is_infinite() || other.is_infinite()
+        {
+            return Self::infinite();
+        }
+        let offset = other.center.sub(self.center);
+        let distance = offset.magnitude();
+        if distance + other.radius <= self.radius
+        {
+            return *self;
+        }
+        if distance + self.radius <= other.radius
+        {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        let center = self.center.add(offset.multiply((radius - self.radius) / distance));
+        BoundingSphere{center, radius}
+    }
+
+    // The sphere enclosing this sphere after it has been transformed by a matrix
+    pub fn transform(&self, matrix: &Matrix) -> BoundingSphere
+    {
+        if self.is_empty() || self.is_infinite()
+        {
+            return *self;
+        }
+        BoundingSphere{center: matrix.multiply_tuple(self.center),
+            radius: self.radius * max_stretch(matrix)}
+    }
+}
+
+impl fmt::Display for BoundingSphere
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "center {} radius {}", self.center, self.radius)
+    }
+}
+
+impl PartialEq for BoundingSphere
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.center == other.center &&
+            (self.radius == other.radius || fuzzy_equal(self.radius, other.radius))
+    }
+}
+
+// The largest factor by which the matrix can lengthen a vector, which is
+// the square root of the largest eigenvalue of A^T A, where A is the upper
+// 3x3 part of the matrix. Computed in closed form for symmetric 3x3 matrices.
+pub fn max_stretch(matrix: &Matrix) -> Scalar
+{
+    let mut b = [[0.0; 3]; 3];
+    for y in 0..3
+    {
+        for x in 0..3
+        {
+            b[y][x] = (0..3).map(|i| matrix.at(i, y) * matrix.at(i, x)).sum();
+        }
+    }
+
+    let p1 = b[0][1].powi(2) + b[0][2].powi(2) + b[1][2].powi(2);
+    if p1 == 0.0
+    {
+        return b[0][0].max(b[1][1]).max(b[2][2]).sqrt();
+    }
+    let q = (b[0][0] + b[1][1] + b[2][2]) / 3.0;
+    let p2 = (b[0][0] - q).powi(2) + (b[1][1] - q).powi(2) + (b[2][2] - q).powi(2) + 2.0 * p1;
+    let p = (p2 / 6.0).sqrt();
+    let mut c = b;
+    for i in 0..3
+    {
+        c[i][i] -= q;
+    }
+    for y in 0..3
+    {
+        for x in 0..3
+        {
+            c[y][x] /= p;
+        }
+    }
+    let det = c[0][0] * (c[1][1] * c[2][2] - c[1][2] * c[2][1]) -
+        c[0][1] * (c[1][0] * c[2][2] - c[1][2] * c[2][0]) +
+        c[0][2] * (c[1][0] * c[2][1] - c[1][1] * c[2][0]);
+    let r = (det / 2.0).clamp(-1.0, 1.0);
+    let phi = r.acos() / 3.0;
+    let largest = q + 2.0 * p * phi.cos();
+    largest.max(0.0).sqrt()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+
+    #[test]
+    fn test_bounding_box_feature()
+    {
+        // Scenario: Creating an empty bounding box
+        let box1 = BoundingBox::new();
+        assert!(box1.is_empty());
+
+        // Scenario: Adding points to an empty bounding box
+        let mut box2 = BoundingBox::new();
+        box2.add_point(create_point(-5.0, 2.0, 0.0));
+        box2.add_point(create_point(7.0, 0.0, -3.0));
+        assert_eq!(box2.min, create_point(-5.0, 0.0, -3.0));
+        assert_eq!(box2.max, create_point(7.0, 2.0, 0.0));
+
+        // Scenario: Adding one bounding box to another
+        let mut box3 = BoundingBox::new_with_corners(create_point(-5.0, -2.0, 0.0),
+            create_point(7.0, 4.0, 4.0));
+        let box4 = BoundingBox::new_with_corners(create_point(8.0, -7.0, -2.0),
+            create_point(14.0, 2.0, 8.0));
+        box3.add_box(&box4);
+        assert_eq!(box3.min, create_point(-5.0, -7.0, -2.0));
+        assert_eq!(box3.max, create_point(14.0, 4.0, 8.0));
+
+        // Scenario: Checking to see if a box contains a given point
+        let box5 = BoundingBox::new_with_corners(create_point(5.0, -2.0, 0.0),
+            create_point(11.0, 4.0, 7.0));
+        assert!(box5.contains_point(create_point(5.0, -2.0, 0.0)));
+        assert!(box5.contains_point(create_point(8.0, 1.0, 3.0)));
+        assert!(!box5.contains_point(create_point(3.0, 0.0, 3.0)));
+        assert!(!box5.contains_point(create_point(8.0, -4.0, 3.0)));
+
+        // Scenario: Transforming a bounding box
+        let box6 = BoundingBox::new_with_corners(create_point(-1.0, -1.0, -1.0),
+            create_point(1.0, 1.0, 1.0));
+        let matrix6 = Matrix::rotation_x(PI / 4.0).multiply(&Matrix::rotation_y(PI / 4.0));
+        let box7 = box6.transform(&matrix6);
+        assert_eq!(box7.min, create_point(-1.41421, -1.70711, -1.70711));
+        assert_eq!(box7.max, create_point(1.41421, 1.70711, 1.70711));
+
+        // Scenario: Intersecting a ray with a bounding box
+        let box8 = BoundingBox::new_with_corners(create_point(5.0, -2.0, 0.0),
+            create_point(11.0, 4.0, 7.0));
+        let r81 = Ray::new(create_point(15.0, 1.0, 2.0), create_vector(-1.0, 0.0, 0.0));
+        let r82 = Ray::new(create_point(15.0, 20.0, 2.0), create_vector(-1.0, 0.0, 0.0));
+        assert!(box8.intersects(r81));
+        assert!(!box8.intersects(r82));
+        assert_eq!(box8.intersection_range(r81), Some((4.0, 10.0)));
+        assert_eq!(box8.intersection_range(r82), None);
+
+        // Scenario: A ray parallel to a slab is inside it only between its planes,
+        // including when it starts on one of them
+        let r83 = Ray::new(create_point(15.0, 4.0, 2.0), create_vector(-1.0, 0.0, 0.0));
+        let r84 = Ray::new(create_point(15.0, 4.0, 7.5), create_vector(-1.0, 0.0, 0.0));
+        assert_eq!(box8.intersection_range(r83), Some((4.0, 10.0)));
+        assert_eq!(box8.intersection_range(r84), None);
+        let r85 = Ray::new(create_point(8.0, 1.0, 3.0), create_vector(0.0, 1.0, 1.0).normalize());
+        let range85 = SlabRay::new(r85);
+        let (tmin85, tmax85) = box8.slab_range(&range85).unwrap();
+        assert!(tmin85 < 0.0 && tmax85 > 0.0);
+        assert!(BoundingBox::new().slab_range(&range85).is_none());
+
+        // Scenario: An infinite box contains the whole line
+        let box9 = BoundingBox::new_with_corners(
+            create_point(Scalar::NEG_INFINITY, Scalar::NEG_INFINITY, Scalar::NEG_INFINITY),
+            create_point(Scalar::INFINITY, Scalar::INFINITY, Scalar::INFINITY));
+        assert_eq!(box9.intersection_range(r83), Some((Scalar::NEG_INFINITY, Scalar::INFINITY)));
+
+        // Scenario: The surface area of a bounding box
+        assert!(fuzzy_equal(box8.surface_area(), 2.0 * (36.0 + 42.0 + 42.0)));
+    }
+
+    #[test]
+    fn test_bounding_sphere_feature()
+    {
+        // Scenario: A bounding sphere around a box
+        let box1 = BoundingBox::new_with_corners(create_point(-1.0, -1.0, -1.0),
+            create_point(1.0, 1.0, 1.0));
+        let sphere1 = BoundingSphere::from_box(&box1);
+        assert_eq!(sphere1.center, create_point(0.0, 0.0, 0.0));
+        assert!(fuzzy_equal(sphere1.radius, Scalar::sqrt(3.0)));
+
+        // Scenario: Merging two bounding spheres
+        let sphere2 = BoundingSphere::new(create_point(-2.0, 0.0, 0.0), 1.0);
+        let sphere3 = BoundingSphere::new(create_point(2.0, 0.0, 0.0), 1.0);
+        let merged = sphere2.merge(&sphere3);
+        assert_eq!(merged.center, create_point(0.0, 0.0, 0.0));
+        assert!(fuzzy_equal(merged.radius, 3.0));
+
+        // Scenario: Transforming a bounding sphere
+        let sphere4 = BoundingSphere::new(create_point(0.0, 0.0, 0.0), 1.0);
+        let matrix4 = Matrix::translation(1.0, 2.0, 3.0)
+            .multiply(&Matrix::rotation_z(PI / 3.0))
+            .multiply(&Matrix::scaling(1.0, 4.0, 2.0));
+        let sphere5 = sphere4.transform(&matrix4);
+        assert_eq!(sphere5.center, create_point(1.0, 2.0, 3.0));
+        assert!(fuzzy_equal(sphere5.radius, 4.0));
+    }
+}
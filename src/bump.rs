@@ -0,0 +1,132 @@
+use crate::arithmetic::*;
+use crate::noise::*;
+use crate::pattern::*;
+use crate::shape::*;
+use crate::tuple::*;
+
+// What raises and lowers the surface of a bumped material
+#[derive(Clone, Debug)]
+pub enum BumpSource
+{
+    // Perlin noise in the shape's space, with features about 1/scale apart
+    Noise{scale: Scalar},
+    // the average of the red, green and blue of a pattern, from 0 for low
+    // to 1 for high
+    Pattern(Box<Pattern>),
+}
+
+// Fakes small bumps in a surface by tilting its normal, as if the surface
+// were raised by a height field, without moving the surface itself. The
+// bumps show in the shading but not in the outline of the shape.
+#[derive(Clone, Debug)]
+pub struct Bump
+{
+    pub source: BumpSource,
+    // the height of the field where the source is 1
+    pub amount: Scalar,
+}
+
+// How far apart the heights are sampled to find their slope
+const DELTA: Scalar = 0.001;
+
+impl Bump
+{
+    pub fn new(source: BumpSource, amount: Scalar) -> Self
+    {
+        Bump{source, amount}
+    }
+
+    pub fn noise(scale: Scalar, amount: Scalar) -> Self
+    {
+        Bump::new(BumpSource::Noise{scale}, amount)
+    }
+
+    pub fn pattern(pattern: Pattern, amount: Scalar) -> Self
+    {
+        Bump::new(BumpSource::Pattern(Box::new(pattern)), amount)
+    }
+
+    // The height of the field at a point in world space
+    pub fn height_at(&self, object: &Shape, world_point: Tuple) -> Scalar
+    {
+        let height = match &self.source
+        {
+            BumpSource::Noise{scale} => perlin(object.world_to_object(world_point).multiply(*scale)),
+            BumpSource::Pattern(p) =>
+            {
+                let c = p.pattern_at_shape(object, world_point).to_array();
+                (c[0] + c[1] + c[2]) / 3.0
+            },
+        };
+        self.amount * height
+    }
+
+    // The normal tilted away from where the field rises, keeping only the
+    // slope along the surface
+    pub fn perturb(&self, object: &Shape, world_point: Tuple, normal: Tuple) -> Tuple
+    {
+        let slope = |axis: Tuple| (self.height_at(object, world_point.add(axis.multiply(DELTA)))
+            - self.height_at(object, world_point.sub(axis.multiply(DELTA)))) / (2.0 * DELTA);
+        let gradient = create_vector(slope(create_vector(1.0, 0.0, 0.0)), slope(create_vector(0.0, 1.0, 0.0)),
+            slope(create_vector(0.0, 0.0, 1.0)));
+        let along_surface = gradient.sub(normal.multiply(gradient.dot_product(normal)));
+        normal.sub(along_surface).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::intersections::*;
+    use crate::material::*;
+    use crate::matrix::*;
+    use crate::ray::*;
+
+    #[test]
+    fn test_bump_feature()
+    {
+        let up = create_vector(0.0, 1.0, 0.0);
+        let plane = Shape::new_plane(1);
+
+        // Scenario: A flat height field leaves the normal alone
+        let flat = Bump::pattern(Pattern::new_solid_pattern(create_color(0.5, 0.5, 0.5)), 1.0);
+        assert_eq!(flat.perturb(&plane, create_point(0.3, 0.0, 0.7), up), up);
+
+        // Scenario: The normal tilts away from a rising slope
+        let gradient = Pattern::new_gradient_pattern(create_color(1.0, 1.0, 1.0), create_color(0.0, 0.0, 0.0));
+        let falling = Bump::pattern(gradient, 0.5);
+        assert!(fuzzy_equal(falling.height_at(&plane, create_point(0.5, 0.0, 0.0)), 0.25));
+        let n1 = falling.perturb(&plane, create_point(0.5, 0.0, 0.0), up);
+        assert_eq!(n1, create_vector(0.5, 1.0, 0.0).normalize());
+
+        // Scenario: The bumps follow the shape's transform
+        let mut moved = Shape::new_plane(2);
+        moved.set_transform(Matrix::scaling(2.0, 1.0, 1.0));
+        let n2 = falling.perturb(&moved, create_point(1.0, 0.0, 0.0), up);
+        assert_eq!(n2, create_vector(0.25, 1.0, 0.0).normalize());
+
+        // Scenario: Noise bumps tilt the normal a little, by the amount
+        let p = create_point(0.37, 0.0, 1.61);
+        let rough = Bump::noise(4.0, 0.05).perturb(&plane, p, up);
+        let rougher = Bump::noise(4.0, 0.2).perturb(&plane, p, up);
+        assert_ne!(rough, up);
+        assert!(fuzzy_equal(rough.magnitude(), 1.0));
+        assert!(rougher.dot_product(up) < rough.dot_product(up) && rough.dot_product(up) > 0.0);
+
+        // Scenario: Hits on a bumped shape use the tilted normal for shading
+        let mut m = Material::new();
+        m.bump = Some(Bump::pattern(Pattern::new_gradient_pattern(create_color(1.0, 1.0, 1.0),
+            create_color(0.0, 0.0, 0.0)), 0.5));
+        let mut bumped = Shape::new_plane(3);
+        bumped.set_material(m);
+        let r = Ray::new(create_point(0.5, 1.0, 0.0), create_vector(0.0, -1.0, 0.0));
+        let i = Intersection::new(1.0, &bumped);
+        let comps = i.prepare_computations(r, Intersections::new(vec![i.clone()]));
+        assert_eq!(comps.normalv, create_vector(0.5, 1.0, 0.0).normalize());
+        // the points off the surface are still along the true normal
+        assert_eq!(comps.over_point.to_array()[0], 0.5);
+        assert!(comps.over_point.to_array()[1] > 0.0);
+        assert_eq!(comps.reflectv, r.direction.reflect(comps.normalv));
+    }
+}
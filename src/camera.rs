@@ -1,9 +1,22 @@
 use std::fmt;
-use std::f64::consts::PI;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use crate::aov::*;
+use crate::arithmetic::consts::PI;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::canvas::*;
+use crate::heatmap::*;
+use crate::integrator::*;
 use crate::matrix::*;
+use crate::pointlight::*;
+use crate::postprocess::*;
 use crate::ray::*;
+use crate::sampler::*;
+use crate::shape::*;
 use crate::tuple::*;
 use crate::world::*;
 
@@ -12,19 +25,125 @@ pub struct Camera
 {
     pub hsize: u16,
     pub vsize: u16,
-    pub field_of_view: f64,
-    pub half_width: f64,
-    pub half_height: f64,
-    pub pixel_size: f64,
+    pub field_of_view: Scalar,
+    pub half_width: Scalar,
+    pub half_height: Scalar,
+    pub pixel_size: Scalar,
     pub transform: Matrix,
+    // in stops, so that each 1 doubles the brightness of the image
+    pub exposure: Scalar,
+    // multiplies each channel, to take a color cast out of the lighting
+    pub white_balance: Tuple,
+    // applied to the finished image
+    pub effects: PostEffects,
+    // used when writing 8-bit images
+    pub dither: Dither,
+    // how the images rendered are encoded when written
+    pub gamma: Gamma,
+    pub bit_depth: BitDepth,
+    // when set, each pixel is sampled until its color settles, for smooth
+    // edges; otherwise one ray goes through each pixel's center
+    pub sampling: Option<AdaptiveSampling>,
+    // picks the points in each pixel that are sampled
+    pub sampler: SamplerKind,
+    // the same seed gives the same samples, so renders can be repeated
+    pub seed: u64,
+    // how many threads render uses, or 0 for one per core
+    pub threads: usize,
+    // shades this camera's renders in place of the world's integrator
+    pub integrator: Option<Arc<dyn Integrator>>,
+    // the width of the lens, 0 for a pinhole camera with everything in
+    // focus. Wider lenses blur more of what is not at the focal distance.
+    pub aperture: Scalar,
+    // how far in front of the camera things are in focus
+    pub focal_distance: Scalar,
+    // when, from 0 to 1, the shutter opens and closes. Moving shapes are
+    // blurred along the part of their path in between, or seen where they
+    // are when the shutter opens if it closes at the same time.
+    pub shutter_open: Scalar,
+    pub shutter_close: Scalar,
+    // how many samples each pixel takes when the camera blurs it, through
+    // its lens or over its shutter, and has no adaptive sampling
+    pub blur_samples: u32,
+    pub projection: Projection,
+    // how far apart the eyes of a stereo render are, in the scene's units
+    pub interocular: Scalar,
+    // when set, only the pixels from the first column and row up to, but
+    // not including, the last are rendered, and the rest are left black
+    pub crop: Option<[u16; 4]>,
+}
+
+// One of the two views of a stereo render
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Eye
+{
+    Left,
+    Right,
+}
+
+// How the pixels of the canvas are mapped to directions from the camera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection
+{
+    // the book's camera, where straight lines stay straight
+    Perspective,
+    // an equidistant fisheye lens, where the angle from the middle of the
+    // image grows evenly with the distance from it. The field of view is
+    // measured across the diagonal, so 180 degrees fills the image with
+    // half of everything and 360 degrees shows all of it.
+    Fisheye,
+    // every direction, as an environment map or panorama with longitude
+    // across and latitude down, looking straight ahead in the middle. The
+    // field of view is not used, and images are usually twice as wide as
+    // they are high.
+    Equirectangular,
+}
+
+// Sampling each pixel at least min_samples and at most max_samples
+// times, stopping once the estimated error of its brightness is below
+// the threshold, so that flat areas take few samples and edges many
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveSampling
+{
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: Scalar,
+}
+
+impl AdaptiveSampling
+{
+    pub fn new() -> Self
+    {
+        AdaptiveSampling{min_samples: 4, max_samples: 64, threshold: 0.01}
+    }
+}
+
+// What a sample map shows for each pixel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SampleMetric
+{
+    // the number of samples taken
+    Count,
+    // how much the brightness of the samples varied
+    Variance,
+}
+
+// The color of a pixel and how it was arrived at
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelSamples
+{
+    pub color: Tuple,
+    pub count: u32,
+    // of the samples' brightness
+    pub variance: Scalar,
 }
 
 impl Camera
 {
-    pub fn new(hsize: u16, vsize: u16, field_of_view: f64) -> Self
+    pub fn new(hsize: u16, vsize: u16, field_of_view: Scalar) -> Self
     {
         let half_view = (field_of_view / 2.0).tan();
-        let aspect = f64::from(hsize) / f64::from(vsize);
+        let aspect = Scalar::from(hsize) / Scalar::from(vsize);
         let half_width;
         let half_height;
         if aspect >= 1.0
@@ -37,53 +156,873 @@ impl Camera
             half_width = half_view * aspect;
             half_height = half_view;
         }
-        let pixel_size = (half_width * 2.0) / f64::from(hsize);
+        let pixel_size = (half_width * 2.0) / Scalar::from(hsize);
         Camera{hsize, vsize, field_of_view,
             half_width, half_height, pixel_size,
-            transform: Matrix::identity(4)}
+            transform: Matrix::identity(4),
+            exposure: 0.0, white_balance: create_color(1.0, 1.0, 1.0),
+            effects: PostEffects::new(), dither: Dither::None, gamma: Gamma::Srgb,
+            bit_depth: BitDepth::Eight, sampling: None,
+            sampler: SamplerKind::Sequence(SequenceKind::Sobol), seed: 0, threads: 0,
+            integrator: None, aperture: 0.0, focal_distance: 1.0,
+            shutter_open: 0.0, shutter_close: 0.0, blur_samples: 16,
+            projection: Projection::Perspective, interocular: 0.065,
+            crop: None}
+    }
+
+    // Changes the field of view, keeping the size of the image
+    pub fn set_field_of_view(&mut self, field_of_view: Scalar)
+    {
+        let resized = Camera::new(self.hsize, self.vsize, field_of_view);
+        self.field_of_view = field_of_view;
+        self.half_width = resized.half_width;
+        self.half_height = resized.half_height;
+        self.pixel_size = resized.pixel_size;
+    }
+
+    // Changes the size of the image, keeping the field of view
+    pub fn set_size(&mut self, hsize: u16, vsize: u16)
+    {
+        self.hsize = hsize;
+        self.vsize = vsize;
+        self.set_field_of_view(self.field_of_view);
+    }
+
+    // Renders only a rectangle of the image, from column x0 and row y0 up
+    // to, but not including, column x1 and row y1, to look at one part of
+    // it without waiting for the rest
+    pub fn set_crop(&mut self, x0: u16, y0: u16, x1: u16, y1: u16)
+    {
+        self.crop = Some([x0, y0, x1, y1]);
+    }
+
+    pub fn clear_crop(&mut self)
+    {
+        self.crop = None;
+    }
+
+    // Whether a pixel is rendered
+    pub fn in_crop(&self, x: u16, y: u16) -> bool
+    {
+        match self.crop
+        {
+            Some([x0, y0, x1, y1]) => x >= x0 && x < x1 && y >= y0 && y < y1,
+            None => true,
+        }
+    }
+
+    // Renders with an integrator other than the world's, such as a path
+    // tracer for a final image and the Whitted integrator for previews
+    pub fn set_integrator<I: Integrator + 'static>(&mut self, integrator: I)
+    {
+        self.integrator = Some(Arc::new(integrator));
+    }
+
+    // Whether pixels are blurred by the lens or the shutter, and so need
+    // more than one sample
+    fn blurs(&self) -> bool
+    {
+        self.aperture > 0.0 || self.shutter_close > self.shutter_open
+    }
+
+    // The camera for one eye, moved half the interocular distance to its
+    // side and looking the same way, so that the eyes' views are parallel
+    pub fn eye(&self, eye: Eye) -> Camera
+    {
+        // the camera's x axis points to the left of the image
+        let offset = match eye
+        {
+            Eye::Left => -self.interocular / 2.0,
+            Eye::Right => self.interocular / 2.0,
+        };
+        let mut camera = self.clone();
+        camera.transform = Matrix::translation(offset, 0.0, 0.0).multiply(&self.transform);
+        camera
+    }
+
+    // Renders the images seen by the left and right eyes
+    pub fn render_stereo(&self, world: &World) -> (Canvas, Canvas)
+    {
+        (self.eye(Eye::Left).render(world.clone()), self.eye(Eye::Right).render(world.clone()))
+    }
+
+    // Renders both eyes into one image for red and cyan glasses
+    pub fn render_anaglyph(&self, world: &World) -> Canvas
+    {
+        let (left, right) = self.render_stereo(world);
+        Canvas::anaglyph(&left, &right)
+    }
+
+    // Applies the exposure and white balance to a color seen by the camera
+    pub fn develop(&self, color: Tuple) -> Tuple
+    {
+        color.hadamard_product(self.white_balance).multiply(self.exposure.exp2())
     }
 
     pub fn ray_for_pixel(&self, px: u16, py: u16) -> Ray
     {
-        // the offset from the edge of the canvas to the pixel's center.
-        let xoffset = (f64::from(px) + 0.5) * self.pixel_size;
-        let yoffset = (f64::from(py) + 0.5) * self.pixel_size;
+        self.ray_for_pixel_with(&self.transform.inverse(), px, py)
+    }
 
-        // the untransformed coordinates of the pixel in world space.
-        let world_x = self.half_width - xoffset;
-        let world_y = self.half_height - yoffset;
+    // Like ray_for_pixel, with the inverse of the camera transform already
+    // computed
+    fn ray_for_pixel_with(&self, inverse: &Matrix, px: u16, py: u16) -> Ray
+    {
+        self.ray_through(inverse, Scalar::from(px) + 0.5, Scalar::from(py) + 0.5)
+    }
 
+    // A point that a point of the canvas, measured in pixels from its top
+    // left corner, is seen in the direction of, in the camera's own space
+    // where it looks along -z
+    fn canvas_point(&self, x: Scalar, y: Scalar) -> Tuple
+    {
+        match self.projection
+        {
+            Projection::Perspective =>
+            {
+                // the offset from the edge of the canvas to the point.
+                let xoffset = x * self.pixel_size;
+                let yoffset = y * self.pixel_size;
+
+                // the untransformed coordinates of the pixel in world space.
+                // (remember that the canvas is at z=-1).
+                create_point(self.half_width - xoffset, self.half_height - yoffset, -1.0)
+            },
+            Projection::Fisheye =>
+            {
+                let half_w = Scalar::from(self.hsize) / 2.0;
+                let half_h = Scalar::from(self.vsize) / 2.0;
+                // as a share of the distance from the middle to a corner
+                let diagonal = (half_w * half_w + half_h * half_h).sqrt();
+                let (u, v) = ((half_w - x) / diagonal, (half_h - y) / diagonal);
+                let theta = (u * u + v * v).sqrt() * self.field_of_view / 2.0;
+                let around = v.atan2(u);
+                create_point(theta.sin() * around.cos(), theta.sin() * around.sin(), -theta.cos())
+            },
+            Projection::Equirectangular =>
+            {
+                let longitude = (0.5 - x / Scalar::from(self.hsize)) * 2.0 * PI;
+                let latitude = (0.5 - y / Scalar::from(self.vsize)) * PI;
+                create_point(latitude.cos() * longitude.sin(), latitude.sin(), -latitude.cos() * longitude.cos())
+            },
+        }
+    }
+
+    // The ray through a point of the canvas, measured in pixels from its
+    // top left corner
+    fn ray_through(&self, inverse: &Matrix, x: Scalar, y: Scalar) -> Ray
+    {
         // using the camera matrix, transform the canvas point and the origin,
         // and then compute the ray's direction vector.
-        // (remember that the camera is at z=-1).
-        let inverse = self.transform.inverse();
-        let pixel = inverse.multiply_tuple(create_point(world_x, world_y, -1.0));
+        let pixel = inverse.multiply_tuple(self.canvas_point(x, y));
         let origin = inverse.multiply_tuple(create_point(0.0, 0.0, 0.0));
         let direction = pixel.sub(origin).normalize();
         Ray::new(origin, direction)
     }
 
+    // Like ray_through, for a ray through a point on the lens, given as
+    // two numbers from 0 to 1. Every ray through the lens towards a point
+    // of the canvas meets at the focal distance, so things there are
+    // sharp and things nearer or further are blurred.
+    fn ray_through_lens(&self, inverse: &Matrix, x: Scalar, y: Scalar, lens: (Scalar, Scalar)) -> Ray
+    {
+        let p = self.canvas_point(x, y).to_array();
+        let focus = create_point(p[0] * self.focal_distance, p[1] * self.focal_distance,
+            p[2] * self.focal_distance);
+        // spread evenly over the disc of the lens
+        let radius = self.aperture / 2.0 * lens.0.sqrt();
+        let angle = 2.0 * PI * lens.1;
+        let origin = inverse.multiply_tuple(create_point(radius * angle.cos(), radius * angle.sin(), 0.0));
+        let direction = inverse.multiply_tuple(focus).sub(origin).normalize();
+        Ray::new(origin, direction)
+    }
+
+    // Checks whether a bounding sphere in world space is at least partly
+    // inside the camera's view frustum. The frustum is the four planes
+    // through the camera origin and the edges of the canvas at z=-1,
+    // plus the plane z=0 so that anything behind the camera is culled.
+    pub fn sphere_in_view(&self, sphere: &BoundingSphere) -> bool
+    {
+        if sphere.is_empty()
+        {
+            return false;
+        }
+        // other projections see too much to cull anything
+        if sphere.is_infinite() || self.projection != Projection::Perspective
+        {
+            return true;
+        }
+        let camera_sphere = sphere.transform(&self.transform);
+        let center = camera_sphere.center.get_vec();
+        let radius = camera_sphere.radius;
+
+        // outward facing normals of the side planes in camera space
+        let planes = [create_vector(1.0, 0.0, self.half_width),
+            create_vector(-1.0, 0.0, self.half_width),
+            create_vector(0.0, 1.0, self.half_height),
+            create_vector(0.0, -1.0, self.half_height),
+            create_vector(0.0, 0.0, 1.0)];
+        let center_vector = create_vector(center[0], center[1], center[2]);
+        for plane in planes
+        {
+            let distance = plane.normalize().dot_product(center_vector);
+            if distance > radius
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    // The pixels a point in world space, and the points beyond it along
+    // the direction, can be seen at, as the smallest and largest x and y.
+    // None means they could be anywhere, since part of the line is behind
+    // the camera.
+    fn line_pixel_range(&self, point: Tuple, direction: Option<Tuple>) -> Option<[Scalar; 4]>
+    {
+        let mut range = [Scalar::INFINITY, Scalar::INFINITY, -Scalar::INFINITY, -Scalar::INFINITY];
+        let p = self.transform.multiply_tuple(point).get_vec();
+        let mut ends = vec![p];
+        if let Some(d) = direction
+        {
+            // where the line vanishes into the distance
+            ends.push(self.transform.multiply_tuple(d).get_vec());
+        }
+        for e in ends
+        {
+            if e[2] >= -EPSILON
+            {
+                return None;
+            }
+            let x = (self.half_width + e[0] / e[2]) / self.pixel_size - 0.5;
+            let y = (self.half_height + e[1] / e[2]) / self.pixel_size - 0.5;
+            range = [range[0].min(x), range[1].min(y), range[2].max(x), range[3].max(y)];
+        }
+        Some(range)
+    }
+
+    // The smallest rectangle of pixels, as the first and last column and
+    // row, where a box in world space can be seen. With a light, the
+    // shadow the box casts is included. Returns None if none of it can be
+    // seen.
+    pub fn pixel_bounds(&self, bounds: &BoundingBox, light: Option<&PointLight>) -> Option<[u16; 4]>
+    {
+        if bounds.is_empty()
+        {
+            return None;
+        }
+        let last_x = self.hsize.saturating_sub(1);
+        let last_y = self.vsize.saturating_sub(1);
+        let everything = Some([0, 0, last_x, last_y]);
+        if bounds.is_infinite() || self.projection != Projection::Perspective
+        {
+            return everything;
+        }
+        if light.is_none() && bounds.transform(&self.transform).min.get_vec()[2] >= 0.0
+        {
+            // all of it is behind the camera
+            return None;
+        }
+        let mut range = [Scalar::INFINITY, Scalar::INFINITY, -Scalar::INFINITY, -Scalar::INFINITY];
+        let min = bounds.min.get_vec();
+        let max = bounds.max.get_vec();
+        for corner in 0..8
+        {
+            let point = create_point(if corner & 1 == 0 { min[0] } else { max[0] },
+                if corner & 2 == 0 { min[1] } else { max[1] },
+                if corner & 4 == 0 { min[2] } else { max[2] });
+            let shadow = light.map(|l| match l.direction
+            {
+                Some(d) => d,
+                None => point.sub(l.position),
+            });
+            match self.line_pixel_range(point, shadow)
+            {
+                Some(r) => range = [range[0].min(r[0]), range[1].min(r[1]),
+                    range[2].max(r[2]), range[3].max(r[3])],
+                None => return everything,
+            }
+        }
+        // a pixel is rendered along the ray through its center, so the
+        // range grows by a pixel on each side to allow for round-off
+        if range[2] < -1.0 || range[3] < -1.0 ||
+            range[0] > Scalar::from(last_x) + 1.0 || range[1] > Scalar::from(last_y) + 1.0
+        {
+            return None;
+        }
+        let clamp = |v: Scalar, last: u16| v.clamp(0.0, Scalar::from(last)) as u16;
+        Some([clamp(range[0].floor() - 1.0, last_x), clamp(range[1].floor() - 1.0, last_y),
+            clamp(range[2].ceil() + 1.0, last_x), clamp(range[3].ceil() + 1.0, last_y)])
+    }
+
+    pub fn is_visible(&self, shape: &Shape) -> bool
+    {
+        self.sphere_in_view(&shape.bounding_sphere())
+    }
+
+    // The objects that primary rays from this camera could possibly hit.
+    // Reflected, refracted and shadow rays still need every object.
+    pub fn visible_objects(&self, objects: &[Shape]) -> Vec<Shape>
+    {
+        objects.iter().filter(|o| self.is_visible(o)).cloned().collect()
+    }
+
+    // Whether renders start threads of their own. They don't with one
+    // thread, or in a browser, where threads can't be started.
+    fn uses_threads(&self) -> bool
+    {
+        self.get_thread_count() > 1 && !cfg!(target_arch = "wasm32")
+    }
+
+    // Runs the work on each of the camera's threads, or once on this one
+    // if it doesn't use threads, giving back what each run returned
+    fn on_threads<T, F>(&self, work: F) -> Vec<T>
+        where T: Send, F: Fn() -> T + Sync
+    {
+        if !self.uses_threads()
+        {
+            return vec![work()];
+        }
+        thread::scope(|scope|
+        {
+            let workers: Vec<_> = (0..self.get_thread_count()).map(|_| scope.spawn(&work)).collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        })
+    }
+
+    // Renders on as many threads as the camera is set to use. Each takes
+    // the next row no thread has started, so that slow rows don't hold
+    // the others up.
     pub fn render(&self, world: World) -> Canvas
     {
         let mut image = Canvas::new(self.hsize.into(), self.vsize.into());
-        for y in 0..self.vsize - 1
+        let next_row = AtomicU16::new(0);
+        let render_rows = ||
+        {
+            let mut worker = RenderWorker::new(self, &world);
+            let mut rows = Vec::new();
+            loop
+            {
+                let y = next_row.fetch_add(1, Ordering::Relaxed);
+                if y >= self.vsize
+                {
+                    break;
+                }
+                rows.push((y, worker.render_row(y, self.hsize).to_vec()));
+                self.report_progress(y);
+            }
+            rows
+        };
+        let results = self.on_threads(render_rows);
+        for (y, row) in results.into_iter().flatten()
+        {
+            for (x, color) in row.iter().enumerate()
+            {
+                image.write_pixel(x, y.into(), *color);
+            }
+        }
+        self.finish(image)
+    }
+
+    // Renders the image along with depth, normal and object id passes, on
+    // the camera's threads. The effects are only applied to the beauty
+    // image.
+    pub fn render_passes(&self, world: &World) -> RenderPasses
+    {
+        let mut passes = RenderPasses::new(self.hsize.into(), self.vsize.into());
+        let next_row = AtomicU16::new(0);
+        let results = self.on_threads(||
+        {
+            let mut worker = RenderWorker::new(self, world);
+            let mut rows = Vec::new();
+            loop
+            {
+                let y = next_row.fetch_add(1, Ordering::Relaxed);
+                if y >= self.vsize
+                {
+                    break;
+                }
+                rows.push((y, (0..self.hsize).map(|x| worker.render_pixel_with_aovs(x, y)).collect::<Vec<_>>()));
+                self.report_progress(y);
+            }
+            rows
+        });
+        for (y, row) in results.into_iter().flatten()
+        {
+            for (x, (color, aovs)) in row.iter().enumerate()
+            {
+                passes.write_pixel(x, y.into(), *color, aovs);
+            }
+        }
+        passes.beauty = self.finish(passes.beauty);
+        passes
+    }
+
+    // Renders the image in square tiles, left to right and top to bottom,
+    // on the camera's threads. The callback is called on this thread as
+    // each tile is finished, with the number of tiles done and the total,
+    // in whatever order the tiles finish.
+    pub fn render_tiles<F>(&self, world: &World, tile_size: u16, mut callback: F) -> Canvas
+        where F: FnMut(&Tile, usize, usize)
+    {
+        let tile_size = tile_size.max(1);
+        let mut tiles = Vec::new();
+        for y in (0..self.vsize).step_by(tile_size.into())
+        {
+            for x in (0..self.hsize).step_by(tile_size.into())
+            {
+                tiles.push(Tile{x, y, width: tile_size.min(self.hsize - x),
+                    height: tile_size.min(self.vsize - y), pixels: Vec::new()});
+            }
+        }
+        let total = tiles.len();
+        let mut image = Canvas::new(self.hsize.into(), self.vsize.into());
+        if !self.uses_threads()
         {
-            for x in 0..self.hsize - 1
+            let mut worker = RenderWorker::new(self, world);
+            for (done, mut tile) in tiles.into_iter().enumerate()
             {
-                let ray = self.ray_for_pixel(x.into(), y.into());
-                let color = world.color_at(ray, World::REFLECTION_RECURSION);
-                image.write_pixel(x.into(), y.into(), color);
+                worker.render_tile(&mut tile);
+                tile.write_to(&mut image);
+                callback(&tile, done + 1, total);
             }
+            return self.finish(image);
+        }
+        let next_tile = AtomicUsize::new(0);
+        thread::scope(|scope|
+        {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..self.get_thread_count().min(total)
+            {
+                let sender = sender.clone();
+                let (tiles, next_tile) = (&tiles, &next_tile);
+                scope.spawn(move ||
+                {
+                    let mut worker = RenderWorker::new(self, world);
+                    loop
+                    {
+                        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                        let mut tile: Tile = match tiles.get(index)
+                        {
+                            Some(tile) => tile.clone(),
+                            None => break,
+                        };
+                        worker.render_tile(&mut tile);
+                        // the receiver only goes away if the callback panics
+                        if sender.send(tile).is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+            for (done, tile) in receiver.iter().enumerate()
+            {
+                tile.write_to(&mut image);
+                callback(&tile, done + 1, total);
+            }
+        });
+        self.finish(image)
+    }
 
-            // Display rendering progress to stderr every N rows
-            if y % 10 == 0
+    // Renders a quick blocky preview and then sharpens it, for when a
+    // scene is still being laid out. The first pass renders one pixel in
+    // each block_size square (rounded up to a power of two) and fills the
+    // square with it; each pass after halves the squares, rendering only
+    // the pixels not rendered before, until every pixel is. The callback
+    // is given the image after each pass, with that pass's block size.
+    pub fn render_progressive<F>(&self, world: &World, block_size: u16, mut callback: F) -> Canvas
+        where F: FnMut(&Canvas, u16)
+    {
+        let mut image = Canvas::new(self.hsize.into(), self.vsize.into());
+        let mut worker = RenderWorker::new(self, world);
+        let mut block = block_size.max(1).checked_next_power_of_two().unwrap_or(1 << 15);
+        let mut previous: Option<u16> = None;
+        loop
+        {
+            for y in (0..self.vsize).step_by(block.into())
             {
-                let percentage = (y * 100) / self.vsize;
-                eprintln!("progress {percentage}%");
+                for x in (0..self.hsize).step_by(block.into())
+                {
+                    // a pixel rendered in an earlier pass is at the corner
+                    // of a bigger block
+                    let color = match previous
+                    {
+                        Some(p) if x % p == 0 && y % p == 0 => image.pixel_at(x.into(), y.into()),
+                        _ => worker.render_pixel(x, y),
+                    };
+                    for by in y..(y + block).min(self.vsize)
+                    {
+                        for bx in x..(x + block).min(self.hsize)
+                        {
+                            image.write_pixel(bx.into(), by.into(), color);
+                        }
+                    }
+                }
             }
+            callback(&image, block);
+            if block == 1
+            {
+                break;
+            }
+            previous = Some(block);
+            block = block / 2;
+        }
+        self.finish(image)
+    }
+
+    // The number of threads render uses: the camera's setting, or one
+    // for each core when that is 0
+    pub fn get_thread_count(&self) -> usize
+    {
+        match self.threads
+        {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n,
+        }
+    }
+
+    // A diagnostic image showing how much work each pixel took, from
+    // black for the least to red for the most, to find where a scene
+    // needs an acceleration structure or simpler shapes
+    pub fn render_heat_map(&self, world: &World, metric: HeatMetric) -> Canvas
+    {
+        let mut worker = RenderWorker::new(self, world);
+        let mut counts = Vec::with_capacity(usize::from(self.hsize) * usize::from(self.vsize));
+        take_counts();
+        for y in 0..self.vsize
+        {
+            for x in 0..self.hsize
+            {
+                worker.render_pixel(x, y);
+                counts.push(take_counts().get(metric));
+            }
+        }
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let mut image = Canvas::new(self.hsize.into(), self.vsize.into());
+        for (i, &count) in counts.iter().enumerate()
+        {
+            image.write_pixel(i % usize::from(self.hsize), i / usize::from(self.hsize), heat_color(count, max));
+        }
+        image
+    }
+
+    // A diagnostic image of how many samples each pixel took, or how
+    // much they varied, from black for none to white for the most, to
+    // tune the adaptive sampling threshold
+    pub fn render_sample_map(&self, world: &World, metric: SampleMetric) -> Canvas
+    {
+        let mut worker = RenderWorker::new(self, world);
+        let mut values = Vec::with_capacity(usize::from(self.hsize) * usize::from(self.vsize));
+        for y in 0..self.vsize
+        {
+            for x in 0..self.hsize
+            {
+                let samples = worker.sample_pixel(x, y);
+                values.push(match metric
+                {
+                    SampleMetric::Count => samples.count as Scalar,
+                    SampleMetric::Variance => samples.variance,
+                });
+            }
+        }
+        let max = match (metric, self.sampling)
+        {
+            (SampleMetric::Count, Some(sampling)) => sampling.max_samples as Scalar,
+            _ => values.iter().copied().fold(0.0, Scalar::max),
+        };
+        let mut image = Canvas::new(self.hsize.into(), self.vsize.into());
+        for (i, value) in values.iter().enumerate()
+        {
+            let shade = if max > 0.0 { value / max } else { 0.0 };
+            image.write_pixel(i % usize::from(self.hsize), i / usize::from(self.hsize),
+                create_color(shade, shade, shade));
         }
         image
     }
+
+    // Like render, but each row is written to a binary PPM as soon as it
+    // is finished instead of the whole image being kept in a canvas
+    pub fn render_to<W: Write>(&self, world: &World, writer: W) -> io::Result<W>
+    {
+        let mut stream = PpmStream::with_depth(writer, self.hsize.into(), self.vsize.into(), self.dither,
+            self.gamma, self.bit_depth)?;
+        let mut worker = RenderWorker::new(self, world);
+        if !self.effects.is_empty()
+        {
+            // the effects need the whole image, so it can't be streamed
+            let mut image = Canvas::new(self.hsize.into(), self.vsize.into());
+            for y in 0..self.vsize
+            {
+                for (x, color) in worker.render_row(y, self.hsize).iter().enumerate()
+                {
+                    image.write_pixel(x, y.into(), *color);
+                }
+                self.report_progress(y);
+            }
+            let image = self.effects.apply(&image);
+            for y in 0..image.height
+            {
+                stream.write_row(image.row(y))?;
+            }
+            return stream.finish();
+        }
+        for y in 0..self.vsize
+        {
+            stream.write_row(worker.render_row(y, self.hsize))?;
+            self.report_progress(y);
+        }
+        stream.finish()
+    }
+
+    // Applies the effects to a rendered image and sets how it is encoded
+    fn finish(&self, image: Canvas) -> Canvas
+    {
+        let mut image = match self.effects.is_empty()
+        {
+            true => image,
+            false => self.effects.apply(&image),
+        };
+        image.gamma = self.gamma;
+        image.bit_depth = self.bit_depth;
+        image
+    }
+
+    // Display rendering progress to stderr every N rows
+    fn report_progress(&self, y: u16)
+    {
+        if y % 10 == 0
+        {
+            let percentage = (u32::from(y) * 100) / u32::from(self.vsize);
+            eprintln!("progress {percentage}%");
+        }
+    }
+}
+
+// Cameras are equal when they would render the same image. Integrators
+// can't be compared, so they are only equal when they are the same one.
+impl PartialEq for Camera
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        let same_integrator = match (&self.integrator, &other.integrator)
+        {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        self.hsize == other.hsize && self.vsize == other.vsize &&
+            self.field_of_view == other.field_of_view && self.transform == other.transform &&
+            self.exposure == other.exposure && self.white_balance == other.white_balance &&
+            self.effects == other.effects && self.dither == other.dither &&
+            self.gamma == other.gamma && self.bit_depth == other.bit_depth &&
+            self.sampling == other.sampling && self.sampler == other.sampler &&
+            self.seed == other.seed && same_integrator &&
+            self.aperture == other.aperture && self.focal_distance == other.focal_distance &&
+            self.shutter_open == other.shutter_open && self.shutter_close == other.shutter_close &&
+            self.blur_samples == other.blur_samples && self.projection == other.projection &&
+            self.interocular == other.interocular && self.crop == other.crop
+    }
+}
+
+// A rectangle of the image, given to render_tiles' callback once its
+// pixels are rendered. The pixels are row by row, before effects.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile
+{
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<Tuple>,
+}
+
+impl Tile
+{
+    pub fn pixel_at(&self, x: u16, y: u16) -> Tuple
+    {
+        self.pixels[usize::from(y - self.y) * usize::from(self.width) + usize::from(x - self.x)]
+    }
+
+    // Copies the tile's pixels into its place in the image
+    fn write_to(&self, image: &mut Canvas)
+    {
+        for (i, color) in self.pixels.iter().enumerate()
+        {
+            let x = usize::from(self.x) + i % usize::from(self.width);
+            let y = usize::from(self.y) + i / usize::from(self.width);
+            image.write_pixel(x, y, *color);
+        }
+    }
+}
+
+// Counters kept by each rendering worker, added together when the
+// workers are done
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats
+{
+    pub rows: u64,
+    pub pixels: u64,
+}
+
+impl RenderStats
+{
+    pub fn merge(&self, other: &RenderStats) -> RenderStats
+    {
+        RenderStats{rows: self.rows + other.rows, pixels: self.pixels + other.pixels}
+    }
+}
+
+// The state a rendering worker reuses from pixel to pixel, so that the
+// pixel loop neither allocates nor shares anything with other workers
+pub struct RenderWorker<'a>
+{
+    camera: &'a Camera,
+    world: &'a World,
+    integrator: &'a dyn Integrator,
+    inverse: Matrix,
+    row: Vec<Tuple>,
+    sampler: Box<dyn Sampler>,
+    // picks the points on the lens, apart from the points in the pixel
+    lens_sampler: Box<dyn Sampler>,
+    // picks the times while the shutter is open
+    time_sampler: Box<dyn Sampler>,
+    pub stats: RenderStats,
+}
+
+impl<'a> RenderWorker<'a>
+{
+    pub fn new(camera: &'a Camera, world: &'a World) -> Self
+    {
+        let integrator = camera.integrator.as_deref().unwrap_or(world.get_integrator());
+        RenderWorker{camera, world, integrator, inverse: camera.transform.inverse(),
+            row: Vec::with_capacity(camera.hsize.into()), sampler: camera.sampler.create(),
+            lens_sampler: camera.sampler.create(), time_sampler: camera.sampler.create(),
+            stats: RenderStats::default()}
+    }
+
+    // Renders the first width pixels of a row. The returned row is only
+    // valid until the next one is rendered.
+    pub fn render_row(&mut self, y: u16, width: u16) -> &[Tuple]
+    {
+        self.row.clear();
+        for x in 0..width
+        {
+            let color = self.render_pixel(x, y);
+            self.row.push(color);
+        }
+        self.stats.rows += 1;
+        &self.row
+    }
+
+    // Fills in the pixels of an empty tile
+    fn render_tile(&mut self, tile: &mut Tile)
+    {
+        for y in tile.y..tile.y + tile.height
+        {
+            for x in tile.x..tile.x + tile.width
+            {
+                tile.pixels.push(self.render_pixel(x, y));
+            }
+        }
+    }
+
+    pub fn render_pixel(&mut self, x: u16, y: u16) -> Tuple
+    {
+        self.sample_pixel(x, y).color
+    }
+
+    // Like render_pixel, also giving what the ray through the pixel first
+    // hit. With more than one sample, the outputs are of the ray through
+    // the center of the pixel, since ids and normals can't be averaged.
+    pub fn render_pixel_with_aovs(&mut self, x: u16, y: u16) -> (Tuple, Aovs)
+    {
+        let ray = self.camera.ray_for_pixel_with(&self.inverse, x, y).with_time(self.camera.shutter_open);
+        if !self.camera.in_crop(x, y)
+        {
+            return (self.render_pixel(x, y), Aovs::new());
+        }
+        if self.camera.sampling.is_some() || self.camera.blurs()
+        {
+            return (self.render_pixel(x, y), self.world.aovs_at(ray));
+        }
+        self.stats.pixels += 1;
+        let (color, aovs) = self.world.color_for_ray_with_aovs(self.integrator, ray);
+        (self.camera.develop(color), aovs)
+    }
+
+    pub fn sample_pixel(&mut self, x: u16, y: u16) -> PixelSamples
+    {
+        if !self.camera.in_crop(x, y)
+        {
+            return PixelSamples{color: create_color(0.0, 0.0, 0.0), count: 0, variance: 0.0};
+        }
+        self.stats.pixels += 1;
+        let sampling = match self.camera.sampling
+        {
+            Some(sampling) => sampling,
+            // blurred, but not antialiased, so every sample goes through
+            // the center of the pixel
+            None if self.camera.blurs() => AdaptiveSampling{min_samples: self.camera.blur_samples,
+                max_samples: self.camera.blur_samples, threshold: 0.0},
+            None =>
+            {
+                let ray = self.camera.ray_for_pixel_with(&self.inverse, x, y).with_time(self.camera.shutter_open);
+                let color = self.camera.develop(self.world.color_for_ray_with(self.integrator, ray));
+                return PixelSamples{color, count: 1, variance: 0.0};
+            },
+        };
+        // each pixel's samples depend only on the camera's seed and where
+        // the pixel is, not on the order pixels are rendered in
+        let pixel = u64::from(y) * u64::from(self.camera.hsize) + u64::from(x);
+        let seed = (self.camera.seed << 32) ^ pixel;
+        self.sampler.start(seed, sampling.max_samples.max(1));
+        self.lens_sampler.start(seed ^ 0x9e3779b97f4a7c15, sampling.max_samples.max(1));
+        self.time_sampler.start(seed ^ 0x6a09e667f3bcc909, sampling.max_samples.max(1));
+        let shutter = self.camera.shutter_close - self.camera.shutter_open;
+        let mut sum = create_color(0.0, 0.0, 0.0);
+        let mut count = 0;
+        // the running mean and sum of squared differences of the
+        // brightness (Welford's method)
+        let mut mean = 0.0;
+        let mut squares = 0.0;
+        while count < sampling.max_samples.max(1)
+        {
+            let (u, v) = match self.camera.sampling
+            {
+                Some(_) => self.sampler.next_2d(),
+                None => (0.5, 0.5),
+            };
+            let (px, py) = (Scalar::from(x) + u, Scalar::from(y) + v);
+            let ray = match self.camera.aperture > 0.0
+            {
+                true => self.camera.ray_through_lens(&self.inverse, px, py, self.lens_sampler.next_2d()),
+                false => self.camera.ray_through(&self.inverse, px, py),
+            };
+            let ray = match shutter > 0.0
+            {
+                true => ray.with_time(self.camera.shutter_open + self.time_sampler.next_2d().0 * shutter),
+                false => ray.with_time(self.camera.shutter_open),
+            };
+            let color = self.world.color_for_ray_with(self.integrator, ray);
+            sum = sum.add(color);
+            count += 1;
+            let c = color.get_vec();
+            let brightness = (c[0] + c[1] + c[2]) / 3.0;
+            let delta = brightness - mean;
+            mean = mean + delta / count as Scalar;
+            squares = squares + delta * (brightness - mean);
+            // the variance of the mean falls as more samples are taken
+            if count >= sampling.min_samples.max(2) &&
+                squares / (count - 1) as Scalar / count as Scalar <= sampling.threshold * sampling.threshold
+            {
+                break;
+            }
+        }
+        let variance = if count > 1 { squares / (count - 1) as Scalar } else { 0.0 };
+        PixelSamples{color: self.camera.develop(sum.multiply(1.0 / count as Scalar)), count, variance}
+    }
 }
 
 #[cfg(test)]
@@ -109,6 +1048,14 @@ mod tests
         let c3 = Camera::new(125, 200, PI / 2.0);
         assert!(fuzzy_equal(c3.pixel_size, 0.01));
 
+        // Scenario: Changing the field of view changes the pixel size
+        let mut c3b = c2.clone();
+        c3b.set_field_of_view(PI / 3.0);
+        assert_eq!((c3b.hsize, c3b.vsize), (200, 125));
+        assert!(fuzzy_equal(c3b.pixel_size, Camera::new(200, 125, PI / 3.0).pixel_size));
+        c3b.set_size(100, 100);
+        assert!(fuzzy_equal(c3b.pixel_size, Camera::new(100, 100, PI / 3.0).pixel_size));
+
         // p.103 Scenario: Constructing a ray through the center of the canvas
         let c4 = Camera::new(201, 101, PI / 2.0);
         let r4 = c4.ray_for_pixel(100, 50);
@@ -126,7 +1073,7 @@ mod tests
         c6.transform = Matrix::rotation_y(PI / 4.0).multiply(&Matrix::translation(0.0, -2.0, 5.0));
         let r6 = c6.ray_for_pixel(100, 50);
         assert_eq!(r6.origin, create_point(0.0, 2.0, -5.0));
-        let sqrt2 = 2.0_f64.sqrt();
+        let sqrt2 = Scalar::sqrt(2.0);
         assert_eq!(r6.direction, create_vector(sqrt2 / 2.0, 0.0, -sqrt2 / 2.0));
 
         // p.104 Scenario: Rendering a world with a camera
@@ -136,7 +1083,374 @@ mod tests
         let to7 = create_point(0.0, 0.0, 0.0);
         let up7 = create_point(0.0, 1.0, 0.0);
         c7.transform = Matrix::view_transform(from7, to7, up7);
-        let image7 = c7.render(world7);
+        let image7 = c7.render(world7.clone());
         assert_eq!(image7.pixel_at(5, 5), create_color(0.38066, 0.47583, 0.2855));
+
+        // Scenario: Rendering on several threads gives the same image as
+        // on one
+        let mut c7b = c7.clone();
+        c7b.threads = 1;
+        let mut c7c = c7.clone();
+        c7c.threads = 3;
+        assert_eq!(c7b.render(world7.clone()), c7c.render(world7.clone()));
+
+        // Scenario: A camera's integrator is used in place of the world's
+        let mut c7d = c7.clone();
+        c7d.set_integrator(NormalIntegrator);
+        assert_eq!(c7d.render(world7.clone()).pixel_at(5, 5), create_color(0.5, 0.5, 0.0));
+        assert_eq!(world7.get_integrator().li(c7.ray_for_pixel(5, 5), &world7, 4),
+            create_color(0.38066, 0.47583, 0.2855));
+        assert_eq!(c7c.get_thread_count(), 3);
+        assert!(c7.get_thread_count() >= 1);
+
+        // Scenario: Rendering in tiles calls back once for each tile and
+        // fills the whole image
+        let mut tiles7d = Vec::new();
+        let image7d = c7c.render_tiles(&world7, 4, |tile, done, total|
+        {
+            assert_eq!((done, total), (tiles7d.len() + 1, 9));
+            tiles7d.push(tile.clone());
+        });
+        assert_eq!(image7d.pixel_at(5, 5), image7.pixel_at(5, 5));
+        assert_eq!(image7d.pixel_at(10, 10), RenderWorker::new(&c7, &world7).render_pixel(10, 10));
+        let corner7d = tiles7d.iter().find(|t| (t.x, t.y) == (8, 8)).unwrap();
+        assert_eq!((corner7d.width, corner7d.height, corner7d.pixels.len()), (3, 3, 9));
+        assert_eq!(corner7d.pixel_at(10, 10), image7d.pixel_at(10, 10));
+        // and the same on one thread, in the same order
+        let mut order7b = Vec::new();
+        let image7b = c7b.render_tiles(&world7, 4, |tile, done, total| order7b.push((done, total, tile.x, tile.y)));
+        assert_eq!(image7b, image7d);
+        assert_eq!(order7b.len(), 9);
+        assert_eq!((order7b[0], order7b[8]), ((1, 9, 0, 0), (9, 9, 8, 8)));
+
+        // render covers the last row and column too, on one thread or many
+        assert_eq!(c7b.render(world7.clone()), image7d);
+        assert_eq!(c7c.render(world7.clone()), image7d);
+
+        // Scenario: A progressive render starts blocky and ends the same
+        // as a full render
+        let mut passes7e = Vec::new();
+        let image7e = c7.render_progressive(&world7, 5, |image, block| passes7e.push((image.clone(), block)));
+        assert_eq!(passes7e.iter().map(|p| p.1).collect::<Vec<u16>>(), vec![8, 4, 2, 1]);
+        assert_eq!(passes7e[0].0.pixel_at(7, 7), passes7e[0].0.pixel_at(0, 0));
+        assert_eq!(passes7e[0].0.pixel_at(10, 10), passes7e[0].0.pixel_at(8, 8));
+        assert_eq!(image7e, image7d);
+        assert_eq!(passes7e[3].0, image7e);
+
+        // Scenario: Streaming a render writes each row as a binary PPM
+        let ppm8 = c7.render_to(&world7, Vec::new()).unwrap();
+        let header8 = b"P6\n11 11\n255\n";
+        assert_eq!(&ppm8[..header8.len()], header8);
+        assert_eq!(ppm8.len(), header8.len() + 11 * 11 * 3);
+        let center8 = header8.len() + (5 * 11 + 5) * 3;
+        assert_eq!(&ppm8[center8..center8 + 3], &[166, 183, 146]);
+
+        // Scenario: A 16-bit render writes two bytes for each channel
+        let mut c8 = c7.clone();
+        c8.bit_depth = BitDepth::Sixteen;
+        let ppm8b = c8.render_to(&world7, Vec::new()).unwrap();
+        let header8b = b"P6\n11 11\n65535\n";
+        assert_eq!(&ppm8b[..header8b.len()], header8b);
+        assert_eq!(ppm8b.len(), header8b.len() + 11 * 11 * 6);
+        assert_eq!(Canvas::from_ppm(ppm8b.as_slice()).unwrap().bit_depth, BitDepth::Sixteen);
+        assert_eq!(c8.render(world7.clone()).bit_depth, BitDepth::Sixteen);
+
+        // Scenario: A render worker renders rows and counts its work
+        let mut worker9 = RenderWorker::new(&c7, &world7);
+        assert_eq!(worker9.render_row(5, 11)[5], image7.pixel_at(5, 5));
+        worker9.render_row(6, 4);
+        assert_eq!(worker9.stats, RenderStats{rows: 2, pixels: 15});
+        assert_eq!(worker9.stats.merge(&worker9.stats), RenderStats{rows: 4, pixels: 30});
+
+        // Scenario: Exposure and white balance change the rendered colors
+        let mut c11 = c7.clone();
+        c11.exposure = 1.0;
+        c11.white_balance = create_color(1.0, 0.5, 1.0);
+        assert_eq!(c11.develop(create_color(0.25, 0.5, 0.1)), create_color(0.5, 0.5, 0.2));
+        let image11 = c11.render(world7.clone());
+        assert_eq!(image11.pixel_at(5, 5), create_color(0.76132, 0.47583, 0.571));
+
+        // Scenario: A heat map shows the pixels needing shadow rays as hotter
+        let heat10 = c7.render_heat_map(&world7, HeatMetric::Rays);
+        assert_eq!(heat10.pixel_at(5, 5), create_color(1.0, 0.0, 0.0));
+        assert_eq!(heat10.pixel_at(0, 0), create_color(0.0, 1.0, 0.0));
+
+        // Scenario: Adaptive sampling takes few samples in flat areas and
+        // more along edges
+        let mut c12 = c7.clone();
+        c12.sampling = Some(AdaptiveSampling{min_samples: 4, max_samples: 32, threshold: 0.005});
+        let mut worker12 = RenderWorker::new(&c12, &world7);
+        let flat12 = worker12.sample_pixel(0, 0);
+        assert_eq!(flat12.count, 4);
+        assert_eq!(flat12.variance, 0.0);
+        assert_eq!(flat12.color, create_color(0.0, 0.0, 0.0));
+        let edge12 = (0..11).map(|x| worker12.sample_pixel(x, 5)).max_by_key(|s| s.count).unwrap();
+        assert!(edge12.count > 4);
+        assert!(edge12.variance > 0.0);
+
+        // Scenario: A sample map shows where more samples were taken
+        let map13 = c12.render_sample_map(&world7, SampleMetric::Count);
+        assert_eq!(map13.pixel_at(0, 0), create_color(4.0 / 32.0, 4.0 / 32.0, 4.0 / 32.0));
+        assert!(map13.pixels.iter().any(|p| p.get_vec()[0] > 4.0 / 32.0));
+        let map14 = c12.render_sample_map(&world7, SampleMetric::Variance);
+        assert_eq!(map14.pixel_at(0, 0), create_color(0.0, 0.0, 0.0));
+        assert!(map14.pixels.iter().any(|p| p.get_vec()[0] == 1.0));
+
+        // Scenario: Random samples repeat for the same seed and change
+        // with it
+        let edge15 = (0..11).max_by_key(|x| worker12.sample_pixel(*x, 5).count).unwrap();
+        let mut c15 = c12.clone();
+        c15.sampler = SamplerKind::Uniform;
+        c15.seed = 1;
+        let samples15 = RenderWorker::new(&c15, &world7).sample_pixel(edge15, 5);
+        assert_eq!(RenderWorker::new(&c15, &world7).sample_pixel(edge15, 5), samples15);
+        c15.seed = 2;
+        assert_ne!(RenderWorker::new(&c15, &world7).sample_pixel(edge15, 5), samples15);
+    }
+
+    #[test]
+    fn test_camera_lens_feature()
+    {
+        // Scenario: Rays through any part of the lens meet at the focal
+        // distance
+        let mut c1 = Camera::new(11, 11, PI / 2.0);
+        c1.aperture = 0.5;
+        c1.focal_distance = 4.0;
+        let inverse1 = Matrix::identity(4);
+        let r1 = c1.ray_through_lens(&inverse1, 5.5, 5.5, (0.0, 0.0));
+        assert_eq!(r1.origin, create_point(0.0, 0.0, 0.0));
+        assert_eq!(r1.direction, create_vector(0.0, 0.0, -1.0));
+        for lens in [(1.0, 0.25), (0.5, 0.6), (0.3, 0.0)]
+        {
+            let r = c1.ray_through_lens(&inverse1, 8.0, 2.0, lens);
+            assert_ne!(r.origin, create_point(0.0, 0.0, 0.0));
+            let t = -4.0 / r.direction.to_array()[2];
+            let pinhole = c1.ray_through(&inverse1, 8.0, 2.0);
+            assert_eq!(r.position(t), pinhole.position(-4.0 / pinhole.direction.to_array()[2]));
+        }
+
+        // Scenario: Out of focus, a sphere blurs into pixels beside it
+        let world2 = World::default_world();
+        let mut c2 = Camera::new(11, 11, PI / 2.0);
+        c2.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0), create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+        assert_eq!(RenderWorker::new(&c2, &world2).render_pixel(7, 5), create_color(0.0, 0.0, 0.0));
+        c2.aperture = 2.0;
+        c2.focal_distance = 1.0;
+        let blurred2 = RenderWorker::new(&c2, &world2).sample_pixel(7, 5);
+        assert_eq!(blurred2.count, c2.blur_samples);
+        assert!(blurred2.color.get_vec()[1] > 0.0);
+
+        // Scenario: In focus, it does not
+        c2.focal_distance = 4.0;
+        assert_eq!(RenderWorker::new(&c2, &world2).render_pixel(8, 5), create_color(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_camera_motion_blur_feature()
+    {
+        // Scenario: A moving sphere streaks across the pixels it passes
+        // while the shutter is open
+        let mut world1 = World::default_world();
+        world1.objects.truncate(1);
+        world1.objects[0].set_motion(create_vector(2.0, 0.0, 0.0));
+        let mut c1 = Camera::new(11, 11, PI / 2.0);
+        c1.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0), create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+        let black = create_color(0.0, 0.0, 0.0);
+        assert_eq!(RenderWorker::new(&c1, &world1).render_pixel(8, 5), black);
+        c1.shutter_open = 1.0;
+        c1.shutter_close = 1.0;
+        assert_ne!(RenderWorker::new(&c1, &world1).render_pixel(8, 5), black);
+        assert_eq!(RenderWorker::new(&c1, &world1).render_pixel(5, 5), black);
+        c1.shutter_open = 0.0;
+        let streak1 = RenderWorker::new(&c1, &world1).sample_pixel(8, 5);
+        assert_eq!(streak1.count, c1.blur_samples);
+        let full = RenderWorker::new(&c1, &world1).render_pixel(5, 5);
+        let streak = RenderWorker::new(&c1, &world1).render_pixel(7, 5);
+        assert!(streak.get_vec()[1] > 0.0 && streak.get_vec()[1] < full.get_vec()[1] + 0.2);
+        assert_ne!(streak1.color, black);
+    }
+
+    #[test]
+    fn test_camera_passes_feature()
+    {
+        // Scenario: The passes are rendered along with the usual image
+        let world1 = World::default_world();
+        let mut c1 = Camera::new(11, 11, PI / 2.0);
+        c1.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0), create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+        c1.effects.vignette = Some(Vignette{strength: 0.5});
+        let passes = c1.render_passes(&world1);
+        assert_eq!(passes.beauty, c1.render_tiles(&world1, 4, |_, _, _| {}));
+        assert!(fuzzy_equal(passes.depth.pixel_at(5, 5).to_array()[0], 4.0));
+        assert_eq!(passes.normal.pixel_at(5, 5), create_color(0.0, 0.0, -1.0));
+        assert_eq!(passes.object_id.pixel_at(5, 5), id_color(world1.objects[0].get_id()));
+        let black = create_color(0.0, 0.0, 0.0);
+        assert_eq!(passes.depth.pixel_at(0, 0), black);
+        assert_eq!(passes.object_id.pixel_at(0, 0), black);
+        // and the same on one thread, without starting any
+        let mut one1 = c1.clone();
+        one1.threads = 1;
+        let passes1 = one1.render_passes(&world1);
+        assert_eq!(passes1.beauty, passes.beauty);
+        assert_eq!(passes1.depth, passes.depth);
+        assert_eq!(passes1.normal, passes.normal);
+        assert_eq!(passes1.object_id, passes.object_id);
+
+        // Scenario: Sampled pixels take their passes from the center ray
+        c1.sampling = Some(AdaptiveSampling::new());
+        let passes2 = c1.render_passes(&world1);
+        assert_eq!(passes2.depth.pixel_at(5, 5), passes.depth.pixel_at(5, 5));
+        assert_eq!(passes2.object_id, passes.object_id);
+    }
+
+    #[test]
+    fn test_camera_crop_feature()
+    {
+        // Scenario: Only the pixels in the crop window are rendered
+        let world1 = World::default_world();
+        let mut c1 = Camera::new(11, 11, PI / 2.0);
+        c1.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0), create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+        let full = c1.render_tiles(&world1, 4, |_, _, _| {});
+        c1.set_crop(4, 5, 7, 6);
+        assert!(c1.in_crop(4, 5) && c1.in_crop(6, 5));
+        assert!(!c1.in_crop(7, 5) && !c1.in_crop(5, 6) && !c1.in_crop(3, 5));
+        let black = create_color(0.0, 0.0, 0.0);
+        let mut images = vec![c1.render(world1.clone()), c1.render_tiles(&world1, 4, |_, _, _| {}),
+            c1.render_progressive(&world1, 4, |_, _| {})];
+        for image in &images
+        {
+            assert_eq!(image.pixel_at(5, 5), full.pixel_at(5, 5));
+        }
+        // the streamed image is rounded to bytes
+        images.push(Canvas::from_ppm(c1.render_to(&world1, Vec::new()).unwrap().as_slice()).unwrap());
+        for image in &images
+        {
+            assert_ne!(image.pixel_at(4, 5), black);
+            assert_eq!(image.pixel_at(5, 4), black);
+            assert_eq!(image.pixel_at(7, 5), black);
+        }
+
+        // Scenario: Pixels outside the window take no samples
+        let mut worker = RenderWorker::new(&c1, &world1);
+        assert_eq!(worker.sample_pixel(0, 0).count, 0);
+        worker.render_row(5, 11);
+        assert_eq!(worker.stats.pixels, 3);
+        c1.clear_crop();
+        assert!(c1.in_crop(0, 0));
+    }
+
+    #[test]
+    fn test_camera_stereo_feature()
+    {
+        // Scenario: Each eye is half the interocular distance to its side
+        let mut c1 = Camera::new(11, 11, PI / 2.0);
+        c1.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0), create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+        c1.interocular = 0.5;
+        let position = |c: &Camera| c.transform.inverse().multiply_tuple(create_point(0.0, 0.0, 0.0));
+        assert_eq!(position(&c1.eye(Eye::Left)), create_point(-0.25, 0.0, -5.0));
+        assert_eq!(position(&c1.eye(Eye::Right)), create_point(0.25, 0.0, -5.0));
+        let r1 = c1.eye(Eye::Left).ray_for_pixel(5, 5);
+        assert_eq!(r1.direction, create_vector(0.0, 0.0, 1.0));
+
+        // Scenario: Something near the camera is further right in the
+        // left eye's image
+        let mut world1 = World::default_world();
+        world1.objects.truncate(1);
+        world1.objects[0].set_transform(Matrix::translation(0.0, 0.0, -3.8).multiply(&Matrix::scaling(0.2, 0.2, 0.2)));
+        let (left, right) = c1.render_stereo(&world1);
+        let black = create_color(0.0, 0.0, 0.0);
+        assert_ne!(left.pixel_at(6, 5), black);
+        assert_eq!(left.pixel_at(4, 5), black);
+        assert_ne!(right.pixel_at(4, 5), black);
+        assert_eq!(right.pixel_at(6, 5), black);
+
+        // Scenario: The anaglyph is red where the left eye sees it and
+        // cyan where the right eye does
+        let anaglyph = c1.render_anaglyph(&world1);
+        assert_eq!(anaglyph.pixel_at(6, 5), create_color(left.pixel_at(6, 5).to_array()[0], 0.0, 0.0));
+        assert_eq!(anaglyph.pixel_at(4, 5).to_array()[0], 0.0);
+    }
+
+    #[test]
+    fn test_camera_projection_feature()
+    {
+        let inverse = Matrix::identity(4);
+        let sqrt2 = Scalar::sqrt(2.0);
+
+        // Scenario: A fisheye camera looks straight ahead in the middle,
+        // and half its field of view away at the corners
+        let mut c1 = Camera::new(200, 100, PI / 2.0);
+        c1.projection = Projection::Fisheye;
+        c1.field_of_view = PI;
+        assert_eq!(c1.ray_through(&inverse, 100.0, 50.0).direction, create_vector(0.0, 0.0, -1.0));
+        let corner1 = c1.ray_through(&inverse, 0.0, 0.0).direction;
+        assert!(fuzzy_equal(corner1.to_array()[2], 0.0));
+        assert!(corner1.to_array()[0] > 0.0 && corner1.to_array()[1] > 0.0);
+        let edge1 = c1.ray_through(&inverse, 200.0, 50.0).direction;
+        let angle1 = (-edge1.to_array()[2]).acos();
+        assert!(fuzzy_equal(angle1, PI / 2.0 * 100.0 / (100.0 * 100.0 + 50.0 * 50.0 as Scalar).sqrt()));
+        assert!(edge1.to_array()[0] < 0.0);
+
+        // Scenario: An equirectangular camera sees every direction
+        let mut c2 = Camera::new(360, 180, PI / 2.0);
+        c2.projection = Projection::Equirectangular;
+        assert_eq!(c2.ray_through(&inverse, 180.0, 90.0).direction, create_vector(0.0, 0.0, -1.0));
+        assert_eq!(c2.ray_through(&inverse, 90.0, 90.0).direction, create_vector(1.0, 0.0, 0.0));
+        assert_eq!(c2.ray_through(&inverse, 0.0, 90.0).direction, create_vector(0.0, 0.0, 1.0));
+        assert_eq!(c2.ray_through(&inverse, 180.0, 0.0).direction, create_vector(0.0, 1.0, 0.0));
+        assert_eq!(c2.ray_through(&inverse, 270.0, 45.0).direction,
+            create_vector(-sqrt2 / 2.0, sqrt2 / 2.0, 0.0));
+
+        // Scenario: A panorama from inside a world sees all around it
+        let mut world3 = World::default_world();
+        world3.objects.truncate(1);
+        let mut c3 = Camera::new(16, 8, PI / 2.0);
+        c3.projection = Projection::Equirectangular;
+        c3.transform = Matrix::view_transform(create_point(0.0, 0.0, -2.0), create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+        let image3 = c3.render(world3);
+        assert_ne!(image3.pixel_at(8, 4), create_color(0.0, 0.0, 0.0));
+        assert_eq!(image3.pixel_at(0, 4), create_color(0.0, 0.0, 0.0));
+        assert!(c3.is_visible(&Shape::new_sphere(9)));
+    }
+
+    #[test]
+    fn test_camera_culling_feature()
+    {
+        let mut camera = Camera::new(201, 101, PI / 2.0);
+        camera.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0),
+            create_point(0.0, 0.0, 0.0),
+            create_vector(0.0, 1.0, 0.0));
+
+        // Scenario: A sphere in front of the camera is visible
+        let s1 = Shape::new_sphere(1);
+        assert!(camera.is_visible(&s1));
+
+        // Scenario: A sphere behind the camera is culled
+        let mut s2 = Shape::new_sphere(2);
+        s2.set_transform(Matrix::translation(0.0, 0.0, -10.0));
+        assert!(!camera.is_visible(&s2));
+
+        // Scenario: A sphere far off to the side is culled
+        let mut s3 = Shape::new_sphere(3);
+        s3.set_transform(Matrix::translation(20.0, 0.0, 0.0));
+        assert!(!camera.is_visible(&s3));
+
+        // Scenario: A sphere straddling the edge of the view is visible
+        let mut s4 = Shape::new_sphere(4);
+        s4.set_transform(Matrix::translation(5.5, 0.0, 0.0));
+        assert!(camera.is_visible(&s4));
+
+        // Scenario: A plane is never culled
+        let mut p5 = Shape::new_plane(5);
+        p5.set_transform(Matrix::translation(0.0, 0.0, -100.0));
+        assert!(camera.is_visible(&p5));
+
+        let objects = vec![s1, s2, s3, s4, p5];
+        assert_eq!(camera.visible_objects(&objects).len(), 3);
     }
 }
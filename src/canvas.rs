@@ -1,10 +1,17 @@
+use std::io::{self, Read, Write};
+use crate::arithmetic::*;
 use crate::tuple::*;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Canvas
 {
     pub width: usize,
     pub height: usize,
-    pub pixels: Vec<Vec<Tuple>>,
+    // row-major, the pixel at (x, y) is at index y * width + x
+    pub pixels: Vec<Tuple>,
+    // how the colors are encoded when the image is written
+    pub gamma: Gamma,
+    pub bit_depth: BitDepth,
 }
 
 impl Canvas
@@ -12,48 +19,52 @@ impl Canvas
 	pub fn new(width: usize, height: usize) -> Self
 	{
 		let color_black = create_color(0.0, 0.0, 0.0);
-		let mut pixels = Vec::with_capacity(height);
-		for _ in 0..height
-		{
-			let mut row = Vec::with_capacity(width);
-			for _ in 0..width
-			{
-				row.push(color_black);
-			}
-			pixels.push(row);
-		}
-		Canvas{width: width, height: height, pixels: pixels}
+		let pixels = vec![color_black; width * height];
+		Canvas{width: width, height: height, pixels: pixels, gamma: Gamma::Srgb,
+			bit_depth: BitDepth::Eight}
 	}
 
+    fn index(&self, x: usize, y: usize) -> usize
+    {
+        y * self.width + x
+    }
+
+    // One row of pixels, from left to right
+    pub fn row(&self, y: usize) -> &[Tuple]
+    {
+        let start = self.index(0, y);
+        &self.pixels[start..start + self.width]
+    }
+
     pub fn write_pixel(&mut self, x : usize, y: usize, c: Tuple)
     {
         if x >= self.width || y >= self.height
         {
             return;
         }
-        self.pixels[y][x] = c;
+        let index = self.index(x, y);
+        self.pixels[index] = c;
     }
 
     pub fn pixel_at(&self, x : usize, y: usize) -> Tuple
     {
-        return self.pixels[y][x];
+        return self.pixels[self.index(x, y)];
     }
 
     pub fn to_ppm(&self) -> String
     {
-        let max_value = 255;
+        let max_value = self.bit_depth.max_value();
         let mut ppm = format!("P3\n{} {}\n{}\n", self.width, self.height, max_value);
         for y in 0..self.height
         {
             let mut line = String::new();
-            for x in 0..self.width
+            for pixel in self.row(y)
             {
-                let mut rgb = self.pixel_at(x, y).get_vec();
+                let mut rgb = pixel.get_vec();
                 rgb.resize(3, 0.0); // want only RGB components
                 for p1 in rgb
                 {
-                    let p2 = (p1 * f64::from(max_value)).clamp(0.0, 255.0).round();
-                    let p3 = format!("{}", p2);
+                    let p3 = format!("{}", quantize(p1, self.gamma, max_value));
                     if line.len() + 1 + p3.len() > 70
                     {
                         // Split long lines.
@@ -77,6 +88,531 @@ impl Canvas
         }
         ppm
     }
+
+    // Reads a PPM image, in either the text (P3) or binary (P6) form,
+    // whose colors are encoded with the sRGB curve as most images are
+    pub fn from_ppm<R: Read>(reader: R) -> io::Result<Canvas>
+    {
+        Self::from_ppm_with_gamma(reader, Gamma::Srgb)
+    }
+
+    pub fn from_ppm_with_gamma<R: Read>(reader: R, gamma: Gamma) -> io::Result<Canvas>
+    {
+        let mut bytes = Vec::new();
+        let mut reader = reader;
+        reader.read_to_end(&mut bytes)?;
+        let mut header = PpmHeader{bytes: &bytes, position: 0};
+        let magic = header.next_word();
+        if magic != b"P3" && magic != b"P6"
+        {
+            return Err(invalid_ppm("not a P3 or P6 PPM image"));
+        }
+        let width = header.next_number()?;
+        let height = header.next_number()?;
+        let max_value = header.next_number()? as Scalar;
+        if max_value == 0.0
+        {
+            return Err(invalid_ppm("the maximum color value is zero"));
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        canvas.gamma = gamma;
+        if max_value > 255.0
+        {
+            canvas.bit_depth = BitDepth::Sixteen;
+        }
+        let mut values = Vec::with_capacity(width * height * 3);
+        if magic == b"P3"
+        {
+            while values.len() < width * height * 3
+            {
+                values.push(header.next_number()? as Scalar);
+            }
+        }
+        else
+        {
+            // one whitespace byte separates the header from the pixels,
+            // which take two bytes each when the maximum is over 255
+            let start = header.position + 1;
+            let size = if max_value > 255.0 { 2 } else { 1 };
+            let data = bytes.get(start..start + width * height * 3 * size)
+                .ok_or_else(|| invalid_ppm("the image is missing pixels"))?;
+            values.extend(data.chunks(size).map(|c| match size
+            {
+                1 => Scalar::from(c[0]),
+                _ => Scalar::from(u16::from_be_bytes([c[0], c[1]])),
+            }));
+        }
+        for (pixel, rgb) in canvas.pixels.iter_mut().zip(values.chunks(3))
+        {
+            *pixel = create_color(gamma.decode(rgb[0] / max_value), gamma.decode(rgb[1] / max_value),
+                gamma.decode(rgb[2] / max_value));
+        }
+        Ok(canvas)
+    }
+
+    // The pixels as 8-bit red, green, blue and alpha bytes, row by row, as
+    // used by an HTML canvas
+    pub fn to_rgba(&self) -> Vec<u8>
+    {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in &self.pixels
+        {
+            let v = pixel.get_vec();
+            rgba.extend_from_slice(&[to_byte(v[0], self.gamma), to_byte(v[1], self.gamma),
+                to_byte(v[2], self.gamma), 255]);
+        }
+        rgba
+    }
+
+    // The red, green and blue of the pixels, row by row, as in a binary
+    // PPM: a byte each for 8-bit images, and two, high byte first, for
+    // 16-bit ones
+    pub fn to_rgb_bytes(&self, dither: Dither) -> Vec<u8>
+    {
+        let mut quantizer = Quantizer::new(dither, self.gamma, self.bit_depth, self.width);
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 3 * self.bit_depth.bytes());
+        for y in 0..self.height
+        {
+            quantizer.quantize_row(self.row(y), &mut bytes);
+        }
+        bytes
+    }
+
+    // Combines the two images of a stereo pair into one to be seen through
+    // red and cyan glasses, with the red channel from the left image and
+    // the green and blue channels from the right. The images have to be
+    // the same size.
+    pub fn anaglyph(left: &Canvas, right: &Canvas) -> Canvas
+    {
+        assert_eq!((left.width, left.height), (right.width, right.height),
+            "the images of a stereo pair must be the same size");
+        let mut image = Canvas::new(left.width, left.height);
+        for (i, pixel) in image.pixels.iter_mut().enumerate()
+        {
+            let (l, r) = (left.pixels[i].to_array(), right.pixels[i].to_array());
+            *pixel = create_color(l[0], r[1], r[2]);
+        }
+        image
+    }
+
+    // A copy of the image at another size. Rendering at a few times the
+    // wanted size and shrinking gives smooth edges; shrinking a lot gives
+    // thumbnails.
+    pub fn resize(&self, width: usize, height: usize, filter: ResizeFilter) -> Canvas
+    {
+        // an empty image has no pixels to blend, so all it gives is black
+        if self.width == 0 || self.height == 0
+        {
+            return Canvas::new(width, height);
+        }
+
+        // resizing the rows and then the columns gives the same result as
+        // a 2D filter, for far fewer samples
+        let mut wide = Canvas::new(width, self.height);
+        let columns = filter.weights(self.width, width);
+        for y in 0..self.height
+        {
+            for (x, taps) in columns.iter().enumerate()
+            {
+                let color = taps.iter().fold(create_color(0.0, 0.0, 0.0),
+                    |sum, (i, w)| sum.add(self.pixel_at(*i, y).multiply(*w)));
+                wide.write_pixel(x, y, color);
+            }
+        }
+        let mut result = Canvas::new(width, height);
+        let rows = filter.weights(self.height, height);
+        for (y, taps) in rows.iter().enumerate()
+        {
+            for x in 0..width
+            {
+                let color = taps.iter().fold(create_color(0.0, 0.0, 0.0),
+                    |sum, (i, w)| sum.add(wide.pixel_at(x, *i).multiply(*w)));
+                result.write_pixel(x, y, color);
+            }
+        }
+        result
+    }
+}
+
+// How pixels are blended when an image is resized
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResizeFilter
+{
+    // averages the pixels each new pixel covers
+    Box,
+    // blends neighbouring pixels by distance
+    Bilinear,
+    // sharper than bilinear, but can ring a little at hard edges
+    Lanczos,
+}
+
+impl ResizeFilter
+{
+    // How far the filter reaches, in source pixels when enlarging
+    fn radius(&self) -> Scalar
+    {
+        match self
+        {
+            ResizeFilter::Box => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, x: Scalar) -> Scalar
+    {
+        let sinc = |x: Scalar| match x == 0.0
+        {
+            true => 1.0,
+            false => (x * consts::PI).sin() / (x * consts::PI),
+        };
+        match self
+        {
+            ResizeFilter::Box => if (-0.5..0.5).contains(&x) { 1.0 } else { 0.0 },
+            ResizeFilter::Bilinear => (1.0 - x.abs()).max(0.0),
+            ResizeFilter::Lanczos => if x.abs() < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 },
+        }
+    }
+
+    // For each pixel along a row or column of the new size, the source
+    // pixels it is made of and how much each counts. When shrinking, the
+    // filter is stretched to cover every source pixel.
+    fn weights(&self, from: usize, to: usize) -> Vec<Vec<(usize, Scalar)>>
+    {
+        let scale = from as Scalar / to as Scalar;
+        let stretch = scale.max(1.0);
+        let reach = self.radius() * stretch;
+        (0..to).map(|i|
+        {
+            let center = (i as Scalar + 0.5) * scale - 0.5;
+            let first = (center - reach).floor() as isize;
+            let last = (center + reach).ceil() as isize;
+            let mut taps: Vec<(usize, Scalar)> = (first..=last)
+                .map(|j| (j.clamp(0, from as isize - 1) as usize,
+                    self.weight((j as Scalar - center) / stretch)))
+                .filter(|(_, w)| *w != 0.0)
+                .collect();
+            let total: Scalar = taps.iter().map(|(_, w)| w).sum();
+            if total == 0.0
+            {
+                return vec![((center.round() as isize).clamp(0, from as isize - 1) as usize, 1.0)];
+            }
+            taps.iter_mut().for_each(|(_, w)| *w = *w / total);
+            taps
+        }).collect()
+    }
+}
+
+// How the light in an image is encoded in the values written to a file.
+// Displays brighten values along a curve, so writing the light as it is
+// makes renders look too dark.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gamma
+{
+    // the values are the light, as in the book
+    Linear,
+    // the curve of the sRGB standard, which most images and displays use
+    Srgb,
+    // the values are the light to the power of 1 / gamma
+    Power(Scalar),
+}
+
+impl Gamma
+{
+    // The value, from 0 to 1, written for an amount of light, which is
+    // clipped to 0 to 1 first
+    pub fn encode(&self, light: Scalar) -> Scalar
+    {
+        let light = light.clamp(0.0, 1.0);
+        match self
+        {
+            Gamma::Linear => light,
+            Gamma::Srgb if light <= 0.0031308 => light * 12.92,
+            Gamma::Srgb => 1.055 * light.powf(1.0 / 2.4) - 0.055,
+            Gamma::Power(gamma) => light.powf(1.0 / gamma),
+        }
+    }
+
+    // The light that a value read from a file stands for
+    pub fn decode(&self, value: Scalar) -> Scalar
+    {
+        match self
+        {
+            Gamma::Linear => value,
+            Gamma::Srgb if value <= 0.04045 => value / 12.92,
+            Gamma::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            Gamma::Power(gamma) => value.max(0.0).powf(*gamma),
+        }
+    }
+}
+
+// How many bits each channel of a written image has. 16 bits keep smooth
+// gradients from showing bands, but not every program can read them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BitDepth
+{
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth
+{
+    // The value written for full brightness
+    pub fn max_value(&self) -> u16
+    {
+        match self
+        {
+            BitDepth::Eight => 255,
+            BitDepth::Sixteen => 65535,
+        }
+    }
+
+    // The bytes each channel takes in a binary image
+    pub fn bytes(&self) -> usize
+    {
+        match self
+        {
+            BitDepth::Eight => 1,
+            BitDepth::Sixteen => 2,
+        }
+    }
+}
+
+// How colors are rounded to 8 bits. Plain rounding leaves visible bands
+// in smooth gradients and dark areas; dithering breaks the bands up into
+// fine noise that averages out to the true color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dither
+{
+    None,
+    // adds a repeating 4x4 pattern of offsets before rounding down
+    Ordered,
+    // passes each pixel's rounding error on to the pixels right of and
+    // below it
+    FloydSteinberg,
+}
+
+// Turns rows of colors into bytes, keeping the rounding error that
+// Floyd-Steinberg dithering carries from one row to the next
+pub struct Quantizer
+{
+    dither: Dither,
+    gamma: Gamma,
+    depth: BitDepth,
+    y: usize,
+    // the error carried into each channel of the current and next rows,
+    // in steps of the bit depth
+    errors: Vec<Scalar>,
+    next_errors: Vec<Scalar>,
+}
+
+impl Quantizer
+{
+    const BAYER: [[Scalar; 4]; 4] = [[0.0, 8.0, 2.0, 10.0], [12.0, 4.0, 14.0, 6.0],
+        [3.0, 11.0, 1.0, 9.0], [15.0, 7.0, 13.0, 5.0]];
+
+    pub fn new(dither: Dither, gamma: Gamma, depth: BitDepth, width: usize) -> Self
+    {
+        let errors = match dither
+        {
+            Dither::FloydSteinberg => vec![0.0; width * 3],
+            _ => Vec::new(),
+        };
+        Quantizer{dither, gamma, depth, y: 0, next_errors: errors.clone(), errors}
+    }
+
+    // Appends the red, green and blue bytes of the next row
+    pub fn quantize_row(&mut self, row: &[Tuple], bytes: &mut Vec<u8>)
+    {
+        let max_value = Scalar::from(self.depth.max_value());
+        match self.dither
+        {
+            Dither::None =>
+            {
+                for pixel in row
+                {
+                    for c in &pixel.to_array()[..3]
+                    {
+                        self.push(quantize(*c, self.gamma, self.depth.max_value()), bytes);
+                    }
+                }
+            },
+            Dither::Ordered =>
+            {
+                for (x, pixel) in row.iter().enumerate()
+                {
+                    let offset = (Self::BAYER[self.y % 4][x % 4] + 0.5) / 16.0;
+                    for c in &pixel.to_array()[..3]
+                    {
+                        let value = (self.gamma.encode(*c) * max_value + offset).floor().clamp(0.0, max_value);
+                        self.push(value as u16, bytes);
+                    }
+                }
+            },
+            Dither::FloydSteinberg =>
+            {
+                let width = row.len();
+                self.next_errors.iter_mut().for_each(|e| *e = 0.0);
+                for (x, pixel) in row.iter().enumerate()
+                {
+                    let v = pixel.get_vec();
+                    for channel in 0..3
+                    {
+                        let wanted = self.gamma.encode(v[channel]) * max_value + self.errors[x * 3 + channel];
+                        let value = wanted.round().clamp(0.0, max_value);
+                        self.push(value as u16, bytes);
+                        let error = wanted - value;
+                        if x + 1 < width
+                        {
+                            self.errors[(x + 1) * 3 + channel] += error * 7.0 / 16.0;
+                            self.next_errors[(x + 1) * 3 + channel] += error / 16.0;
+                        }
+                        if x > 0
+                        {
+                            self.next_errors[(x - 1) * 3 + channel] += error * 3.0 / 16.0;
+                        }
+                        self.next_errors[x * 3 + channel] += error * 5.0 / 16.0;
+                    }
+                }
+                std::mem::swap(&mut self.errors, &mut self.next_errors);
+            },
+        }
+        self.y += 1;
+    }
+
+    fn push(&self, value: u16, bytes: &mut Vec<u8>)
+    {
+        match self.depth
+        {
+            BitDepth::Eight => bytes.push(value as u8),
+            BitDepth::Sixteen => bytes.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+}
+
+// A color component encoded and scaled to the range from 0 to max_value
+pub(crate) fn quantize(component: Scalar, gamma: Gamma, max_value: u16) -> u16
+{
+    (gamma.encode(component) * Scalar::from(max_value)).round() as u16
+}
+
+// A color component encoded and scaled to the 0 to 255 range of an
+// 8-bit image
+pub(crate) fn to_byte(component: Scalar, gamma: Gamma) -> u8
+{
+    quantize(component, gamma, 255) as u8
+}
+
+fn invalid_ppm(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// The words of a PPM header, which are separated by whitespace and may
+// be followed by comments starting with '#'
+struct PpmHeader<'a>
+{
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PpmHeader<'a>
+{
+    fn next_word(&mut self) -> &'a [u8]
+    {
+        loop
+        {
+            match self.bytes.get(self.position)
+            {
+                Some(b'#') =>
+                {
+                    while self.bytes.get(self.position).map_or(false, |b| *b != b'\n')
+                    {
+                        self.position += 1;
+                    }
+                },
+                Some(b) if b.is_ascii_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+        let start = self.position;
+        while self.bytes.get(self.position).map_or(false, |b| !b.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+        &self.bytes[start..self.position]
+    }
+
+    fn next_number(&mut self) -> io::Result<usize>
+    {
+        let word = self.next_word();
+        std::str::from_utf8(word).ok().and_then(|w| w.parse().ok())
+            .ok_or_else(|| invalid_ppm("expected a number"))
+    }
+}
+
+// Writes a binary (P6) PPM image one row at a time, as the rows are
+// rendered, so the whole image never has to be kept in memory
+pub struct PpmStream<W: Write>
+{
+    writer: W,
+    width: usize,
+    rows_left: usize,
+    quantizer: Quantizer,
+}
+
+impl<W: Write> PpmStream<W>
+{
+    pub fn new(writer: W, width: usize, height: usize) -> io::Result<Self>
+    {
+        Self::with_dither(writer, width, height, Dither::None)
+    }
+
+    pub fn with_dither(writer: W, width: usize, height: usize, dither: Dither) -> io::Result<Self>
+    {
+        Self::with_gamma(writer, width, height, dither, Gamma::Srgb)
+    }
+
+    pub fn with_gamma(writer: W, width: usize, height: usize, dither: Dither, gamma: Gamma) -> io::Result<Self>
+    {
+        Self::with_depth(writer, width, height, dither, gamma, BitDepth::Eight)
+    }
+
+    pub fn with_depth(writer: W, width: usize, height: usize, dither: Dither, gamma: Gamma,
+        depth: BitDepth) -> io::Result<Self>
+    {
+        let mut writer = writer;
+        write!(writer, "P6\n{} {}\n{}\n", width, height, depth.max_value())?;
+        Ok(PpmStream{writer, width, rows_left: height, quantizer: Quantizer::new(dither, gamma, depth, width)})
+    }
+
+    pub fn write_row(&mut self, row: &[Tuple]) -> io::Result<()>
+    {
+        if row.len() != self.width || self.rows_left == 0
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "row does not fit the image"));
+        }
+        let mut bytes = Vec::with_capacity(row.len() * 3);
+        self.quantizer.quantize_row(row, &mut bytes);
+        self.writer.write_all(&bytes)?;
+        self.rows_left -= 1;
+        Ok(())
+    }
+
+    // Flushes the image, which must have all its rows, and returns the writer
+    pub fn finish(self) -> io::Result<W>
+    {
+        let mut writer = self.writer;
+        if self.rows_left > 0
+        {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                "image is missing rows"));
+        }
+        writer.flush()?;
+        Ok(writer)
+    }
 }
 
 #[cfg(test)]
@@ -115,6 +651,7 @@ mod tests
 
         // p.21 Scenario: Constructing the pixel data
         let mut c4 = Canvas::new(5, 3);
+        c4.gamma = Gamma::Linear;
         c4.write_pixel(0, 0, create_color(1.5, 0.0, 0.0));
         c4.write_pixel(2, 1, create_color(0.0, 0.5, 0.0));
         c4.write_pixel(4, 2, create_color(-0.5, 0.0, 1.0));
@@ -129,6 +666,7 @@ mod tests
 
         // p.22 Scenario: Splitting long lines in PPM files
         let mut c5 = Canvas::new(10, 2);
+        c5.gamma = Gamma::Linear;
         for y in 0..c5.height
         {
             for x in 0..c5.width
@@ -146,9 +684,263 @@ mod tests
         assert_eq!(lines5.next(), Some("255 204 153 255 204 153 255 204 153 255 204 153 255 204 153 255 204"));
         assert_eq!(lines5.next(), Some("153 255 204 153 255 204 153 255 204 153 255 204 153"));
 
+        // Scenario: Pixels are stored row by row in one buffer
+        let mut c7 = Canvas::new(4, 3);
+        c7.write_pixel(1, 2, color_red);
+        assert_eq!(c7.pixels.len(), 12);
+        assert_eq!(c7.pixels[9], color_red);
+        assert_eq!(c7.row(2)[1], color_red);
+
         // p.22 Scenario: PPM files are terminated by a newline character
         let c6 = Canvas::new(5, 3);
         let ppm6 = c6.to_ppm();
         assert!(ppm6.ends_with("\n"));
     }
+
+    #[test]
+    fn test_canvas_ppm_stream()
+    {
+        // Scenario: Rows are written to a binary PPM as they arrive
+        let mut stream = PpmStream::with_gamma(Vec::new(), 2, 2, Dither::None, Gamma::Linear).unwrap();
+        stream.write_row(&[create_color(1.5, 0.0, 0.0), create_color(0.0, 0.5, 0.0)]).unwrap();
+        assert!(stream.write_row(&[create_color(0.0, 0.0, 1.0)]).is_err());
+        stream.write_row(&[create_color(0.0, 0.0, 1.0), create_color(-0.5, 0.0, 0.0)]).unwrap();
+        assert!(stream.write_row(&[create_color(0.0, 0.0, 1.0); 2]).is_err());
+        let bytes = stream.finish().unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], &[255, 0, 0, 0, 128, 0, 0, 0, 255, 0, 0, 0]);
+
+        // Scenario: Streams are sRGB encoded unless asked otherwise
+        let mut stream2 = PpmStream::new(Vec::new(), 1, 1).unwrap();
+        stream2.write_row(&[create_color(0.5, 0.0, 1.0)]).unwrap();
+        assert_eq!(&stream2.finish().unwrap()[header.len()..], &[188, 0, 255]);
+
+        // Scenario: An image missing rows can't be finished
+        let stream = PpmStream::new(Vec::new(), 2, 2).unwrap();
+        assert!(stream.finish().is_err());
+    }
+
+    #[test]
+    fn test_canvas_from_ppm()
+    {
+        // Scenario: Reading a file with the wrong magic number fails
+        assert!(Canvas::from_ppm("P32\n1 1\n255\n0 0 0\n".as_bytes()).is_err());
+
+        // Scenario: Reading a PPM returns a canvas of the right size
+        let text2 = format!("P3\n10 2\n255\n{}", "0 ".repeat(60));
+        let c2 = Canvas::from_ppm(text2.as_bytes()).unwrap();
+        assert_eq!((c2.width, c2.height), (10, 2));
+
+        // Scenario: Reading pixel data from a PPM file
+        let text3 = "P3\n4 3\n255\n255 127 0  0 127 255  127 255 0  255 255 255\n\
+            0 0 0  255 0 0  0 255 0  0 0 255\n\
+            255 255 0  0 255 255  255 0 255  127 127 127\n";
+        let c3 = Canvas::from_ppm_with_gamma(text3.as_bytes(), Gamma::Linear).unwrap();
+        assert_eq!(c3.pixel_at(0, 0), create_color(1.0, 0.49804, 0.0));
+        assert_eq!(c3.pixel_at(2, 1), create_color(0.0, 1.0, 0.0));
+        assert_eq!(c3.pixel_at(3, 2), create_color(0.49804, 0.49804, 0.49804));
+
+        // Scenario: PPM parsing ignores comment lines
+        let text4 = "P3\n# this is a comment\n2 1\n# this, too\n255\n# another comment\n\
+            255 255 255\n# oh, no, comments in the pixel data!\n255 0 255\n";
+        let c4 = Canvas::from_ppm(text4.as_bytes()).unwrap();
+        assert_eq!(c4.pixel_at(1, 0), create_color(1.0, 0.0, 1.0));
+
+        // Scenario: PPM parsing allows an RGB triple to span lines
+        let c5 = Canvas::from_ppm_with_gamma("P3\n1 1\n255\n51\n153\n\n204\n".as_bytes(), Gamma::Linear).unwrap();
+        assert_eq!(c5.pixel_at(0, 0), create_color(0.2, 0.6, 0.8));
+
+        // Scenario: PPM parsing respects the scale setting
+        let c6 = Canvas::from_ppm_with_gamma("P3\n2 1\n100\n100 100 100  50 50 50\n".as_bytes(), Gamma::Linear).unwrap();
+        assert_eq!(c6.pixel_at(1, 0), create_color(0.5, 0.5, 0.5));
+
+        // Scenario: A binary PPM reads back what was written
+        let mut stream = PpmStream::with_gamma(Vec::new(), 2, 1, Dither::None, Gamma::Linear).unwrap();
+        stream.write_row(&[create_color(1.0, 0.0, 0.2), create_color(0.0, 0.6, 1.0)]).unwrap();
+        let c7 = Canvas::from_ppm_with_gamma(stream.finish().unwrap().as_slice(), Gamma::Linear).unwrap();
+        assert_eq!(c7.pixel_at(0, 0), create_color(1.0, 0.0, 0.2));
+        assert_eq!(c7.pixel_at(1, 0), create_color(0.0, 0.6, 1.0));
+        assert!(Canvas::from_ppm("P6\n2 1\n255\n\x01".as_bytes()).is_err());
+
+        // Scenario: Reading an sRGB image gives back the light it encodes
+        let c8 = Canvas::from_ppm("P3\n1 1\n255\n188 0 255\n".as_bytes()).unwrap();
+        let v8 = c8.pixel_at(0, 0).to_array();
+        assert!((v8[0] - 0.5).abs() < 0.005 && v8[1] == 0.0 && fuzzy_equal(v8[2], 1.0));
+        assert_eq!(c8.gamma, Gamma::Srgb);
+    }
+
+    #[test]
+    fn test_canvas_rgba()
+    {
+        // Scenario: Pixels as RGBA bytes for an HTML canvas
+        let mut c1 = Canvas::new(2, 1);
+        c1.gamma = Gamma::Linear;
+        c1.write_pixel(0, 0, create_color(1.5, 0.0, 0.5));
+        assert_eq!(c1.to_rgba(), vec![255, 0, 128, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_canvas_resize()
+    {
+        // Scenario: Shrinking with a box filter averages each block
+        let mut c1 = Canvas::new(4, 2);
+        c1.write_pixel(0, 0, create_color(1.0, 0.0, 0.0));
+        c1.write_pixel(3, 1, create_color(0.0, 0.0, 1.0));
+        let c2 = c1.resize(2, 1, ResizeFilter::Box);
+        assert_eq!((c2.width, c2.height), (2, 1));
+        assert_eq!(c2.pixel_at(0, 0), create_color(0.25, 0.0, 0.0));
+        assert_eq!(c2.pixel_at(1, 0), create_color(0.0, 0.0, 0.25));
+
+        // Scenario: A flat color stays the same with every filter
+        let mut c3 = Canvas::new(5, 3);
+        c3.pixels.iter_mut().for_each(|p| *p = create_color(0.2, 0.4, 0.6));
+        for filter in [ResizeFilter::Box, ResizeFilter::Bilinear, ResizeFilter::Lanczos]
+        {
+            for (width, height) in [(2, 2), (12, 7), (5, 3)]
+            {
+                let c4 = c3.resize(width, height, filter);
+                assert!(c4.pixels.iter().all(|p| *p == create_color(0.2, 0.4, 0.6)), "{:?}", filter);
+            }
+        }
+
+        // Scenario: Enlarging with a bilinear filter blends between pixels
+        let mut c5 = Canvas::new(2, 1);
+        c5.write_pixel(1, 0, create_color(1.0, 1.0, 1.0));
+        let c6 = c5.resize(4, 1, ResizeFilter::Bilinear);
+        assert_eq!(c6.pixel_at(0, 0), create_color(0.0, 0.0, 0.0));
+        assert_eq!(c6.pixel_at(1, 0), create_color(0.25, 0.25, 0.25));
+        assert_eq!(c6.pixel_at(2, 0), create_color(0.75, 0.75, 0.75));
+        assert_eq!(c6.pixel_at(3, 0), create_color(1.0, 1.0, 1.0));
+
+        // Scenario: Resizing an empty image gives a black one
+        for c7 in [Canvas::new(0, 0), Canvas::new(3, 0), Canvas::new(0, 2)]
+        {
+            let c8 = c7.resize(4, 2, ResizeFilter::Lanczos);
+            assert_eq!((c8.width, c8.height), (4, 2));
+            assert!(c8.pixels.iter().all(|p| *p == create_color(0.0, 0.0, 0.0)));
+        }
+        assert_eq!(c5.resize(0, 0, ResizeFilter::Box).pixels.len(), 0);
+    }
+
+    #[test]
+    fn test_canvas_anaglyph()
+    {
+        // Scenario: The red comes from the left image and the rest from
+        // the right
+        let mut c1 = Canvas::new(2, 1);
+        let mut c2 = Canvas::new(2, 1);
+        c1.pixels.iter_mut().for_each(|p| *p = create_color(0.8, 0.6, 0.4));
+        c2.write_pixel(1, 0, create_color(0.1, 0.2, 0.3));
+        let c3 = Canvas::anaglyph(&c1, &c2);
+        assert_eq!(c3.pixel_at(0, 0), create_color(0.8, 0.0, 0.0));
+        assert_eq!(c3.pixel_at(1, 0), create_color(0.8, 0.2, 0.3));
+    }
+
+    #[test]
+    #[should_panic(expected = "the images of a stereo pair must be the same size")]
+    fn test_canvas_anaglyph_size()
+    {
+        // Scenario: Images of different sizes can't be combined
+        Canvas::anaglyph(&Canvas::new(2, 1), &Canvas::new(1, 2));
+    }
+
+    #[test]
+    fn test_canvas_gamma()
+    {
+        // Scenario: Encoding and decoding give back the light
+        for gamma in [Gamma::Linear, Gamma::Srgb, Gamma::Power(2.2)]
+        {
+            for light in [0.0, 0.001, 0.2, 0.5, 1.0]
+            {
+                assert!(fuzzy_equal(gamma.decode(gamma.encode(light)), light), "{:?}", gamma);
+            }
+            assert!(fuzzy_equal(gamma.encode(1.5), 1.0));
+            assert_eq!(gamma.encode(-0.5), 0.0);
+        }
+
+        // Scenario: sRGB brightens the middle tones
+        assert!(fuzzy_equal(Gamma::Srgb.encode(0.5), 0.735357));
+        assert!(fuzzy_equal(Gamma::Srgb.encode(0.002), 0.02584));
+        assert!(fuzzy_equal(Gamma::Power(2.0).encode(0.25), 0.5));
+
+        // Scenario: Every writer uses the canvas's gamma
+        let mut c1 = Canvas::new(1, 1);
+        c1.write_pixel(0, 0, create_color(0.5, 0.2, 0.0));
+        let bytes = [188, 124, 0];
+        assert_eq!(c1.to_rgb_bytes(Dither::None), bytes);
+        assert_eq!(c1.to_rgba()[..3], bytes);
+        assert!(c1.to_ppm().ends_with("188 124 0\n"));
+        c1.gamma = Gamma::Power(2.0);
+        assert_eq!(c1.to_rgb_bytes(Dither::None), [180, 114, 0]);
+    }
+
+    #[test]
+    fn test_canvas_bit_depth()
+    {
+        // Scenario: A 16-bit PPM has a maximum value of 65535
+        let mut c1 = Canvas::new(2, 1);
+        c1.gamma = Gamma::Linear;
+        c1.bit_depth = BitDepth::Sixteen;
+        c1.write_pixel(0, 0, create_color(1.0, 0.5, 0.0));
+        c1.write_pixel(1, 0, create_color(0.25, 0.0, 2.0));
+        let ppm1 = c1.to_ppm();
+        let mut lines1 = ppm1.lines();
+        assert_eq!(lines1.nth(2), Some("65535"));
+        assert_eq!(lines1.next(), Some("65535 32768 0 16384 0 65535"));
+
+        // Scenario: Binary 16-bit values are written high byte first and
+        // read back with the finer steps
+        assert_eq!(c1.to_rgb_bytes(Dither::None), vec![255, 255, 128, 0, 0, 0, 64, 0, 0, 0, 255, 255]);
+        let mut stream = PpmStream::with_depth(Vec::new(), 2, 1, Dither::None, Gamma::Srgb, BitDepth::Sixteen).unwrap();
+        let light = create_color(0.1234, 0.5, 0.9);
+        stream.write_row(&[light, light]).unwrap();
+        let c2 = Canvas::from_ppm(stream.finish().unwrap().as_slice()).unwrap();
+        let (read, wanted) = (c2.pixel_at(1, 0).to_array(), light.to_array());
+        assert!((0..3).all(|i| (read[i] - wanted[i]).abs() < 0.0001));
+        assert_eq!(c2.bit_depth, BitDepth::Sixteen);
+
+        // Scenario: Dithering works in 16-bit steps
+        let mut c3 = Canvas::new(8, 8);
+        c3.gamma = Gamma::Linear;
+        c3.bit_depth = BitDepth::Sixteen;
+        c3.pixels.iter_mut().for_each(|p| *p = create_color(1000.5 / 65535.0, 0.0, 0.0));
+        let bytes3 = c3.to_rgb_bytes(Dither::FloydSteinberg);
+        let reds: Vec<u16> = bytes3.chunks(6).map(|p| u16::from_be_bytes([p[0], p[1]])).collect();
+        assert!(reds.iter().all(|r| *r == 1000 || *r == 1001));
+        assert!(reds.contains(&1000) && reds.contains(&1001));
+    }
+
+    #[test]
+    fn test_canvas_dither()
+    {
+        let mean = |bytes: &[u8]| bytes.iter().map(|b| *b as Scalar).sum::<Scalar>() / bytes.len() as Scalar;
+        let mut c1 = Canvas::new(16, 16);
+        c1.gamma = Gamma::Linear;
+        c1.pixels.iter_mut().for_each(|p| *p = create_color(100.25 / 255.0, 0.0, 1.0));
+
+        // Scenario: Without dithering a flat color rounds to one value
+        let bytes1 = c1.to_rgb_bytes(Dither::None);
+        assert!(bytes1.chunks(3).all(|p| p == [100, 0, 255]));
+
+        // Scenario: Dithering mixes neighbouring values to average out to
+        // the true color
+        for dither in [Dither::Ordered, Dither::FloydSteinberg]
+        {
+            let bytes2 = c1.to_rgb_bytes(dither);
+            let reds: Vec<u8> = bytes2.chunks(3).map(|p| p[0]).collect();
+            assert!(reds.iter().all(|r| *r == 100 || *r == 101));
+            assert!((mean(&reds) - 100.25).abs() < 0.02, "{:?}", dither);
+            assert!(bytes2.chunks(3).all(|p| p[1] == 0 && p[2] == 255));
+        }
+
+        // Scenario: A dithered stream writes the same bytes
+        let mut stream = PpmStream::with_gamma(Vec::new(), 16, 16, Dither::FloydSteinberg, Gamma::Linear).unwrap();
+        for y in 0..16
+        {
+            stream.write_row(c1.row(y)).unwrap();
+        }
+        let ppm = stream.finish().unwrap();
+        assert_eq!(&ppm[ppm.len() - 16 * 16 * 3..], c1.to_rgb_bytes(Dither::FloydSteinberg).as_slice());
+    }
 }
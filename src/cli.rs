@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+use crate::arithmetic::*;
+use crate::camera::*;
+use crate::world::*;
+
+pub const USAGE: &str = "Usage: ray_tracer [options]
+
+Renders a scene to a PPM image, written to standard output unless an
+output file is given.
+
+Options:
+  --scene NAME|FILE   'demo' for the built-in scene, or a YAML or JSON
+                      scene file (default: demo)
+  --width PIXELS      width of the image
+  --height PIXELS     height of the image
+  --fov DEGREES       horizontal field of view
+  --depth N           how many reflections and refractions are followed
+  --crop X0,Y0,X1,Y1  renders only the pixels from column X0 and row Y0
+                      up to, but not including, column X1 and row Y1
+  -o, --output FILE   the file to write the image to
+  -h, --help          shows this message
+";
+
+// The options that take a value
+const OPTIONS: [&str; 8] = ["--scene", "--width", "--height", "--fov", "--depth", "--crop", "--output", "-o"];
+
+// Where the scene to render comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneChoice
+{
+    Demo,
+    File(PathBuf),
+}
+
+// The command-line options. Anything not given is left as the scene has
+// it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options
+{
+    pub scene: SceneChoice,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    // in radians
+    pub field_of_view: Option<Scalar>,
+    pub depth: Option<i32>,
+    pub crop: Option<[u16; 4]>,
+    pub output: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Options
+{
+    pub fn new() -> Self
+    {
+        Options{scene: SceneChoice::Demo, width: None, height: None, field_of_view: None,
+            depth: None, crop: None, output: None, help: false}
+    }
+
+    // Reads the arguments after the program name. Values can be given
+    // as the next argument or after an '=', as in --width=640.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String>
+    {
+        let mut options = Options::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next()
+        {
+            let (name, inline) = match arg.split_once('=')
+            {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            if name == "--help" || name == "-h"
+            {
+                options.help = true;
+                continue;
+            }
+            if !name.starts_with('-')
+            {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            if !OPTIONS.contains(&name.as_str())
+            {
+                return Err(format!("unknown option '{}'", name));
+            }
+            let value = match inline.or_else(|| args.next())
+            {
+                Some(value) => value,
+                None => return Err(format!("'{}' needs a value", name)),
+            };
+            match name.as_str()
+            {
+                "--scene" => options.scene = match value.as_str()
+                {
+                    "demo" => SceneChoice::Demo,
+                    _ => SceneChoice::File(PathBuf::from(value)),
+                },
+                "--width" => options.width = Some(size(&name, &value)?),
+                "--height" => options.height = Some(size(&name, &value)?),
+                "--fov" =>
+                {
+                    let degrees = value.parse::<Scalar>().ok().filter(|d| *d > 0.0 && *d < 180.0)
+                        .ok_or_else(|| format!("'{}' must be an angle between 0 and 180 degrees", name))?;
+                    options.field_of_view = Some(degrees.to_radians());
+                },
+                "--depth" =>
+                {
+                    let depth = value.parse::<i32>().ok().filter(|d| (0..=World::MAX_DEPTH).contains(d))
+                        .ok_or_else(|| format!("'{}' must be a whole number from 0 to {}", name, World::MAX_DEPTH))?;
+                    options.depth = Some(depth);
+                },
+                "--crop" =>
+                {
+                    let numbers = value.split(',').map(|n| n.trim().parse::<u16>().ok()).collect::<Option<Vec<u16>>>();
+                    options.crop = match numbers.as_deref()
+                    {
+                        Some(&[x0, y0, x1, y1]) if x0 < x1 && y0 < y1 => Some([x0, y0, x1, y1]),
+                        _ => return Err(format!("'{}' must be four pixel numbers, as X0,Y0,X1,Y1", name)),
+                    };
+                },
+                _ => options.output = Some(PathBuf::from(value)),
+            }
+        }
+        Ok(options)
+    }
+
+    // Changes a camera to the size and field of view asked for. A width
+    // or height given alone keeps the camera's shape.
+    pub fn apply_to_camera(&self, camera: &mut Camera)
+    {
+        let aspect = Scalar::from(camera.hsize) / Scalar::from(camera.vsize);
+        let (width, height) = match (self.width, self.height)
+        {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, (Scalar::from(w) / aspect).round().max(1.0) as u16),
+            (None, Some(h)) => ((Scalar::from(h) * aspect).round().max(1.0) as u16, h),
+            (None, None) => (camera.hsize, camera.vsize),
+        };
+        if (width, height) != (camera.hsize, camera.vsize)
+        {
+            camera.set_size(width, height);
+        }
+        if let Some(field_of_view) = self.field_of_view
+        {
+            camera.set_field_of_view(field_of_view);
+        }
+        if let Some([x0, y0, x1, y1]) = self.crop
+        {
+            camera.set_crop(x0, y0, x1, y1);
+        }
+    }
+
+    pub fn apply_to_world(&self, world: &mut World)
+    {
+        if let Some(depth) = self.depth
+        {
+            world.set_max_depth(depth);
+        }
+    }
+}
+
+fn size(name: &str, value: &str) -> Result<u16, String>
+{
+    value.parse::<u16>().ok().filter(|n| *n > 0)
+        .ok_or_else(|| format!("'{}' must be a number of pixels from 1 to {}", name, u16::MAX))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+
+    fn args(list: &[&str]) -> Vec<String>
+    {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cli_feature()
+    {
+        // Scenario: No arguments render the demo scene as it is
+        let options1 = Options::parse(args(&[])).unwrap();
+        assert_eq!(options1, Options::new());
+
+        // Scenario: Every option, with values separate or after '='
+        let options2 = Options::parse(args(&["--scene", "scenes/cover.yaml", "--width=640",
+            "--height", "480", "--fov", "60", "--depth=8", "--crop", "10,20,30,40", "-o", "out.ppm"])).unwrap();
+        assert_eq!(options2.scene, SceneChoice::File(PathBuf::from("scenes/cover.yaml")));
+        assert_eq!((options2.width, options2.height), (Some(640), Some(480)));
+        assert!(fuzzy_equal(options2.field_of_view.unwrap(), PI / 3.0));
+        assert_eq!(options2.depth, Some(8));
+        assert_eq!(options2.crop, Some([10, 20, 30, 40]));
+        assert_eq!(options2.output, Some(PathBuf::from("out.ppm")));
+        assert!(Options::parse(args(&["--help"])).unwrap().help);
+
+        // Scenario: Bad arguments are reported
+        assert_eq!(Options::parse(args(&["--width"])), Err("'--width' needs a value".to_string()));
+        assert!(Options::parse(args(&["--width", "0"])).is_err());
+        assert!(Options::parse(args(&["--fov", "180"])).is_err());
+        assert!(Options::parse(args(&["--depth", "-1"])).is_err());
+        assert!(Options::parse(args(&["--crop", "10,20,5,40"])).is_err());
+        assert!(Options::parse(args(&["--crop", "10,20,30"])).is_err());
+        assert_eq!(Options::parse(args(&["--colour"])), Err("unknown option '--colour'".to_string()));
+        assert_eq!(Options::parse(args(&["scene.yaml"])), Err("unexpected argument 'scene.yaml'".to_string()));
+
+        // Scenario: A width alone keeps the camera's shape
+        let mut camera = Camera::new(100, 50, PI / 2.0);
+        let options3 = Options{width: Some(300), ..Options::new()};
+        options3.apply_to_camera(&mut camera);
+        assert_eq!((camera.hsize, camera.vsize), (300, 150));
+        assert!(fuzzy_equal(camera.pixel_size, Camera::new(300, 150, PI / 2.0).pixel_size));
+
+        // Scenario: The depth is set on the world
+        let mut world = World::default_world();
+        Options{depth: Some(2), ..Options::new()}.apply_to_world(&mut world);
+        assert_eq!(world.get_max_depth(), 2);
+    }
+}
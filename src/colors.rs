@@ -0,0 +1,132 @@
+use crate::arithmetic::*;
+use crate::tuple::*;
+
+// Colors by name, from the CSS palette
+const NAMED_COLORS: [(&str, u32); 20] = [
+    ("black", 0x000000), ("white", 0xffffff), ("gray", 0x808080), ("grey", 0x808080),
+    ("silver", 0xc0c0c0), ("red", 0xff0000), ("maroon", 0x800000), ("green", 0x008000),
+    ("lime", 0x00ff00), ("olive", 0x808000), ("blue", 0x0000ff), ("navy", 0x000080),
+    ("yellow", 0xffff00), ("orange", 0xffa500), ("gold", 0xffd700), ("purple", 0x800080),
+    ("cyan", 0x00ffff), ("magenta", 0xff00ff), ("pink", 0xffc0cb), ("brown", 0xa52a2a),
+];
+
+// The color of light from a black body at a temperature in kelvin, with
+// its brightest channel at 1: about 1900K for a candle, 3200K for a
+// tungsten bulb and 6500K for daylight. Uses Tanner Helland's fit to the
+// blackbody curve, which holds from 1000K to 40000K.
+pub fn kelvin_to_color(temperature: Scalar) -> Tuple
+{
+    let t = temperature.clamp(1000.0, 40000.0) / 100.0;
+    let red = match t <= 66.0
+    {
+        true => 255.0,
+        false => 329.698727446 * (t - 60.0).powf(-0.1332047592),
+    };
+    let green = match t <= 66.0
+    {
+        true => 99.4708025861 * t.ln() - 161.1195681661,
+        false => 288.1221695283 * (t - 60.0).powf(-0.0755148492),
+    };
+    let blue = if t >= 66.0
+    {
+        255.0
+    }
+    else if t <= 19.0
+    {
+        0.0
+    }
+    else
+    {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+    let c = |v: Scalar| v.clamp(0.0, 255.0) / 255.0;
+    create_color(c(red), c(green), c(blue))
+}
+
+pub fn named_color(name: &str) -> Option<Tuple>
+{
+    NAMED_COLORS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, rgb)| rgb_color(*rgb))
+}
+
+// A color written as "#rrggbb" or the shorter "#rgb"
+pub fn parse_hex_color(text: &str) -> Option<Tuple>
+{
+    let digits = text.strip_prefix('#')?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    match digits.len()
+    {
+        6 => u32::from_str_radix(digits, 16).ok().map(rgb_color),
+        3 =>
+        {
+            // each digit is doubled, so "#fc0" is "#ffcc00"
+            let doubled: String = digits.chars().flat_map(|c| [c, c]).collect();
+            u32::from_str_radix(&doubled, 16).ok().map(rgb_color)
+        },
+        _ => None,
+    }
+}
+
+// A color written as a hex string, a name, or a temperature such as
+// "3200K"
+pub fn parse_color(text: &str) -> Option<Tuple>
+{
+    let text = text.trim();
+    if text.starts_with('#')
+    {
+        return parse_hex_color(text);
+    }
+    if let Some(kelvin) = text.strip_suffix('K').or_else(|| text.strip_suffix('k'))
+    {
+        if let Ok(temperature) = kelvin.trim().parse::<Scalar>()
+        {
+            return Some(kelvin_to_color(temperature));
+        }
+    }
+    named_color(text)
+}
+
+fn rgb_color(rgb: u32) -> Tuple
+{
+    let channel = |shift: u32| ((rgb >> shift) & 0xff) as Scalar / 255.0;
+    create_color(channel(16), channel(8), channel(0))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_colors_feature()
+    {
+        // Scenario: Hex colors are read in long and short forms
+        assert_eq!(parse_hex_color("#ffcc00"), Some(create_color(1.0, 0.8, 0.0)));
+        assert_eq!(parse_hex_color("#FC0"), Some(create_color(1.0, 0.8, 0.0)));
+        assert_eq!(parse_hex_color("#ffcc0"), None);
+        assert_eq!(parse_hex_color("#ggcc00"), None);
+        assert_eq!(parse_hex_color("ffcc00"), None);
+
+        // Scenario: Colors are looked up by name
+        assert_eq!(named_color("Orange"), Some(create_color(1.0, 165.0 / 255.0, 0.0)));
+        assert_eq!(named_color("octarine"), None);
+
+        // Scenario: Low temperatures are red and high ones blue
+        let candle = kelvin_to_color(1900.0).get_vec();
+        assert_eq!(candle[0], 1.0);
+        assert!(candle[2] < 0.1);
+        let daylight = kelvin_to_color(6600.0).get_vec();
+        assert!(daylight.iter().take(3).all(|c| *c > 0.95));
+        let sky = kelvin_to_color(15000.0).get_vec();
+        assert_eq!(sky[2], 1.0);
+        assert!(sky[0] < 0.8);
+
+        // Scenario: Any form can be parsed as a color
+        assert_eq!(parse_color(" white "), Some(create_color(1.0, 1.0, 1.0)));
+        assert_eq!(parse_color("#000"), Some(create_color(0.0, 0.0, 0.0)));
+        assert_eq!(parse_color("3200K"), Some(kelvin_to_color(3200.0)));
+        assert_eq!(parse_color("warm"), None);
+    }
+}
@@ -6,10 +6,10 @@ use crate::sphere::*;
 use crate::tuple::*;
 
 #[derive(Clone, Debug)]
-pub struct Computations
+pub struct Computations<'a>
 {
-    pub t: f64,
-    pub object: Shape,
+    pub t: Scalar,
+    pub object: &'a Shape,
     pub point: Tuple,
     pub eyev: Tuple,
     pub normalv: Tuple,
@@ -17,21 +17,25 @@ pub struct Computations
     pub over_point: Tuple,
     pub under_point: Tuple,
     pub reflectv: Tuple,
-    pub n1: f64,
-    pub n2: f64,
+    pub n1: Scalar,
+    pub n2: Scalar,
+    // interpolated texture coordinates, for meshes that have them
+    pub texture_uv: Option<(Scalar, Scalar)>,
+    // the time of the ray, which the rays leaving the hit are cast at
+    pub time: Scalar,
 }
 
-impl Computations
+impl<'a> Computations<'a>
 {
-    pub fn new(t: f64, object: Shape, point: Tuple, eyev: Tuple,
+    pub fn new(t: Scalar, object: &'a Shape, point: Tuple, eyev: Tuple,
          normalv: Tuple, inside: bool, over_point: Tuple,
-         under_point: Tuple, reflectv: Tuple, n1: f64, n2: f64) -> Self
+         under_point: Tuple, reflectv: Tuple, n1: Scalar, n2: Scalar) -> Self
     {
         Computations{t, object, point, eyev, normalv, inside, over_point,
-            under_point, reflectv, n1, n2}
+            under_point, reflectv, n1, n2, texture_uv: None, time: 0.0}
     }
 
-    pub fn schlick(&self) -> f64
+    pub fn schlick(&self) -> Scalar
     {
         // find the cosine of the angle between the eye and normal vectors
         let mut cos = self.eyev.dot_product(self.normalv);
@@ -68,11 +72,11 @@ mod tests
     {
         // p.161 Scenario: The Schlick approximation under total internal reflection
         let shape1 = Shape::glass_sphere(1);
-        let sqrt2 = 2.0_f64.sqrt();
+        let sqrt2 = Scalar::sqrt(2.0);
         let ray1 = Ray::new(create_point(0.0, 0.0, sqrt2 / 2.0),
             create_vector(0.0, 1.0, 0.0));
-        let i11 = Intersection::new(-sqrt2 / 2.0, shape1.clone());
-        let i12 = Intersection::new(sqrt2 / 2.0, shape1.clone());
+        let i11 = Intersection::new(-sqrt2 / 2.0, &shape1);
+        let i12 = Intersection::new(sqrt2 / 2.0, &shape1);
         let xs1 = Intersections::new(vec![i11.clone(), i12.clone()]);
         let comps1 = i12.prepare_computations(ray1, xs1);
         let reflectance1 = comps1.schlick();
@@ -82,8 +86,8 @@ mod tests
         let shape2 = Shape::glass_sphere(2);
         let ray2 = Ray::new(create_point(0.0, 0.0, 0.0),
             create_vector(0.0, 1.0, 0.0));
-        let i21 = Intersection::new(-1.0, shape2.clone());
-        let i22 = Intersection::new(1.0, shape2.clone());
+        let i21 = Intersection::new(-1.0, &shape2);
+        let i22 = Intersection::new(1.0, &shape2);
         let xs2 = Intersections::new(vec![i21.clone(), i22.clone()]);
         let comps2 = i22.prepare_computations(ray2, xs2);
         let reflectance2 = comps2.schlick();
@@ -93,7 +97,7 @@ mod tests
         let shape3 = Shape::glass_sphere(3);
         let ray3 = Ray::new(create_point(0.0, 0.99, -2.0),
             create_vector(0.0, 0.0, 1.0));
-        let i31 = Intersection::new(1.8589, shape3.clone());
+        let i31 = Intersection::new(1.8589, &shape3);
         let xs3 = Intersections::new(vec![i31.clone()]);
         let comps3 = i31.prepare_computations(ray3, xs3);
         let reflectance3 = comps3.schlick();
@@ -1,7 +1,8 @@
 use std::fmt;
 use std::cmp;
-use std::f64::consts::PI;
+use crate::arithmetic::consts::PI;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::intersections::*;
 use crate::tuple::*;
 use crate::ray::*;
@@ -13,8 +14,8 @@ use crate::shape::*;
 #[derive(Clone, Debug)]
 pub struct Cone
 {
-    pub minimum: f64,
-    pub maximum: f64,
+    pub minimum: Scalar,
+    pub maximum: Scalar,
     pub closed: bool,
 }
 
@@ -23,39 +24,39 @@ impl Cone
 {
     pub fn new() -> Self
     {
-        Cone{minimum: f64::NEG_INFINITY, maximum: f64::INFINITY,
+        Cone{minimum: Scalar::NEG_INFINITY, maximum: Scalar::INFINITY,
             closed: false}
     }
 
     // a helper function to reduce duplication.
     // checks to see if the intersection as `t` is within a radius
     // of the cone from the y axis.
-    fn check_cap(&self, ray: Ray, t: f64, radius: f64) -> bool
+    fn check_cap(&self, ray: Ray, t: Scalar, radius: Scalar) -> bool
     {
-        let vo = ray.origin.get_vec();
-        let vd = ray.direction.get_vec();
+        let vo = ray.origin.to_array();
+        let vd = ray.direction.to_array();
         let x = vo[0] + t * vd[0];
         let z = vo[2] + t * vd[2];
         let dist_squared = (x * x) + (z * z);
         dist_squared <= radius * radius
     }
 
-    fn intersect_caps(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    fn intersect_caps(&self, ray: Ray) -> LocalHits
     {
-        let mut xs = Vec::new();
+        let mut xs = LocalHits::new();
         let u = 0.0;
         let v = 0.0;
 
         // caps only matter if the cylinder is closed, and might possibly be
         // intersected by the ray.
-        if (!self.closed) || fuzzy_equal(ray.direction.get_vec()[1], 0.0)
+        if (!self.closed) || fuzzy_equal(ray.direction.to_array()[1], 0.0)
         {
             return xs;
         }
 
         // check for an intersection with the lower end cap by intersecting
         // the ray with the plane at y=cly.minimum
-        let t0 = (self.minimum - ray.origin.get_vec()[1]) / ray.direction.get_vec()[1];
+        let t0 = (self.minimum - ray.origin.to_array()[1]) / ray.direction.to_array()[1];
         if self.check_cap(ray, t0, self.minimum)
         {
             xs.push((t0, u, v));
@@ -63,7 +64,7 @@ impl Cone
 
         // check for an intersection with the upper end cap by intersecting
         // the ray with the plane at y=cly.maximum
-        let t1 = (self.maximum - ray.origin.get_vec()[1]) / ray.direction.get_vec()[1];
+        let t1 = (self.maximum - ray.origin.to_array()[1]) / ray.direction.to_array()[1];
         if self.check_cap(ray, t1, self.maximum)
         {
             xs.push((t1, u, v));
@@ -71,26 +72,26 @@ impl Cone
         return xs;
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
     {
         let u = 0.0;
         let v = 0.0;
 
-        let vd = ray.direction.get_vec();
+        let vd = ray.direction.to_array();
         let a = (vd[0] * vd[0]) - (vd[1] * vd[1]) + (vd[2] * vd[2]);
 
-        let vo = ray.origin.get_vec();
+        let vo = ray.origin.to_array();
         let b = 2.0 * vo[0] * vd[0] - 2.0 * vo[1] * vd[1] + 2.0 * vo[2] * vd[2];
         let c = (vo[0] * vo[0]) - (vo[1] * vo[1]) + (vo[2] * vo[2]);
         let disc = (b * b) - 4.0 * a * c;
 
-        let mut xs = Vec::new();
+        let mut xs = LocalHits::new();
 
         if fuzzy_equal(a, 0.0)
         {
             if fuzzy_equal(b, 0.0)
             {
-                return Vec::new();
+                return LocalHits::new();
             }
             let t = -c / (2.0 * b);
             xs.push((t, u, v));
@@ -124,16 +125,15 @@ impl Cone
             xs.push((t1, u, v));
         }
 
-        let mut caps = self.intersect_caps(ray);
-        xs.append(&mut caps);
+        xs.append(&self.intersect_caps(ray));
 
         return xs;
     }
 
-    pub fn local_normal_at(&self, point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
         // compute the square of the distance from the y axis
-        let v = point.get_vec();
+        let v = point.to_array();
         let dist = (v[0] * v[0]) + (v[2] * v[2]);
 
         if dist < self.maximum.powi(2) && v[1] >= self.maximum - EPSILON
@@ -154,6 +154,25 @@ impl Cone
             return create_vector(v[0], y, v[2]);
         }
     }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        let limit = self.minimum.abs().max(self.maximum.abs());
+        BoundingBox::new_with_corners(create_point(-limit, self.minimum, -limit),
+            create_point(limit, self.maximum, limit))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        if self.minimum.is_infinite() || self.maximum.is_infinite()
+        {
+            return BoundingSphere::infinite();
+        }
+        let limit = self.minimum.abs().max(self.maximum.abs());
+        let half_height = (self.maximum - self.minimum) / 2.0;
+        BoundingSphere::new(create_point(0.0, self.minimum + half_height, 0.0),
+            (limit * limit + half_height * half_height).sqrt())
+    }
 }
 
 impl fmt::Display for Cone
@@ -170,6 +189,7 @@ mod tests
     use super::*;
 
     #[test]
+    #[cfg_attr(feature = "f32", ignore = "the book's values need f64 precision")]
     fn test_cones_feature1()
     {
         // p.189 Scenario: Intersecting a cone with a ray
@@ -239,7 +259,7 @@ mod tests
         let points4 = vec![create_point(0.0, 0.0, 0.0),
             create_point(1.0, 1.0, 1.0),
             create_point(-1.0, -1.0, 0.0)];
-        let sqrt2 = 2.0_f64.sqrt();
+        let sqrt2 = Scalar::sqrt(2.0);
         let normals4 = vec![create_vector(0.0, 0.0, 0.0),
             create_vector(1.0, -sqrt2, 1.0),
             create_vector(-1.0, 1.0, 0.0)];
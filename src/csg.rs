@@ -0,0 +1,346 @@
+use std::fmt;
+use crate::arena::*;
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::intersections::*;
+use crate::ray::*;
+use crate::shape::*;
+use crate::tuple::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOperation
+{
+    Union,
+    Intersection,
+    Difference,
+}
+
+// Constructive Solid Geometry, combining two shapes with a set operation.
+// Either shape may itself be a group (such as a mesh loaded from an OBJ
+// file) or another CSG shape, as long as it is closed so that every
+// ray entering it also leaves it.
+#[derive(Clone, Debug)]
+pub struct Csg
+{
+    pub operation: CsgOperation,
+    // the left operand is the first root of the arena and the right
+    // operand is the second
+    pub arena: ShapeArena,
+}
+
+impl Csg
+{
+    pub fn new(operation: CsgOperation) -> Self
+    {
+        Csg{operation, arena: ShapeArena::new()}
+    }
+
+    pub fn left(&self) -> Shape
+    {
+        self.arena.extract(self.arena.get_roots()[0])
+    }
+
+    pub fn right(&self) -> Shape
+    {
+        self.arena.extract(self.arena.get_roots()[1])
+    }
+
+    // lhit is true if the left shape was hit, and false if the right shape
+    // was hit. inl is true if the hit occurs inside the left shape, and inr
+    // is true if the hit occurs inside the right shape.
+    pub fn intersection_allowed(operation: CsgOperation,
+        lhit: bool, inl: bool, inr: bool) -> bool
+    {
+        match operation
+        {
+            CsgOperation::Union => (lhit && !inr) || (!lhit && !inl),
+            CsgOperation::Intersection => (lhit && inr) || (!lhit && inl),
+            CsgOperation::Difference => (lhit && !inr) || (!lhit && inl),
+        }
+    }
+
+    // Each intersection is paired with lhit, which is true if it is with
+    // the left operand
+    pub fn filter_intersections<'a>(&self, xs: Vec<(bool, Intersection<'a>)>) -> Vec<Intersection<'a>>
+    {
+        Self::filter(self.operation, xs)
+    }
+
+    pub fn filter<'a>(operation: CsgOperation, xs: Vec<(bool, Intersection<'a>)>) -> Vec<Intersection<'a>>
+    {
+        // begin outside of both children
+        let mut inl = false;
+        let mut inr = false;
+
+        let mut result = Vec::new();
+        for (lhit, i) in xs
+        {
+            if Self::intersection_allowed(operation, lhit, inl, inr)
+            {
+                result.push(i.clone());
+            }
+
+            // depending on which object was hit, toggle either inl or inr
+            if lhit
+            {
+                inl = !inl;
+            }
+            else
+            {
+                inr = !inr;
+            }
+        }
+        result
+    }
+
+    // A ray through the edge shared by two triangles of a mesh hits both
+    // of them at the same distance, but only crosses the surface once, so
+    // the second hit must not toggle inside/outside again. Hits at the
+    // same distance that cross the other way, such as leaving one shape
+    // where it touches another, are both kept. The hits are sorted by t.
+    pub fn merge_mesh_edges<'a>(xs: Vec<(bool, Intersection<'a>)>, ray: Ray) -> Vec<(bool, Intersection<'a>)>
+    {
+        let entering = |i: &Intersection| i.normal_at(ray.position(i.t)).dot_product(ray.direction) < 0.0;
+        let is_triangle = |i: &Intersection| i.object.is_triangle() || i.object.is_smooth_triangle();
+        let mesh = |i: &Intersection| i.parents.last().map(|p| p.get_id());
+        let mut merged: Vec<(bool, Intersection<'a>)> = Vec::with_capacity(xs.len());
+        for (lhit, i) in xs
+        {
+            if let Some((previous_lhit, previous)) = merged.last()
+            {
+                if *previous_lhit == lhit && fuzzy_equal(previous.t, i.t) && previous.object != i.object &&
+                    is_triangle(previous) && is_triangle(&i) && mesh(previous).is_some() &&
+                    mesh(previous) == mesh(&i) && entering(previous) == entering(&i)
+                {
+                    continue;
+                }
+            }
+            merged.push((lhit, i));
+        }
+        merged
+    }
+
+    pub fn local_intersect(&self, ray: Ray) -> Vec<Intersection<'_>>
+    {
+        self.arena.intersect_children(self.arena.get_roots(), Some(self.operation), ray)
+    }
+
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
+    {
+        // Intersections always refer to the child shapes, never the CSG itself
+        create_vector(0.0, 0.0, 1.0)
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        self.arena.children_bounds(self.arena.get_roots())
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        self.arena.children_bounding_sphere(self.arena.get_roots())
+    }
+}
+
+impl fmt::Display for Csg
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let mut separator = "";
+        write!(f, "{:?}(", self.operation)?;
+        for &child in self.arena.get_roots()
+        {
+            write!(f, "{}{}", separator, self.arena.extract(child))?;
+            separator = ", ";
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::matrix::*;
+
+    #[test]
+    fn test_csg_feature1()
+    {
+        // p.230 Scenario: CSG is created with an operation and two shapes
+        let s1 = Shape::new_sphere(1);
+        let s2 = Shape::new_cube(2);
+        let c = Shape::new_csg(3, CsgOperation::Union, s1.clone(), s2.clone());
+        let csg = c.get_csg();
+        assert_eq!(csg.operation, CsgOperation::Union);
+        assert_eq!(csg.left(), s1);
+        assert_eq!(csg.right(), s2);
+        for &id in csg.arena.get_roots()
+        {
+            assert_eq!(c.get_descendant_parents(id), vec![&c]);
+        }
+    }
+
+    #[test]
+    fn test_csg_feature2()
+    {
+        // p.231 Scenario Outline: Evaluating the rule for a CSG operation
+        let rules = vec![
+            (CsgOperation::Union, true, true, true, false),
+            (CsgOperation::Union, true, true, false, true),
+            (CsgOperation::Union, true, false, true, false),
+            (CsgOperation::Union, true, false, false, true),
+            (CsgOperation::Union, false, true, true, false),
+            (CsgOperation::Union, false, true, false, false),
+            (CsgOperation::Union, false, false, true, true),
+            (CsgOperation::Union, false, false, false, true),
+            (CsgOperation::Intersection, true, true, true, true),
+            (CsgOperation::Intersection, true, true, false, false),
+            (CsgOperation::Intersection, true, false, true, true),
+            (CsgOperation::Intersection, true, false, false, false),
+            (CsgOperation::Intersection, false, true, true, true),
+            (CsgOperation::Intersection, false, true, false, true),
+            (CsgOperation::Intersection, false, false, true, false),
+            (CsgOperation::Intersection, false, false, false, false),
+            (CsgOperation::Difference, true, true, true, false),
+            (CsgOperation::Difference, true, true, false, true),
+            (CsgOperation::Difference, true, false, true, false),
+            (CsgOperation::Difference, true, false, false, true),
+            (CsgOperation::Difference, false, true, true, true),
+            (CsgOperation::Difference, false, true, false, true),
+            (CsgOperation::Difference, false, false, true, false),
+            (CsgOperation::Difference, false, false, false, false)];
+        for (operation, lhit, inl, inr, result) in rules
+        {
+            assert_eq!(Csg::intersection_allowed(operation, lhit, inl, inr), result);
+        }
+    }
+
+    #[test]
+    fn test_csg_feature3()
+    {
+        // p.234 Scenario Outline: Filtering a list of intersections
+        let operations = vec![(CsgOperation::Union, 0, 3),
+            (CsgOperation::Intersection, 1, 2),
+            (CsgOperation::Difference, 0, 1)];
+        for (operation, x0, x1) in operations
+        {
+            let s1 = Shape::new_sphere(1);
+            let s2 = Shape::new_cube(2);
+            let c = Shape::new_csg(3, operation, s1.clone(), s2.clone());
+            let xs = vec![Intersection::new(1.0, &s1),
+                Intersection::new(2.0, &s2),
+                Intersection::new(3.0, &s1),
+                Intersection::new(4.0, &s2)];
+            let lhits = xs.iter().map(|i| *i.object == s1);
+            let result = c.get_csg().filter_intersections(lhits.zip(xs.clone()).collect());
+            assert_eq!(result.len(), 2);
+            assert_eq!(result[0], xs[x0]);
+            assert_eq!(result[1], xs[x1]);
+        }
+    }
+
+    #[test]
+    fn test_csg_feature4()
+    {
+        // p.236 Scenario: A ray misses a CSG object
+        let s1 = Shape::new_sphere(1);
+        let s2 = Shape::new_cube(2);
+        let c = Shape::new_csg(3, CsgOperation::Union, s1, s2);
+        let r = Ray::new(create_point(0.0, 2.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let csg = c.get_csg();
+        let xs = csg.local_intersect(r);
+        assert!(xs.is_empty());
+    }
+
+    #[test]
+    fn test_csg_feature5()
+    {
+        // p.236 Scenario: A ray hits a CSG object
+        let s1 = Shape::new_sphere(1);
+        let mut s2 = Shape::new_sphere(2);
+        s2.set_transform(Matrix::translation(0.0, 0.0, 0.5));
+        let c = Shape::new_csg(3, CsgOperation::Union, s1.clone(), s2.clone());
+        let r = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let csg = c.get_csg();
+        let xs = csg.local_intersect(r);
+        assert_eq!(xs.len(), 2);
+        assert!(fuzzy_equal(xs[0].t, 4.0));
+        assert_eq!(*xs[0].object, s1);
+        assert!(fuzzy_equal(xs[1].t, 6.5));
+        assert_eq!(*xs[1].object, s2);
+    }
+
+    fn cube_mesh(id: i32) -> Shape
+    {
+        // A closed triangle mesh of the cube from -1 to +1 on each axis
+        let corners = vec![create_point(-1.0, -1.0, -1.0),
+            create_point(1.0, -1.0, -1.0),
+            create_point(1.0, 1.0, -1.0),
+            create_point(-1.0, 1.0, -1.0),
+            create_point(-1.0, -1.0, 1.0),
+            create_point(1.0, -1.0, 1.0),
+            create_point(1.0, 1.0, 1.0),
+            create_point(-1.0, 1.0, 1.0)];
+        let faces = vec![(0, 1, 2, 3), (5, 4, 7, 6), (4, 0, 3, 7),
+            (1, 5, 6, 2), (3, 2, 6, 7), (4, 5, 1, 0)];
+        let mut mesh = Shape::new_group(id);
+        let mut next_id = id;
+        for (a, b, c, d) in faces
+        {
+            next_id = next_id + 1;
+            mesh.add_child(Shape::new_triangle(next_id, corners[a], corners[b], corners[c]));
+            next_id = next_id + 1;
+            mesh.add_child(Shape::new_triangle(next_id, corners[a], corners[c], corners[d]));
+        }
+        mesh
+    }
+
+    #[test]
+    fn test_csg_mesh_feature()
+    {
+        // Scenario: A triangle mesh can be used as a CSG operand
+        let mesh = cube_mesh(100);
+        let mut s = Shape::new_sphere(1);
+        s.set_transform(Matrix::translation(0.0, 0.0, -1.0));
+        let c = Shape::new_csg(2, CsgOperation::Difference, mesh, s.clone());
+        let r = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let csg = c.get_csg();
+        let xs = csg.local_intersect(r);
+        assert_eq!(xs.len(), 2);
+        assert!(fuzzy_equal(xs[0].t, 5.0));
+        assert_eq!(*xs[0].object, s);
+        assert!(fuzzy_equal(xs[1].t, 6.0));
+        assert!(xs[1].object.is_triangle());
+
+        // Scenario: A ray through an edge shared by two mesh triangles
+        // only enters the mesh once
+        let mesh2 = cube_mesh(200);
+        let mut s2 = Shape::new_sphere(3);
+        s2.set_transform(Matrix::scaling(0.5, 0.5, 0.5));
+        let c2 = Shape::new_csg(4, CsgOperation::Intersection, mesh2, s2.clone());
+        let r2 = Ray::new(create_point(-5.0, -5.0, 0.0), create_vector(1.0, 1.0, 0.0).normalize());
+        let csg2 = c2.get_csg();
+        let xs2 = csg2.local_intersect(r2);
+        assert_eq!(xs2.len(), 2);
+        assert_eq!(*xs2[0].object, s2);
+        assert_eq!(*xs2[1].object, s2);
+
+        // Scenario: Leaving one shape where it touches another is not
+        // taken for a shared edge
+        let mut left3 = Shape::new_group(5);
+        let mut cube3a = Shape::new_cube(6);
+        cube3a.set_transform(Matrix::translation(-0.5, 0.0, 0.0).multiply(&Matrix::scaling(0.5, 0.5, 0.5)));
+        let mut cube3b = Shape::new_cube(7);
+        cube3b.set_transform(Matrix::translation(0.5, 0.0, 0.0).multiply(&Matrix::scaling(0.5, 0.5, 0.5)));
+        left3.add_child(cube3a);
+        left3.add_child(cube3b);
+        let mut s3 = Shape::new_sphere(8);
+        s3.set_transform(Matrix::scaling(0.25, 0.25, 0.25));
+        let c3 = Shape::new_csg(9, CsgOperation::Difference, left3, s3);
+        let r3 = Ray::new(create_point(-5.0, 0.0, 0.0), create_vector(1.0, 0.0, 0.0));
+        let csg3 = c3.get_csg();
+        let xs3 = csg3.local_intersect(r3);
+        let ts3: Vec<Scalar> = xs3.iter().map(|i| i.t).collect();
+        assert_eq!(ts3, vec![4.0, 4.75, 5.25, 6.0]);
+    }
+}
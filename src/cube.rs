@@ -1,7 +1,8 @@
 use std::fmt;
 use std::cmp;
-use std::f64::consts::PI;
+use crate::arithmetic::consts::PI;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::intersections::*;
 use crate::tuple::*;
 use crate::ray::*;
@@ -27,7 +28,7 @@ impl Cube
         Cube{}
     }
 
-    fn check_axis(&self, origin: f64, direction: f64) -> (f64, f64)
+    fn check_axis(&self, origin: Scalar, direction: Scalar) -> (Scalar, Scalar)
     {
         let tmin_numerator = -1.0 - origin;
         let tmax_numerator = 1.0 - origin;
@@ -41,8 +42,8 @@ impl Cube
         }
         else
         {
-            tmin = tmin_numerator * f64::INFINITY;
-            tmax = tmax_numerator * f64::INFINITY;
+            tmin = tmin_numerator * Scalar::INFINITY;
+            tmax = tmax_numerator * Scalar::INFINITY;
         }
 
         if tmin > tmax
@@ -55,7 +56,7 @@ impl Cube
         (tmin, tmax)
     }
 
-    fn max3(&self, a: f64, b: f64, c: f64) -> f64
+    fn max3(&self, a: Scalar, b: Scalar, c: Scalar) -> Scalar
     {
         let mut n = a;
         if b > n
@@ -69,7 +70,7 @@ impl Cube
         n
     }
 
-    fn min3(&self, a: f64, b: f64, c: f64) -> f64
+    fn min3(&self, a: Scalar, b: Scalar, c: Scalar) -> Scalar
     {
         let mut n = a;
         if b < n
@@ -83,31 +84,31 @@ impl Cube
         n
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
     {
-        let (xtmin, xtmax) = self.check_axis(ray.origin.get_vec()[0],
-            ray.direction.get_vec()[0]);
-        let (ytmin, ytmax) = self.check_axis(ray.origin.get_vec()[1],
-            ray.direction.get_vec()[1]);
-        let (ztmin, ztmax) = self.check_axis(ray.origin.get_vec()[2],
-            ray.direction.get_vec()[2]);
+        let (xtmin, xtmax) = self.check_axis(ray.origin.to_array()[0],
+            ray.direction.to_array()[0]);
+        let (ytmin, ytmax) = self.check_axis(ray.origin.to_array()[1],
+            ray.direction.to_array()[1]);
+        let (ztmin, ztmax) = self.check_axis(ray.origin.to_array()[2],
+            ray.direction.to_array()[2]);
 
         let tmin = self.max3(xtmin, ytmin, ztmin);
         let tmax = self.min3(xtmax, ytmax, ztmax);
 
         if tmin > tmax
         {
-            return vec![];
+            return LocalHits::new();
         }
         let u = 0.0;
         let v = 0.0;
 
-        return vec![(tmin, u, v), (tmax, u, v)];
+        return [(tmin, u, v), (tmax, u, v)].into();
     }
 
-    pub fn local_normal_at(&self, point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
-        let v = point.get_vec();
+        let v = point.to_array();
         let x = v[0];
         let y = v[1];
         let z =  v[2];
@@ -123,6 +124,17 @@ impl Cube
         }
         return create_vector(0.0, 0.0, z);
     }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        BoundingBox::new_with_corners(create_point(-1.0, -1.0, -1.0),
+            create_point(1.0, 1.0, 1.0))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::new(create_point(0.0, 0.0, 0.0), Scalar::sqrt(3.0))
+    }
 }
 
 impl fmt::Display for Cube
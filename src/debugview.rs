@@ -0,0 +1,231 @@
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::camera::*;
+use crate::canvas::*;
+use crate::matrix::*;
+use crate::shape::*;
+use crate::tuple::*;
+use crate::world::*;
+
+// Lines drawn over a render to show how a scene is put together: the
+// boxes around groups, the cells of the acceleration structure and the
+// edges of shapes. Each is drawn in its color, or not at all if None.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DebugOverlay
+{
+    pub group_bounds: Option<Tuple>,
+    pub accelerator_cells: Option<Tuple>,
+    // the edges of triangles, and the boxes around other shapes
+    pub wireframes: Option<Tuple>,
+}
+
+impl DebugOverlay
+{
+    pub fn new() -> Self
+    {
+        DebugOverlay{group_bounds: Some(create_color(1.0, 1.0, 0.0)),
+            accelerator_cells: Some(create_color(0.0, 1.0, 1.0)), wireframes: None}
+    }
+
+    // Draws the overlay onto an image of the world rendered with the camera
+    pub fn draw(&self, camera: &Camera, world: &World, image: &mut Canvas)
+    {
+        let mut lines = Vec::new();
+        if let (Some(color), Some(accelerator)) = (self.accelerator_cells, world.get_accelerator())
+        {
+            for bounds in accelerator.cell_bounds()
+            {
+                box_edges(&bounds, &Matrix::identity(4), color, &mut lines);
+            }
+        }
+        for object in &world.objects
+        {
+            self.shape_lines(object, &world.get_transform(), &mut lines);
+        }
+        for (from, to, color) in lines
+        {
+            draw_line(camera, image, from, to, color);
+        }
+    }
+
+    fn shape_lines(&self, shape: &Shape, parent_transform: &Matrix, lines: &mut Vec<(Tuple, Tuple, Tuple)>)
+    {
+        let transform = parent_transform.multiply(&shape.get_transform());
+        if shape.is_composite()
+        {
+            if let Some(color) = self.group_bounds
+            {
+                box_edges(&shape.bounds(), &transform, color, lines);
+            }
+            for child in shape.get_children()
+            {
+                self.shape_lines(&child, &transform, lines);
+            }
+            return;
+        }
+        let color = match self.wireframes
+        {
+            Some(color) => color,
+            None => return,
+        };
+        let corners = if shape.is_triangle()
+        {
+            let t = shape.get_triangle();
+            Some([t.p1, t.p2, t.p3])
+        }
+        else if shape.is_smooth_triangle()
+        {
+            let t = shape.get_smooth_triangle();
+            Some([t.p1, t.p2, t.p3])
+        }
+        else
+        {
+            None
+        };
+        match corners
+        {
+            Some(p) =>
+            {
+                let p: Vec<Tuple> = p.iter().map(|c| transform.multiply_tuple(*c)).collect();
+                lines.push((p[0], p[1], color));
+                lines.push((p[1], p[2], color));
+                lines.push((p[2], p[0], color));
+            },
+            None => box_edges(&shape.bounds(), &transform, color, lines),
+        }
+    }
+}
+
+// The twelve edges of a box, moved by a transformation. Empty and
+// infinite boxes have no edges to draw.
+fn box_edges(bounds: &BoundingBox, transform: &Matrix, color: Tuple, lines: &mut Vec<(Tuple, Tuple, Tuple)>)
+{
+    if bounds.is_empty() || bounds.is_infinite()
+    {
+        return;
+    }
+    let (min, max) = (bounds.min.to_array(), bounds.max.to_array());
+    let corner = |i: usize| transform.multiply_tuple(create_point(
+        if i & 1 == 0 { min[0] } else { max[0] },
+        if i & 2 == 0 { min[1] } else { max[1] },
+        if i & 4 == 0 { min[2] } else { max[2] }));
+    for i in 0..8
+    {
+        // join corners that differ along one axis
+        for bit in [1, 2, 4]
+        {
+            if i & bit == 0
+            {
+                lines.push((corner(i), corner(i | bit), color));
+            }
+        }
+    }
+}
+
+// The position on the canvas, in pixels from its top left corner, of a
+// point in the camera's space in front of the camera
+fn project(camera: &Camera, point: Tuple) -> (Scalar, Scalar)
+{
+    let p = point.to_array();
+    // the camera looks down -z, with the canvas at z = -1
+    let x = (camera.half_width - p[0] / -p[2]) / camera.pixel_size;
+    let y = (camera.half_height - p[1] / -p[2]) / camera.pixel_size;
+    (x, y)
+}
+
+fn draw_line(camera: &Camera, image: &mut Canvas, from: Tuple, to: Tuple, color: Tuple)
+{
+    let mut a = camera.transform.multiply_tuple(from);
+    let mut b = camera.transform.multiply_tuple(to);
+    // cut off the part of the line behind the camera
+    let near = -EPSILON;
+    let (za, zb) = (a.to_array()[2], b.to_array()[2]);
+    if za > near && zb > near
+    {
+        return;
+    }
+    if za > near
+    {
+        a = b.add(a.sub(b).multiply((near - zb) / (za - zb)));
+    }
+    else if zb > near
+    {
+        b = a.add(b.sub(a).multiply((near - za) / (zb - za)));
+    }
+    let (x0, y0) = project(camera, a);
+    let (x1, y1) = project(camera, b);
+    // one dot per pixel along the longer direction, stopping at a sane
+    // length in case the line runs far off the canvas
+    let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().min(1e5) as usize;
+    for i in 0..=steps
+    {
+        let t = if steps == 0 { 0.0 } else { i as Scalar / steps as Scalar };
+        let x = (x0 + (x1 - x0) * t).floor();
+        let y = (y0 + (y1 - y0) * t).floor();
+        if x >= 0.0 && y >= 0.0 && (x as usize) < image.width && (y as usize) < image.height
+        {
+            image.write_pixel(x as usize, y as usize, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::accel::*;
+    use crate::arithmetic::consts::PI;
+
+    #[test]
+    fn test_debugview_feature()
+    {
+        let camera = {
+            let mut c = Camera::new(21, 21, PI / 2.0);
+            c.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0),
+                create_point(0.0, 0.0, 0.0), create_vector(0.0, 1.0, 0.0));
+            c
+        };
+        let yellow = create_color(1.0, 1.0, 0.0);
+
+        // Scenario: A group's box is outlined, passing through the corners
+        // of its children
+        let mut group1 = Shape::new_group(1);
+        group1.add_child(Shape::new_cube(2));
+        let world1 = World::new(crate::pointlight::PointLight::new(create_point(0.0, 5.0, -5.0),
+            create_color(1.0, 1.0, 1.0)), vec![group1]);
+        let mut image1 = Canvas::new(21, 21);
+        DebugOverlay::new().draw(&camera, &world1, &mut image1);
+        // the front face of the cube, at z = -1, spans 1/4 of the view
+        // either side of the center
+        assert_eq!(image1.pixel_at(10, 7), yellow);
+        assert_eq!(image1.pixel_at(10, 10), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: Wireframes outline triangles
+        let triangle2 = Shape::new_triangle(3, create_point(0.0, 1.0, 0.0), create_point(-1.0, 0.0, 0.0),
+            create_point(1.0, 0.0, 0.0));
+        let world2 = World::new(world1.get_light(), vec![triangle2]);
+        let mut image2 = Canvas::new(21, 21);
+        let overlay2 = DebugOverlay{group_bounds: None, accelerator_cells: None,
+            wireframes: Some(create_color(1.0, 1.0, 1.0))};
+        overlay2.draw(&camera, &world2, &mut image2);
+        assert_eq!(image2.pixel_at(10, 10), create_color(1.0, 1.0, 1.0));
+        assert_eq!(image2.pixel_at(10, 12), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: The cells of the acceleration structure are outlined
+        let mut world3 = world1.clone();
+        world3.set_acceleration(Acceleration::KdTree);
+        let mut image3 = Canvas::new(21, 21);
+        let overlay3 = DebugOverlay{group_bounds: None, accelerator_cells: Some(yellow), wireframes: None};
+        overlay3.draw(&camera, &world3, &mut image3);
+        assert!(image3.pixels.iter().any(|p| *p == yellow));
+
+        // Scenario: Lines behind the camera are not drawn
+        let mut group4 = Shape::new_group(4);
+        group4.add_child(Shape::new_cube(5));
+        group4.set_transform(Matrix::translation(0.0, 0.0, -20.0));
+        let world4 = World::new(world1.get_light(), vec![group4]);
+        let mut image4 = Canvas::new(21, 21);
+        DebugOverlay::new().draw(&camera, &world4, &mut image4);
+        assert!(image4.pixels.iter().all(|p| *p == create_color(0.0, 0.0, 0.0)));
+    }
+}
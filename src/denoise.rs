@@ -0,0 +1,198 @@
+use crate::arithmetic::*;
+use crate::camera::*;
+use crate::canvas::*;
+use crate::integrator::*;
+use crate::tuple::*;
+use crate::world::*;
+
+// Images of the surfaces seen at each pixel, which tell the denoiser
+// where the edges between surfaces are so it doesn't blur across them
+#[derive(Clone, Debug)]
+pub struct DenoiseGuides
+{
+    // as drawn by NormalIntegrator
+    pub normals: Canvas,
+    // as drawn by DepthIntegrator
+    pub depths: Canvas,
+}
+
+impl DenoiseGuides
+{
+    // Renders the guides for an image of the world from the camera, with
+    // depths fading out at the far distance
+    pub fn render(camera: &Camera, world: &World, far: Scalar) -> Self
+    {
+        let width = usize::from(camera.hsize);
+        let height = usize::from(camera.vsize);
+        let mut normals = Canvas::new(width, height);
+        let mut depths = Canvas::new(width, height);
+        let depth = DepthIntegrator{far};
+        for y in 0..camera.vsize
+        {
+            for x in 0..camera.hsize
+            {
+                let ray = camera.ray_for_pixel(x, y);
+                normals.write_pixel(x.into(), y.into(), NormalIntegrator.li(ray, world, 0));
+                depths.write_pixel(x.into(), y.into(), depth.li(ray, world, 0));
+            }
+        }
+        DenoiseGuides{normals, depths}
+    }
+}
+
+// An edge-avoiding à-trous filter: blurs with a 5x5 kernel whose taps
+// spread twice as far apart on each pass, giving less weight to pixels
+// whose color, normal or depth is unlike the pixel being filtered
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Denoiser
+{
+    pub iterations: usize,
+    // how different colors can be and still be blurred together
+    pub color_sigma: Scalar,
+    // higher powers keep surfaces facing different ways more apart
+    pub normal_power: Scalar,
+    // how different depths can be and still be blurred together
+    pub depth_sigma: Scalar,
+}
+
+impl Denoiser
+{
+    const KERNEL: [Scalar; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+    pub fn new() -> Self
+    {
+        Denoiser{iterations: 4, color_sigma: 0.5, normal_power: 64.0, depth_sigma: 0.05}
+    }
+
+    pub fn denoise(&self, image: &Canvas, guides: &DenoiseGuides) -> Canvas
+    {
+        assert_eq!((image.width, image.height), (guides.normals.width, guides.normals.height));
+        assert_eq!((image.width, image.height), (guides.depths.width, guides.depths.height));
+        let mut current = image.clone();
+        let mut step = 1;
+        for _ in 0..self.iterations
+        {
+            current = self.filter_pass(&current, guides, step);
+            step = step * 2;
+        }
+        current
+    }
+
+    fn filter_pass(&self, image: &Canvas, guides: &DenoiseGuides, step: usize) -> Canvas
+    {
+        let mut result = Canvas::new(image.width, image.height);
+        for y in 0..image.height
+        {
+            for x in 0..image.width
+            {
+                let color = image.pixel_at(x, y);
+                let normal = to_normal(guides.normals.pixel_at(x, y));
+                let depth = guides.depths.pixel_at(x, y).get_vec()[0];
+                let mut total = create_color(0.0, 0.0, 0.0);
+                let mut total_weight = 0.0;
+                for (j, ky) in Self::KERNEL.iter().enumerate()
+                {
+                    for (i, kx) in Self::KERNEL.iter().enumerate()
+                    {
+                        let qx = x as isize + (i as isize - 2) * step as isize;
+                        let qy = y as isize + (j as isize - 2) * step as isize;
+                        if qx < 0 || qy < 0 || qx as usize >= image.width || qy as usize >= image.height
+                        {
+                            continue;
+                        }
+                        let (qx, qy) = (qx as usize, qy as usize);
+                        let q_color = image.pixel_at(qx, qy);
+                        let q_normal = to_normal(guides.normals.pixel_at(qx, qy));
+                        let q_depth = guides.depths.pixel_at(qx, qy).get_vec()[0];
+
+                        let difference = color.sub(q_color);
+                        let color_weight = (-difference.dot_product(difference) /
+                            (self.color_sigma * self.color_sigma)).exp();
+                        let normal_weight = normal.dot_product(q_normal).max(0.0).powf(self.normal_power);
+                        let depth_weight = (-(depth - q_depth).abs() / (self.depth_sigma * step as Scalar)).exp();
+                        let weight = kx * ky * color_weight * normal_weight * depth_weight;
+                        total = total.add(q_color.multiply(weight));
+                        total_weight = total_weight + weight;
+                    }
+                }
+                // the pixel itself always has some weight, unless its
+                // normal is zero, as it is for a miss
+                let filtered = match total_weight > 0.0
+                {
+                    true => total.multiply(1.0 / total_weight),
+                    false => color,
+                };
+                result.write_pixel(x, y, filtered);
+            }
+        }
+        result
+    }
+}
+
+// Undoes NormalIntegrator's mapping of a normal to a color. Pixels where
+// nothing was hit give a zero vector.
+fn to_normal(color: Tuple) -> Tuple
+{
+    let c = color.get_vec();
+    if c[0] == 0.0 && c[1] == 0.0 && c[2] == 0.0
+    {
+        return create_vector(0.0, 0.0, 0.0);
+    }
+    create_vector(c[0] * 2.0 - 1.0, c[1] * 2.0 - 1.0, c[2] * 2.0 - 1.0)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // The spread of the pixels in a rectangle around their average
+    fn variance(image: &Canvas, xs: std::ops::Range<usize>, ys: std::ops::Range<usize>) -> Scalar
+    {
+        let values: Vec<Scalar> = ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+            .map(|(x, y)| image.pixel_at(x, y).get_vec()[0]).collect();
+        let mean = values.iter().sum::<Scalar>() / values.len() as Scalar;
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<Scalar>() / values.len() as Scalar
+    }
+
+    #[test]
+    fn test_denoise_feature()
+    {
+        // Scenario: Noise is smoothed without blurring across an edge
+        // between two surfaces facing different ways
+        let (width, height) = (32, 16);
+        let mut image = Canvas::new(width, height);
+        let mut normals = Canvas::new(width, height);
+        let mut depths = Canvas::new(width, height);
+        let mut noise: u32 = 12345;
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                noise = noise.wrapping_mul(1103515245).wrapping_add(12345);
+                let jitter = ((noise >> 16) % 100) as Scalar / 1000.0 - 0.05;
+                let left = x < width / 2;
+                let base = if left { 0.2 } else { 0.8 };
+                image.write_pixel(x, y, create_color(base + jitter, base + jitter, base + jitter));
+                normals.write_pixel(x, y, if left { create_color(0.5, 1.0, 0.5) } else { create_color(0.5, 0.5, 0.0) });
+                depths.write_pixel(x, y, create_color(0.5, 0.5, 0.5));
+            }
+        }
+        let guides = DenoiseGuides{normals, depths};
+        let result = Denoiser::new().denoise(&image, &guides);
+        assert!(variance(&result, 0..16, 0..16) < variance(&image, 0..16, 0..16) / 4.0);
+        assert!(variance(&result, 16..32, 0..16) < variance(&image, 16..32, 0..16) / 4.0);
+        assert!((result.pixel_at(15, 8).get_vec()[0] - 0.2).abs() < 0.05);
+        assert!((result.pixel_at(16, 8).get_vec()[0] - 0.8).abs() < 0.05);
+
+        // Scenario: Guides are rendered from the normal and depth integrators
+        let world = World::default_world();
+        let mut camera = Camera::new(11, 11, crate::arithmetic::consts::PI / 2.0);
+        camera.transform = crate::matrix::Matrix::view_transform(create_point(0.0, 0.0, -5.0),
+            create_point(0.0, 0.0, 0.0), create_vector(0.0, 1.0, 0.0));
+        let guides2 = DenoiseGuides::render(&camera, &world, 8.0);
+        assert_eq!(guides2.normals.pixel_at(5, 5), create_color(0.5, 0.5, 0.0));
+        assert_eq!(guides2.depths.pixel_at(5, 5), create_color(0.5, 0.5, 0.5));
+        assert_eq!(guides2.depths.pixel_at(0, 0), create_color(0.0, 0.0, 0.0));
+    }
+}
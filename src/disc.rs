@@ -0,0 +1,113 @@
+use std::fmt;
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::intersections::*;
+use crate::tuple::*;
+use crate::ray::*;
+
+// A flat disc of radius 1 in the x and z axes, centered on the origin.
+// With an inner radius it is a ring (an annulus), with a hole in the
+// middle.
+#[derive(Clone, Debug)]
+pub struct Disc
+{
+    pub inner_radius: Scalar,
+}
+
+impl Disc
+{
+    pub fn new() -> Self
+    {
+        Disc{inner_radius: 0.0}
+    }
+
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
+    {
+        let o = ray.origin.to_array();
+        let d = ray.direction.to_array();
+        if d[1].abs() < EPSILON
+        {
+            // parallel to the disc, or in it, which is the same as missing it
+            return LocalHits::new();
+        }
+        let t = -o[1] / d[1];
+        let x = o[0] + t * d[0];
+        let z = o[2] + t * d[2];
+        let dist_squared = x * x + z * z;
+        if dist_squared > 1.0 || dist_squared < self.inner_radius * self.inner_radius
+        {
+            return LocalHits::new();
+        }
+        [(t, 0.0, 0.0)].into()
+    }
+
+    pub fn local_normal_at(&self, _point: Tuple, _hit_uv: (Scalar, Scalar)) -> Tuple
+    {
+        create_vector(0.0, 1.0, 0.0)
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        BoundingBox::new_with_corners(create_point(-1.0, 0.0, -1.0), create_point(1.0, 0.0, 1.0))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::new(create_point(0.0, 0.0, 0.0), 1.0)
+    }
+}
+
+impl fmt::Display for Disc
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "disc {}", self.inner_radius)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_disc_feature()
+    {
+        // Scenario: The normal of a disc is constant everywhere
+        let d1 = Disc::new();
+        assert_eq!(d1.local_normal_at(create_point(0.5, 0.0, -0.5), (0.0, 0.0)), create_vector(0.0, 1.0, 0.0));
+
+        // Scenario: Rays hit a disc inside its radius, from either side
+        let d2 = Disc::new();
+        let hits2 = [(create_point(0.0, 1.0, 0.0), create_vector(0.0, -1.0, 0.0), 1.0),
+            (create_point(0.5, -2.0, 0.5), create_vector(0.0, 1.0, 0.0), 2.0),
+            (create_point(0.0, 1.0, -1.0), create_vector(0.0, -1.0, 1.0).normalize(), (2.0 as Scalar).sqrt())];
+        for (origin, direction, t) in hits2
+        {
+            let xs = d2.local_intersect(Ray::new(origin, direction));
+            assert_eq!(xs.len(), 1);
+            assert!(fuzzy_equal(xs[0].0, t));
+        }
+
+        // Scenario: Rays miss a disc outside its radius, or parallel to it
+        let misses3 = [(create_point(1.0, 1.0, 0.5), create_vector(0.0, -1.0, 0.0)),
+            (create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0)),
+            (create_point(0.0, 1.0, 0.0), create_vector(1.0, 0.0, 0.0))];
+        for (origin, direction) in misses3
+        {
+            assert_eq!(d2.local_intersect(Ray::new(origin, direction)).len(), 0);
+        }
+
+        // Scenario: A ring has a hole in the middle
+        let d4 = Disc{inner_radius: 0.5};
+        let down = create_vector(0.0, -1.0, 0.0);
+        assert_eq!(d4.local_intersect(Ray::new(create_point(0.0, 1.0, 0.0), down)).len(), 0);
+        assert_eq!(d4.local_intersect(Ray::new(create_point(0.3, 1.0, 0.3), down)).len(), 0);
+        assert_eq!(d4.local_intersect(Ray::new(create_point(0.0, 1.0, 0.75), down)).len(), 1);
+
+        // Scenario: A disc is bounded by a flat box
+        let b5 = d2.bounds();
+        assert_eq!(b5.min, create_point(-1.0, 0.0, -1.0));
+        assert_eq!(b5.max, create_point(1.0, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::arithmetic::*;
+use crate::tuple::*;
+
+// Blends the colors of distant hits toward a fog color, as a cheap way of
+// showing depth in large scenes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Fog
+{
+    // no fog before start, rising evenly to all fog at end
+    Linear{color: Tuple, start: Scalar, end: Scalar},
+    // the share of the color left falls by a factor of e every
+    // 1 / density units
+    Exponential{color: Tuple, density: Scalar},
+}
+
+impl Fog
+{
+    pub fn get_color(&self) -> Tuple
+    {
+        match self
+        {
+            Fog::Linear{color, ..} => *color,
+            Fog::Exponential{color, ..} => *color,
+        }
+    }
+
+    // How much of the fog color is seen at a distance, from 0 to 1
+    pub fn amount(&self, distance: Scalar) -> Scalar
+    {
+        match self
+        {
+            Fog::Linear{start, end, ..} =>
+            {
+                if distance <= *start
+                {
+                    0.0
+                }
+                else if distance >= *end
+                {
+                    1.0
+                }
+                else
+                {
+                    (distance - start) / (end - start)
+                }
+            },
+            Fog::Exponential{density, ..} => 1.0 - (-density * distance.max(0.0)).exp(),
+        }
+    }
+
+    pub fn apply(&self, color: Tuple, distance: Scalar) -> Tuple
+    {
+        let amount = self.amount(distance);
+        color.multiply(1.0 - amount).add(self.get_color().multiply(amount))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_fog_feature()
+    {
+        // Scenario: Linear fog rises between its start and end
+        let fog1 = Fog::Linear{color: create_color(1.0, 1.0, 1.0), start: 10.0, end: 20.0};
+        assert_eq!(fog1.amount(5.0), 0.0);
+        assert_eq!(fog1.amount(15.0), 0.5);
+        assert_eq!(fog1.amount(25.0), 1.0);
+        assert_eq!(fog1.apply(create_color(0.0, 0.5, 1.0), 15.0), create_color(0.5, 0.75, 1.0));
+
+        // Scenario: Exponential fog thickens with distance
+        let fog2 = Fog::Exponential{color: create_color(0.5, 0.5, 0.5), density: 0.1};
+        assert_eq!(fog2.amount(0.0), 0.0);
+        assert!(fuzzy_equal(fog2.amount(10.0), 1.0 - (-1.0 as Scalar).exp()));
+        assert!(fog2.amount(100.0) > 0.9999);
+    }
+}
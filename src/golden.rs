@@ -0,0 +1,130 @@
+// Regression tests comparing renders of the scenes in the golden
+// directory with the reference images stored beside them, so that
+// changes to shading or intersection code that alter the images are
+// noticed. Running the tests with UPDATE_GOLDEN=1 set writes new
+// reference images instead.
+
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::scene::*;
+
+// How far a render is from its reference image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageDifference
+{
+    // the largest difference in any channel of any pixel
+    pub max_error: Scalar,
+    // the average difference over every channel of every pixel
+    pub mean_error: Scalar,
+    // the number of pixels with a channel differing by more than the
+    // tolerance
+    pub pixels_over: usize,
+}
+
+pub fn compare_images(image: &Canvas, reference: &Canvas, tolerance: Scalar) -> ImageDifference
+{
+    assert_eq!((image.width, image.height), (reference.width, reference.height),
+        "the images are different sizes");
+    let mut max_error: Scalar = 0.0;
+    let mut total = 0.0;
+    let mut pixels_over = 0;
+    for (p, q) in image.pixels.iter().zip(reference.pixels.iter())
+    {
+        let (p, q) = (p.get_vec(), q.get_vec());
+        let error = (0..3).map(|i| (p[i] - q[i]).abs()).fold(0.0, Scalar::max);
+        total = total + (0..3).map(|i| (p[i] - q[i]).abs()).sum::<Scalar>();
+        max_error = max_error.max(error);
+        if error > tolerance
+        {
+            pixels_over += 1;
+        }
+    }
+    let channels = (image.pixels.len() * 3).max(1) as Scalar;
+    ImageDifference{max_error, mean_error: total / channels, pixels_over}
+}
+
+// Renders a scene file with its own camera, as an 8-bit image like the
+// references are stored in
+pub fn render_scene_file(path: &Path) -> Canvas
+{
+    let scene = Scene::from_path(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let camera = scene.camera.expect("golden scenes need a camera");
+    let ppm = camera.render_to(&scene.world, Vec::new()).unwrap();
+    Canvas::from_ppm(ppm.as_slice()).unwrap()
+}
+
+fn golden_dir() -> PathBuf
+{
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // a little more than one 8-bit step, for rounding that differs
+    // between platforms
+    const TOLERANCE: Scalar = 2.5 / 255.0;
+    // edges can land on either side of a pixel's center
+    const MAX_PIXELS_OVER: usize = 8;
+
+    #[test]
+    fn test_golden_compare()
+    {
+        // Scenario: Comparing two images reports the largest and average errors
+        let mut a = Canvas::new(2, 1);
+        let mut b = Canvas::new(2, 1);
+        a.write_pixel(0, 0, crate::tuple::create_color(0.5, 0.0, 0.0));
+        b.write_pixel(1, 0, crate::tuple::create_color(0.0, 0.0, 0.03));
+        let difference = compare_images(&a, &b, 0.1);
+        assert_eq!(difference.max_error, 0.5);
+        assert!(fuzzy_equal(difference.mean_error, 0.53 / 6.0));
+        assert_eq!(difference.pixels_over, 1);
+    }
+
+    #[test]
+    #[cfg_attr(feature = "f32", ignore = "the reference images are rendered with f64")]
+    fn test_golden_images()
+    {
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut scenes: Vec<PathBuf> = fs::read_dir(golden_dir()).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |e| e == "yml"))
+            .collect();
+        scenes.sort();
+        assert!(!scenes.is_empty());
+
+        let mut failures = Vec::new();
+        for scene in scenes
+        {
+            let image = render_scene_file(&scene);
+            let reference_path = scene.with_extension("ppm");
+            if update
+            {
+                fs::write(&reference_path, image.to_ppm()).unwrap();
+                continue;
+            }
+            let file = fs::File::open(&reference_path)
+                .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1 to create it)",
+                    reference_path.display(), e));
+            let reference = Canvas::from_ppm(BufReader::new(file)).unwrap();
+            let difference = compare_images(&image, &reference, TOLERANCE);
+            eprintln!("{}: max error {:.4}, mean error {:.6}, {} pixels over",
+                scene.display(), difference.max_error, difference.mean_error, difference.pixels_over);
+            if difference.pixels_over > MAX_PIXELS_OVER
+            {
+                // keep the render, to look at beside the reference
+                let actual = std::env::temp_dir().join(scene.with_extension("actual.ppm").file_name().unwrap());
+                fs::write(&actual, image.to_ppm()).unwrap();
+                failures.push(format!("{} differs from its reference in {} pixels (max error {:.4}); \
+                    the render is in {}", scene.display(), difference.pixels_over, difference.max_error,
+                    actual.display()));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
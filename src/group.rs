@@ -1,8 +1,10 @@
 use std::fmt;
 use std::cmp;
 use std::rc::Rc;
-use std::f64::consts::PI;
+use crate::arithmetic::consts::PI;
+use crate::arena::*;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::intersections::*;
 use crate::matrix::*;
 use crate::tuple::*;
@@ -13,7 +15,7 @@ use crate::shape::*;
 #[derive(Clone, Debug)]
 pub struct Group
 {
-    pub child_shapes: Vec<Shape>,
+    pub arena: ShapeArena,
 }
 
 // A collection of other Shapes
@@ -21,29 +23,28 @@ impl Group
 {
     pub fn new() -> Self
     {
-        Group{child_shapes: Vec::new()}
+        Group{arena: ShapeArena::new()}
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_intersect(&self, ray: Ray) -> Vec<Intersection<'_>>
     {
-        let mut xs = Vec::<(f64, f64, f64)>::new();
-        for shape in &self.child_shapes
-        {
-            let mut child_shape = shape.clone();
-            let intersections = child_shape.intersect(ray);
-            for tuv in intersections
-            {
-                xs.push(tuv);
-            }
-        }
-        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        return xs;
+        self.arena.intersect_children(self.arena.get_roots(), None, ray)
     }
 
-    pub fn local_normal_at(&self, point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
         create_vector(0.0, 0.0, 1.0)
     }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        self.arena.children_bounds(self.arena.get_roots())
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        self.arena.children_bounding_sphere(self.arena.get_roots())
+    }
 }
 
 impl fmt::Display for Group
@@ -52,9 +53,9 @@ impl fmt::Display for Group
     {
         let mut separator = "";
         write!(f, "[")?;
-        for child in &self.child_shapes
+        for &child in self.arena.get_roots()
         {
-            write!(f, "{}{}", separator, child)?;
+            write!(f, "{}{}", separator, self.arena.extract(child))?;
             separator = ", ";
         }
         write!(f, "]")
@@ -79,8 +80,12 @@ mod tests
     fn test_group_feature2()
     {
         // p.195 Scenario: A shape has a parent attribute
-        let s2 = Shape::test_shape(2);
-        assert!(s2.get_parent().is_none());
+        let mut group2 = Shape::new_group(2);
+        let s2 = group2.add_child(Shape::test_shape(2));
+        assert!(group2.get_arena().unwrap().get_parent(s2).is_none());
+        let g21 = group2.add_child(Shape::new_group(21));
+        let s22 = group2.add_child_to(g21, Shape::test_shape(22));
+        assert_eq!(group2.get_arena().unwrap().get_parent(s22), Some(g21));
     }
 
     #[test]
@@ -88,14 +93,13 @@ mod tests
     {
         // p.195 Scenario: Adding a child to a group
         let mut group3 = Shape::new_group(3);
-        let mut s3 = Shape::test_shape(3);
-        group3.add_child(&mut s3);
-        let mut s4 = Shape::test_shape(4);
+        let s3 = Shape::test_shape(3);
+        let id3 = group3.add_child(s3.clone());
+        let s4 = Shape::test_shape(4);
         assert!(group3.get_children().contains(&s3));
         assert!(!group3.get_children().contains(&s4));
-        assert!(!s3.get_parent().is_none());
-        assert_eq!(*s3.get_parent().unwrap(), group3);
-        assert!(s4.get_parent().is_none());
+        assert_eq!(*group3.get_descendant(id3), s3);
+        assert_eq!(group3.get_descendant_parents(id3), vec![&group3]);
     }
 
     #[test]
@@ -119,9 +123,9 @@ mod tests
         s52.set_transform(Matrix::translation(0.0, 0.0, -3.0));
         let mut s53 = Shape::test_shape(53);
         s53.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-        group5.add_child(&mut s51);
-        group5.add_child(&mut s52);
-        group5.add_child(&mut s53);
+        group5.add_child(s51);
+        group5.add_child(s52);
+        group5.add_child(s53);
         let r5 = Ray::new(create_point(0.0, 0.0, -5.0),
             create_vector(0.0, 0.0, 1.0));
         let xs5 = group5.intersect(r5);
@@ -136,7 +140,7 @@ mod tests
         group6.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
         let mut s6 = Shape::new_sphere(61);
         s6.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-        group6.add_child(&mut s6);
+        group6.add_child(s6);
         let r6 = Ray::new(create_point(10.0, 0.0, -10.0),
             create_vector(0.0, 0.0, 1.0));
         let xs6 = group6.intersect(r6);
@@ -151,11 +155,13 @@ mod tests
         let mut group72 = Shape::new_group(72);
         group71.set_transform(Matrix::rotation_y(PI / 2.0));
         group72.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
-        group71.add_child(&mut group72);
+        let id72 = group71.add_child(group72);
         let mut s73 = Shape::new_sphere(73);
         s73.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-        group72.add_child(&mut s73);
-        let p7 = s73.world_to_object(create_point(-2.0, 0.0, -10.0));
+        let id73 = group71.add_child_to(id72, s73);
+        let parents = group71.get_descendant_parents(id73);
+        let p7 = group71.get_descendant(id73)
+            .world_to_object_in(&parents, create_point(-2.0, 0.0, -10.0), 0.0);
         assert_eq!(p7, create_point(0.0, 0.0, -1.0));
     }
 
@@ -167,13 +173,14 @@ mod tests
         let mut group82 = Shape::new_group(82);
         group81.set_transform(Matrix::rotation_y(PI / 2.0));
         group82.set_transform(Matrix::scaling(1.0, 2.0, 3.0));
-        group81.add_child(&mut group82);
+        let id82 = group81.add_child(group82);
         let mut s83 = Shape::new_sphere(83);
         s83.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-        group82.add_child(&mut s83);
-        let sqrt3 = 3.0_f64.sqrt();
-        let n8 = s83.normal_to_world(create_point(sqrt3 / 3.0,
-            sqrt3 / 3.0, sqrt3 / 3.0));
+        let id83 = group81.add_child_to(id82, s83);
+        let sqrt3 = Scalar::sqrt(3.0);
+        let parents = group81.get_descendant_parents(id83);
+        let n8 = group81.get_descendant(id83).normal_to_world_in(&parents,
+            create_point(sqrt3 / 3.0, sqrt3 / 3.0, sqrt3 / 3.0));
         assert!(n8.approx_equal(create_vector(0.2857, 0.4286, -0.8571)));
     }
 
@@ -185,11 +192,37 @@ mod tests
         let mut group92 = Shape::new_group(92);
         group91.set_transform(Matrix::rotation_y(PI / 2.0));
         group92.set_transform(Matrix::scaling(1.0, 2.0, 3.0));
-        group91.add_child(&mut group92);
+        let id92 = group91.add_child(group92);
         let mut s93 = Shape::new_sphere(93);
         s93.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-        group92.add_child(&mut s93);
-        let n9 = s93.normal_at(create_point(1.7321, 1.1547, -5.5774), (0.0, 0.0));
+        let id93 = group91.add_child_to(id92, s93);
+        let parents = group91.get_descendant_parents(id93);
+        let n9 = group91.get_descendant(id93).normal_at_in(&parents,
+            create_point(1.7321, 1.1547, -5.5774), (0.0, 0.0), 0.0);
         assert!(n9.approx_equal(create_vector(0.2857, 0.4286, -0.8571)));
     }
+
+    #[test]
+    fn test_group_intersection_parents()
+    {
+        // Scenario: An intersection inside nested groups refers to the child
+        // and lists the groups containing it, even when their transforms
+        // were set after the children were added
+        let mut group1 = Shape::new_group(1);
+        let mut group2 = Shape::new_group(2);
+        let mut s3 = Shape::new_sphere(3);
+        s3.set_transform(Matrix::translation(5.0, 0.0, 0.0));
+        group2.add_child(s3.clone());
+        group2.set_transform(Matrix::scaling(1.0, 2.0, 3.0));
+        group1.add_child(group2.clone());
+        group1.set_transform(Matrix::rotation_y(PI / 2.0));
+        let r = Ray::new(create_point(1.7321, 1.1547, -20.0), create_vector(0.0, 0.0, 1.0));
+        let xs = group1.intersect(r);
+        assert_eq!(*xs[0].object, s3);
+        assert_eq!(xs[0].parents.len(), 2);
+        assert_eq!(*xs[0].parents[0], group1);
+        assert_eq!(*xs[0].parents[1], group2);
+        let n = xs[0].normal_at(create_point(1.7321, 1.1547, -5.5774));
+        assert!(n.approx_equal(create_vector(0.2857, 0.4286, -0.8571)));
+    }
 }
@@ -0,0 +1,100 @@
+use std::cell::Cell;
+use crate::arithmetic::*;
+use crate::tuple::*;
+
+// What a heat map shows for each pixel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HeatMetric
+{
+    // the number of shapes rays were tested against
+    Tests,
+    // the number of rays traced, including shadow rays
+    Rays,
+}
+
+// The work done by the current thread since the counts were last taken
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RayCounts
+{
+    pub rays: u64,
+    pub tests: u64,
+}
+
+impl RayCounts
+{
+    pub fn get(&self, metric: HeatMetric) -> u64
+    {
+        match metric
+        {
+            HeatMetric::Tests => self.tests,
+            HeatMetric::Rays => self.rays,
+        }
+    }
+}
+
+// Kept per thread, so that counting needs no locking and threads
+// rendering different pixels don't mix up their counts
+thread_local!
+{
+    static COUNTS: Cell<RayCounts> = Cell::new(RayCounts::default());
+}
+
+pub fn count_ray()
+{
+    COUNTS.with(|c| c.set(RayCounts{rays: c.get().rays + 1, ..c.get()}));
+}
+
+pub fn count_test()
+{
+    COUNTS.with(|c| c.set(RayCounts{tests: c.get().tests + 1, ..c.get()}));
+}
+
+// The counts so far, which start again from zero
+pub fn take_counts() -> RayCounts
+{
+    COUNTS.with(|c| c.replace(RayCounts::default()))
+}
+
+// A color going from black through blue, green and yellow to red as the
+// value goes from zero to the maximum
+pub fn heat_color(value: u64, max: u64) -> Tuple
+{
+    let stops = [create_color(0.0, 0.0, 0.0), create_color(0.0, 0.0, 1.0),
+        create_color(0.0, 1.0, 0.0), create_color(1.0, 1.0, 0.0), create_color(1.0, 0.0, 0.0)];
+    if max == 0
+    {
+        return stops[0];
+    }
+    let position = (value.min(max) as Scalar / max as Scalar) * (stops.len() - 1) as Scalar;
+    let i = (position.floor() as usize).min(stops.len() - 2);
+    let t = position - i as Scalar;
+    stops[i].add(stops[i + 1].sub(stops[i]).multiply(t))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_heatmap_feature()
+    {
+        // Scenario: Counts are taken and start again
+        take_counts();
+        count_ray();
+        count_test();
+        count_test();
+        let counts1 = take_counts();
+        assert_eq!(counts1, RayCounts{rays: 1, tests: 2});
+        assert_eq!(counts1.get(HeatMetric::Tests), 2);
+        assert_eq!(take_counts(), RayCounts::default());
+
+        // Scenario: Heat colors run from black to red
+        assert_eq!(heat_color(0, 8), create_color(0.0, 0.0, 0.0));
+        assert_eq!(heat_color(1, 8), create_color(0.0, 0.0, 0.5));
+        assert_eq!(heat_color(4, 8), create_color(0.0, 1.0, 0.0));
+        assert_eq!(heat_color(8, 8), create_color(1.0, 0.0, 0.0));
+        assert_eq!(heat_color(20, 8), create_color(1.0, 0.0, 0.0));
+        assert_eq!(heat_color(0, 0), create_color(0.0, 0.0, 0.0));
+    }
+}
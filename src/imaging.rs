@@ -0,0 +1,150 @@
+// Conversions between Canvas and the image crate's buffers, so renders
+// can be saved in other formats or post-processed with other crates.
+// 8-bit and 16-bit images are encoded, clamped and rounded as in PPM
+// output, so a 16-bit buffer saved as a PNG doesn't band, and 8-bit ones
+// are read as sRGB; 32-bit float images keep colors brighter than white.
+
+use image::{ImageBuffer, Rgb, Rgb32FImage, RgbImage};
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::tuple::*;
+
+impl From<&Canvas> for RgbImage
+{
+    fn from(canvas: &Canvas) -> Self
+    {
+        RgbImage::from_fn(canvas.width as u32, canvas.height as u32, |x, y|
+        {
+            let v = canvas.pixel_at(x as usize, y as usize).get_vec();
+            Rgb([to_byte(v[0], canvas.gamma), to_byte(v[1], canvas.gamma), to_byte(v[2], canvas.gamma)])
+        })
+    }
+}
+
+impl From<Canvas> for RgbImage
+{
+    fn from(canvas: Canvas) -> Self
+    {
+        RgbImage::from(&canvas)
+    }
+}
+
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+impl From<&Canvas> for Rgb16Image
+{
+    fn from(canvas: &Canvas) -> Self
+    {
+        let max_value = BitDepth::Sixteen.max_value();
+        Rgb16Image::from_fn(canvas.width as u32, canvas.height as u32, |x, y|
+        {
+            let v = canvas.pixel_at(x as usize, y as usize).get_vec();
+            Rgb([quantize(v[0], canvas.gamma, max_value), quantize(v[1], canvas.gamma, max_value),
+                quantize(v[2], canvas.gamma, max_value)])
+        })
+    }
+}
+
+impl From<&Canvas> for Rgb32FImage
+{
+    fn from(canvas: &Canvas) -> Self
+    {
+        Rgb32FImage::from_fn(canvas.width as u32, canvas.height as u32, |x, y|
+        {
+            let v = canvas.pixel_at(x as usize, y as usize).get_vec();
+            Rgb([v[0] as f32, v[1] as f32, v[2] as f32])
+        })
+    }
+}
+
+impl From<Canvas> for Rgb32FImage
+{
+    fn from(canvas: Canvas) -> Self
+    {
+        Rgb32FImage::from(&canvas)
+    }
+}
+
+impl From<&RgbImage> for Canvas
+{
+    fn from(image: &RgbImage) -> Self
+    {
+        let mut canvas = Canvas::new(image.width() as usize, image.height() as usize);
+        for (x, y, Rgb(p)) in image.enumerate_pixels()
+        {
+            let light = |value: u8| canvas.gamma.decode(Scalar::from(value) / 255.0);
+            let color = create_color(light(p[0]), light(p[1]), light(p[2]));
+            canvas.write_pixel(x as usize, y as usize, color);
+        }
+        canvas
+    }
+}
+
+impl From<RgbImage> for Canvas
+{
+    fn from(image: RgbImage) -> Self
+    {
+        Canvas::from(&image)
+    }
+}
+
+impl From<&Rgb32FImage> for Canvas
+{
+    fn from(image: &Rgb32FImage) -> Self
+    {
+        let mut canvas = Canvas::new(image.width() as usize, image.height() as usize);
+        for (x, y, Rgb(p)) in image.enumerate_pixels()
+        {
+            canvas.write_pixel(x as usize, y as usize, create_color(p[0] as Scalar, p[1] as Scalar,
+                p[2] as Scalar));
+        }
+        canvas
+    }
+}
+
+impl From<Rgb32FImage> for Canvas
+{
+    fn from(image: Rgb32FImage) -> Self
+    {
+        Canvas::from(&image)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_imaging_feature()
+    {
+        let mut canvas1 = Canvas::new(3, 2);
+        canvas1.write_pixel(0, 0, create_color(1.5, 0.0, 0.0));
+        canvas1.write_pixel(2, 1, create_color(0.0, 0.5, 1.0));
+
+        // Scenario: Converting a canvas to an 8-bit image encodes, clamps
+        // and rounds
+        let image1: RgbImage = (&canvas1).into();
+        assert_eq!(image1.dimensions(), (3, 2));
+        assert_eq!(*image1.get_pixel(0, 0), Rgb([255, 0, 0]));
+        assert_eq!(*image1.get_pixel(2, 1), Rgb([0, 188, 255]));
+        canvas1.gamma = Gamma::Linear;
+        assert_eq!(*RgbImage::from(&canvas1).get_pixel(2, 1), Rgb([0, 128, 255]));
+        canvas1.gamma = Gamma::Srgb;
+
+        // Scenario: A 16-bit image has the finer steps of 16-bit PPMs
+        let image3 = Rgb16Image::from(&canvas1);
+        assert_eq!(*image3.get_pixel(0, 0), Rgb([65535, 0, 0]));
+        assert_eq!(image3.get_pixel(2, 1)[1], quantize(0.5, Gamma::Srgb, 65535));
+
+        // Scenario: Converting an 8-bit image to a canvas decodes it
+        let canvas2 = Canvas::from(image1);
+        assert_eq!(canvas2.pixel_at(0, 0), create_color(1.0, 0.0, 0.0));
+        assert_eq!(canvas2.pixel_at(2, 1), create_color(0.0, Gamma::Srgb.decode(188.0 / 255.0), 1.0));
+
+        // Scenario: A float image keeps colors brighter than white
+        let image2: Rgb32FImage = canvas1.clone().into();
+        assert_eq!(*image2.get_pixel(0, 0), Rgb([1.5, 0.0, 0.0]));
+        assert_eq!(Canvas::from(&image2), canvas1);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::bounds::*;
+use crate::camera::*;
+use crate::canvas::*;
+use crate::matrix::*;
+use crate::pointlight::*;
+use crate::shape::*;
+use crate::world::*;
+
+// An image kept between renders of a world that is being edited. Edits
+// made through World::get_object_mut only cause the tiles of the image
+// where the edited objects (and their shadows) were or are now to be
+// rendered again.
+pub struct IncrementalRender
+{
+    canvas: Canvas,
+    // what the image was last rendered with
+    camera: Camera,
+    light: PointLight,
+    world_transform: Matrix,
+    layers: RenderLayers,
+    // the world space bounds of each object when it was last rendered
+    bounds: Vec<BoundingBox>,
+}
+
+impl IncrementalRender
+{
+    pub const TILE_SIZE: u16 = 16;
+
+    // Renders the whole image
+    pub fn new(camera: &Camera, world: &mut World) -> Self
+    {
+        world.take_changed();
+        let mut render = IncrementalRender{
+            canvas: Canvas::new(camera.hsize.into(), camera.vsize.into()),
+            camera: camera.clone(),
+            light: world.light,
+            world_transform: world.get_transform(),
+            layers: world.get_layers().clone(),
+            bounds: Self::object_bounds(world)};
+        render.render_rect(camera, world, [0, 0, camera.hsize, camera.vsize]);
+        render
+    }
+
+    pub fn get_canvas(&self) -> &Canvas
+    {
+        &self.canvas
+    }
+
+    // Brings the image up to date with the world, returning the number of
+    // tiles rendered again. Changing the camera in any way, moving the
+    // light or the whole world, changing the layers, adding or removing
+    // objects, or having
+    // any reflective or transparent objects (which can show an edited
+    // object anywhere) renders everything.
+    pub fn update(&mut self, camera: &Camera, world: &mut World) -> usize
+    {
+        let changed = world.update();
+        let bounds = Self::object_bounds(world);
+        let everything = *camera != self.camera ||
+            world.light.position != self.light.position ||
+            world.light.intensity != self.light.intensity ||
+            world.light.direction != self.light.direction ||
+            world.get_transform() != self.world_transform ||
+            *world.get_layers() != self.layers ||
+            bounds.len() != self.bounds.len() ||
+            (!changed.is_empty() && world.objects.iter().any(has_secondary_rays));
+
+        let tiles_x = (camera.hsize + Self::TILE_SIZE - 1) / Self::TILE_SIZE;
+        let tiles_y = (camera.vsize + Self::TILE_SIZE - 1) / Self::TILE_SIZE;
+        let mut dirty = vec![everything; usize::from(tiles_x) * usize::from(tiles_y)];
+        if everything
+        {
+            self.canvas = Canvas::new(camera.hsize.into(), camera.vsize.into());
+        }
+        else
+        {
+            for &i in &changed
+            {
+                for b in [&self.bounds[i], &bounds[i]]
+                {
+                    if let Some([x0, y0, x1, y1]) = camera.pixel_bounds(b, Some(&world.light))
+                    {
+                        for ty in y0 / Self::TILE_SIZE..=y1 / Self::TILE_SIZE
+                        {
+                            for tx in x0 / Self::TILE_SIZE..=x1 / Self::TILE_SIZE
+                            {
+                                dirty[usize::from(ty) * usize::from(tiles_x) + usize::from(tx)] = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut count = 0;
+        for ty in 0..tiles_y
+        {
+            for tx in 0..tiles_x
+            {
+                if dirty[usize::from(ty) * usize::from(tiles_x) + usize::from(tx)]
+                {
+                    let x = tx * Self::TILE_SIZE;
+                    let y = ty * Self::TILE_SIZE;
+                    self.render_rect(camera, world, [x, y,
+                        (x + Self::TILE_SIZE).min(camera.hsize), (y + Self::TILE_SIZE).min(camera.vsize)]);
+                    count += 1;
+                }
+            }
+        }
+        self.camera = camera.clone();
+        self.light = world.light;
+        self.world_transform = world.get_transform();
+        self.layers = world.get_layers().clone();
+        self.bounds = bounds;
+        count
+    }
+
+    fn object_bounds(world: &World) -> Vec<BoundingBox>
+    {
+        let transform = world.get_transform();
+        world.objects.iter().map(|o| o.parent_space_bounds().transform(&transform)).collect()
+    }
+
+    // Renders the pixels from the first column and row up to, but not
+    // including, the last
+    fn render_rect(&mut self, camera: &Camera, world: &World, rect: [u16; 4])
+    {
+        let mut worker = RenderWorker::new(camera, world);
+        for y in rect[1]..rect[3]
+        {
+            for x in rect[0]..rect[2]
+            {
+                let color = worker.render_pixel(x, y);
+                self.canvas.write_pixel(x.into(), y.into(), color);
+            }
+        }
+    }
+}
+
+// Whether a shape, or any shape inside it, reflects or refracts rays
+fn has_secondary_rays(shape: &Shape) -> bool
+{
+    let material = shape.get_material();
+    if material.reflective > 0.0 || material.transparency > 0.0
+    {
+        return true;
+    }
+    match shape.get_arena()
+    {
+        Some(arena) => arena.shapes().any(has_secondary_rays),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+    use crate::integrator::*;
+    use crate::tuple::*;
+
+    fn assert_same_image(a: &Canvas, b: &Canvas)
+    {
+        assert_eq!(a.pixels.len(), b.pixels.len());
+        for (p, q) in a.pixels.iter().zip(b.pixels.iter())
+        {
+            assert_eq!(p, q);
+        }
+    }
+
+    #[test]
+    fn test_incremental_feature()
+    {
+        // Scenario: Moving an object renders only the tiles around it
+        let mut world1 = World::default_world();
+        world1.light = PointLight::new(create_point(0.0, 0.0, -10.0), create_color(1.0, 1.0, 1.0));
+        let mut small = Shape::new_sphere(3);
+        small.set_transform(Matrix::translation(4.0, 2.0, 0.0).multiply(&Matrix::scaling(0.2, 0.2, 0.2)));
+        world1.objects.push(small);
+        let mut camera1 = Camera::new(64, 48, PI / 2.0);
+        camera1.transform = Matrix::view_transform(create_point(0.0, 0.0, -5.0),
+            create_point(0.0, 0.0, 0.0), create_vector(0.0, 1.0, 0.0));
+        let mut render1 = IncrementalRender::new(&camera1, &mut world1);
+        assert_eq!(render1.update(&camera1, &mut world1), 0);
+        world1.get_object_mut(2).set_transform(Matrix::translation(4.2, 2.0, 0.0)
+            .multiply(&Matrix::scaling(0.2, 0.2, 0.2)));
+        let tiles = render1.update(&camera1, &mut world1);
+        assert!(tiles > 0 && tiles < 12);
+        assert_same_image(render1.get_canvas(), IncrementalRender::new(&camera1, &mut world1).get_canvas());
+
+        // Scenario: Moving the light renders everything
+        world1.light = PointLight::new(create_point(-10.0, 10.0, -10.0), create_color(1.0, 1.0, 1.0));
+        assert_eq!(render1.update(&camera1, &mut world1), 12);
+        assert_same_image(render1.get_canvas(), IncrementalRender::new(&camera1, &mut world1).get_canvas());
+
+        // Scenario: Changing any other camera setting renders everything
+        camera1.set_field_of_view(PI / 3.0);
+        assert_eq!(render1.update(&camera1, &mut world1), 12);
+        assert_same_image(render1.get_canvas(), IncrementalRender::new(&camera1, &mut world1).get_canvas());
+        camera1.projection = Projection::Fisheye;
+        assert_eq!(render1.update(&camera1, &mut world1), 12);
+        camera1.set_integrator(NormalIntegrator);
+        assert_eq!(render1.update(&camera1, &mut world1), 12);
+        assert_eq!(render1.update(&camera1, &mut world1), 0);
+    }
+
+    #[test]
+    fn test_incremental_pixel_bounds()
+    {
+        // Scenario: The pixels a box covers, with and without its shadow
+        let camera1 = Camera::new(101, 101, PI / 2.0);
+        let box1 = BoundingBox::new_with_corners(create_point(-0.5, -0.5, -2.0), create_point(0.5, 0.5, -1.0));
+        assert_eq!(camera1.pixel_bounds(&box1, None), Some([23, 23, 77, 77]));
+        let light1 = PointLight::new(create_point(0.0, 0.0, 0.0), create_color(1.0, 1.0, 1.0));
+        assert_eq!(camera1.pixel_bounds(&box1, Some(&light1)), Some([23, 23, 77, 77]));
+        let light2 = PointLight::new(create_point(0.0, 5.0, 0.0), create_color(1.0, 1.0, 1.0));
+        assert_eq!(camera1.pixel_bounds(&box1, Some(&light2)), Some([23, 23, 77, 100]));
+
+        // Scenario: A box behind the camera covers no pixels
+        let box2 = BoundingBox::new_with_corners(create_point(-0.5, -0.5, 1.0), create_point(0.5, 0.5, 2.0));
+        assert_eq!(camera1.pixel_bounds(&box2, None), None);
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt;
+use crate::arithmetic::consts::PI;
+use crate::aov::*;
+use crate::arithmetic::*;
+use crate::ray::*;
+use crate::sampler::*;
+use crate::tuple::*;
+use crate::world::*;
+
+// A way of working out the light arriving along a ray, so that different
+// shading algorithms can render the same world
+pub trait Integrator: fmt::Debug + Send + Sync
+{
+    // The color seen along the ray, following at most depth bounces
+    fn li(&self, ray: Ray, world: &World, depth: i32) -> Tuple;
+
+    // Like li, also giving what the ray first hit. Integrators that find
+    // the hit themselves can give it instead of it being found again.
+    fn li_with_aovs(&self, ray: Ray, world: &World, depth: i32) -> (Tuple, Aovs)
+    {
+        (self.li(ray, world, depth), world.aovs_at(ray))
+    }
+}
+
+// The book's shading: direct light with hard shadows, plus mirror
+// reflection and refraction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WhittedIntegrator;
+
+impl Integrator for WhittedIntegrator
+{
+    fn li(&self, ray: Ray, world: &World, depth: i32) -> Tuple
+    {
+        world.color_at(ray, depth)
+    }
+
+    fn li_with_aovs(&self, ray: Ray, world: &World, depth: i32) -> (Tuple, Aovs)
+    {
+        world.color_at_with_aovs(ray, depth)
+    }
+}
+
+// Shows the surface normal at the first hit, with each component mapped
+// from -1..1 to a color channel, to check normals and smoothing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator
+{
+    fn li(&self, ray: Ray, world: &World, _depth: i32) -> Tuple
+    {
+        let xs = world.intersect_world(ray);
+        match xs.hit()
+        {
+            Some(hit) =>
+            {
+                let comps = hit.prepare_computations(ray, xs);
+                let n = comps.normalv.get_vec();
+                create_color((n[0] + 1.0) * 0.5, (n[1] + 1.0) * 0.5, (n[2] + 1.0) * 0.5)
+            },
+            None => create_color(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Shows the distance to the first hit, white close up fading to black at
+// the far distance and beyond
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DepthIntegrator
+{
+    pub far: Scalar,
+}
+
+impl Integrator for DepthIntegrator
+{
+    fn li(&self, ray: Ray, world: &World, _depth: i32) -> Tuple
+    {
+        match world.intersect_world(ray).hit()
+        {
+            Some(hit) =>
+            {
+                let distance = hit.t * ray.direction.magnitude();
+                let shade = (1.0 - distance / self.far).clamp(0.0, 1.0);
+                create_color(shade, shade, shade)
+            },
+            None => create_color(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Monte Carlo path tracing. At each hit the direct light is added as the
+// Whitted integrator does, without the ambient term, and the path goes on
+// one of the ways light can leave the surface: bounced off it in a random
+// direction, reflected or refracted, chosen at random in proportion to
+// the light each brings back. This follows light between surfaces, such
+// as the color bleeding from a red wall onto a white floor, which the
+// ambient term only fakes. Each ray averages the given number of paths,
+// which are noisy on their own, and each path follows at most the depth
+// of bounces it is asked for. Fog is not included.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PathIntegrator
+{
+    pub samples: u32,
+}
+
+impl PathIntegrator
+{
+    pub fn new() -> Self
+    {
+        PathIntegrator{samples: 16}
+    }
+
+    fn trace_path(&self, ray: Ray, world: &World, depth: i32, random: &mut Random) -> Tuple
+    {
+        let mut color = create_color(0.0, 0.0, 0.0);
+        let mut throughput = create_color(1.0, 1.0, 1.0);
+        let mut ray = ray;
+        let mut remaining = depth;
+        loop
+        {
+            let xs = world.intersect_world(ray);
+            let hit = match xs.hit()
+            {
+                Some(hit) => hit.clone(),
+                None =>
+                {
+                    color = color.add(self.limit(world.background.color_at(ray.direction)
+                        .hadamard_product(throughput), world, remaining < depth));
+                    break;
+                },
+            };
+            let comps = hit.prepare_computations_with(ray, xs, &world.get_tolerance());
+            let material = comps.object.get_material();
+            let surface = material.color_at(comps.object, comps.point);
+            let shadowed = world.occlusion_at(&world.light, comps.over_point, ray.time) >= 1.0;
+            let direct = material.lighting_with_ambient(surface, create_color(0.0, 0.0, 0.0), world.light,
+                comps.point, comps.eyev, comps.normalv, shadowed);
+            color = color.add(self.limit(direct.hadamard_product(throughput), world, remaining < depth));
+            if remaining <= 0
+            {
+                break;
+            }
+
+            // the shares of the light brought back by each way of leaving,
+            // divided as the Whitted integrator divides them
+            let reflectance = match material.reflective > 0.0 && material.transparency > 0.0
+            {
+                true => comps.schlick(),
+                false => 1.0,
+            };
+            let reflected = material.reflective * reflectance;
+            let refracted = material.transparency * match material.reflective > 0.0
+            {
+                true => 1.0 - reflectance,
+                false => 1.0,
+            };
+            let total = material.diffuse + reflected + refracted;
+            if !(total > 0.0)
+            {
+                break;
+            }
+            // a way picked with a chance of its share over the total
+            // brings back its light divided by that chance
+            let choice = random.next_scalar() * total;
+            let next = match choice < material.diffuse
+            {
+                true =>
+                {
+                    throughput = throughput.hadamard_product(surface);
+                    let (u, v) = (random.next_scalar(), random.next_scalar());
+                    Some(Ray::new_with_kind(comps.over_point, cosine_direction(comps.normalv, u, v),
+                        RayKind::Reflection).with_time(ray.time))
+                },
+                false if choice < material.diffuse + reflected => world.reflect_ray(&comps, remaining),
+                false => world.refract_ray(&comps, remaining).map(|r| match material.refraction_roughness > 0.0
+                {
+                    true => world.scatter_refraction(&comps, r, (random.next_scalar(), random.next_scalar())),
+                    false => r,
+                }),
+            };
+            ray = match next
+            {
+                Some(r) => r,
+                None => break,
+            };
+            throughput = throughput.multiply(total);
+            remaining -= 1;
+        }
+        color
+    }
+
+    fn limit(&self, light: Tuple, world: &World, indirect: bool) -> Tuple
+    {
+        match indirect
+        {
+            true => limit_color(light, world.get_sample_limits().max_indirect),
+            false => light,
+        }
+    }
+}
+
+impl Integrator for PathIntegrator
+{
+    fn li(&self, ray: Ray, world: &World, depth: i32) -> Tuple
+    {
+        // seeded from the ray, so the same ray always gives the same
+        // color however the image is split between threads
+        let mut random = Random::new(ray_seed(ray));
+        let samples = self.samples.max(1);
+        let mut sum = create_color(0.0, 0.0, 0.0);
+        for _ in 0..samples
+        {
+            sum = sum.add(self.trace_path(ray, world, depth, &mut random));
+        }
+        sum.multiply(1.0 / samples as Scalar)
+    }
+}
+
+// A direction about a normal, more likely the closer it is to the normal
+// as the light a matte surface scatters is, from two numbers from 0 to 1
+fn cosine_direction(normal: Tuple, u: Scalar, v: Scalar) -> Tuple
+{
+    let helper = match normal.to_array()[0].abs() > 0.9
+    {
+        true => create_vector(0.0, 1.0, 0.0),
+        false => create_vector(1.0, 0.0, 0.0),
+    };
+    let tangent = helper.cross_product(normal).normalize();
+    let bitangent = normal.cross_product(tangent);
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+    tangent.multiply(r * phi.cos()).add(bitangent.multiply(r * phi.sin()))
+        .add(normal.multiply((1.0 - u).max(0.0).sqrt())).normalize()
+}
+
+fn ray_seed(ray: Ray) -> u64
+{
+    let mut seed: u64 = 0;
+    for v in ray.origin.to_array().iter().chain(ray.direction.to_array().iter())
+    {
+        seed = (seed ^ v.to_bits() as u64).wrapping_mul(0x100000001b3);
+    }
+    seed
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::background::*;
+    use crate::material::*;
+    use crate::matrix::*;
+    use crate::pointlight::*;
+    use crate::shape::*;
+
+    #[test]
+    fn test_integrator_feature()
+    {
+        let world1 = World::default_world();
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let ray2 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 1.0, 0.0));
+
+        // Scenario: The Whitted integrator shades as color_at does
+        assert_eq!(WhittedIntegrator.li(ray1, &world1, 4), create_color(0.38066, 0.47583, 0.2855));
+
+        // Scenario: The normal integrator colors by the normal at the hit
+        assert_eq!(NormalIntegrator.li(ray1, &world1, 4), create_color(0.5, 0.5, 0.0));
+        assert_eq!(NormalIntegrator.li(ray2, &world1, 4), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: The depth integrator fades with distance
+        let depth = DepthIntegrator{far: 8.0};
+        assert_eq!(depth.li(ray1, &world1, 4), create_color(0.5, 0.5, 0.5));
+        assert_eq!(DepthIntegrator{far: 2.0}.li(ray1, &world1, 4), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: With no bounces, the path tracer sees only the direct
+        // light, without the ambient term
+        let path = PathIntegrator::new();
+        assert_eq!(path.li(ray1, &world1, 0), create_color(0.30066, 0.37583, 0.2255));
+        assert_eq!(path.li(ray2, &world1, 4), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: Paths are the same for the same ray
+        assert_eq!(path.li(ray1, &world1, 4), path.li(ray1, &world1, 4));
+
+        // Scenario: Bounced directions stay on the side of the normal
+        let normal5 = create_vector(0.0, 0.6, 0.8);
+        for (u, v) in [(0.0, 0.0), (0.5, 0.25), (0.999, 0.75)]
+        {
+            let d = cosine_direction(normal5, u, v);
+            assert!(fuzzy_equal(d.magnitude(), 1.0) && d.dot_product(normal5) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_integrator_path_feature()
+    {
+        // Scenario: Light bounced off a red wall tints a white floor that
+        // only the white sky lights
+        let mut floor = Shape::new_plane(1);
+        let mut white = Material::new();
+        white.specular = 0.0;
+        floor.set_material(white.clone());
+        let mut wall = Shape::new_cube(2);
+        wall.set_transform(Matrix::translation(2.0, 0.0, 0.0));
+        let mut red = white.clone();
+        red.color = create_color(1.0, 0.0, 0.0);
+        wall.set_material(red);
+        let dark = PointLight::new(create_point(0.0, 10.0, 0.0), create_color(0.0, 0.0, 0.0));
+        let mut world1 = World::new(dark, vec![floor.clone()]);
+        world1.background = Background::Color(create_color(1.0, 1.0, 1.0));
+        let mut world2 = World::new(dark, vec![floor, wall]);
+        world2.background = Background::Color(create_color(1.0, 1.0, 1.0));
+        let ray = Ray::new(create_point(0.8, 1.0, 0.0), create_vector(0.0, -1.0, 0.0));
+        let path = PathIntegrator{samples: 64};
+        let c1 = path.li(ray, &world1, 2).get_vec();
+        let c2 = path.li(ray, &world2, 2).get_vec();
+        assert!(fuzzy_equal(c1[0], c1[1]) && c1[1] > 0.5);
+        assert!(c2[0] > c2[1] + 0.1);
+        assert!(c2[1] < c1[1]);
+    }
+}
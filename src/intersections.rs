@@ -1,4 +1,6 @@
+use std::cell::{Cell, Ref, RefCell};
 use std::fmt;
+use std::ops::Deref;
 use crate::arithmetic::*;
 use crate::computations::*;
 use crate::matrix::*;
@@ -7,36 +9,139 @@ use crate::shape::*;
 use crate::sphere::*;
 use crate::tuple::*;
 
+// The (t, u, v) of each hit on a primitive shape, which never has more
+// than four. They are kept inline so that intersecting a primitive doesn't
+// allocate.
+#[derive(Copy, Clone, Debug)]
+pub struct LocalHits
+{
+    hits: [(Scalar, Scalar, Scalar); 4],
+    len: usize,
+}
+
+impl LocalHits
+{
+    pub fn new() -> Self
+    {
+        LocalHits{hits: [(0.0, 0.0, 0.0); 4], len: 0}
+    }
+
+    // Hits past the fourth are dropped
+    pub fn push(&mut self, hit: (Scalar, Scalar, Scalar))
+    {
+        if self.len < self.hits.len()
+        {
+            self.hits[self.len] = hit;
+            self.len += 1;
+        }
+    }
+
+    pub fn append(&mut self, other: &LocalHits)
+    {
+        for hit in other.iter()
+        {
+            self.push(*hit);
+        }
+    }
+}
+
+impl<const N: usize> From<[(Scalar, Scalar, Scalar); N]> for LocalHits
+{
+    fn from(hits: [(Scalar, Scalar, Scalar); N]) -> Self
+    {
+        let mut local_hits = LocalHits::new();
+        for hit in hits
+        {
+            local_hits.push(hit);
+        }
+        local_hits
+    }
+}
+
+impl Deref for LocalHits
+{
+    type Target = [(Scalar, Scalar, Scalar)];
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.hits[..self.len]
+    }
+}
+
+// An intersection refers to the shape that was hit rather than holding a
+// copy of it, so tracing a ray doesn't clone any shapes.
 #[derive(Clone, Debug)]
-pub struct Intersection
+pub struct Intersection<'a>
 {
-    pub t: f64,
-    pub object: Shape,
-    pub u: f64,
-    pub v: f64,
+    pub t: Scalar,
+    pub object: &'a Shape,
+    pub u: Scalar,
+    pub v: Scalar,
+    // the groups and CSG shapes containing the object, outermost first
+    pub parents: Vec<&'a Shape>,
 }
 
-impl Intersection
+impl<'a> Intersection<'a>
 {
-    pub fn new(t: f64, object: Shape) -> Self
+    pub fn new(t: Scalar, object: &'a Shape) -> Self
+    {
+        Intersection{t: t, object: object, u: 0.0, v: 0.0, parents: Vec::new()}
+    }
+
+    pub fn new_with_uv(t: Scalar, object: &'a Shape, u: Scalar, v: Scalar) -> Self
+    {
+        Intersection{t: t, object: object, u: u, v: v, parents: Vec::new()}
+    }
+
+    // The object is only visible to a ray if it and all the groups
+    // containing it are visible to that kind of ray.
+    pub fn is_visible_to(&self, kind: RayKind) -> bool
     {
-        Intersection{t: t, object: object, u: 0.0, v: 0.0}
+        self.object.get_visibility().allows(kind) &&
+            self.parents.iter().all(|p| p.get_visibility().allows(kind))
     }
 
-    pub fn new_with_uv(t: f64, object: Shape, u: f64, v: f64) -> Self
+    // Whether the object, or a group or CSG shape containing it, has one
+    // of the ids
+    pub fn involves(&self, ids: &[i32]) -> bool
     {
-        Intersection{t: t, object: object, u: u, v: v}
+        ids.contains(&self.object.get_id()) || self.parents.iter().any(|p| ids.contains(&p.get_id()))
     }
 
-    pub fn prepare_computations(&self, ray: Ray, intersections: Intersections) -> Computations
+    // The layer of the object, or of the innermost group containing it
+    // that has one
+    pub fn get_layer(&self) -> Option<&str>
+    {
+        self.object.get_layer().or_else(|| self.parents.iter().rev().find_map(|p| p.get_layer()))
+    }
+
+    pub fn normal_at(&self, world_point: Tuple) -> Tuple
+    {
+        self.normal_at_time(world_point, 0.0)
+    }
+
+    // Like normal_at, with moving shapes where they are at the time
+    pub fn normal_at_time(&self, world_point: Tuple, time: Scalar) -> Tuple
+    {
+        self.object.normal_at_in(&self.parents, world_point, (self.u, self.v), time)
+    }
+
+    pub fn prepare_computations(&self, ray: Ray, intersections: Intersections<'a>) -> Computations<'a>
+    {
+        self.prepare_computations_with(ray, intersections, &Tolerance::new())
+    }
+
+    // Like prepare_computations, with the over and under points moved off
+    // the surface by the tolerance's offset instead of EPSILON
+    pub fn prepare_computations_with(&self, ray: Ray, intersections: Intersections<'a>,
+        tolerance: &Tolerance) -> Computations<'a>
     {
         let mut n1 = 0.0;
         let mut n2 = 0.0;
-        let mut containers: Vec<Shape> = Vec::new();
-        for i in 0..intersections.count()
+        let mut containers: Vec<&Shape> = Vec::new();
+        for intersection in intersections.sorted().iter()
         {
-            let intersection = intersections.get_intersection(i);
-            if intersection.clone() == *self
+            if intersection == self
             {
                 n1 = match containers.last()
                 {
@@ -45,7 +150,7 @@ impl Intersection
                 }
             }
 
-            let m = containers.iter().position(|n| n == &intersection.object);
+            let m = containers.iter().position(|n| *n == intersection.object);
             match m
             {
                 Some(index) =>
@@ -55,12 +160,12 @@ impl Intersection
                 },
                 None =>
                 {
-                    containers.push(intersection.object.clone());
+                    containers.push(intersection.object);
                     ()
                 },
             }
 
-            if intersection.clone() == *self
+            if intersection == self
             {
                 n2 = match containers.last()
                 {
@@ -74,7 +179,7 @@ impl Intersection
         // precompute some useful values
         let point = ray.position(self.t);
         let eyev = ray.direction.negate();
-        let mut normalv = self.object.normal_at(point, (self.u, self.v));
+        let mut normalv = self.normal_at_time(point, ray.time);
         let inside: bool;
         if normalv.dot_product(eyev) < 0.0
         {
@@ -85,17 +190,37 @@ impl Intersection
         {
             inside = false;
         }
-        let over_point = point.add(normalv.multiply(EPSILON));
-        let under_point = point.sub(normalv.multiply(EPSILON));
+        let offset = tolerance.offset(&point.to_array()[..3]);
+        let over_point = point.add(normalv.multiply(offset));
+        let under_point = point.sub(normalv.multiply(offset));
+        // bumps only change the shading, so the points off the surface
+        // above keep to the true normal
+        if let Some(bump) = &self.object.get_material().bump
+        {
+            normalv = bump.perturb(self.object, point, normalv);
+        }
+        if let Some(map) = &self.object.get_material().normal_map
+        {
+            let uv = match self.object.texture_uv_at((self.u, self.v))
+            {
+                Some(uv) => uv,
+                None => map.mapping.map(self.object.world_to_object_in(&self.parents, point, ray.time)),
+            };
+            let tangent = self.object.tangent_at_in(&self.parents, point, ray.time);
+            normalv = map.perturb(normalv, tangent, uv);
+        }
 
         let reflectv = ray.direction.reflect(normalv);
-        Computations::new(self.t, self.object.clone(), point,
+        let mut comps = Computations::new(self.t, self.object, point,
             eyev, normalv, inside, over_point, under_point,
-            reflectv, n1, n2)
+            reflectv, n1, n2);
+        comps.texture_uv = self.object.texture_uv_at((self.u, self.v));
+        comps.time = ray.time;
+        comps
     }
 }
 
-impl PartialEq for Intersection
+impl PartialEq for Intersection<'_>
 {
     fn eq(&self, other: &Self) -> bool
     {
@@ -104,7 +229,7 @@ impl PartialEq for Intersection
 }
 
 
-impl fmt::Display for Intersection
+impl fmt::Display for Intersection<'_>
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
     {
@@ -112,42 +237,64 @@ impl fmt::Display for Intersection
     }
 }
 
+// The list is only sorted by t once it is enumerated, since finding the
+// hit, or whether anything is hit at all, doesn't need it in order.
 #[derive(Clone)]
-pub struct Intersections
+pub struct Intersections<'a>
 {
-    intersections: Vec<Intersection>,
+    intersections: RefCell<Vec<Intersection<'a>>>,
+    sorted: Cell<bool>,
 }
 
-impl Intersections
+impl<'a> Intersections<'a>
 {
-    pub fn new(intersections: Vec<Intersection>) -> Self
+    pub fn new(intersections: Vec<Intersection<'a>>) -> Self
     {
-        let mut unsorted = intersections;
-        unsorted.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
-        Intersections{intersections: unsorted}
+        Intersections{intersections: RefCell::new(intersections), sorted: Cell::new(false)}
+    }
+
+    // The intersections in order of t
+    pub fn sorted(&self) -> Ref<'_, Vec<Intersection<'a>>>
+    {
+        if !self.sorted.get()
+        {
+            self.intersections.borrow_mut().sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+            self.sorted.set(true);
+        }
+        self.intersections.borrow()
     }
 
     pub fn count(&self) -> usize
     {
-        self.intersections.len()
+        self.intersections.borrow().len()
     }
 
-    pub fn get_intersection(&self, index: usize) -> Intersection
+    pub fn get_intersection(&self, index: usize) -> Intersection<'a>
     {
-        self.intersections[index].clone()
+        self.sorted()[index].clone()
     }
 
-    pub fn hit(&self) -> Option<Intersection>
+    pub fn hit(&self) -> Option<Intersection<'a>>
     {
-        for i in 0..self.intersections.len()
+        // Return intersection with lowest non-negative t value. Of several
+        // with the same t, the first one is taken, as a sort would keep it.
+        let intersections = self.intersections.borrow();
+        let mut hit: Option<&Intersection<'a>> = None;
+        for i in intersections.iter().filter(|i| i.t >= 0.0)
         {
-            // Return intersection with lowest non-negative t value.
-            if self.intersections[i].t >= 0.0
+            if hit.map_or(true, |h| i.t < h.t)
             {
-                return Some(self.intersections[i].clone());
+                hit = Some(i);
             }
         }
-        return None;
+        hit.cloned()
+    }
+
+    // Whether any intersection lies in front of the ray's origin and
+    // closer than the distance, as when testing a shadow ray
+    pub fn any_hit_before(&self, distance: Scalar) -> bool
+    {
+        self.intersections.borrow().iter().any(|i| i.t >= 0.0 && i.t < distance)
     }
 }
 
@@ -161,23 +308,23 @@ mod tests
     {
         // p.63 Scenario: An intersection encapsulates t and object
         let s = Shape::new_sphere(1);
-        let i = Intersection::new(3.5, s.clone());
+        let i = Intersection::new(3.5, &s);
         assert_eq!(i.t, 3.5);
-        assert_eq!(i.object, s);
+        assert_eq!(*i.object, s);
 
         // p.64 Scenario: Aggregating intersections
         let s2 = Shape::new_sphere(2);
-        let i1 = Intersection::new(1.0, s2.clone());
-        let i2 = Intersection::new(2.0, s2.clone());
+        let i1 = Intersection::new(1.0, &s2);
+        let i2 = Intersection::new(2.0, &s2);
         let xs = Intersections::new(vec![i1.clone(), i2.clone()]);
         assert_eq!(xs.count(), 2);
-        assert_eq!(xs.get_intersection(0).object, s2.clone());
-        assert_eq!(xs.get_intersection(1).object, s2.clone());
+        assert_eq!(*xs.get_intersection(0).object, s2);
+        assert_eq!(*xs.get_intersection(1).object, s2);
 
         // p.65 Scenario: The hit, when all intersections have positive t
         let s3 = Shape::new_sphere(3);
-        let j31 = Intersection::new(1.0, s3.clone());
-        let j32 = Intersection::new(2.0, s3.clone());
+        let j31 = Intersection::new(1.0, &s3);
+        let j32 = Intersection::new(2.0, &s3);
         let xs3 = Intersections::new(vec![j31.clone(), j32.clone()]);
         let j3 = xs3.hit();
         match j3
@@ -188,8 +335,8 @@ mod tests
 
         // p.65 Scenario: The hit, when some intersections have negative t
         let s4 = Shape::new_sphere(4);
-        let j41 = Intersection::new(-1.0, s4.clone());
-        let j42 = Intersection::new(1.0, s4.clone());
+        let j41 = Intersection::new(-1.0, &s4);
+        let j42 = Intersection::new(1.0, &s4);
         let xs4 = Intersections::new(vec![j41.clone(), j42.clone()]);
         let j4 = xs4.hit();
         match j4
@@ -200,8 +347,8 @@ mod tests
 
         // p.65 Scenario: The hit, when some intersections have negative t
         let s5 = Shape::new_sphere(5);
-        let j51 = Intersection::new(-2.0, s5.clone());
-        let j52 = Intersection::new(-1.0, s5.clone());
+        let j51 = Intersection::new(-2.0, &s5);
+        let j52 = Intersection::new(-1.0, &s5);
         let xs5 = Intersections::new(vec![j51.clone(), j52.clone()]);
         let j5 = xs5.hit();
         match j5
@@ -212,10 +359,10 @@ mod tests
 
         // p.66 Scenario: The hit is always the lowest non-negative intersection
         let s6 = Shape::new_sphere(6);
-        let j61 = Intersection::new(5.0, s6.clone());
-        let j62 = Intersection::new(7.0, s6.clone());
-        let j63 = Intersection::new(-3.0, s6.clone());
-        let j64 = Intersection::new(2.0, s6.clone());
+        let j61 = Intersection::new(5.0, &s6);
+        let j62 = Intersection::new(7.0, &s6);
+        let j63 = Intersection::new(-3.0, &s6);
+        let j64 = Intersection::new(2.0, &s6);
         let xs6 = Intersections::new(vec![j61.clone(), j62.clone(), j63.clone(), j64.clone()]);
         let j6 = xs6.hit();
         match j6
@@ -223,6 +370,42 @@ mod tests
             Some(x) => assert_eq!(x, j64),
             None => assert!(false),
         }
+
+        // Scenario: Intersections are in order of t when enumerated
+        assert_eq!(xs6.get_intersection(0), j63);
+        assert_eq!(xs6.get_intersection(3), j62);
+        assert_eq!(xs6.hit(), Some(j64));
+    }
+
+    #[test]
+    fn test_intersections_any_hit()
+    {
+        // Scenario: Only intersections in front of the origin and closer than
+        // the distance count as a hit
+        let s = Shape::new_sphere(1);
+        let xs = Intersections::new(vec![Intersection::new(4.0, &s),
+            Intersection::new(-1.0, &s)]);
+        assert!(xs.any_hit_before(5.0));
+        assert!(!xs.any_hit_before(4.0));
+        assert!(!Intersections::new(vec![]).any_hit_before(5.0));
+    }
+
+    #[test]
+    fn test_intersections_local_hits()
+    {
+        // Scenario: Hits on a primitive are collected without a Vec
+        let mut hits = LocalHits::from([(1.0, 0.0, 0.0), (2.0, 0.5, 0.5)]);
+        assert_eq!(hits.len(), 2);
+        hits.append(&LocalHits::from([(3.0, 0.0, 0.0)]));
+        hits.push((4.0, 0.0, 0.0));
+        assert_eq!(hits.iter().map(|h| h.0).collect::<Vec<Scalar>>(), vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(hits[1], (2.0, 0.5, 0.5));
+        assert!(LocalHits::new().is_empty());
+
+        // Scenario: Hits past the fourth are dropped
+        hits.push((5.0, 0.0, 0.0));
+        assert_eq!(hits.len(), 4);
+        assert_eq!(hits[3], (4.0, 0.0, 0.0));
     }
 
     #[test]
@@ -232,10 +415,22 @@ mod tests
         let r1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let mut shape1 = Shape::new_sphere(1);
         shape1.set_transform(Matrix::translation(0.0, 0.0, 1.0));
-        let i1 = Intersection::new(5.0, shape1);
+        let i1 = Intersection::new(5.0, &shape1);
         let comps1 = i1.prepare_computations(r1, Intersections::new(vec![i1.clone()]));
         assert!(comps1.over_point.get_vec()[2] < -EPSILON / 2.0);
         assert!(comps1.point.get_vec()[2] > comps1.over_point.get_vec()[2]);
+
+        // Scenario: Far from the origin the offset grows with the coordinates
+        let r2 = Ray::new(create_point(0.0, 0.0, 1e7 - 5.0), create_vector(0.0, 0.0, 1.0));
+        let mut shape2 = Shape::new_sphere(2);
+        shape2.set_transform(Matrix::translation(0.0, 0.0, 1e7 + 1.0));
+        let i2 = Intersection::new(5.0, &shape2);
+        let tolerance2 = Tolerance{absolute: EPSILON, relative: 1e-6};
+        let comps2 = i2.prepare_computations_with(r2, Intersections::new(vec![i2.clone()]), &tolerance2);
+        assert!(comps2.point.get_vec()[2] - comps2.over_point.get_vec()[2] > 5.0);
+        assert!(tolerance2.equal(1e7, 1e7 + 5.0));
+        assert!(!tolerance2.equal(1.0, 1.001));
+        assert_eq!(Tolerance::new().offset(&[1.0, -2.0, 3.0]), EPSILON);
     }
 
     #[test]
@@ -245,7 +440,7 @@ mod tests
         let r1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let mut shape1 = Shape::glass_sphere(1);
         shape1.set_transform(Matrix::translation(0.0, 0.0, 1.0));
-        let i1 = Intersection::new(5.0, shape1);
+        let i1 = Intersection::new(5.0, &shape1);
         let xs1 = Intersections::new(vec![i1.clone()]);
         let comps1 = i1.prepare_computations(r1, xs1);
         assert!(comps1.under_point.get_vec()[2] > EPSILON / 2.0);
@@ -1,6 +1,17 @@
+mod accel;
+mod aov;
+mod animation;
+mod arena;
 mod arithmetic;
+mod background;
+mod bounds;
+mod bump;
+mod fog;
 mod tuple;
+mod transform;
 mod canvas;
+mod cli;
+mod colors;
 mod sphere;
 mod matrix;
 mod ray;
@@ -12,16 +23,46 @@ mod world;
 mod camera;
 mod shape;
 mod plane;
+mod metaballs;
+mod normalmap;
+mod noise;
 mod pattern;
+mod postprocess;
+mod quadric;
+mod quaternion;
 mod cube;
 mod cylinder;
+mod debugview;
+mod disc;
 mod cone;
+mod denoise;
+mod csg;
 mod group;
+#[cfg(test)]
+mod golden;
+mod heatmap;
+mod incremental;
+mod integrator;
 mod triangle;
 mod smoothtriangle;
 mod objfile;
+mod mtlfile;
+mod objwriter;
+mod objcache;
+mod yaml;
+mod scene;
+mod sampler;
+mod texturemap;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "image")]
+mod imaging;
 
-use std::f64::consts::PI;
+use std::env;
+use std::fs;
+use std::process;
+use crate::arithmetic::consts::PI;
+use crate::arithmetic::*;
 use crate::tuple::*;
 use crate::canvas::*;
 use crate::sphere::*;
@@ -33,6 +74,7 @@ use crate::computations::*;
 use crate::world::*;
 use crate::camera::*;
 use crate::matrix::*;
+use crate::transform::*;
 use crate::shape::*;
 use crate::plane::*;
 use crate::pattern::*;
@@ -42,30 +84,31 @@ use crate::cone::*;
 use crate::group::*;
 use crate::smoothtriangle::*;
 use crate::objfile::*;
+use crate::accel::*;
+use crate::cli::*;
+use crate::scene::*;
 
 fn hexagon_corner(id: i32) -> Shape
 {
     let mut corner = Shape::new_sphere(id);
-    corner.set_transform(Matrix::translation(0.0, 0.0, -1.0)
-        .multiply(&Matrix::scaling(0.25, 0.25, 0.25)));
+    corner.set_transform(Transform::identity().scale(0.25, 0.25, 0.25).translate(0.0, 0.0, -1.0).into());
     return corner;
 }
 
 fn hexagon_edge(id: i32) -> Shape
 {
     let mut edge = Shape::new_cylinder(id, true, 0.0, 1.0);
-    edge.set_transform(Matrix::translation(0.0, 0.0, -1.0)
-        .multiply(&Matrix::rotation_y(-PI / 6.0))
-        .multiply(&Matrix::rotation_z(-PI / 2.0))
-        .multiply(&Matrix::scaling(0.25, 1.0, 0.25)));
+    // from this side's corner to the next one round the hexagon
+    let next_corner = Matrix::rotation_y(-PI / 3.0).multiply_tuple(create_point(0.0, 0.0, -1.0));
+    edge.set_transform(Transform::between(create_point(0.0, 0.0, -1.0), next_corner, 0.25).into());
     return edge;
 }
 
 fn hexagon_side(id: i32) -> Shape
 {
     let mut side = Shape::new_group(id);
-    side.add_child(&mut hexagon_corner(id + 1));
-    side.add_child(&mut hexagon_edge(id + 2));
+    side.add_child(hexagon_corner(id + 1));
+    side.add_child(hexagon_edge(id + 2));
     return side;
 }
 
@@ -75,8 +118,8 @@ fn hexagon(id: i32) -> Shape
     for n in 0..6
     {
         let mut side = hexagon_side(id + (n + 1) * 10);
-        side.set_transform(Matrix::rotation_y(f64::from(n) * PI / 3.0));
-        hex.add_child(&mut side);
+        side.set_transform(Matrix::rotation_y(n as Scalar * PI / 3.0));
+        hex.add_child(side);
     }
     let mut material = Material::new();
     material.color = create_color(0.8, 0.1, 0.8);
@@ -120,20 +163,20 @@ fn pyramid(id: i32) -> Shape
     let mut triangle6 = Shape::new_triangle(id + 6, p4, p1, p5);
     triangle6.set_material(material.clone());
 
-    pyramid.add_child(&mut triangle1);
-    pyramid.add_child(&mut triangle2);
-    pyramid.add_child(&mut triangle3);
-    pyramid.add_child(&mut triangle4);
-    pyramid.add_child(&mut triangle5);
-    pyramid.add_child(&mut triangle6);
+    pyramid.add_child(triangle1);
+    pyramid.add_child(triangle2);
+    pyramid.add_child(triangle3);
+    pyramid.add_child(triangle4);
+    pyramid.add_child(triangle5);
+    pyramid.add_child(triangle6);
     pyramid.set_material(material.clone());
 
     return pyramid;
 }
 
-fn main()
+// p.107 Chapter 7, Putting It Together
+fn demo_scene() -> (World, Camera)
 {
-    // p.107 Chapter 7, Putting It Together
 
     // 1. The floor is a plane with a matte texture and stripe pattern.
     let mut floor = Shape::new_plane(1);
@@ -162,9 +205,7 @@ fn main()
 
     // 5. The smaller green sphere on the right is scalled in half.
     let mut right_sphere = Shape::new_sphere(5);
-    let right_sphere_translation = Matrix::translation(1.5, 0.5, -0.5);
-    let right_sphere_scaling = Matrix::scaling(0.5, 0.5, 0.5);
-    right_sphere.set_transform(right_sphere_translation.multiply(&right_sphere_scaling));
+    right_sphere.set_transform(Transform::identity().scale(0.5, 0.5, 0.5).translate(1.5, 0.5, -0.5).into());
     let mut right_sphere_material = Material::new();
     right_sphere_material.color = create_color(0.5, 1.0, 0.1);
     right_sphere_material.diffuse = 0.7;
@@ -176,9 +217,7 @@ fn main()
 
     // 6. The smallest sphere is scaled by a third, before being translated.
     let mut left_sphere = Shape::new_sphere(6);
-    let left_sphere_translation = Matrix::translation(-1.5, 0.33, -0.75);
-    let left_sphere_scaling = Matrix::scaling(0.33, 0.33, 0.33);
-    left_sphere.set_transform(left_sphere_translation.multiply(&left_sphere_scaling));
+    left_sphere.set_transform(Transform::identity().scale(0.33, 0.33, 0.33).translate(-1.5, 0.33, -0.75).into());
     let mut left_sphere_material = Material::new();
     left_sphere_material.color = create_color(1.0, 0.8, 0.1);
     left_sphere_material.diffuse = 0.7;
@@ -187,10 +226,11 @@ fn main()
 
     // 7. A cube with high z value, far in the background (to test that shape too)
     let mut cube = Shape::new_cube(7);
-    let cube_translation = Matrix::translation(4.0, 1.0, 9.0);
-    let cube_scaling = Matrix::scaling(0.66, 0.66, 0.66);
-    let cube_rotation = Matrix::rotation_x(PI / 4.0);
-    cube.set_transform(cube_translation.multiply(&cube_rotation.multiply(&cube_scaling)));
+    cube.set_transform(Transform::identity()
+        .scale(0.66, 0.66, 0.66)
+        .rotate_x(PI / 4.0)
+        .translate(4.0, 1.0, 9.0)
+        .into());
     let mut cube_material = Material::new();
     cube_material.color = create_color(0.8, 0.1, 0.1);
     cube_material.diffuse = 0.5;
@@ -198,9 +238,7 @@ fn main()
 
     // 8. A cylinder with high z value, far in the background (to test that shape too)
     let mut cylinder = Shape::new_cylinder(8, true, 0.0, 2.0);
-    let cylinder_translation = Matrix::translation(-6.0, 0.707, 9.0);
-    let cylinder_rotation = Matrix::rotation_x(-PI / 4.0);
-    cylinder.set_transform(cylinder_translation.multiply(&cylinder_rotation));
+    cylinder.set_transform(Transform::identity().rotate_x(-PI / 4.0).translate(-6.0, 0.707, 9.0).into());
     let mut cylinder_material = Material::new();
     cylinder_material.color = create_color(0.6, 0.6, 0.6);
     cylinder_material.ambient = 0.2;
@@ -211,9 +249,9 @@ fn main()
 
     // A group of objects making up a hexagon
     let mut hex1 = hexagon(9);
-    hex1.set_transform(Matrix::translation(5.0, 1.0, 4.0).multiply(&Matrix::rotation_x(-PI / 4.0)));
+    hex1.set_transform(Transform::identity().rotate_x(-PI / 4.0).translate(5.0, 1.0, 4.0).into());
     let mut hex2 = hexagon(99);
-    hex2.set_transform(Matrix::translation(-5.4, 1.0, 2.0).multiply(&Matrix::rotation_x(-PI / 4.0)));
+    hex2.set_transform(Transform::identity().rotate_x(-PI / 4.0).translate(-5.4, 1.0, 2.0).into());
 
     let mut pyramid1 = pyramid(333);
     pyramid1.set_transform(Matrix::translation(3.0, 0.0, -1.0));
@@ -238,8 +276,70 @@ fn main()
     camera.transform = Matrix::view_transform(create_point(0.0, 1.5, -5.0),
         create_point(0.0, 1.0, 0.0),
         create_vector(0.0, 1.0, 0.0));
+    (world, camera)
+}
+
+fn load_scene(choice: &SceneChoice) -> Result<(World, Camera), String>
+{
+    match choice
+    {
+        SceneChoice::Demo => Ok(demo_scene()),
+        SceneChoice::File(path) =>
+        {
+            let scene = Scene::from_path(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            match scene.camera
+            {
+                Some(camera) => Ok((scene.world, camera)),
+                None => Err(format!("{}: the scene has no camera", path.display())),
+            }
+        },
+    }
+}
+
+fn run(options: &Options) -> Result<(), String>
+{
+    let (mut world, mut camera) = load_scene(&options.scene)?;
+    options.apply_to_camera(&mut camera);
+    options.apply_to_world(&mut world);
+    world.compile();
+    // a BVH keeps rays from testing every shape in big scenes
+    if world.get_acceleration() == Acceleration::None
+    {
+        world.set_acceleration(Acceleration::Bvh);
+    }
 
     // render the result to a canvas.
     let canvas = camera.render(world);
-    print!("{}", canvas.to_ppm());
+    match &options.output
+    {
+        Some(path) => fs::write(path, canvas.to_ppm()).map_err(|e| format!("{}: {}", path.display(), e)),
+        None =>
+        {
+            print!("{}", canvas.to_ppm());
+            Ok(())
+        },
+    }
+}
+
+fn main()
+{
+    let options = match Options::parse(env::args().skip(1))
+    {
+        Ok(options) => options,
+        Err(message) =>
+        {
+            eprint!("ray_tracer: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        },
+    };
+    if options.help
+    {
+        print!("{}", USAGE);
+        return;
+    }
+    if let Err(message) = run(&options)
+    {
+        eprintln!("ray_tracer: {}", message);
+        process::exit(1);
+    }
 }
@@ -1,5 +1,5 @@
 use std::fmt;
-use std::f64::consts::PI;
+use crate::arithmetic::consts::PI;
 use crate::tuple::*;
 use crate::arithmetic::*;
 
@@ -8,12 +8,12 @@ pub struct Matrix
 {
     rows: usize,
     columns: usize,
-    cells: Vec<Vec<f64>>,
+    cells: Vec<Vec<Scalar>>,
 }
 
 impl Matrix
 {
-    pub fn new(rows: usize, columns: usize, cell_values: &Vec<f64>) -> Self
+    pub fn new(rows: usize, columns: usize, cell_values: &Vec<Scalar>) -> Self
     {
         if rows * columns != cell_values.len()
         {
@@ -35,7 +35,7 @@ impl Matrix
         Matrix{rows: rows, columns: columns, cells: cells}
     }
 
-    pub fn at(&self, y : usize, x: usize) -> f64
+    pub fn at(&self, y : usize, x: usize) -> Scalar
     {
         self.cells[y][x]
     }
@@ -113,7 +113,7 @@ impl Matrix
         Matrix{rows: self.columns, columns: self.rows, cells: cells}
     }
 
-    pub fn determinant(&self) -> f64
+    pub fn determinant(&self) -> Scalar
     {
         if self.rows == 2 && self.columns == 2
         {
@@ -150,13 +150,13 @@ impl Matrix
         Matrix{rows: self.rows - 1, columns: self.columns - 1, cells: cells}
     }
 
-    pub fn minor(&self, row: usize, column: usize) -> f64
+    pub fn minor(&self, row: usize, column: usize) -> Scalar
     {
         let submatrix = self.submatrix(row, column);
         submatrix.determinant()
     }
 
-    pub fn cofactor(&self, row: usize, column: usize) -> f64
+    pub fn cofactor(&self, row: usize, column: usize) -> Scalar
     {
         let minor = self.minor(row, column);
         if ((row + column) % 2) == 0
@@ -194,7 +194,7 @@ impl Matrix
         m2
     }
 
-    pub fn translation(x: f64, y: f64, z: f64) -> Matrix
+    pub fn translation(x: Scalar, y: Scalar, z: Scalar) -> Matrix
     {
         let mut m = Matrix::identity(4);
         m.cells[0][3] = x;
@@ -203,7 +203,7 @@ impl Matrix
         m
     }
 
-    pub fn scaling(x: f64, y: f64, z: f64) -> Matrix
+    pub fn scaling(x: Scalar, y: Scalar, z: Scalar) -> Matrix
     {
         let mut m = Matrix::identity(4);
         m.cells[0][0] = x;
@@ -212,7 +212,7 @@ impl Matrix
         m
     }
 
-    pub fn rotation_x(r: f64) -> Matrix
+    pub fn rotation_x(r: Scalar) -> Matrix
     {
         let mut m = Matrix::identity(4);
         m.cells[1][1] = r.cos();
@@ -222,7 +222,7 @@ impl Matrix
         m
     }
 
-    pub fn rotation_y(r: f64) -> Matrix
+    pub fn rotation_y(r: Scalar) -> Matrix
     {
         let mut m = Matrix::identity(4);
         m.cells[0][0] = r.cos();
@@ -232,7 +232,7 @@ impl Matrix
         m
     }
 
-    pub fn rotation_z(r: f64) -> Matrix
+    pub fn rotation_z(r: Scalar) -> Matrix
     {
         let mut m = Matrix::identity(4);
         m.cells[0][0] = r.cos();
@@ -242,7 +242,29 @@ impl Matrix
         m
     }
 
-    pub fn shearing(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Matrix
+    // A rotation by an angle around an axis through the origin, turning
+    // the same way as rotation_x, rotation_y and rotation_z do around
+    // their axes
+    pub fn rotation_axis(axis: Tuple, r: Scalar) -> Matrix
+    {
+        let a = axis.normalize().to_array();
+        let (x, y, z) = (a[0], a[1], a[2]);
+        let (c, s) = (r.cos(), r.sin());
+        let t = 1.0 - c;
+        let mut m = Matrix::identity(4);
+        m.cells[0][0] = t * x * x + c;
+        m.cells[0][1] = t * x * y - s * z;
+        m.cells[0][2] = t * x * z + s * y;
+        m.cells[1][0] = t * x * y + s * z;
+        m.cells[1][1] = t * y * y + c;
+        m.cells[1][2] = t * y * z - s * x;
+        m.cells[2][0] = t * x * z - s * y;
+        m.cells[2][1] = t * y * z + s * x;
+        m.cells[2][2] = t * z * z + c;
+        m
+    }
+
+    pub fn shearing(xy: Scalar, xz: Scalar, yx: Scalar, yz: Scalar, zx: Scalar, zy: Scalar) -> Matrix
     {
         let mut m = Matrix::identity(4);
         m.cells[0][1] = xy;
@@ -344,7 +366,7 @@ pub fn matrix_from(cell_values: &str) -> Matrix
         let mut row = Vec::new();
         for n in line.split_whitespace()
         {
-            row.push(n.parse::<f64>().unwrap());
+            row.push(n.parse::<Scalar>().unwrap());
         }
         columns = row.len();
         cells.push(row);
@@ -645,7 +667,7 @@ mod tests
         let p1 = create_point(0.0, 1.0, 0.0);
         let half_quarter1 = Matrix::rotation_x(PI / 4.0);
         let full_quarter1 = Matrix::rotation_x(PI / 2.0);
-        let two = 2.0_f64;
+        let two: Scalar = 2.0;
         assert_eq!(half_quarter1.multiply_tuple(p1), create_point(0.0, two.sqrt() / 2.0, two.sqrt() / 2.0));
         assert_eq!(full_quarter1.multiply_tuple(p1), create_point(0.0, 0.0, 1.0));
 
@@ -666,6 +688,17 @@ mod tests
         let full_quarter3 = Matrix::rotation_z(PI / 2.0);
         assert_eq!(half_quarter3.multiply_tuple(p3), create_point(-two.sqrt() / 2.0, two.sqrt() / 2.0, 0.0));
         assert_eq!(full_quarter3.multiply_tuple(p3), create_point(-1.0, 0.0, 0.0));
+
+        // Scenario: Rotating around an axis matches the rotations around
+        // the x, y and z axes
+        assert_eq!(Matrix::rotation_axis(create_vector(2.0, 0.0, 0.0), PI / 3.0), Matrix::rotation_x(PI / 3.0));
+        assert_eq!(Matrix::rotation_axis(create_vector(0.0, 1.0, 0.0), PI / 3.0), Matrix::rotation_y(PI / 3.0));
+        assert_eq!(Matrix::rotation_axis(create_vector(0.0, 0.0, 1.0), PI / 3.0), Matrix::rotation_z(PI / 3.0));
+
+        // Scenario: Rotating a third of a turn around the diagonal swaps the axes
+        let diagonal4 = Matrix::rotation_axis(create_vector(1.0, 1.0, 1.0), 2.0 * PI / 3.0);
+        assert_eq!(diagonal4.multiply_tuple(create_point(1.0, 0.0, 0.0)), create_point(0.0, 1.0, 0.0));
+        assert_eq!(diagonal4.multiply_tuple(create_vector(0.0, 0.0, 1.0)), create_vector(1.0, 0.0, 0.0));
     }
 
     #[test]
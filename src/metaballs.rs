@@ -0,0 +1,253 @@
+use std::fmt;
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::tuple::*;
+use crate::ray::*;
+
+// A point whose influence fades smoothly to nothing at the radius
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Metaball
+{
+    pub center: Tuple,
+    pub radius: Scalar,
+    // negative strengths push the surface in instead of out
+    pub strength: Scalar,
+}
+
+impl Metaball
+{
+    pub fn new(center: Tuple, radius: Scalar) -> Self
+    {
+        Metaball{center, radius, strength: 1.0}
+    }
+
+    // The influence at a point, which is the strength at the center and
+    // falls to 0 at the radius: strength * (1 - d²/r²)²
+    fn field(&self, point: Tuple) -> Scalar
+    {
+        let offset = point.sub(self.center);
+        let x = offset.dot_product(offset) / (self.radius * self.radius);
+        if x >= 1.0
+        {
+            return 0.0;
+        }
+        self.strength * (1.0 - x) * (1.0 - x)
+    }
+}
+
+// The surface where the influences of the balls add up to the threshold.
+// Balls close together merge into one smooth blob. There is no formula
+// for where a ray meets the surface, so it is found by stepping along the
+// ray through the balls and narrowing down on each crossing.
+#[derive(Clone, Debug)]
+pub struct Metaballs
+{
+    pub balls: Vec<Metaball>,
+    pub threshold: Scalar,
+}
+
+impl Metaballs
+{
+    // how many steps are taken across the smallest ball
+    const STEPS_PER_BALL: Scalar = 32.0;
+    const REFINEMENTS: usize = 40;
+
+    pub fn new(balls: Vec<Metaball>, threshold: Scalar) -> Self
+    {
+        Metaballs{balls, threshold}
+    }
+
+    pub fn field(&self, point: Tuple) -> Scalar
+    {
+        self.balls.iter().map(|b| b.field(point)).sum()
+    }
+
+    // Every crossing along the ray, behind its origin as well as ahead of
+    // it, so that rays starting inside a blob know they are in it. Blobs
+    // can cross a ray more than the four times LocalHits holds.
+    pub fn local_intersect(&self, ray: Ray) -> Vec<(Scalar, Scalar, Scalar)>
+    {
+        let mut xs = Vec::new();
+        let speed = ray.direction.magnitude();
+        if self.balls.is_empty() || !(speed > 0.0)
+        {
+            return xs;
+        }
+        let smallest = self.balls.iter().map(|b| b.radius).fold(Scalar::INFINITY, Scalar::min);
+        let step = smallest / Self::STEPS_PER_BALL / speed;
+        let surface = |t: Scalar| self.field(ray.position(t)) - self.threshold;
+        for (start, end) in self.spans(ray)
+        {
+            let mut t0 = start;
+            let mut f0 = surface(t0);
+            while t0 < end
+            {
+                let t1 = (t0 + step).min(end);
+                let f1 = surface(t1);
+                if (f0 < 0.0) != (f1 < 0.0)
+                {
+                    xs.push((self.refine(&surface, t0, t1, f0 < 0.0), 0.0, 0.0));
+                }
+                t0 = t1;
+                f0 = f1;
+            }
+        }
+        xs
+    }
+
+    // The parts of the ray inside any ball, in order and without overlaps,
+    // since the field is 0 everywhere else
+    fn spans(&self, ray: Ray) -> Vec<(Scalar, Scalar)>
+    {
+        let mut spans: Vec<(Scalar, Scalar)> = self.balls.iter().filter_map(|b|
+        {
+            let offset = ray.origin.sub(b.center);
+            let a = ray.direction.dot_product(ray.direction);
+            let half_b = ray.direction.dot_product(offset);
+            let c = offset.dot_product(offset) - b.radius * b.radius;
+            let discriminant = half_b * half_b - a * c;
+            match discriminant > 0.0
+            {
+                true => Some(((-half_b - discriminant.sqrt()) / a, (-half_b + discriminant.sqrt()) / a)),
+                false => None,
+            }
+        }).collect();
+        spans.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut merged: Vec<(Scalar, Scalar)> = Vec::with_capacity(spans.len());
+        for span in spans
+        {
+            match merged.last_mut()
+            {
+                Some(last) if span.0 <= last.1 => last.1 = last.1.max(span.1),
+                _ => merged.push(span),
+            }
+        }
+        merged
+    }
+
+    // Halves the gap around a crossing until it is too small to matter
+    fn refine<F: Fn(Scalar) -> Scalar>(&self, surface: &F, t0: Scalar, t1: Scalar, outside_first: bool) -> Scalar
+    {
+        let (mut low, mut high) = (t0, t1);
+        for _ in 0..Self::REFINEMENTS
+        {
+            let middle = (low + high) / 2.0;
+            if (surface(middle) < 0.0) == outside_first
+            {
+                low = middle;
+            }
+            else
+            {
+                high = middle;
+            }
+        }
+        (low + high) / 2.0
+    }
+
+    // The field gets weaker going out through the surface, so the normal
+    // is the opposite of its gradient
+    pub fn local_normal_at(&self, point: Tuple, _hit_uv: (Scalar, Scalar)) -> Tuple
+    {
+        let mut gradient = create_vector(0.0, 0.0, 0.0);
+        for ball in &self.balls
+        {
+            let offset = point.sub(ball.center);
+            let r2 = ball.radius * ball.radius;
+            let x = offset.dot_product(offset) / r2;
+            if x < 1.0
+            {
+                gradient = gradient.add(offset.multiply(-4.0 * ball.strength * (1.0 - x) / r2));
+            }
+        }
+        gradient.negate().normalize()
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        let mut bounds = BoundingBox::new();
+        for ball in &self.balls
+        {
+            let r = create_vector(ball.radius, ball.radius, ball.radius);
+            bounds.add_point(ball.center.sub(r));
+            bounds.add_point(ball.center.add(r));
+        }
+        bounds
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::from_box(&self.bounds())
+    }
+}
+
+impl fmt::Display for Metaballs
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "metaballs {} {}", self.balls.len(), self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::intersections::*;
+    use crate::shape::*;
+
+    #[test]
+    fn test_metaballs_feature()
+    {
+        // Scenario: A single ball is a sphere inside its radius, where
+        // (1 - d²)² = 0.25, so d² = 0.5
+        let blob1 = Metaballs::new(vec![Metaball::new(create_point(0.0, 0.0, 0.0), 1.0)], 0.25);
+        let xs1 = blob1.local_intersect(Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0)));
+        let d = (0.5 as Scalar).sqrt();
+        assert_eq!(xs1.len(), 2);
+        assert!((xs1[0].0 - (5.0 - d)).abs() < 1e-6);
+        assert!((xs1[1].0 - (5.0 + d)).abs() < 1e-6);
+        assert_eq!(blob1.local_normal_at(create_point(0.0, 0.0, -d), (0.0, 0.0)), create_vector(0.0, 0.0, -1.0));
+        assert_eq!(blob1.local_intersect(Ray::new(create_point(0.0, 0.9, -5.0), create_vector(0.0, 0.0, 1.0))).len(), 0);
+
+        // Scenario: Two balls close together merge, filling the gap
+        // that would be between two spheres of the same size
+        let blob2 = Metaballs::new(vec![Metaball::new(create_point(-0.8, 0.0, 0.0), 1.0),
+            Metaball::new(create_point(0.8, 0.0, 0.0), 1.0)], 0.25);
+        let xs2 = blob2.local_intersect(Ray::new(create_point(-5.0, 0.0, 0.0), create_vector(1.0, 0.0, 0.0)));
+        assert_eq!(xs2.len(), 2);
+        assert!(0.8 > d && blob2.field(create_point(0.0, 0.0, 0.0)) > 0.25);
+        assert_eq!(blob2.local_intersect(Ray::new(create_point(0.0, -5.0, 0.0), create_vector(0.0, 1.0, 0.0))).len(), 2);
+
+        // Scenario: Balls far apart stay separate
+        let blob3 = Metaballs::new(vec![Metaball::new(create_point(-3.0, 0.0, 0.0), 1.0),
+            Metaball::new(create_point(3.0, 0.0, 0.0), 1.0)], 0.25);
+        let xs3 = blob3.local_intersect(Ray::new(create_point(-5.0, 0.0, 0.0), create_vector(2.0, 0.0, 0.0)));
+        assert_eq!(xs3.len(), 4);
+        assert!((xs3[0].0 - (2.0 - d) / 2.0).abs() < 1e-6);
+
+        // Scenario: The bounds cover every ball
+        let b4 = blob3.bounds();
+        assert_eq!(b4.min, create_point(-4.0, -1.0, -1.0));
+        assert_eq!(b4.max, create_point(4.0, 1.0, 1.0));
+
+        // Scenario: Balls behind the ray don't hide those in front of it
+        let blob5 = Metaballs::new(vec![Metaball::new(create_point(0.0, 0.0, -5.0), 1.0),
+            Metaball::new(create_point(0.0, 0.0, -10.0), 1.0), Metaball::new(create_point(0.0, 0.0, 5.0), 1.0)], 0.25);
+        let xs5 = blob5.local_intersect(Ray::new(create_point(0.0, 0.0, 0.0), create_vector(0.0, 0.0, 1.0)));
+        assert_eq!(xs5.len(), 6);
+        assert!((xs5[4].0 - (5.0 - d)).abs() < 1e-6);
+        assert!((xs5[5].0 - (5.0 + d)).abs() < 1e-6);
+
+        // Scenario: A ray starting inside a ball finds the way in behind it
+        // too, so it refracts out of the blob the way it would a sphere
+        let xs6 = blob1.local_intersect(Ray::new(create_point(0.0, 0.0, 0.0), create_vector(0.0, 0.0, 1.0)));
+        assert_eq!(xs6.len(), 2);
+        assert!((xs6[0].0 + d).abs() < 1e-6 && (xs6[1].0 - d).abs() < 1e-6);
+        let mut glass = Shape::new_metaballs(1, blob1.balls.clone(), 0.25);
+        glass.set_material(Shape::glass_sphere(2).get_material().clone());
+        let r6 = Ray::new(create_point(0.0, 0.0, 0.0), create_vector(0.0, 0.0, 1.0));
+        let xs6 = Intersections::new(glass.intersect(r6));
+        let comps6 = xs6.get_intersection(1).prepare_computations(r6, xs6.clone());
+        assert_eq!((comps6.n1, comps6.n2), (1.5, 1.0));
+    }
+}
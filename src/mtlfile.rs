@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use crate::arithmetic::*;
+use crate::tuple::*;
+use crate::material::*;
+use crate::objfile::*;
+
+// Builds a table of named materials from the lines of an MTL file
+struct MtlParser
+{
+    materials: HashMap<String, Material>,
+    current: Option<String>,
+}
+
+impl MtlParser
+{
+    fn new() -> Self
+    {
+        MtlParser{materials: HashMap::new(), current: None}
+    }
+
+    fn parse_numbers(line_number: usize, words: &[&str], count: usize) -> Result<Vec<Scalar>, ObjError>
+    {
+        if words.len() != count + 1
+        {
+            return Err(ObjError::Parse{line: line_number,
+                message: format!("'{}' expects {} values", words[0], count)});
+        }
+        let mut numbers = Vec::new();
+        for word in &words[1..]
+        {
+            match word.parse::<Scalar>()
+            {
+                Ok(n) => numbers.push(n),
+                Err(_) => return Err(ObjError::Parse{line: line_number,
+                    message: format!("invalid number '{}'", word)}),
+            }
+        }
+        Ok(numbers)
+    }
+
+    fn parse_line(&mut self, line_number: usize, line: &str) -> Result<(), ObjError>
+    {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        if words.len() < 2
+        {
+            return Ok(());
+        }
+        if words[0] == "newmtl"
+        {
+            let name = String::from(words[1]);
+            self.materials.insert(name.clone(), Material::new());
+            self.current = Some(name);
+            return Ok(());
+        }
+
+        let material = match &self.current
+        {
+            Some(name) => self.materials.get_mut(name).unwrap(),
+            None => return Ok(()),
+        };
+        // the book's material has a single ambient and specular value, so
+        // the average of the MTL's rgb values is used for these
+        match words[0]
+        {
+            "Kd" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 3)?;
+                material.color = create_color(n[0], n[1], n[2]);
+            },
+            "Ka" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 3)?;
+                material.ambient = (n[0] + n[1] + n[2]) / 3.0;
+            },
+            "Ks" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 3)?;
+                material.specular = (n[0] + n[1] + n[2]) / 3.0;
+            },
+            "Ns" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 1)?;
+                material.shininess = n[0];
+            },
+            "d" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 1)?;
+                material.transparency = 1.0 - n[0];
+            },
+            "Tr" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 1)?;
+                material.transparency = n[0];
+            },
+            "Ni" =>
+            {
+                let n = Self::parse_numbers(line_number, &words, 1)?;
+                material.refractive_index = n[0];
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+// Parses lines of an MTL file, skipping any lines that cannot be understood
+pub fn parse_mtl_file(lines: Vec<&str>) -> HashMap<String, Material>
+{
+    let mut parser = MtlParser::new();
+    for (index, line) in lines.iter().enumerate()
+    {
+        let _ = parser.parse_line(index + 1, line);
+    }
+    parser.materials
+}
+
+pub fn mtl_from_path<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Material>, ObjError>
+{
+    let file = File::open(path)?;
+    mtl_from_reader(file)
+}
+
+// Reads an MTL file, stopping at the first line that cannot be parsed
+pub fn mtl_from_reader<R: Read>(reader: R) -> Result<HashMap<String, Material>, ObjError>
+{
+    let mut parser = MtlParser::new();
+    for (index, line) in BufReader::new(reader).lines().enumerate()
+    {
+        parser.parse_line(index + 1, &line?)?;
+    }
+    Ok(parser.materials)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::*;
+
+    #[test]
+    fn test_mtlfile_feature()
+    {
+        // Scenario: Parsing named materials
+        let lines1 = vec![
+            "# two materials",
+            "newmtl red",
+            "Kd 1 0 0",
+            "Ka 0.3 0.3 0.3",
+            "Ks 0 0 0",
+            "Ns 50",
+            "",
+            "newmtl glass",
+            "Kd 0 0 0",
+            "d 0.1",
+            "Ni 1.5"];
+        let materials1 = parse_mtl_file(lines1);
+        assert_eq!(materials1.len(), 2);
+        let red = materials1.get("red").unwrap();
+        assert_eq!(red.color, create_color(1.0, 0.0, 0.0));
+        assert!(fuzzy_equal(red.ambient, 0.3));
+        assert!(fuzzy_equal(red.specular, 0.0));
+        assert!(fuzzy_equal(red.shininess, 50.0));
+        let glass = materials1.get("glass").unwrap();
+        assert!(fuzzy_equal(glass.transparency, 0.9));
+        assert!(fuzzy_equal(glass.refractive_index, 1.5));
+
+        // Scenario: A malformed value is reported with its line number
+        let text2 = "newmtl red\nKd 1 zero 0\n";
+        match mtl_from_reader(text2.as_bytes())
+        {
+            Err(ObjError::Parse{line, message: _}) => assert_eq!(line, 2),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
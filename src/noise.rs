@@ -0,0 +1,100 @@
+use crate::arithmetic::*;
+use crate::tuple::*;
+
+// Ken Perlin's improved gradient noise: smooth, repeatable and about -1
+// to 1, with features about a unit apart. It is 0 at every point with
+// whole coordinates.
+pub fn perlin(point: Tuple) -> Scalar
+{
+    let p = point.to_array();
+    let (xi, yi, zi) = (p[0].floor(), p[1].floor(), p[2].floor());
+    let (x, y, z) = (p[0] - xi, p[1] - yi, p[2] - zi);
+    let (xi, yi, zi) = (xi as i64, yi as i64, zi as i64);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    // the gradient at each corner of the unit cube around the point, dotted
+    // with the direction to the point
+    let corner = |dx: i64, dy: i64, dz: i64| grad(hash(xi + dx, yi + dy, zi + dz),
+        x - dx as Scalar, y - dy as Scalar, z - dz as Scalar);
+    lerp(w,
+        lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+        lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+}
+
+// Noise at octaves of doubling frequency and halving strength, each
+// folded to be positive, which gives the sharp creases of turbulence.
+// About 0 to 1.
+pub fn turbulence(point: Tuple, octaves: u8) -> Scalar
+{
+    let mut sum = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves
+    {
+        sum += perlin(point.multiply(frequency)).abs() / frequency;
+        frequency *= 2.0;
+    }
+    sum
+}
+
+// The curve 6t^5 - 15t^4 + 10t^3, which is flat at both ends so the
+// noise has no creases at the cube edges
+fn fade(t: Scalar) -> Scalar
+{
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: Scalar, a: Scalar, b: Scalar) -> Scalar
+{
+    a + t * (b - a)
+}
+
+// Scrambles the corner coordinates in place of Perlin's permutation table
+fn hash(x: i64, y: i64, z: i64) -> u32
+{
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b3c6d);
+    h ^= h >> 12;
+    h
+}
+
+// One of the twelve directions to the edges of a cube, picked by the hash
+fn grad(hash: u32, x: Scalar, y: Scalar, z: Scalar) -> Scalar
+{
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_noise_feature()
+    {
+        // Scenario: Noise is zero at whole coordinates
+        assert_eq!(perlin(create_point(0.0, 0.0, 0.0)), 0.0);
+        assert_eq!(perlin(create_point(3.0, -2.0, 7.0)), 0.0);
+        assert_eq!(turbulence(create_point(1.0, 2.0, 3.0), 1), 0.0);
+
+        // Scenario: Noise is repeatable, bounded and not constant
+        let samples: Vec<Scalar> = (0..200).map(|i| i as Scalar * 0.173)
+            .map(|t| perlin(create_point(t, t * 0.7 - 3.0, 1.3 - t * 0.4))).collect();
+        assert_eq!(samples[17], perlin(create_point(17.0 * 0.173, 17.0 * 0.173 * 0.7 - 3.0, 1.3 - 17.0 * 0.173 * 0.4)));
+        assert!(samples.iter().all(|n| n.abs() <= 1.0));
+        assert!(samples.iter().any(|n| *n > 0.1) && samples.iter().any(|n| *n < -0.1));
+
+        // Scenario: Noise is smooth
+        let p = create_point(0.37, 1.61, -2.2);
+        let nearby = create_point(0.3701, 1.61, -2.2);
+        assert!((perlin(p) - perlin(nearby)).abs() < 0.001);
+
+        // Scenario: Turbulence is positive and grows with octaves
+        let t1 = turbulence(p, 1);
+        let t4 = turbulence(p, 4);
+        assert!(t1 >= 0.0 && t4 >= t1);
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::texturemap::*;
+use crate::tuple::*;
+
+// An image of normals in tangent space, which replaces the normal of a
+// surface with the one in the image where the surface is hit. Red, green
+// and blue hold the x, y and z of the normal from -1 at 0 to 1 at 1, where
+// x runs along the u of the texture, y along its v and z out of the
+// surface, so a flat surface is (0.5, 0.5, 1). The image holds data
+// rather than colors, so it should be read without gamma.
+#[derive(Clone, Debug)]
+pub struct NormalMap
+{
+    pub image: Arc<Canvas>,
+    // how the shape's points are turned into (u, v), for shapes without
+    // texture coordinates of their own
+    pub mapping: UvMapping,
+}
+
+impl NormalMap
+{
+    pub fn new(image: Arc<Canvas>, mapping: UvMapping) -> Self
+    {
+        NormalMap{image, mapping}
+    }
+
+    // The normal from the image at (u, v), turned from tangent space to
+    // that of the surface normal and tangent. Without a tangent, or with
+    // one along the normal, any direction along the surface is used.
+    pub fn perturb(&self, normal: Tuple, tangent: Option<Tuple>, uv: (Scalar, Scalar)) -> Tuple
+    {
+        let c = image_at(&self.image, uv.0, uv.1).to_array();
+        let (x, y, z) = (2.0 * c[0] - 1.0, 2.0 * c[1] - 1.0, 2.0 * c[2] - 1.0);
+        let t = tangent_frame(normal, tangent);
+        let b = t.cross_product(normal);
+        t.multiply(x).add(b.multiply(y)).add(normal.multiply(z)).normalize()
+    }
+}
+
+// The tangent made a unit vector at right angles to the normal
+fn tangent_frame(normal: Tuple, tangent: Option<Tuple>) -> Tuple
+{
+    let along = |t: Tuple| t.sub(normal.multiply(t.dot_product(normal)));
+    match tangent.map(along)
+    {
+        Some(t) if t.magnitude() > EPSILON => t.normalize(),
+        _ =>
+        {
+            // whichever axis is furthest from the normal
+            let n = normal.to_array();
+            let axis = if n[0].abs() < 0.9 { create_vector(1.0, 0.0, 0.0) } else { create_vector(0.0, 1.0, 0.0) };
+            along(axis).normalize()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::intersections::*;
+    use crate::material::*;
+    use crate::matrix::*;
+    use crate::ray::*;
+    use crate::shape::*;
+    use crate::triangle::*;
+    use crate::arithmetic::consts::PI;
+
+    fn flat_image(color: Tuple) -> Arc<Canvas>
+    {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.write_pixel(0, 0, color);
+        Arc::new(canvas)
+    }
+
+    #[test]
+    fn test_normalmap_feature()
+    {
+        let up = create_vector(0.0, 1.0, 0.0);
+        let x = create_vector(1.0, 0.0, 0.0);
+
+        // Scenario: A flat normal map leaves the normal alone
+        let flat = NormalMap::new(flat_image(create_color(0.5, 0.5, 1.0)), UvMapping::Planar);
+        assert_eq!(flat.perturb(up, Some(x), (0.3, 0.6)), up);
+        assert_eq!(flat.perturb(up, None, (0.3, 0.6)), up);
+
+        // Scenario: The map's x, y and z run along u, v and the normal
+        let along_u = NormalMap::new(flat_image(create_color(1.0, 0.5, 0.5)), UvMapping::Planar);
+        assert_eq!(along_u.perturb(up, Some(x), (0.0, 0.0)), x);
+        let along_v = NormalMap::new(flat_image(create_color(0.5, 1.0, 0.5)), UvMapping::Planar);
+        assert_eq!(along_v.perturb(up, Some(x), (0.0, 0.0)), create_vector(0.0, 0.0, 1.0));
+        // a tangent that leans out of the surface is straightened
+        assert_eq!(along_u.perturb(up, Some(create_vector(2.0, 2.0, 0.0)), (0.0, 0.0)), x);
+
+        // Scenario: The tangents of spheres, planes and triangles follow u
+        let mut sphere = Shape::new_sphere(1);
+        assert_eq!(sphere.tangent_at_in(&[], create_point(0.0, 0.0, -1.0), 0.0), Some(x));
+        assert_eq!(sphere.tangent_at_in(&[], create_point(1.0, 0.0, 0.0), 0.0), Some(create_vector(0.0, 0.0, 1.0)));
+        sphere.set_transform(Matrix::rotation_y(PI / 2.0));
+        let rotated = sphere.tangent_at_in(&[], create_point(-1.0, 0.0, 0.0), 0.0).unwrap();
+        assert_eq!(rotated, create_vector(0.0, 0.0, -1.0));
+        let plane = Shape::new_plane(2);
+        assert_eq!(plane.tangent_at_in(&[], create_point(3.0, 0.0, 4.0), 0.0), Some(x));
+        let e1 = create_vector(0.0, 0.0, 1.0);
+        let e2 = create_vector(1.0, 0.0, 0.0);
+        assert_eq!(uv_tangent(e1, e2, None), e1);
+        assert_eq!(uv_tangent(e1, e2, Some([(0.0, 0.0), (0.0, 1.0), (1.0, 0.0)])), e2);
+        assert_eq!(Shape::new_cube(3).tangent_at_in(&[], create_point(1.0, 0.0, 0.0), 0.0), None);
+
+        // Scenario: Hits on a normal mapped shape use the mapped normal
+        let mut m = Material::new();
+        m.normal_map = Some(NormalMap::new(flat_image(create_color(1.0, 0.5, 1.0)), UvMapping::Planar));
+        let mut mapped = Shape::new_plane(4);
+        mapped.set_material(m);
+        let r = Ray::new(create_point(0.5, 1.0, 0.5), create_vector(0.0, -1.0, 0.0));
+        let i = Intersection::new(1.0, &mapped);
+        let comps = i.prepare_computations(r, Intersections::new(vec![i.clone()]));
+        assert_eq!(comps.normalv, create_vector(1.0, 1.0, 0.0).normalize());
+        assert_eq!(comps.over_point.to_array()[0], 0.5);
+    }
+}
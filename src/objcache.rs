@@ -0,0 +1,527 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use crate::arithmetic::*;
+use crate::material::*;
+use crate::objfile::*;
+use crate::shape::*;
+use crate::tuple::*;
+
+// Start of every cache file, followed by the format version. The version
+// changes whenever the layout does, so old caches are parsed again.
+const CACHE_MAGIC: &[u8; 4] = b"RTOC";
+const CACHE_VERSION: u32 = 1;
+
+// The size and modification time of a file, which must match for a cache
+// of it to be used
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct FileStamp
+{
+    length: u64,
+    seconds: u64,
+    nanos: u32,
+}
+
+fn file_stamp(path: &Path) -> io::Result<FileStamp>
+{
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(FileStamp{length: metadata.len(), seconds: modified.as_secs(),
+        nanos: modified.subsec_nanos()})
+}
+
+fn write_u8<W: Write>(w: &mut W, n: u8) -> io::Result<()>
+{
+    w.write_all(&[n])
+}
+
+fn write_u32<W: Write>(w: &mut W, n: u32) -> io::Result<()>
+{
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()>
+{
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_i32<W: Write>(w: &mut W, n: i32) -> io::Result<()>
+{
+    w.write_all(&n.to_le_bytes())
+}
+
+// Numbers are always stored as f64, whichever precision the build uses
+fn write_f64<W: Write>(w: &mut W, n: Scalar) -> io::Result<()>
+{
+    w.write_all(&(n as f64).to_le_bytes())
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()>
+{
+    write_u32(w, s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+fn write_tuple<W: Write>(w: &mut W, t: Tuple) -> io::Result<()>
+{
+    for n in t.get_vec()
+    {
+        write_f64(w, n)?;
+    }
+    Ok(())
+}
+
+fn write_stamp<W: Write>(w: &mut W, stamp: FileStamp) -> io::Result<()>
+{
+    write_u64(w, stamp.length)?;
+    write_u64(w, stamp.seconds)?;
+    write_u32(w, stamp.nanos)
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]>
+{
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8>
+{
+    Ok(read_bytes::<R, 1>(r)?[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32>
+{
+    Ok(u32::from_le_bytes(read_bytes(r)?))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64>
+{
+    Ok(u64::from_le_bytes(read_bytes(r)?))
+}
+
+fn read_i32<R: Read>(r: &mut R) -> io::Result<i32>
+{
+    Ok(i32::from_le_bytes(read_bytes(r)?))
+}
+
+fn read_f64<R: Read>(r: &mut R) -> io::Result<Scalar>
+{
+    Ok(f64::from_le_bytes(read_bytes(r)?) as Scalar)
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String>
+{
+    let length = read_u32(r)? as usize;
+    let mut bytes = vec![0; length];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_tuple<R: Read>(r: &mut R) -> io::Result<Tuple>
+{
+    Ok(create_tuple(read_f64(r)?, read_f64(r)?, read_f64(r)?, read_f64(r)?))
+}
+
+fn invalid_data(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_material<W: Write>(w: &mut W, m: &Material) -> io::Result<()>
+{
+    // materials from MTL files have no pattern, so none is stored
+    write_tuple(w, m.color)?;
+    for n in [m.ambient, m.diffuse, m.specular, m.shininess,
+        m.reflective, m.transparency, m.refractive_index]
+    {
+        write_f64(w, n)?;
+    }
+    Ok(())
+}
+
+fn read_material<R: Read>(r: &mut R) -> io::Result<Material>
+{
+    let mut m = Material::new();
+    m.color = read_tuple(r)?;
+    m.ambient = read_f64(r)?;
+    m.diffuse = read_f64(r)?;
+    m.specular = read_f64(r)?;
+    m.shininess = read_f64(r)?;
+    m.reflective = read_f64(r)?;
+    m.transparency = read_f64(r)?;
+    m.refractive_index = read_f64(r)?;
+    Ok(m)
+}
+
+fn write_uvs<W: Write>(w: &mut W, uvs: Option<[(Scalar, Scalar); 3]>) -> io::Result<()>
+{
+    match uvs
+    {
+        Some(uv) =>
+        {
+            write_u8(w, 1)?;
+            for (u, v) in uv
+            {
+                write_f64(w, u)?;
+                write_f64(w, v)?;
+            }
+            Ok(())
+        },
+        None => write_u8(w, 0),
+    }
+}
+
+fn read_uvs<R: Read>(r: &mut R) -> io::Result<Option<[(Scalar, Scalar); 3]>>
+{
+    match read_u8(r)?
+    {
+        0 => Ok(None),
+        _ => Ok(Some([(read_f64(r)?, read_f64(r)?), (read_f64(r)?, read_f64(r)?),
+            (read_f64(r)?, read_f64(r)?)])),
+    }
+}
+
+// Groups of a freshly parsed model only hold triangles
+fn write_group<W: Write>(w: &mut W, group: &Shape) -> io::Result<()>
+{
+    let children = group.get_children();
+    write_i32(w, group.get_id())?;
+    write_u32(w, children.len() as u32)?;
+    for child in children.iter()
+    {
+        write_i32(w, child.get_id())?;
+        if child.is_smooth_triangle()
+        {
+            let t = child.get_smooth_triangle();
+            write_u8(w, 1)?;
+            for p in [t.p1, t.p2, t.p3, t.n1, t.n2, t.n3]
+            {
+                write_tuple(w, p)?;
+            }
+            write_uvs(w, t.uvs)?;
+        }
+        else if child.is_triangle()
+        {
+            let t = child.get_triangle();
+            write_u8(w, 0)?;
+            for p in [t.p1, t.p2, t.p3]
+            {
+                write_tuple(w, p)?;
+            }
+            write_uvs(w, t.uvs)?;
+        }
+        else
+        {
+            return Err(invalid_data("only triangles can be cached"));
+        }
+    }
+    Ok(())
+}
+
+fn read_group<R: Read>(r: &mut R, materials: &HashMap<String, Arc<Material>>,
+    face_materials: &HashMap<i32, String>) -> io::Result<Shape>
+{
+    let mut group = Shape::new_group(read_i32(r)?);
+    let count = read_u32(r)?;
+    for _ in 0..count
+    {
+        let id = read_i32(r)?;
+        let mut t = match read_u8(r)?
+        {
+            0 => Shape::new_triangle(id, read_tuple(r)?, read_tuple(r)?, read_tuple(r)?),
+            1 => Shape::new_smooth_triangle(id, read_tuple(r)?, read_tuple(r)?, read_tuple(r)?,
+                read_tuple(r)?, read_tuple(r)?, read_tuple(r)?),
+            _ => return Err(invalid_data("unknown shape in cache")),
+        };
+        if let Some(uvs) = read_uvs(r)?
+        {
+            t.set_texture_uvs(uvs);
+        }
+        if let Some(m) = face_materials.get(&id).and_then(|name| materials.get(name))
+        {
+            t.set_shared_material(Arc::clone(m));
+        }
+        group.add_child(t);
+    }
+    Ok(group)
+}
+
+fn write_tuples<W: Write>(w: &mut W, tuples: &Vec<Tuple>) -> io::Result<()>
+{
+    write_u32(w, tuples.len() as u32)?;
+    for t in tuples
+    {
+        write_tuple(w, *t)?;
+    }
+    Ok(())
+}
+
+fn read_tuples<R: Read>(r: &mut R) -> io::Result<Vec<Tuple>>
+{
+    let count = read_u32(r)?;
+    (0..count).map(|_| read_tuple(r)).collect()
+}
+
+// Writes a parsed model in the cache format
+pub fn write_obj_cache<W: Write>(w: &mut W, obj: &ObjFile) -> io::Result<()>
+{
+    write_tuples(w, &obj.vertices)?;
+    write_tuples(w, &obj.normals)?;
+    write_u32(w, obj.texture_coords.len() as u32)?;
+    for (u, v) in obj.texture_coords.iter()
+    {
+        write_f64(w, *u)?;
+        write_f64(w, *v)?;
+    }
+    write_u32(w, obj.material_libraries.len() as u32)?;
+    for name in obj.material_libraries.iter()
+    {
+        write_str(w, name)?;
+    }
+    write_u32(w, obj.materials.len() as u32)?;
+    for (name, m) in obj.materials.iter()
+    {
+        write_str(w, name)?;
+        write_material(w, m)?;
+    }
+    write_u32(w, obj.face_materials.len() as u32)?;
+    for (id, name) in obj.face_materials.iter()
+    {
+        write_i32(w, *id)?;
+        write_str(w, name)?;
+    }
+    write_i32(w, obj.root_id)?;
+    write_group(w, &obj.default_group)?;
+    write_u32(w, obj.groups.len() as u32)?;
+    for (name, group) in obj.groups.iter()
+    {
+        write_str(w, name)?;
+        write_group(w, group)?;
+    }
+    Ok(())
+}
+
+// Reads a model written by write_obj_cache
+pub fn read_obj_cache<R: Read>(r: &mut R) -> io::Result<ObjFile>
+{
+    let vertices = read_tuples(r)?;
+    let normals = read_tuples(r)?;
+    let count = read_u32(r)?;
+    let mut texture_coords = Vec::new();
+    for _ in 0..count
+    {
+        texture_coords.push((read_f64(r)?, read_f64(r)?));
+    }
+    let count = read_u32(r)?;
+    let mut material_libraries = Vec::new();
+    for _ in 0..count
+    {
+        material_libraries.push(read_str(r)?);
+    }
+    let count = read_u32(r)?;
+    let mut materials = HashMap::new();
+    for _ in 0..count
+    {
+        let name = read_str(r)?;
+        materials.insert(name, Arc::new(read_material(r)?));
+    }
+    let count = read_u32(r)?;
+    let mut face_materials = HashMap::new();
+    for _ in 0..count
+    {
+        let id = read_i32(r)?;
+        face_materials.insert(id, read_str(r)?);
+    }
+    let root_id = read_i32(r)?;
+    let default_group = read_group(r, &materials, &face_materials)?;
+    let count = read_u32(r)?;
+    let mut groups = HashMap::new();
+    for _ in 0..count
+    {
+        let name = read_str(r)?;
+        groups.insert(name, read_group(r, &materials, &face_materials)?);
+    }
+    Ok(ObjFile{vertices, normals, texture_coords, default_group, groups,
+        materials, material_libraries, face_materials,
+        styled_groups: HashSet::new(), root_id})
+}
+
+// Where the cache for a source file is kept
+fn cache_path(source: &Path, cache_dir: &Path) -> PathBuf
+{
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    cache_dir.join(format!("{:016x}.objcache", hasher.finish()))
+}
+
+// Everything a cached model depends on: the OBJ file, its MTL files and
+// the options it was loaded with
+fn cache_key(source: &Path, libraries: &Vec<String>, options: &ObjOptions) -> io::Result<Vec<u8>>
+{
+    let mut key = Vec::new();
+    write_str(&mut key, &source.to_string_lossy())?;
+    write_stamp(&mut key, file_stamp(source)?)?;
+    let base_dir = source.parent().unwrap_or(Path::new(""));
+    write_u32(&mut key, libraries.len() as u32)?;
+    for name in libraries
+    {
+        write_str(&mut key, name)?;
+        write_stamp(&mut key, file_stamp(&base_dir.join(name))?)?;
+    }
+    let triangulation = match options.triangulation
+    {
+        Triangulation::Fan => 0,
+        Triangulation::EarClipping => 1,
+        Triangulation::Auto => 2,
+    };
+    write_u8(&mut key, triangulation)?;
+    write_i32(&mut key, options.first_id)?;
+    write_u8(&mut key, options.strict as u8)?;
+    Ok(key)
+}
+
+fn load_cache(source: &Path, cache: &Path, options: &ObjOptions) -> io::Result<ObjFile>
+{
+    let mut r = BufReader::new(File::open(cache)?);
+    let magic: [u8; 4] = read_bytes(&mut r)?;
+    if &magic != CACHE_MAGIC || read_u32(&mut r)? != CACHE_VERSION
+    {
+        return Err(invalid_data("not a current cache file"));
+    }
+    let libraries_count = read_u32(&mut r)?;
+    let mut libraries = Vec::new();
+    for _ in 0..libraries_count
+    {
+        libraries.push(read_str(&mut r)?);
+    }
+    let length = read_u32(&mut r)? as usize;
+    let mut key = vec![0; length];
+    r.read_exact(&mut key)?;
+    if key != cache_key(source, &libraries, options)?
+    {
+        return Err(invalid_data("cache is out of date"));
+    }
+    read_obj_cache(&mut r)
+}
+
+fn save_cache(source: &Path, cache: &Path, obj: &ObjFile, options: &ObjOptions) -> io::Result<()>
+{
+    // write to a temporary file first, so an interrupted write never
+    // leaves a broken cache behind
+    let temporary = cache.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(File::create(&temporary)?);
+        w.write_all(CACHE_MAGIC)?;
+        write_u32(&mut w, CACHE_VERSION)?;
+        write_u32(&mut w, obj.material_libraries.len() as u32)?;
+        for name in obj.material_libraries.iter()
+        {
+            write_str(&mut w, name)?;
+        }
+        let key = cache_key(source, &obj.material_libraries, options)?;
+        write_u32(&mut w, key.len() as u32)?;
+        w.write_all(&key)?;
+        write_obj_cache(&mut w, obj)?;
+        w.flush()?;
+    }
+    fs::rename(&temporary, cache)
+}
+
+impl ObjFile
+{
+    // Loads an OBJ file, reusing the model saved in cache_dir by an
+    // earlier load when neither the file, its MTL files nor the options
+    // have changed since. The cache is only an aid, so problems reading
+    // or writing it just mean the file is parsed again.
+    pub fn from_path_cached<P: AsRef<Path>>(path: P, cache_dir: &Path,
+        options: &ObjOptions) -> Result<ObjFile, ObjError>
+    {
+        let source = path.as_ref();
+        let cache = cache_path(source, cache_dir);
+        if let Ok(obj) = load_cache(source, &cache, options)
+        {
+            return Ok(obj);
+        }
+        let (obj, _) = ObjFile::from_path_with_options(source, options)?;
+        let _ = fs::create_dir_all(cache_dir)
+            .and_then(|_| save_cache(source, &cache, &obj, options));
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_objcache_feature()
+    {
+        // Scenario: A model is the same after a round trip through the cache
+        let lines1 = vec!["v 0 1 0",
+            "v -1 0 0",
+            "v 1 0 0",
+            "v 1 1 0",
+            "vn 0 0 -1",
+            "vt 0 1",
+            "vt 1 0",
+            "f 1 2 3",
+            "g Smooth",
+            "f 1/1/1 3/2/1 4/2/1"];
+        let obj1 = parse_obj_file(lines1);
+        let mut bytes1 = Vec::new();
+        write_obj_cache(&mut bytes1, &obj1).unwrap();
+        let cached1 = read_obj_cache(&mut bytes1.as_slice()).unwrap();
+        assert_eq!(cached1.vertices, obj1.vertices);
+        assert_eq!(cached1.texture_coords, obj1.texture_coords);
+        assert_eq!(cached1.root_id, obj1.root_id);
+        let t1 = cached1.default_group.get_children()[0].get_triangle();
+        assert_eq!(t1.p2, create_point(-1.0, 0.0, 0.0));
+        let s1 = cached1.groups.get("Smooth").unwrap().get_children()[0].clone();
+        let original1 = obj1.groups.get("Smooth").unwrap().get_children()[0].clone();
+        assert_eq!(s1.get_id(), original1.get_id());
+        assert_eq!(s1.get_smooth_triangle().n1, original1.get_smooth_triangle().n1);
+        assert_eq!(s1.texture_uv_at((0.0, 0.0)), Some((0.0, 1.0)));
+
+        // Scenario: A cache is used until its source file changes
+        let dir = std::env::temp_dir().join("ray_tracer_test_objcache");
+        let cache_dir = dir.join("cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("model.obj");
+        fs::write(&source, "v 0 1 0\nv -1 0 0\nv 1 0 0\nf 1 2 3\n").unwrap();
+        let options = ObjOptions::new();
+        let obj2 = ObjFile::from_path_cached(&source, &cache_dir, &options).unwrap();
+        assert_eq!(obj2.default_group.get_children().len(), 1);
+        assert!(cache_path(&source, &cache_dir).exists());
+        let obj3 = load_cache(&source, &cache_path(&source, &cache_dir), &options).unwrap();
+        assert_eq!(obj3.vertices, obj2.vertices);
+
+        // a different set of options does not use the cache
+        let mut options4 = ObjOptions::new();
+        options4.first_id = 100;
+        assert!(load_cache(&source, &cache_path(&source, &cache_dir), &options4).is_err());
+
+        fs::write(&source, "v 0 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3 4\n").unwrap();
+        let obj5 = ObjFile::from_path_cached(&source, &cache_dir, &options).unwrap();
+        assert_eq!(obj5.default_group.get_children().len(), 2);
+
+        // Scenario: A model cached by a lenient load still fails a strict one
+        fs::write(&source, "v 0 1 0\nv -1 0 0\nv 1 0 0\nv 1 x 0\nf 1 2 3\n").unwrap();
+        let mut lenient = ObjOptions::new();
+        lenient.strict = false;
+        let obj6 = ObjFile::from_path_cached(&source, &cache_dir, &lenient).unwrap();
+        assert_eq!(obj6.default_group.get_children().len(), 1);
+        assert!(ObjFile::from_path_cached(&source, &cache_dir, &lenient).is_ok());
+        assert!(ObjFile::from_path_cached(&source, &cache_dir, &options).is_err());
+    }
+}
@@ -1,5 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::arithmetic::*;
 use crate::tuple::*;
+use crate::material::*;
+use crate::matrix::*;
+use crate::mtlfile::*;
 use crate::shape::*;
 
 #[derive(Clone, Debug)]
@@ -7,138 +18,871 @@ pub struct ObjFile
 {
     pub vertices: Vec<Tuple>,
     pub normals: Vec<Tuple>,
+    pub texture_coords: Vec<(Scalar, Scalar)>,
     pub default_group: Shape,
     pub groups: HashMap<String, Shape>,
+    // materials from the mtllib files, which may be replaced before
+    // calling obj_to_group
+    pub materials: HashMap<String, Arc<Material>>,
+    pub material_libraries: Vec<String>,
+    // the usemtl name in effect for each triangle, by shape id
+    pub face_materials: HashMap<i32, String>,
+    // groups given a material with set_group_material, which takes the
+    // place of their usemtl materials
+    pub styled_groups: HashSet<String>,
+    // the id of the group made by obj_to_group
+    pub root_id: i32,
 }
 
-pub fn parse_obj_file(lines: Vec<&str>) -> ObjFile
+// One corner of a face, in any of the forms v, v/vt, v//vn or v/vt/vn
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FaceVertex
+{
+    pub vertex: usize,
+    pub texture: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+// Converts a 1-based index, or a negative index counting back from the
+// most recent record, into an index into the 1-based record list.
+fn resolve_index(word: &str, count: usize) -> Option<usize>
+{
+    let n = word.parse::<i64>().ok()?;
+    let index = if n < 0
+    {
+        count as i64 + 1 + n
+    }
+    else
+    {
+        n
+    };
+    if index >= 1 && index <= count as i64
+    {
+        Some(index as usize)
+    }
+    else
+    {
+        None
+    }
+}
+
+pub fn parse_face_vertex(word: &str, vertex_count: usize,
+    texture_count: usize, normal_count: usize) -> Option<FaceVertex>
+{
+    let tokens: Vec<&str> = word.split('/').collect();
+    if tokens.len() > 3
+    {
+        return None;
+    }
+    let vertex = resolve_index(tokens[0], vertex_count)?;
+
+    // missing or unusable texture and normal indices are ignored
+    let texture = match tokens.get(1)
+    {
+        Some(t) if !t.is_empty() => resolve_index(t, texture_count),
+        _ => None,
+    };
+    let normal = match tokens.get(2)
+    {
+        Some(n) if !n.is_empty() => resolve_index(n, normal_count),
+        _ => None,
+    };
+    Some(FaceVertex{vertex, texture, normal})
+}
+
+// Problems found while loading an OBJ file
+#[derive(Debug)]
+pub enum ObjError
+{
+    Io(io::Error),
+    Parse{line: usize, message: String},
+}
+
+impl fmt::Display for ObjError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            ObjError::Io(e) => write!(f, "{}", e),
+            ObjError::Parse{line, message} => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for ObjError
+{
+}
+
+impl From<io::Error> for ObjError
+{
+    fn from(e: io::Error) -> Self
+    {
+        ObjError::Io(e)
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> ObjError
+{
+    ObjError::Parse{line, message: String::from(message)}
+}
+
+// How faces with more than three vertices are split into triangles
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Triangulation
+{
+    // triangles share the first vertex, which only works for convex faces
+    Fan,
+    // triangles are cut off the outside of the face, which also works
+    // for concave faces
+    EarClipping,
+    // fan triangulation for convex faces, ear clipping for concave ones
+    Auto,
+}
+
+// Settings for loading an OBJ file
+#[derive(Copy, Clone, Debug)]
+pub struct ObjOptions
+{
+    pub triangulation: Triangulation,
+    // the id of the group made by obj_to_group. The shapes inside it are
+    // numbered from the next id on, so several models can share a world.
+    pub first_id: i32,
+    // stop at the first line that cannot be parsed, instead of skipping
+    // it and listing it in the report
+    pub strict: bool,
+}
+
+impl ObjOptions
+{
+    pub fn new() -> Self
+    {
+        ObjOptions{triangulation: Triangulation::Auto, first_id: 1, strict: true}
+    }
+}
+
+// Normal of a polygon, using Newell's method so that it does not depend
+// on any one corner being convex
+fn polygon_normal(points: &Vec<Tuple>) -> Tuple
+{
+    let mut n = [0.0, 0.0, 0.0];
+    for i in 0..points.len()
+    {
+        let a = points[i].get_vec();
+        let b = points[(i + 1) % points.len()].get_vec();
+        n[0] = n[0] + (a[1] - b[1]) * (a[2] + b[2]);
+        n[1] = n[1] + (a[2] - b[2]) * (a[0] + b[0]);
+        n[2] = n[2] + (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    create_vector(n[0], n[1], n[2])
+}
+
+// Whether the corner at b, between a and c, turns the same way as the polygon
+fn is_convex_corner(a: Tuple, b: Tuple, c: Tuple, normal: Tuple) -> bool
+{
+    b.sub(a).cross_product(c.sub(b)).dot_product(normal) > EPSILON
+}
+
+// Whether the corner at b turns against the polygon, making it concave
+fn is_reflex_corner(a: Tuple, b: Tuple, c: Tuple, normal: Tuple) -> bool
+{
+    b.sub(a).cross_product(c.sub(b)).dot_product(normal) < -EPSILON
+}
+
+fn in_triangle(p: Tuple, a: Tuple, b: Tuple, c: Tuple, normal: Tuple) -> bool
+{
+    b.sub(a).cross_product(p.sub(a)).dot_product(normal) >= 0.0
+        && c.sub(b).cross_product(p.sub(b)).dot_product(normal) >= 0.0
+        && a.sub(c).cross_product(p.sub(c)).dot_product(normal) >= 0.0
+}
+
+// Whether no corner of a polygon is concave. Corners where the polygon
+// runs straight on, or repeats a point, do not count as concave.
+pub fn is_convex_polygon(points: &Vec<Tuple>) -> bool
+{
+    let normal = polygon_normal(points);
+    let n = points.len();
+    !(0..n).any(|i| is_reflex_corner(points[(i + n - 1) % n], points[i],
+        points[(i + 1) % n], normal))
+}
+
+// Splits a polygon into triangles, returned as indices into its points
+pub fn triangulate(points: &Vec<Tuple>, method: Triangulation) -> Vec<[usize; 3]>
 {
-    let mut id = 1;
-    let mut v = Vec::new();
-    let mut vn = Vec::new();
-    // Add unused entry at index 0, so we can used 1-based indexing
-    v.push(create_point(0.0, 0.0, 0.0));
-    vn.push(create_point(0.0, 0.0, 0.0));
-    let mut default_group = Shape::new_group(id);
-    let mut groups: HashMap<String, Shape> = HashMap::new();
-    let mut current_groups: Vec<String> = Vec::new();
-    for line in lines
-    {
-        let words: Vec<String> = line.split_ascii_whitespace().map(String::from).collect();
-        if words.len() >= 2
-        {
-            if words[0] == "v" && words.len() == 4
+    let use_fan = match method
+    {
+        Triangulation::Fan => true,
+        Triangulation::EarClipping => false,
+        Triangulation::Auto => points.len() == 3 || is_convex_polygon(points),
+    };
+    if use_fan
+    {
+        return (1..points.len() - 1).map(|i| [0, i, i + 1]).collect();
+    }
+
+    let normal = polygon_normal(points);
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::new();
+    while remaining.len() > 3
+    {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i|
+        {
+            let a = points[remaining[(i + n - 1) % n]];
+            let b = points[remaining[i]];
+            let c = points[remaining[(i + 1) % n]];
+            is_convex_corner(a, b, c, normal) && remaining.iter()
+                .filter(|&&k| k != remaining[(i + n - 1) % n]
+                    && k != remaining[i] && k != remaining[(i + 1) % n])
+                .all(|&k| !in_triangle(points[k], a, b, c, normal))
+        });
+        // a degenerate polygon may have no ears, so cut off any corner
+        let i = ear.unwrap_or(1);
+        triangles.push([remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+// What was found while leniently parsing an OBJ file
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjReport
+{
+    // lines with statements that are not supported
+    pub ignored_lines: Vec<usize>,
+    // lines that could not be parsed, with the reason
+    pub errors: Vec<(usize, String)>,
+    pub vertex_count: usize,
+    pub normal_count: usize,
+    pub texture_coord_count: usize,
+    pub face_count: usize,
+    pub triangle_count: usize,
+    pub group_count: usize,
+    // the highest shape id used by the model
+    pub last_id: i32,
+}
+
+impl ObjReport
+{
+    pub fn new() -> Self
+    {
+        ObjReport{ignored_lines: Vec::new(), errors: Vec::new(),
+            vertex_count: 0, normal_count: 0, texture_coord_count: 0,
+            face_count: 0, triangle_count: 0, group_count: 0, last_id: 0}
+    }
+}
+
+// Builds an ObjFile one line at a time
+struct ObjParser
+{
+    id: i32,
+    v: Vec<Tuple>,
+    vn: Vec<Tuple>,
+    vt: Vec<(Scalar, Scalar)>,
+    // triangles are collected here and only put in groups once parsing
+    // is finished, as adding to a group in a HashMap means copying it
+    default_children: Vec<Shape>,
+    groups: HashMap<String, (i32, Vec<Shape>)>,
+    current_groups: Vec<String>,
+    base_dir: Option<PathBuf>,
+    materials: HashMap<String, Arc<Material>>,
+    material_libraries: Vec<String>,
+    current_material: Option<String>,
+    // the current smoothing group, 0 when smoothing is off
+    smoothing_group: u32,
+    // triangles without vn data in a smoothing group, by shape id, with
+    // their smoothing group and vertex indices
+    smoothed_triangles: Vec<(i32, u32, [usize; 3])>,
+    // sum of the normals of the faces around each vertex in each smoothing group
+    smoothing_normals: HashMap<(u32, usize), Tuple>,
+    face_materials: HashMap<i32, String>,
+    report: ObjReport,
+    triangulation: Triangulation,
+    first_id: i32,
+}
+
+impl ObjParser
+{
+    fn new(base_dir: Option<PathBuf>, options: &ObjOptions) -> Self
+    {
+        // the first id is kept for obj_to_group, the next for the default group
+        let id = options.first_id + 1;
+        // Add unused entry at index 0, so we can used 1-based indexing
+        let v = vec![create_point(0.0, 0.0, 0.0)];
+        let vn = vec![create_point(0.0, 0.0, 0.0)];
+        let vt = vec![(0.0, 0.0)];
+        ObjParser{id, v, vn, vt,
+            default_children: Vec::new(),
+            groups: HashMap::new(),
+            current_groups: Vec::new(),
+            base_dir,
+            materials: HashMap::new(),
+            material_libraries: Vec::new(),
+            current_material: None,
+            smoothing_group: 0,
+            smoothed_triangles: Vec::new(),
+            smoothing_normals: HashMap::new(),
+            face_materials: HashMap::new(),
+            report: ObjReport::new(),
+            triangulation: options.triangulation,
+            first_id: options.first_id}
+    }
+
+    fn parse_coordinates(line_number: usize, words: &[&str]) -> Result<Tuple, ObjError>
+    {
+        // an optional fourth (w) coordinate is allowed, and ignored
+        if words.len() < 4 || words.len() > 5
+        {
+            return Err(parse_error(line_number, "expected three coordinates"));
+        }
+        let mut xyz = Vec::new();
+        for word in &words[1..4]
+        {
+            match word.parse::<Scalar>()
+            {
+                Ok(n) => xyz.push(n),
+                Err(_) => return Err(ObjError::Parse{line: line_number,
+                    message: format!("invalid number '{}'", word)}),
+            }
+        }
+        Ok(create_point(xyz[0], xyz[1], xyz[2]))
+    }
+
+    // Parses a line, recording any error in the report rather than failing
+    fn parse_line_lenient(&mut self, line_number: usize, line: &str)
+    {
+        if let Err(e) = self.parse_line(line_number, line)
+        {
+            let message = match e
+            {
+                ObjError::Parse{line: _, message} => message,
+                ObjError::Io(e) => e.to_string(),
+            };
+            self.report.errors.push((line_number, message));
+        }
+    }
+
+    fn parse_line(&mut self, line_number: usize, line: &str) -> Result<(), ObjError>
+    {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#')
+        {
+            return Ok(());
+        }
+        if words[0] == "v"
+        {
+            let p = Self::parse_coordinates(line_number, &words)?;
+            self.v.push(p);
+        }
+        else if words[0] == "vn"
+        {
+            let p = Self::parse_coordinates(line_number, &words)?;
+            self.vn.push(p);
+        }
+        else if words[0] == "vt"
+        {
+            if words.len() < 2
             {
-                let p = create_point(
-                    words[1].parse::<f64>().unwrap(),
-                    words[2].parse::<f64>().unwrap(),
-                    words[3].parse::<f64>().unwrap());
-                v.push(p);
+                return Err(parse_error(line_number, "expected texture coordinates"));
             }
-            else if words[0] == "vn" && words.len() == 4
+            // v and w are optional, and w is not used
+            let mut uv = [0.0, 0.0];
+            for index in 1..words.len().min(3)
+            {
+                match words[index].parse::<Scalar>()
+                {
+                    Ok(n) => uv[index - 1] = n,
+                    Err(_) => return Err(ObjError::Parse{line: line_number,
+                        message: format!("invalid number '{}'", words[index])}),
+                }
+            }
+            self.vt.push((uv[0], uv[1]));
+        }
+        else if words[0] == "f"
+        {
+            if words.len() < 4
+            {
+                return Err(parse_error(line_number, "a face needs at least three vertices"));
+            }
+            let mut face = Vec::new();
+            for word in &words[1..]
+            {
+                match parse_face_vertex(word, self.v.len() - 1, self.vt.len() - 1, self.vn.len() - 1)
+                {
+                    Some(fv) => face.push(fv),
+                    None => return Err(ObjError::Parse{line: line_number,
+                        message: format!("invalid face vertex '{}'", word)}),
+                }
+            }
+            self.report.face_count = self.report.face_count + 1;
+            self.add_face(&face);
+        }
+        else if words[0] == "usemtl"
+        {
+            if words.len() < 2
+            {
+                return Err(parse_error(line_number, "expected a material name"));
+            }
+            self.current_material = Some(String::from(words[1]));
+        }
+        else if words[0] == "mtllib"
+        {
+            for name in &words[1..]
+            {
+                self.material_libraries.push(String::from(*name));
+                // libraries can only be found relative to a file on disk
+                if let Some(dir) = &self.base_dir
+                {
+                    let materials = mtl_from_path(dir.join(name))?;
+                    self.materials.extend(materials.into_iter().map(|(n, m)| (n, Arc::new(m))));
+                }
+            }
+        }
+        else if words[0] == "s"
+        {
+            if words.len() < 2
             {
-                let p = create_point(
-                    words[1].parse::<f64>().unwrap(),
-                    words[2].parse::<f64>().unwrap(),
-                    words[3].parse::<f64>().unwrap());
-                vn.push(p);
+                return Err(parse_error(line_number, "expected a smoothing group"));
             }
-            else if words[0] == "f" && words.len() >= 4
+            self.smoothing_group = match words[1]
             {
-                // Add single triangle if three vertices, or polygon
-                // with fan triangulation if more than three vertices
-                let last_index = words.len() - 1;
-                for index in 2..last_index
+                "off" => 0,
+                word => match word.parse::<u32>()
                 {
-                    let tokens1: Vec<&str> = words[1].split('/').collect();
-                    let tokens2: Vec<&str> = words[index].split('/').collect();
-                    let tokens3: Vec<&str> = words[index + 1].split('/').collect();
+                    Ok(n) => n,
+                    Err(_) => return Err(ObjError::Parse{line: line_number,
+                        message: format!("invalid smoothing group '{}'", word)}),
+                },
+            };
+        }
+        else if words[0] == "g"
+        {
+            self.current_groups.clear();
+            for index in 1..words.len()
+            {
+                self.current_groups.push(String::from(words[index]));
+            }
+        }
+        else
+        {
+            self.report.ignored_lines.push(line_number);
+        }
+        Ok(())
+    }
 
-                    let j1 = tokens1[0].parse::<usize>().unwrap();
-                    let j2 = tokens2[0].parse::<usize>().unwrap();
-                    let j3 = tokens3[0].parse::<usize>().unwrap();
+    fn add_face(&mut self, face: &Vec<FaceVertex>)
+    {
+        let v = &self.v;
+        let vn = &self.vn;
 
-                    id = id + 1;
-                    let mut t: Shape;
-                    if tokens1.len() >= 3 && tokens2.len() >= 3 && tokens3.len() >= 3
-                    {
-                        let k1 = tokens1[2].parse::<usize>().unwrap();
-                        let k2 = tokens2[2].parse::<usize>().unwrap();
-                        let k3 = tokens3[2].parse::<usize>().unwrap();
-                        t = Shape::new_smooth_triangle(id, v[j1], v[j2], v[j3],
-                            vn[k1], vn[k2], vn[k3]);
-                    }
-                    else
-                    {
-                        t = Shape::new_triangle(id, v[j1], v[j2], v[j3]);
-                    }
+        let points = face.iter().map(|fv| v[fv.vertex]).collect();
+        let triangles = triangulate(&points, self.triangulation);
+        self.report.triangle_count = self.report.triangle_count + triangles.len();
+        for [i1, i2, i3] in triangles
+        {
+            let fv1 = face[i1];
+            let fv2 = face[i2];
+            let fv3 = face[i3];
 
-                    let groups2 = current_groups.clone();
-                    if !current_groups.is_empty()
+            self.id = self.id + 1;
+            let mut t: Shape;
+            match (fv1.normal, fv2.normal, fv3.normal)
+            {
+                (Some(k1), Some(k2), Some(k3)) =>
+                {
+                    t = Shape::new_smooth_triangle(self.id,
+                        v[fv1.vertex], v[fv2.vertex], v[fv3.vertex],
+                        vn[k1], vn[k2], vn[k3]);
+                },
+                _ =>
+                {
+                    t = Shape::new_triangle(self.id,
+                        v[fv1.vertex], v[fv2.vertex], v[fv3.vertex]);
+                    if self.smoothing_group != 0
                     {
-                        for name in groups2
+                        // the unnormalized normal weights larger faces more
+                        let triangle = t.get_triangle();
+                        let normal = triangle.e2.cross_product(triangle.e1);
+                        let indices = [fv1.vertex, fv2.vertex, fv3.vertex];
+                        for index in indices
                         {
-                            match groups.get(&name)
+                            let key = (self.smoothing_group, index);
+                            let sum = match self.smoothing_normals.get(&key)
                             {
-                                Some(g) =>
-                                {
-                                    let mut g2 = g.clone();
-                                    g2.add_child(&mut t);
-                                    groups.insert(name, g2);
-                                },
-                                _ =>
-                                {
-                                    id = id + 1;
-                                    let mut group = Shape::new_group(id);
-                                    group.add_child(&mut t);
-                                    groups.insert(name, group);
-                                },
-                            }
+                                Some(n) => n.add(normal),
+                                None => normal,
+                            };
+                            self.smoothing_normals.insert(key, sum);
                         }
+                        self.smoothed_triangles.push((self.id, self.smoothing_group, indices));
                     }
-                    else
-                    {
-                        default_group.add_child(&mut t);
-                    }
+                },
+            }
+            if let (Some(k1), Some(k2), Some(k3)) = (fv1.texture, fv2.texture, fv3.texture)
+            {
+                t.set_texture_uvs([self.vt[k1], self.vt[k2], self.vt[k3]]);
+            }
+
+            if let Some(name) = &self.current_material
+            {
+                if let Some(material) = self.materials.get(name)
+                {
+                    t.set_shared_material(Arc::clone(material));
                 }
+                self.face_materials.insert(self.id, name.clone());
             }
-            else if words[0] == "g"
+
+            if !self.current_groups.is_empty()
             {
-                current_groups.clear();
-                for index in 1..words.len()
+                for name in &self.current_groups
                 {
-                    current_groups.push(words[index].clone());
+                    match self.groups.get_mut(name)
+                    {
+                        Some((_, children)) => children.push(t.clone()),
+                        None =>
+                        {
+                            self.id = self.id + 1;
+                            self.groups.insert(name.clone(), (self.id, vec![t.clone()]));
+                        },
+                    }
                 }
             }
+            else
+            {
+                self.default_children.push(t);
+            }
+        }
+    }
+
+    fn finish(self) -> (ObjFile, ObjReport)
+    {
+        let mut report = self.report;
+        report.vertex_count = self.v.len() - 1;
+        report.normal_count = self.vn.len() - 1;
+        report.texture_coord_count = self.vt.len() - 1;
+        report.group_count = self.groups.len();
+        report.last_id = self.id;
+
+        // triangles in smoothing groups get the average normal of the
+        // faces around each vertex in the same group
+        let mut smoothed = HashMap::new();
+        for (id, group, indices) in &self.smoothed_triangles
+        {
+            let normals = indices.map(|index|
+                self.smoothing_normals[&(*group, index)].normalize());
+            smoothed.insert(*id, normals);
+        }
+        let smooth = |child: Shape| -> Shape
+        {
+            match smoothed.get(&child.get_id())
+            {
+                Some(n) => to_smooth_triangle(&child, *n),
+                None => child,
+            }
+        };
+
+        let mut default_group = Shape::new_group(self.first_id + 1);
+        for child in self.default_children
+        {
+            default_group.add_child(smooth(child));
+        }
+        let mut groups = HashMap::with_capacity(self.groups.len());
+        for (name, (id, children)) in self.groups
+        {
+            let mut group = Shape::new_group(id);
+            for child in children
+            {
+                group.add_child(smooth(child));
+            }
+            groups.insert(name, group);
         }
+        let obj = ObjFile{vertices: self.v, normals: self.vn, texture_coords: self.vt,
+            default_group, groups,
+            materials: self.materials,
+            material_libraries: self.material_libraries,
+            face_materials: self.face_materials,
+            styled_groups: HashSet::new(),
+            root_id: self.first_id};
+        (obj, report)
+    }
+}
+
+// A smooth triangle with the same id, corners, material and texture
+// coordinates as a flat triangle
+fn to_smooth_triangle(triangle: &Shape, normals: [Tuple; 3]) -> Shape
+{
+    let t = triangle.get_triangle();
+    let mut s = Shape::new_smooth_triangle(triangle.get_id(),
+        t.p1, t.p2, t.p3, normals[0], normals[1], normals[2]);
+    s.set_shared_material(triangle.get_shared_material());
+    if let Some(uvs) = t.uvs
+    {
+        s.set_texture_uvs(uvs);
     }
-    ObjFile{vertices: v, normals: vn,
-        default_group: default_group, groups: groups}
+    s
+}
+
+// Angle between the edges from a corner to two other points
+fn corner_angle(corner: Tuple, a: Tuple, b: Tuple) -> Scalar
+{
+    let e1 = a.sub(corner).normalize();
+    let e2 = b.sub(corner).normalize();
+    e1.dot_product(e2).max(-1.0).min(1.0).acos()
+}
+
+// Vertices are matched by position, as triangles keep copies of them
+fn vertex_key(p: Tuple) -> [u64; 3]
+{
+    let v = p.get_vec();
+    [u64::from(v[0].to_bits()), u64::from(v[1].to_bits()), u64::from(v[2].to_bits())]
+}
+
+// Parses lines of an OBJ file, skipping any lines that cannot be understood
+pub fn parse_obj_file(lines: Vec<&str>) -> ObjFile
+{
+    parse_obj_file_with_report(lines).0
+}
+
+// Parses lines of an OBJ file, listing the lines that were skipped
+pub fn parse_obj_file_with_report(lines: Vec<&str>) -> (ObjFile, ObjReport)
+{
+    let mut options = ObjOptions::new();
+    options.strict = false;
+    parse_obj_file_with_options(lines, &options).unwrap()
+}
+
+pub fn parse_obj_file_with_options(lines: Vec<&str>,
+    options: &ObjOptions) -> Result<(ObjFile, ObjReport), ObjError>
+{
+    let mut parser = ObjParser::new(None, options);
+    for (index, line) in lines.iter().enumerate()
+    {
+        if options.strict
+        {
+            parser.parse_line(index + 1, line)?;
+        }
+        else
+        {
+            parser.parse_line_lenient(index + 1, line);
+        }
+    }
+    Ok(parser.finish())
 }
 
 impl ObjFile
 {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ObjFile, ObjError>
+    {
+        Ok(Self::from_path_with_options(path, &ObjOptions::new())?.0)
+    }
+
+    // Reads an OBJ file, stopping at the first line that cannot be parsed.
+    // mtllib files are not loaded, as there is no directory to find them in.
+    pub fn from_reader<R: Read>(reader: R) -> Result<ObjFile, ObjError>
+    {
+        Ok(Self::from_reader_with_options(reader, &ObjOptions::new())?.0)
+    }
+
+    // Reads an OBJ file, skipping lines that cannot be parsed and listing
+    // them in the report. Only failing to read the file is an error.
+    pub fn from_path_with_report<P: AsRef<Path>>(path: P) -> Result<(ObjFile, ObjReport), ObjError>
+    {
+        let mut options = ObjOptions::new();
+        options.strict = false;
+        Self::from_path_with_options(path, &options)
+    }
+
+    pub fn from_reader_with_report<R: Read>(reader: R) -> Result<(ObjFile, ObjReport), ObjError>
+    {
+        let mut options = ObjOptions::new();
+        options.strict = false;
+        Self::from_reader_with_options(reader, &options)
+    }
+
+    pub fn from_path_with_options<P: AsRef<Path>>(path: P,
+        options: &ObjOptions) -> Result<(ObjFile, ObjReport), ObjError>
+    {
+        let file = File::open(path.as_ref())?;
+        let base_dir = path.as_ref().parent().map(|dir| dir.to_path_buf());
+        Self::parse_reader(file, base_dir, options)
+    }
+
+    pub fn from_reader_with_options<R: Read>(reader: R,
+        options: &ObjOptions) -> Result<(ObjFile, ObjReport), ObjError>
+    {
+        Self::parse_reader(reader, None, options)
+    }
+
+    fn parse_reader<R: Read>(reader: R, base_dir: Option<PathBuf>,
+        options: &ObjOptions) -> Result<(ObjFile, ObjReport), ObjError>
+    {
+        let mut parser = ObjParser::new(base_dir, options);
+        let mut reader = BufReader::new(reader);
+        // one line buffer is reused for the whole file
+        let mut line = String::new();
+        let mut line_number = 0;
+        while reader.read_line(&mut line)? > 0
+        {
+            line_number = line_number + 1;
+            if options.strict
+            {
+                parser.parse_line(line_number, &line)?;
+            }
+            else
+            {
+                parser.parse_line_lenient(line_number, &line);
+            }
+            line.clear();
+        }
+        Ok(parser.finish())
+    }
+
     pub fn obj_to_group(&self) -> Shape
     {
-        let mut group = Shape::new_group(1);
-        let mut g2 = self.default_group.clone();
+        let mut group = Shape::new_group(self.root_id);
+        let g2 = self.with_materials(&self.default_group);
         if !self.default_group.get_children().is_empty()
         {
-            group.add_child(&mut g2);
+            group.add_child(g2);
         }
-        for g in self.groups.values()
+        for (name, g) in self.groups.iter()
         {
-            let mut g3 = g.clone();
-            group.add_child(&mut g3);
+            let g3 = match self.styled_groups.contains(name)
+            {
+                true => g.clone(),
+                false => self.with_materials(g),
+            };
+            group.add_child(g3);
         }
         group
     }
+
+    // Gives every triangle in a named group the same material. Returns
+    // false if there is no group with that name.
+    pub fn set_group_material(&mut self, name: &str, material: Material) -> bool
+    {
+        match self.groups.get_mut(name)
+        {
+            Some(g) =>
+            {
+                g.set_all_materials(Arc::new(material));
+                self.styled_groups.insert(String::from(name));
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Sets the transform of a named group. Returns false if there is no
+    // group with that name.
+    pub fn set_group_transform(&mut self, name: &str, transform: Matrix) -> bool
+    {
+        match self.groups.get_mut(name)
+        {
+            Some(g) =>
+            {
+                g.set_transform(transform);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // The model as a group which fits within the cube from -1 to 1 on
+    // each axis, centered at the origin
+    pub fn normalized(&self) -> Shape
+    {
+        self.obj_to_group().normalized()
+    }
+
+    // A copy of the model where every flat triangle is replaced by a smooth
+    // one. The normal at each vertex is the average of the normals of the
+    // faces around it, weighted by the angle of each face at the vertex.
+    pub fn with_computed_normals(&self) -> ObjFile
+    {
+        let mut groups: Vec<&Shape> = self.groups.values().collect();
+        groups.push(&self.default_group);
+
+        let mut sums: HashMap<[u64; 3], Tuple> = HashMap::new();
+        for group in groups.iter()
+        {
+            for child in group.get_children().iter().filter(|c| c.is_triangle())
+            {
+                let t = child.get_triangle();
+                let corners = [(t.p1, t.p2, t.p3), (t.p2, t.p3, t.p1), (t.p3, t.p1, t.p2)];
+                for (corner, a, b) in corners
+                {
+                    let weighted = t.normal.multiply(corner_angle(corner, a, b));
+                    let sum = match sums.get(&vertex_key(corner))
+                    {
+                        Some(s) => s.add(weighted),
+                        None => weighted,
+                    };
+                    sums.insert(vertex_key(corner), sum);
+                }
+            }
+        }
+
+        let smooth_group = |group: &Shape| -> Shape
+        {
+            let mut g = Shape::new_group(group.get_id());
+            g.set_transform(group.get_transform());
+            g.set_shared_material(group.get_shared_material());
+            for child in group.get_children()
+            {
+                let c = match child.is_triangle()
+                {
+                    true =>
+                    {
+                        let t = child.get_triangle();
+                        let normals = [t.p1, t.p2, t.p3]
+                            .map(|p| sums[&vertex_key(p)].normalize());
+                        to_smooth_triangle(&child, normals)
+                    },
+                    false => child,
+                };
+                g.add_child(c);
+            }
+            g
+        };
+
+        let mut obj = self.clone();
+        obj.default_group = smooth_group(&self.default_group);
+        obj.groups = self.groups.iter()
+            .map(|(name, g)| (name.clone(), smooth_group(g))).collect();
+        obj
+    }
+
+    // Copies a group, giving each triangle the current material table's
+    // entry for its usemtl name
+    fn with_materials(&self, group: &Shape) -> Shape
+    {
+        let mut g = Shape::new_group(group.get_id());
+        g.set_transform(group.get_transform());
+        for child in group.get_children()
+        {
+            let mut c = child.clone();
+            let material = self.face_materials.get(&c.get_id())
+                .and_then(|name| self.materials.get(name));
+            if let Some(m) = material
+            {
+                c.set_shared_material(Arc::clone(m));
+            }
+            g.add_child(c);
+        }
+        g
+    }
 }
 
 #[cfg(test)]
 mod tests
 {
     use super::*;
+    use crate::intersections::*;
+    use crate::ray::*;
+    use crate::triangle::*;
+    use crate::arithmetic::consts::PI;
 
     #[test]
     fn test_objfile_feature8()
@@ -308,6 +1052,374 @@ mod tests
         assert!(children213[0].is_triangle());
     }
 
+    #[test]
+    fn test_objfile_face_formats()
+    {
+        // Scenario: Faces in all four vertex reference formats
+        let lines = vec!["v 0 1 0",
+            "v -1 0 0",
+            "v 1 0 0",
+            "vt 0 0",
+            "vt 1 0",
+            "vt 0 1",
+            "vn -1 0 0",
+            "vn 1 0 0",
+            "vn 0 1 0",
+            "f 1 2 3",
+            "f 1/1 2/2 3/3",
+            "f 1//3 2//1 3//2",
+            "f 1/1/3 2/2/1 3/3/2",
+            "f 1/1/ 2/2/ 3/3/",
+            "f -3/-3/-1 -2/-2/-3 -1/-1/-2"];
+        let obj = parse_obj_file(lines);
+        let children = obj.default_group.get_children();
+        assert_eq!(children.len(), 6);
+        assert!(children[0].is_triangle());
+        assert!(children[1].is_triangle());
+        assert!(children[2].is_smooth_triangle());
+        assert!(children[3].is_smooth_triangle());
+        assert!(children[4].is_triangle());
+        assert!(children[5].is_smooth_triangle());
+        for child in &children[0..2]
+        {
+            let t = child.get_triangle();
+            assert_eq!(t.p1, obj.vertices[1]);
+            assert_eq!(t.p2, obj.vertices[2]);
+            assert_eq!(t.p3, obj.vertices[3]);
+        }
+        for child in &children[2..4]
+        {
+            let t = child.get_smooth_triangle();
+            assert_eq!(t.n1, obj.normals[3]);
+            assert_eq!(t.n2, obj.normals[1]);
+            assert_eq!(t.n3, obj.normals[2]);
+        }
+        let t5 = children[5].get_smooth_triangle();
+        assert_eq!(t5.p1, obj.vertices[1]);
+        assert_eq!(t5.n1, obj.normals[3]);
+
+        // Scenario: Faces referring to missing vertices are ignored
+        let lines2 = vec!["v 0 1 0",
+            "v -1 0 0",
+            "v 1 0 0",
+            "f 1 2 4",
+            "f x 2 3",
+            "f 1/1/1/1 2 3"];
+        let obj2 = parse_obj_file(lines2);
+        assert!(obj2.default_group.get_children().is_empty());
+
+        // Scenario: Parsing a single face vertex
+        assert_eq!(parse_face_vertex("2/1/3", 3, 1, 3),
+            Some(FaceVertex{vertex: 2, texture: Some(1), normal: Some(3)}));
+        assert_eq!(parse_face_vertex("2//3", 3, 0, 3),
+            Some(FaceVertex{vertex: 2, texture: None, normal: Some(3)}));
+        assert_eq!(parse_face_vertex("0", 3, 0, 0), None);
+    }
+
+    #[test]
+    fn test_objfile_texture_coords()
+    {
+        // Scenario: Texture coordinates are attached to mesh triangles
+        let lines = vec!["v 0 1 0",
+            "v -1 0 0",
+            "v 1 0 0",
+            "vt 0.5 1",
+            "vt 0 0 0",
+            "vt 1",
+            "vn 0 0 -1",
+            "f 1/1 2/2 3/3",
+            "f 1/1/1 2/2/1 3/3/1",
+            "f 1 2 3"];
+        let obj = parse_obj_file(lines);
+        assert_eq!(obj.texture_coords, vec![(0.0, 0.0), (0.5, 1.0), (0.0, 0.0), (1.0, 0.0)]);
+        let children = obj.default_group.get_children();
+        assert_eq!(children[0].texture_uv_at((0.0, 0.0)), Some((0.5, 1.0)));
+        assert_eq!(children[1].texture_uv_at((0.5, 0.5)), Some((0.5, 0.0)));
+        assert_eq!(children[2].texture_uv_at((0.5, 0.5)), None);
+
+        // Scenario: The texture coordinates of a hit are part of its computations
+        let r = Ray::new(create_point(0.0, 0.5, -2.0), create_vector(0.0, 0.0, 1.0));
+        let mut t = children[0].clone();
+        let xs = t.intersect(r);
+        let comps = xs[0].prepare_computations(r, Intersections::new(xs.clone()));
+        let uv = comps.texture_uv.unwrap();
+        assert!(fuzzy_equal(uv.0, 0.5));
+        assert!(fuzzy_equal(uv.1, 0.5));
+    }
+
+    #[test]
+    fn test_objfile_group_styles()
+    {
+        // Scenario: Named groups can be given their own material and transform
+        let lines = vec!["v -1 1 0",
+            "v -1 0 0",
+            "v 1 0 0",
+            "g Body",
+            "f 1 2 3",
+            "g Wheel",
+            "f 1 2 3",
+            "f 3 2 1"];
+        let mut obj = parse_obj_file(lines);
+        let mut m = Material::new();
+        m.color = create_color(0.1, 0.1, 0.1);
+        assert!(obj.set_group_material("Wheel", m));
+        assert!(obj.set_group_transform("Wheel", Matrix::translation(0.0, -1.0, 0.0)));
+        assert!(!obj.set_group_material("Door", Material::new()));
+        assert!(!obj.set_group_transform("Door", Matrix::identity(4)));
+
+        let g = obj.obj_to_group();
+        let wheel = g.get_children().into_iter()
+            .find(|c| c.get_children().len() == 2).unwrap();
+        assert_eq!(wheel.get_transform(), Matrix::translation(0.0, -1.0, 0.0));
+        for triangle in wheel.get_children()
+        {
+            assert_eq!(triangle.get_material().color, create_color(0.1, 0.1, 0.1));
+        }
+        let body = g.get_children().into_iter()
+            .find(|c| c.get_children().len() == 1).unwrap();
+        assert_eq!(body.get_children()[0].get_material().color, create_color(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_objfile_report()
+    {
+        // Scenario: Skipped lines and counts are reported
+        let text = "# a comment\n\
+            o model\n\
+            v 0 1 0\n\
+            v -1 zero 0\n\
+            v -1 0 0\n\
+            v 1 0 0\n\
+            v 1 1 0\n\
+            vn 0 0 1\n\
+            vt\n\
+            g Top\n\
+            f 1 2 3 4\n\
+            f 1 2 9\n\
+            usemtl\n\
+            s off\n";
+        let (obj, report) = ObjFile::from_reader_with_report(text.as_bytes()).unwrap();
+        assert_eq!(report.ignored_lines, vec![2]);
+        assert_eq!(report.errors.len(), 4);
+        assert_eq!(report.errors[0], (4, String::from("invalid number 'zero'")));
+        assert_eq!(report.errors[1].0, 9);
+        assert_eq!(report.errors[2], (12, String::from("invalid face vertex '9'")));
+        assert_eq!(report.errors[3].0, 13);
+        assert_eq!(report.vertex_count, 4);
+        assert_eq!(report.normal_count, 1);
+        assert_eq!(report.texture_coord_count, 0);
+        assert_eq!(report.face_count, 1);
+        assert_eq!(report.triangle_count, 2);
+        assert_eq!(report.group_count, 1);
+        assert_eq!(obj.groups.get("Top").unwrap().get_children().len(), 2);
+
+        // Scenario: The same report comes from parsing lines
+        let (_, report2) = parse_obj_file_with_report(text.lines().collect());
+        assert_eq!(report2, report);
+    }
+
+    #[test]
+    fn test_objfile_triangulation()
+    {
+        // Scenario: Triangulating a concave polygon
+        // an arrow head, whose corner at p3 points inwards
+        let points = vec![create_point(0.0, 0.0, 0.0),
+            create_point(2.0, 1.0, 0.0),
+            create_point(0.0, 2.0, 0.0),
+            create_point(1.0, 1.0, 0.0)];
+        assert!(!is_convex_polygon(&points));
+        assert!(is_convex_polygon(&points[0..3].to_vec()));
+        let triangles = triangulate(&points, Triangulation::EarClipping);
+        assert_eq!(triangles.len(), 2);
+        // the fan would use the diagonal from p1 to p3, outside the face
+        for t in triangles.iter()
+        {
+            assert!(!(t.contains(&0) && t.contains(&2)));
+        }
+        assert_eq!(triangulate(&points, Triangulation::Auto), triangles);
+        assert_eq!(triangulate(&points, Triangulation::Fan), vec![[0, 1, 2], [0, 2, 3]]);
+
+        // Scenario: The triangulation can be chosen when loading
+        let text = "v 0 0 0\nv 2 1 0\nv 0 2 0\nv 1 1 0\nf 1 2 3 4\n";
+        let mut options = ObjOptions::new();
+        options.triangulation = Triangulation::Fan;
+        let (obj, _) = ObjFile::from_reader_with_options(text.as_bytes(), &options).unwrap();
+        let t1 = obj.default_group.get_children()[1].get_triangle();
+        assert_eq!(t1.p2, create_point(0.0, 2.0, 0.0));
+        let obj2 = ObjFile::from_reader(text.as_bytes()).unwrap();
+        for child in obj2.default_group.get_children()
+        {
+            let t2 = child.get_triangle();
+            assert!(!(t2.p1 == points[0] && t2.p2 == points[2]));
+        }
+    }
+
+    #[test]
+    fn test_objfile_smoothing_groups()
+    {
+        // Scenario: Faces in a smoothing group share averaged vertex normals
+        let lines = vec!["v -1 0 0",
+            "v 0 0 -1",
+            "v 0 1 0",
+            "v 1 0 0",
+            "v 0 -1 0",
+            "s 1",
+            "f 1 2 3",
+            "f 2 4 3",
+            "s off",
+            "f 1 5 2",
+            "s 2",
+            "f 5 4 2"];
+        let (obj, report) = parse_obj_file_with_report(lines);
+        assert!(report.errors.is_empty());
+        assert!(report.ignored_lines.is_empty());
+        let children = obj.default_group.get_children();
+        assert!(children[0].is_smooth_triangle());
+        assert!(children[1].is_smooth_triangle());
+        assert!(children[2].is_triangle());
+        assert!(children[3].is_smooth_triangle());
+
+        // the shared edge from v2 to v3 has the average of both face normals
+        let t1 = children[0].get_smooth_triangle();
+        let t2 = children[1].get_smooth_triangle();
+        assert_eq!(t1.n2, t2.n1);
+        assert_eq!(t1.n3, t2.n3);
+        let f1 = Triangle::new(t1.p1, t1.p2, t1.p3).normal;
+        let f2 = Triangle::new(t2.p1, t2.p2, t2.p3).normal;
+        assert_eq!(t1.n2, f1.add(f2).normalize());
+        assert_eq!(t1.n1, f1);
+        assert_eq!(t2.n2, f2);
+
+        // the lone face in smoothing group 2 is creased from group 1
+        let t4 = children[3].get_smooth_triangle();
+        let f4 = Triangle::new(t4.p1, t4.p2, t4.p3).normal;
+        assert_eq!(t4.n3, f4);
+    }
+
+    #[test]
+    fn test_objfile_computed_normals()
+    {
+        // Scenario: Computing vertex normals for a model without any
+        // a corner of a cube cut off by a slanted face, with the faces
+        // wound so their normals point inwards
+        let lines = vec!["v 0 0 0",
+            "v 1 0 0",
+            "v 0 1 0",
+            "v 0 0 1",
+            "f 1 3 2",
+            "g Sides",
+            "f 1 2 4",
+            "f 1 4 3",
+            "f 2 3 4"];
+        let obj = parse_obj_file(lines).with_computed_normals();
+        let bottom = obj.default_group.get_children()[0].clone();
+        assert!(bottom.is_smooth_triangle());
+        let sides = obj.groups.get("Sides").unwrap().get_children();
+        assert!(sides.iter().all(|s| s.is_smooth_triangle()));
+
+        // at the origin three faces meet at right angles, so their normals
+        // are weighted equally
+        let t1 = bottom.get_smooth_triangle();
+        let n = 1.0 / Scalar::sqrt(3.0);
+        assert_eq!(t1.n1, create_vector(n, n, n));
+
+        // at (0, 0, 1) the slanted face has a larger angle than the two
+        // faces on the axis planes, so its normal counts for more
+        let t2 = sides[0].get_smooth_triangle();
+        let eighth = PI / 4.0;
+        let sixth = PI / 3.0;
+        let expected = create_vector(0.0, eighth, 0.0)
+            .add(create_vector(eighth, 0.0, 0.0))
+            .add(create_vector(-n, -n, -n).multiply(sixth)).normalize();
+        assert_eq!(t2.n3, expected);
+    }
+
+    #[test]
+    fn test_objfile_reader()
+    {
+        // Scenario: Reading an OBJ file from any reader
+        let text = "v -1 1 0\nv -1 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3 4\n";
+        let obj = ObjFile::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(obj.vertices.len(), 4 + 1);
+        assert_eq!(obj.default_group.get_children().len(), 2);
+
+        // Scenario: A malformed number is reported with its line number
+        let text2 = "v -1 1 0\nv -1 zero 0\n";
+        match ObjFile::from_reader(text2.as_bytes())
+        {
+            Err(ObjError::Parse{line, message: _}) => assert_eq!(line, 2),
+            _ => panic!("expected a parse error"),
+        }
+
+        // Scenario: A face referring to a missing vertex is reported
+        let text3 = "v -1 1 0\nv -1 0 0\n\nf 1 2 3\n";
+        let error3 = ObjFile::from_reader(text3.as_bytes()).unwrap_err();
+        assert_eq!(format!("{}", error3), "line 4: invalid face vertex '3'");
+
+        // Scenario: A missing file is an I/O error
+        match ObjFile::from_path("/nonexistent/model.obj")
+        {
+            Err(ObjError::Io(_)) => (),
+            _ => panic!("expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn test_objfile_materials()
+    {
+        // Scenario: Triangles take the material named by usemtl
+        let dir = std::env::temp_dir().join("ray_tracer_test_objfile_materials");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("colors.mtl"),
+            "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n").unwrap();
+        std::fs::write(dir.join("model.obj"),
+            "mtllib colors.mtl\nv 0 1 0\nv -1 0 0\nv 1 0 0\n\
+            usemtl red\nf 1 2 3\nusemtl blue\nf 1 2 3\nf 3 2 1\n").unwrap();
+        let mut obj = ObjFile::from_path(dir.join("model.obj")).unwrap();
+        assert_eq!(obj.material_libraries, vec![String::from("colors.mtl")]);
+        assert_eq!(obj.materials.len(), 2);
+        let children = obj.default_group.get_children();
+        assert_eq!(children[0].get_material().color, create_color(1.0, 0.0, 0.0));
+        assert_eq!(children[1].get_material().color, create_color(0.0, 0.0, 1.0));
+        assert_eq!(children[2].get_material().color, create_color(0.0, 0.0, 1.0));
+
+        // Scenario: Overriding a material in the table
+        let mut green = Material::new();
+        green.color = create_color(0.0, 1.0, 0.0);
+        obj.materials.insert(String::from("blue"), Arc::new(green));
+        let g = obj.obj_to_group();
+        let meshes = g.get_children()[0].get_children();
+        assert_eq!(meshes[0].get_material().color, create_color(1.0, 0.0, 0.0));
+        assert_eq!(meshes[1].get_material().color, create_color(0.0, 1.0, 0.0));
+        assert_eq!(meshes[2].get_material().color, create_color(0.0, 1.0, 0.0));
+
+        // Scenario: A missing material library is an I/O error
+        std::fs::write(dir.join("broken.obj"), "mtllib missing.mtl\n").unwrap();
+        match ObjFile::from_path(dir.join("broken.obj"))
+        {
+            Err(ObjError::Io(_)) => (),
+            _ => panic!("expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn test_objfile_normalized()
+    {
+        // Scenario: A model is scaled and centered to fit the unit cube
+        let lines1 = vec![
+            "v 100 10 50",
+            "v 110 10 50",
+            "v 100 30 50",
+            "v 100 10 54",
+            "f 1 2 3",
+            "f 1 2 4"];
+        let obj1 = parse_obj_file(lines1);
+        let g1 = obj1.normalized();
+        let bounds1 = g1.parent_space_bounds();
+        assert_eq!(bounds1.min, create_point(-0.5, -1.0, -0.2));
+        assert_eq!(bounds1.max, create_point(0.5, 1.0, 0.2));
+    }
+
     #[test]
     fn test_objfile_feature19()
     {
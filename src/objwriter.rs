@@ -0,0 +1,279 @@
+use crate::arithmetic::consts::PI;
+use std::io;
+use std::io::Write;
+use crate::arithmetic::*;
+use crate::matrix::*;
+use crate::shape::*;
+use crate::tuple::*;
+
+// Number of steps around curved shapes when they are turned into triangles
+const SEGMENTS: usize = 24;
+
+// Writes shapes as triangles in an OBJ file, with all transforms applied
+pub struct ObjWriter<W: Write>
+{
+    out: W,
+    vertex_count: usize,
+    normal_count: usize,
+}
+
+impl<W: Write> ObjWriter<W>
+{
+    pub fn new(out: W) -> Self
+    {
+        ObjWriter{out, vertex_count: 0, normal_count: 0}
+    }
+
+    pub fn write_shapes(&mut self, shapes: &Vec<Shape>) -> io::Result<()>
+    {
+        for shape in shapes
+        {
+            writeln!(self.out, "g shape{}", shape.get_id())?;
+            self.write_shape(shape, &Matrix::identity(4))?;
+        }
+        self.out.flush()
+    }
+
+    fn write_shape(&mut self, shape: &Shape, parent_transform: &Matrix) -> io::Result<()>
+    {
+        let transform = parent_transform.multiply(&shape.get_transform());
+        match shape.get_specific()
+        {
+            // a CSG shape is written as both of its operands, since the
+            // parts it removes are not known until rays are cast
+            ShapeSpecific::Group(_) | ShapeSpecific::Csg(_) =>
+            {
+                for child in shape.get_children()
+                {
+                    self.write_shape(&child, &transform)?;
+                }
+            },
+            ShapeSpecific::Triangle(t) =>
+            {
+                self.write_triangle(&transform, t.p1, t.p2, t.p3)?;
+            },
+            ShapeSpecific::SmoothTriangle(t) =>
+            {
+                let normal_transform = transform.inverse().transpose();
+                let mut indices = Vec::new();
+                for (p, n) in [(t.p1, t.n1), (t.p2, t.n2), (t.p3, t.n3)]
+                {
+                    let v = self.write_vertex(&transform, p)?;
+                    let nv = normal_transform.multiply_tuple(n).get_vec();
+                    let n2 = create_vector(nv[0], nv[1], nv[2]).normalize().get_vec();
+                    writeln!(self.out, "vn {} {} {}", n2[0], n2[1], n2[2])?;
+                    self.normal_count = self.normal_count + 1;
+                    indices.push((v, self.normal_count));
+                }
+                writeln!(self.out, "f {}//{} {}//{} {}//{}",
+                    indices[0].0, indices[0].1, indices[1].0, indices[1].1,
+                    indices[2].0, indices[2].1)?;
+            },
+            ShapeSpecific::Cube(_) =>
+            {
+                self.write_cube(&transform)?;
+            },
+            ShapeSpecific::Sphere(_) =>
+            {
+                self.write_sphere(&transform)?;
+            },
+            ShapeSpecific::Cylinder(c) =>
+            {
+                self.write_revolved(&transform, c.minimum, c.maximum, c.closed,
+                    |_| 1.0)?;
+            },
+            ShapeSpecific::Cone(c) =>
+            {
+                self.write_revolved(&transform, c.minimum, c.maximum, c.closed,
+                    |y: Scalar| y.abs())?;
+            },
+            ShapeSpecific::Disc(d) =>
+            {
+                self.write_disc(&transform, d.inner_radius)?;
+            },
+            // planes, quadrics and metaballs have no edges (or none known
+            // without tracing rays), so cannot be written
+            ShapeSpecific::Plane(_) | ShapeSpecific::Quadric(_) | ShapeSpecific::Metaballs(_) => (),
+        }
+        Ok(())
+    }
+
+    fn write_vertex(&mut self, transform: &Matrix, point: Tuple) -> io::Result<usize>
+    {
+        let p = transform.multiply_tuple(point).get_vec();
+        writeln!(self.out, "v {} {} {}", p[0], p[1], p[2])?;
+        self.vertex_count = self.vertex_count + 1;
+        Ok(self.vertex_count)
+    }
+
+    fn write_triangle(&mut self, transform: &Matrix,
+        p1: Tuple, p2: Tuple, p3: Tuple) -> io::Result<()>
+    {
+        let v1 = self.write_vertex(transform, p1)?;
+        let v2 = self.write_vertex(transform, p2)?;
+        let v3 = self.write_vertex(transform, p3)?;
+        writeln!(self.out, "f {} {} {}", v1, v2, v3)
+    }
+
+    fn write_quad(&mut self, transform: &Matrix,
+        p1: Tuple, p2: Tuple, p3: Tuple, p4: Tuple) -> io::Result<()>
+    {
+        self.write_triangle(transform, p1, p2, p3)?;
+        self.write_triangle(transform, p1, p3, p4)
+    }
+
+    fn write_cube(&mut self, transform: &Matrix) -> io::Result<()>
+    {
+        let corner = |x: Scalar, y: Scalar, z: Scalar| create_point(x, y, z);
+        // -x, +x, -y, +y, -z, +z faces, counterclockwise seen from outside
+        self.write_quad(transform, corner(-1.0, -1.0, -1.0), corner(-1.0, -1.0, 1.0),
+            corner(-1.0, 1.0, 1.0), corner(-1.0, 1.0, -1.0))?;
+        self.write_quad(transform, corner(1.0, -1.0, -1.0), corner(1.0, 1.0, -1.0),
+            corner(1.0, 1.0, 1.0), corner(1.0, -1.0, 1.0))?;
+        self.write_quad(transform, corner(-1.0, -1.0, -1.0), corner(1.0, -1.0, -1.0),
+            corner(1.0, -1.0, 1.0), corner(-1.0, -1.0, 1.0))?;
+        self.write_quad(transform, corner(-1.0, 1.0, -1.0), corner(-1.0, 1.0, 1.0),
+            corner(1.0, 1.0, 1.0), corner(1.0, 1.0, -1.0))?;
+        self.write_quad(transform, corner(-1.0, -1.0, -1.0), corner(-1.0, 1.0, -1.0),
+            corner(1.0, 1.0, -1.0), corner(1.0, -1.0, -1.0))?;
+        self.write_quad(transform, corner(-1.0, -1.0, 1.0), corner(1.0, -1.0, 1.0),
+            corner(1.0, 1.0, 1.0), corner(-1.0, 1.0, 1.0))
+    }
+
+    fn write_sphere(&mut self, transform: &Matrix) -> io::Result<()>
+    {
+        let stacks = SEGMENTS / 2;
+        let point = |stack: usize, slice: usize|
+        {
+            let theta = PI * (stack as Scalar) / (stacks as Scalar);
+            let phi = 2.0 * PI * (slice as Scalar) / (SEGMENTS as Scalar);
+            create_point(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+        };
+        for stack in 0..stacks
+        {
+            for slice in 0..SEGMENTS
+            {
+                let p1 = point(stack, slice);
+                let p2 = point(stack, slice + 1);
+                let p3 = point(stack + 1, slice + 1);
+                let p4 = point(stack + 1, slice);
+                // the first and last stacks meet at the poles, where
+                // each quad has collapsed to a triangle
+                if stack == 0
+                {
+                    self.write_triangle(transform, p1, p3, p4)?;
+                }
+                else if stack == stacks - 1
+                {
+                    self.write_triangle(transform, p1, p2, p4)?;
+                }
+                else
+                {
+                    self.write_quad(transform, p1, p2, p3, p4)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes a shape made by sweeping a radius, which depends on y,
+    // around the y axis. Unbounded shapes are skipped.
+    fn write_disc(&mut self, transform: &Matrix, inner_radius: Scalar) -> io::Result<()>
+    {
+        let point = |radius: Scalar, slice: usize|
+        {
+            let phi = 2.0 * PI * (slice as Scalar) / (SEGMENTS as Scalar);
+            create_point(radius * phi.cos(), 0.0, radius * phi.sin())
+        };
+        for slice in 0..SEGMENTS
+        {
+            if inner_radius > 0.0
+            {
+                self.write_quad(transform, point(inner_radius, slice), point(inner_radius, slice + 1),
+                    point(1.0, slice + 1), point(1.0, slice))?;
+            }
+            else
+            {
+                self.write_triangle(transform, create_point(0.0, 0.0, 0.0),
+                    point(1.0, slice + 1), point(1.0, slice))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_revolved<F: Fn(Scalar) -> Scalar>(&mut self, transform: &Matrix,
+        minimum: Scalar, maximum: Scalar, closed: bool, radius: F) -> io::Result<()>
+    {
+        if minimum.is_infinite() || maximum.is_infinite()
+        {
+            return Ok(());
+        }
+        let point = |y: Scalar, slice: usize|
+        {
+            let phi = 2.0 * PI * (slice as Scalar) / (SEGMENTS as Scalar);
+            create_point(radius(y) * phi.cos(), y, radius(y) * phi.sin())
+        };
+        for slice in 0..SEGMENTS
+        {
+            self.write_quad(transform, point(minimum, slice), point(maximum, slice),
+                point(maximum, slice + 1), point(minimum, slice + 1))?;
+            if closed
+            {
+                self.write_triangle(transform, create_point(0.0, minimum, 0.0),
+                    point(minimum, slice), point(minimum, slice + 1))?;
+                self.write_triangle(transform, create_point(0.0, maximum, 0.0),
+                    point(maximum, slice + 1), point(maximum, slice))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::objfile::*;
+
+    #[test]
+    fn test_objwriter_feature()
+    {
+        // Scenario: Writing a transformed triangle
+        let mut t1 = Shape::new_triangle(1, create_point(0.0, 1.0, 0.0),
+            create_point(-1.0, 0.0, 0.0), create_point(1.0, 0.0, 0.0));
+        t1.set_transform(Matrix::translation(0.0, 0.0, 5.0));
+        let mut out1 = Vec::new();
+        ObjWriter::new(&mut out1).write_shapes(&vec![t1]).unwrap();
+        let obj1 = ObjFile::from_reader(out1.as_slice()).unwrap();
+        assert_eq!(obj1.vertices[1], create_point(0.0, 1.0, 5.0));
+        assert_eq!(obj1.vertices[3], create_point(1.0, 0.0, 5.0));
+        assert_eq!(obj1.groups.get("shape1").unwrap().get_children().len(), 1);
+
+        // Scenario: Primitives inside groups are written as triangles
+        let mut g2 = Shape::new_group(2);
+        g2.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
+        let mut cube2 = Shape::new_cube(3);
+        let mut sphere2 = Shape::new_sphere(4);
+        let mut plane2 = Shape::new_plane(5);
+        g2.add_child(cube2);
+        g2.add_child(sphere2);
+        g2.add_child(plane2);
+        let mut out2 = Vec::new();
+        ObjWriter::new(&mut out2).write_shapes(&vec![g2]).unwrap();
+        let obj2 = ObjFile::from_reader(out2.as_slice()).unwrap();
+        let triangles2 = obj2.groups.get("shape2").unwrap().get_children().len();
+        assert_eq!(triangles2, 12 + SEGMENTS * (SEGMENTS - 2));
+        assert!(obj2.vertices.contains(&create_point(2.0, 2.0, 2.0)));
+        assert!(obj2.vertices.contains(&create_point(0.0, -2.0, 0.0)));
+
+        // Scenario: A disc is a fan of triangles and a ring a band of them
+        let disc3 = Shape::new_disc(6, 0.0);
+        let ring3 = Shape::new_disc(7, 0.5);
+        let mut out3 = Vec::new();
+        ObjWriter::new(&mut out3).write_shapes(&vec![disc3, ring3]).unwrap();
+        let obj3 = ObjFile::from_reader(out3.as_slice()).unwrap();
+        assert_eq!(obj3.groups.get("shape6").unwrap().get_children().len(), SEGMENTS);
+        assert_eq!(obj3.groups.get("shape7").unwrap().get_children().len(), 2 * SEGMENTS);
+        assert!(obj3.vertices.contains(&create_point(0.5, 0.0, 0.0)));
+    }
+}
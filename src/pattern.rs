@@ -1,33 +1,60 @@
+use crate::arithmetic::consts::PI;
+use crate::arithmetic::*;
 use crate::material::*;
 use crate::matrix::*;
+use crate::noise::*;
 use crate::tuple::*;
 use crate::pointlight::*;
 use crate::shape::*;
+use crate::texturemap::*;
 
+// A single color everywhere, which fills the color slots of the other
+// patterns when they are given colors
+#[derive(Clone, Debug)]
+pub struct SolidPattern
+{
+    pub color: Tuple,
+}
+
+impl SolidPattern
+{
+    pub fn new(color: Tuple) -> SolidPattern
+    {
+        SolidPattern{color: color}
+    }
+
+    pub fn pattern_at(&self, _point: Tuple) -> Tuple
+    {
+        self.color
+    }
+}
+
+// Each pattern with two slots takes colors or whole patterns for them,
+// so that stripes can alternate between rings, say
 #[derive(Clone, Debug)]
 pub struct StripePattern
 {
-    pub a: Tuple,
-    pub b: Tuple,
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
 }
 
 impl StripePattern
 {
-    pub fn new(a: Tuple, b: Tuple) -> StripePattern
+    pub fn new(a: impl Into<Pattern>, b: impl Into<Pattern>) -> StripePattern
     {
-        StripePattern{a: a, b: b}
+        StripePattern{a: Box::new(a.into()), b: Box::new(b.into())}
     }
 
     pub fn stripe_at(&self, point: Tuple) -> Tuple
     {
         let x = point.get_vec()[0].floor();
-        if x.rem_euclid(2.0_f64) < 1.0
+        if x.rem_euclid(2.0) < 1.0
         {
-            self.a
+            self.a.pattern_at_nested(point)
         }
         else
         {
-            self.b
+            self.b.pattern_at_nested(point)
         }
     }
 
@@ -59,51 +86,111 @@ impl TestPattern
 #[derive(Clone, Debug)]
 pub struct GradientPattern
 {
-    pub a: Tuple,
-    pub b: Tuple,
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
 }
 
 impl GradientPattern
 {
-    pub fn new(a: Tuple, b: Tuple) -> GradientPattern
+    pub fn new(a: impl Into<Pattern>, b: impl Into<Pattern>) -> GradientPattern
     {
-        GradientPattern{a: a, b: b}
+        GradientPattern{a: Box::new(a.into()), b: Box::new(b.into())}
     }
 
     pub fn pattern_at(&self, point: Tuple) -> Tuple
     {
-        let distance = self.b.sub(self.a);
+        let a = self.a.pattern_at_nested(point);
+        let distance = self.b.pattern_at_nested(point).sub(a);
         let x = point.get_vec()[0];
         let fraction = x - x.floor();
-        self.a.add(distance.multiply(fraction))
+        a.add(distance.multiply(fraction))
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct RingPattern
 {
-    pub a: Tuple,
-    pub b: Tuple,
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
 }
 
 impl RingPattern
 {
-    pub fn new(a: Tuple, b: Tuple) -> RingPattern
+    pub fn new(a: impl Into<Pattern>, b: impl Into<Pattern>) -> RingPattern
     {
-        RingPattern{a: a, b: b}
+        RingPattern{a: Box::new(a.into()), b: Box::new(b.into())}
     }
 
     pub fn pattern_at(&self, point: Tuple) -> Tuple
     {
         let x = point.get_vec()[0];
         let z = point.get_vec()[2];
-        if ((x * x) + (z * z)).sqrt().floor().rem_euclid(2.0_f64) < 1.0
+        if ((x * x) + (z * z)).sqrt().floor().rem_euclid(2.0) < 1.0
         {
-            self.a
+            self.a.pattern_at_nested(point)
         }
         else
         {
-            self.b
+            self.b.pattern_at_nested(point)
+        }
+    }
+}
+
+// A gradient outward from the y axis, repeating every unit, which is the
+// smooth form of the rings
+#[derive(Clone, Debug)]
+pub struct RadialGradientPattern
+{
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
+}
+
+impl RadialGradientPattern
+{
+    pub fn new(a: impl Into<Pattern>, b: impl Into<Pattern>) -> RadialGradientPattern
+    {
+        RadialGradientPattern{a: Box::new(a.into()), b: Box::new(b.into())}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let a = self.a.pattern_at_nested(point);
+        let distance = self.b.pattern_at_nested(point).sub(a);
+        let v = point.get_vec();
+        let radius = ((v[0] * v[0]) + (v[2] * v[2])).sqrt();
+        a.add(distance.multiply(radius - radius.floor()))
+    }
+}
+
+// Two arms winding counterclockwise out from the y axis, looking down,
+// each a unit wide
+#[derive(Clone, Debug)]
+pub struct SpiralPattern
+{
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
+}
+
+impl SpiralPattern
+{
+    pub fn new(a: impl Into<Pattern>, b: impl Into<Pattern>) -> SpiralPattern
+    {
+        SpiralPattern{a: Box::new(a.into()), b: Box::new(b.into())}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let v = point.get_vec();
+        let radius = ((v[0] * v[0]) + (v[2] * v[2])).sqrt();
+        // a turn moves the bands out by two units, one of each arm
+        let turn = v[2].atan2(v[0]) / PI;
+        if (radius - turn).floor().rem_euclid(2.0) < 1.0
+        {
+            self.a.pattern_at_nested(point)
+        }
+        else
+        {
+            self.b.pattern_at_nested(point)
         }
     }
 }
@@ -111,28 +198,180 @@ impl RingPattern
 #[derive(Clone, Debug)]
 pub struct CheckerPattern
 {
-    pub a: Tuple,
-    pub b: Tuple,
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
 }
 
 impl CheckerPattern
 {
-    pub fn new(a: Tuple, b: Tuple) -> CheckerPattern
+    pub fn new(a: impl Into<Pattern>, b: impl Into<Pattern>) -> CheckerPattern
     {
-        CheckerPattern{a: a, b: b}
+        CheckerPattern{a: Box::new(a.into()), b: Box::new(b.into())}
     }
 
     pub fn pattern_at(&self, point: Tuple) -> Tuple
     {
         let v = point.get_vec();
         let sum = v[0].floor() + v[1].floor() + v[2].floor();
-        if sum.rem_euclid(2.0_f64) < 1.0
+        if sum.rem_euclid(2.0) < 1.0
         {
-            self.a
+            self.a.pattern_at_nested(point)
         }
         else
         {
-            self.b
+            self.b.pattern_at_nested(point)
+        }
+    }
+}
+
+// Colors at positions from 0 to 1, blended in between
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorRamp
+{
+    pub stops: Vec<(Scalar, Tuple)>,
+}
+
+impl ColorRamp
+{
+    // The stops must be in order of position
+    pub fn new(stops: Vec<(Scalar, Tuple)>) -> ColorRamp
+    {
+        ColorRamp{stops: stops}
+    }
+
+    // The colors spread evenly from 0 to 1
+    pub fn even(colors: &[Tuple]) -> ColorRamp
+    {
+        let last = (colors.len().max(2) - 1) as Scalar;
+        ColorRamp::new(colors.iter().enumerate().map(|(i, c)| (i as Scalar / last, *c)).collect())
+    }
+
+    pub fn color_at(&self, t: Scalar) -> Tuple
+    {
+        let first = self.stops[0];
+        if t <= first.0
+        {
+            return first.1;
+        }
+        for pair in self.stops.windows(2)
+        {
+            let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+            if t <= t1
+            {
+                let fraction = if t1 > t0 { (t - t0) / (t1 - t0) } else { 1.0 };
+                return c0.add(c1.sub(c0).multiply(fraction));
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+}
+
+// Rings around the y axis, wobbled by noise, for the grain of a log
+#[derive(Clone, Debug)]
+pub struct WoodPattern
+{
+    pub ramp: ColorRamp,
+    // rings per unit
+    pub scale: Scalar,
+}
+
+impl WoodPattern
+{
+    pub fn new(ramp: ColorRamp, scale: Scalar) -> WoodPattern
+    {
+        WoodPattern{ramp: ramp, scale: scale}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let v = point.get_vec();
+        let radius = ((v[0] * v[0]) + (v[2] * v[2])).sqrt() + 0.2 * perlin(point.multiply(2.0));
+        let ring = radius * self.scale;
+        self.ramp.color_at(ring - ring.floor())
+    }
+}
+
+// Veins across x, twisted by turbulence
+#[derive(Clone, Debug)]
+pub struct MarblePattern
+{
+    pub ramp: ColorRamp,
+    // veins per unit
+    pub scale: Scalar,
+}
+
+impl MarblePattern
+{
+    pub fn new(ramp: ColorRamp, scale: Scalar) -> MarblePattern
+    {
+        MarblePattern{ramp: ramp, scale: scale}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let x = point.get_vec()[0] + 2.0 * turbulence(point, 6);
+        self.ramp.color_at(0.5 + 0.5 * (x * self.scale * PI).sin())
+    }
+}
+
+// Speckles of turbulence at a fine grain
+#[derive(Clone, Debug)]
+pub struct GranitePattern
+{
+    pub ramp: ColorRamp,
+    // the size of the grain, larger for finer speckles
+    pub scale: Scalar,
+}
+
+impl GranitePattern
+{
+    pub fn new(ramp: ColorRamp, scale: Scalar) -> GranitePattern
+    {
+        GranitePattern{ramp: ramp, scale: scale}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let t = 2.0 * turbulence(point.multiply(self.scale), 4);
+        self.ramp.color_at(t.min(1.0))
+    }
+}
+
+// How a blended pattern combines the colors of its two patterns
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendMode
+{
+    Add,
+    Multiply,
+    // the weight of b, from 0 for all a to 1 for all b
+    Lerp(Scalar),
+}
+
+// Two patterns combined, each with its own transform inside the blend's
+#[derive(Clone, Debug)]
+pub struct BlendedPattern
+{
+    pub a: Box<Pattern>,
+    pub b: Box<Pattern>,
+    pub mode: BlendMode,
+}
+
+impl BlendedPattern
+{
+    pub fn new(a: Pattern, b: Pattern, mode: BlendMode) -> BlendedPattern
+    {
+        BlendedPattern{a: Box::new(a), b: Box::new(b), mode: mode}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let a = self.a.pattern_at_nested(point);
+        let b = self.b.pattern_at_nested(point);
+        match self.mode
+        {
+            BlendMode::Add => a.add(b),
+            BlendMode::Multiply => a.hadamard_product(b),
+            BlendMode::Lerp(t) => a.add(b.sub(a).multiply(t)),
         }
     }
 }
@@ -140,20 +379,38 @@ impl CheckerPattern
 #[derive(Clone, Debug)]
 pub enum PatternSpecific
 {
+    SolidPattern(SolidPattern),
     StripePattern(StripePattern),
     TestPattern(TestPattern),
     GradientPattern(GradientPattern),
     RingPattern(RingPattern),
     CheckerPattern(CheckerPattern),
+    RadialGradientPattern(RadialGradientPattern),
+    SpiralPattern(SpiralPattern),
+    TextureMapPattern(TextureMapPattern),
+    CubeMapPattern(Box<CubeMapPattern>),
+    WoodPattern(WoodPattern),
+    MarblePattern(MarblePattern),
+    GranitePattern(GranitePattern),
+    BlendedPattern(BlendedPattern),
 }
 
 #[derive(Clone, Debug)]
 pub struct Pattern
 {
     transform: Matrix,
+    inverse: Matrix,
     specific: PatternSpecific,
 }
 
+impl From<Tuple> for Pattern
+{
+    fn from(color: Tuple) -> Self
+    {
+        Pattern::new_solid_pattern(color)
+    }
+}
+
 impl Pattern
 {
     pub fn get_specific(&self) -> PatternSpecific
@@ -161,36 +418,90 @@ impl Pattern
         self.specific.clone()
     }
 
-    pub fn new_stripe_pattern(a: Tuple, b: Tuple) -> Pattern
+    pub fn new_solid_pattern(color: Tuple) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::SolidPattern(SolidPattern::new(color))}
+    }
+
+    pub fn new_stripe_pattern(a: impl Into<Pattern>, b: impl Into<Pattern>) -> Pattern
     {
-        Pattern{transform: Matrix::identity(4),
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
             specific: PatternSpecific::StripePattern(StripePattern::new(a, b))}
     }
 
     pub fn test_pattern() -> Pattern
     {
-        Pattern{transform: Matrix::identity(4),
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
             specific: PatternSpecific::TestPattern(TestPattern::new())}
     }
 
-    pub fn new_gradient_pattern(a: Tuple, b: Tuple) -> Pattern
+    pub fn new_gradient_pattern(a: impl Into<Pattern>, b: impl Into<Pattern>) -> Pattern
     {
-        Pattern{transform: Matrix::identity(4),
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
             specific: PatternSpecific::GradientPattern(GradientPattern::new(a, b))}
     }
 
-    pub fn new_ring_pattern(a: Tuple, b: Tuple) -> Pattern
+    pub fn new_ring_pattern(a: impl Into<Pattern>, b: impl Into<Pattern>) -> Pattern
     {
-        Pattern{transform: Matrix::identity(4),
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
             specific: PatternSpecific::RingPattern(RingPattern::new(a, b))}
     }
 
-    pub fn new_checker_pattern(a: Tuple, b: Tuple) -> Pattern
+    pub fn new_checker_pattern(a: impl Into<Pattern>, b: impl Into<Pattern>) -> Pattern
     {
-        Pattern{transform: Matrix::identity(4),
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
             specific: PatternSpecific::CheckerPattern(CheckerPattern::new(a, b))}
     }
 
+    pub fn new_radial_gradient_pattern(a: impl Into<Pattern>, b: impl Into<Pattern>) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::RadialGradientPattern(RadialGradientPattern::new(a, b))}
+    }
+
+    pub fn new_spiral_pattern(a: impl Into<Pattern>, b: impl Into<Pattern>) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::SpiralPattern(SpiralPattern::new(a, b))}
+    }
+
+    pub fn new_texture_map_pattern(uv_pattern: UvPattern, mapping: UvMapping) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::TextureMapPattern(TextureMapPattern::new(uv_pattern, mapping))}
+    }
+
+    pub fn new_cube_map_pattern(cube_map: CubeMapPattern) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::CubeMapPattern(Box::new(cube_map))}
+    }
+
+    pub fn new_wood_pattern(ramp: ColorRamp, scale: Scalar) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::WoodPattern(WoodPattern::new(ramp, scale))}
+    }
+
+    pub fn new_marble_pattern(ramp: ColorRamp, scale: Scalar) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::MarblePattern(MarblePattern::new(ramp, scale))}
+    }
+
+    pub fn new_granite_pattern(ramp: ColorRamp, scale: Scalar) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::GranitePattern(GranitePattern::new(ramp, scale))}
+    }
+
+    pub fn new_blended_pattern(a: Pattern, b: Pattern, mode: BlendMode) -> Pattern
+    {
+        Pattern{transform: Matrix::identity(4), inverse: Matrix::identity(4),
+            specific: PatternSpecific::BlendedPattern(BlendedPattern::new(a, b, mode))}
+    }
+
     pub fn get_pattern_transform(&self) -> Matrix
     {
         self.transform.clone()
@@ -198,20 +509,57 @@ impl Pattern
 
     pub fn set_pattern_transform(&mut self, transform: Matrix)
     {
+        self.inverse = transform.inverse();
         self.transform = transform;
     }
 
-    pub fn pattern_at_shape(&self, shape: Shape, world_point: Tuple) -> Tuple
+    pub fn get_inverse_transform(&self) -> &Matrix
+    {
+        &self.inverse
+    }
+
+    pub fn is_test_pattern(&self) -> bool
+    {
+        match self.specific
+        {
+            PatternSpecific::TestPattern(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn pattern_at_shape(&self, shape: &Shape, world_point: Tuple) -> Tuple
+    {
+        let object_point = shape.get_inverse_transform().multiply_tuple(world_point);
+        let pattern_point = self.inverse.multiply_tuple(object_point);
+        self.pattern_at(pattern_point)
+    }
+
+    // The color at a point in the space of the pattern this one is nested
+    // in, which is moved into this pattern's space first
+    pub fn pattern_at_nested(&self, parent_point: Tuple) -> Tuple
+    {
+        self.pattern_at(self.inverse.multiply_tuple(parent_point))
+    }
+
+    // The color at a point already in the pattern's space
+    pub fn pattern_at(&self, pattern_point: Tuple) -> Tuple
     {
-        let object_point = shape.get_transform().inverse().multiply_tuple(world_point);
-        let pattern_point = self.get_pattern_transform().inverse().multiply_tuple(object_point);
         match &self.specific
         {
+            PatternSpecific::SolidPattern(s) => s.pattern_at(pattern_point),
             PatternSpecific::StripePattern(s) => s.pattern_at(pattern_point),
             PatternSpecific::TestPattern(t) => t.pattern_at(pattern_point),
             PatternSpecific::GradientPattern(g) => g.pattern_at(pattern_point),
             PatternSpecific::RingPattern(r) => r.pattern_at(pattern_point),
             PatternSpecific::CheckerPattern(c) => c.pattern_at(pattern_point),
+            PatternSpecific::RadialGradientPattern(r) => r.pattern_at(pattern_point),
+            PatternSpecific::SpiralPattern(s) => s.pattern_at(pattern_point),
+            PatternSpecific::TextureMapPattern(m) => m.pattern_at(pattern_point),
+            PatternSpecific::CubeMapPattern(c) => c.pattern_at(pattern_point),
+            PatternSpecific::WoodPattern(w) => w.pattern_at(pattern_point),
+            PatternSpecific::MarblePattern(m) => m.pattern_at(pattern_point),
+            PatternSpecific::GranitePattern(g) => g.pattern_at(pattern_point),
+            PatternSpecific::BlendedPattern(b) => b.pattern_at(pattern_point),
         }
     }
 }
@@ -229,8 +577,8 @@ mod tests
 
         // p.128 Scenario: Creating a stripe pattern
         let p1 = StripePattern::new(white, black);
-        assert_eq!(p1.a, white);
-        assert_eq!(p1.b, black);
+        assert_eq!(p1.a.pattern_at(create_point(0.0, 0.0, 0.0)), white);
+        assert_eq!(p1.b.pattern_at(create_point(0.0, 0.0, 0.0)), black);
 
         // p.129 Scenario: A stripe pattern is constant in y
         let p2 = StripePattern::new(white, black);
@@ -264,9 +612,9 @@ mod tests
         let normalv5 = create_vector(0.0, 0.0, -1.0);
         let light5 = PointLight::new(create_point(0.0, 0.0, -10.0),
             create_color(1.0, 1.0, 1.0));
-        let c51 = m5.lighting(s5.clone(), light5, create_point(0.9, 0.0, 0.0),
+        let c51 = m5.lighting(&s5, light5, create_point(0.9, 0.0, 0.0),
             eyev5, normalv5, false);
-        let c52 = m5.lighting(s5.clone(), light5, create_point(1.1, 0.0, 0.0),
+        let c52 = m5.lighting(&s5, light5, create_point(1.1, 0.0, 0.0),
             eyev5, normalv5, false);
         assert_eq!(c51, white);
         assert_eq!(c52, black);
@@ -278,7 +626,7 @@ mod tests
         let p6 = Pattern::new_stripe_pattern(white, black);
         m6.pattern = Some(p6.clone());
         s6.set_material(m6);
-        let c6 = p6.pattern_at_shape(s6, create_point(1.5, 0.0, 0.0));
+        let c6 = p6.pattern_at_shape(&s6, create_point(1.5, 0.0, 0.0));
         assert_eq!(c6, white);
 
         // p.131 Scenario: Stripes with a pattern transformation
@@ -288,7 +636,7 @@ mod tests
         p7.set_pattern_transform(Matrix::scaling(2.0, 2.0, 2.0));
         m7.pattern = Some(p7.clone());
         s7.set_material(m7);
-        let c7 = p7.pattern_at_shape(s7, create_point(1.5, 0.0, 0.0));
+        let c7 = p7.pattern_at_shape(&s7, create_point(1.5, 0.0, 0.0));
         assert_eq!(c7, white);
 
         // p.131 Scenario: Stripes with both an object and a pattern transformation
@@ -299,7 +647,7 @@ mod tests
         p8.set_pattern_transform(Matrix::translation(0.5, 0.0, 0.0));
         m8.pattern = Some(p8.clone());
         s8.set_material(m8);
-        let c8 = p8.pattern_at_shape(s8, create_point(2.5, 0.0, 0.0));
+        let c8 = p8.pattern_at_shape(&s8, create_point(2.5, 0.0, 0.0));
         assert_eq!(c8, white);
 
         // p.133 Scenario: The default pattern transformation
@@ -315,14 +663,14 @@ mod tests
         let mut s11 = Shape::new_sphere(11);
         s11.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
         let p11 = Pattern::test_pattern();
-        let c11 = p11.pattern_at_shape(s11, create_point(2.0, 3.0, 4.0));
+        let c11 = p11.pattern_at_shape(&s11, create_point(2.0, 3.0, 4.0));
         assert_eq!(c11, create_color(1.0, 1.5, 2.0));
 
         // p.134 Scenario: A pattern with a pattern transformation
         let s12 = Shape::new_sphere(12);
         let mut p12 = Pattern::test_pattern();
         p12.set_pattern_transform(Matrix::scaling(2.0, 2.0, 2.0));
-        let c12 = p12.pattern_at_shape(s12, create_point(2.0, 3.0, 4.0));
+        let c12 = p12.pattern_at_shape(&s12, create_point(2.0, 3.0, 4.0));
         assert_eq!(c12, create_color(1.0, 1.5, 2.0));
 
         // p.134 Scenario: A pattern with both an object and pattern transformation
@@ -330,7 +678,7 @@ mod tests
         s13.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
         let mut p13 = Pattern::test_pattern();
         p13.set_pattern_transform(Matrix::translation(0.5, 1.0, 1.5));
-        let c13 = p13.pattern_at_shape(s13, create_point(2.5, 3.0, 3.5));
+        let c13 = p13.pattern_at_shape(&s13, create_point(2.5, 3.0, 3.5));
         assert_eq!(c13, create_color(0.75, 0.5, 0.25));
 
         // p.135 Scenario: A gradient linearly interpolates between colors
@@ -365,4 +713,138 @@ mod tests
         assert_eq!(p18.pattern_at(create_point(0.0, 0.0, 0.99)), white);
         assert_eq!(p18.pattern_at(create_point(0.0, 0.0, 1.01)), black);
     }
+
+    #[test]
+    fn test_pattern_procedural()
+    {
+        let black = create_color(0.0, 0.0, 0.0);
+        let white = create_color(1.0, 1.0, 1.0);
+        let red = create_color(1.0, 0.0, 0.0);
+
+        // Scenario: A color ramp blends between its stops
+        let ramp1 = ColorRamp::even(&[black, white, red]);
+        assert_eq!(ramp1.stops[1], (0.5, white));
+        assert_eq!(ramp1.color_at(-1.0), black);
+        assert_eq!(ramp1.color_at(0.25), create_color(0.5, 0.5, 0.5));
+        assert_eq!(ramp1.color_at(0.75), create_color(1.0, 0.5, 0.5));
+        assert_eq!(ramp1.color_at(2.0), red);
+
+        // Scenario: Wood, marble and granite start where the noise is zero
+        let ramp2 = ColorRamp::even(&[black, white]);
+        let wood = Pattern::new_wood_pattern(ramp2.clone(), 4.0);
+        let marble = Pattern::new_marble_pattern(ramp2.clone(), 2.0);
+        let granite = Pattern::new_granite_pattern(ramp2.clone(), 8.0);
+        let origin = create_point(0.0, 0.0, 0.0);
+        assert_eq!(wood.pattern_at(origin), black);
+        assert_eq!(marble.pattern_at(origin), create_color(0.5, 0.5, 0.5));
+        assert_eq!(granite.pattern_at(origin), black);
+
+        // Scenario: The procedural patterns only use colors from their ramp
+        for pattern in [&wood, &marble, &granite]
+        {
+            let shades: Vec<Scalar> = (0..100).map(|i| i as Scalar * 0.0731)
+                .map(|t| pattern.pattern_at(create_point(t.sin() * 2.0, t, t.cos() * 2.0)))
+                .map(|c| { let v = c.get_vec(); assert!(v[0] == v[1] && v[1] == v[2]); v[0] }).collect();
+            assert!(shades.iter().all(|s| (0.0..=1.0).contains(s)));
+            assert!(shades.iter().any(|s| *s < 0.4) && shades.iter().any(|s| *s > 0.6));
+        }
+
+        // Scenario: The scale sets the size of the features
+        let fine = WoodPattern::new(ramp2.clone(), 8.0);
+        let coarse = WoodPattern::new(ramp2, 1.0);
+        let p = create_point(0.3, 0.0, 0.0);
+        assert_ne!(fine.pattern_at(p), coarse.pattern_at(p));
+    }
+
+    #[test]
+    fn test_pattern_blended()
+    {
+        let black = create_color(0.0, 0.0, 0.0);
+        let white = create_color(1.0, 1.0, 1.0);
+        let red = create_color(1.0, 0.0, 0.0);
+        let p1 = create_point(0.25, 0.0, 0.0);
+
+        // Scenario: Blending checkers softly with a gradient
+        let checkers = Pattern::new_checker_pattern(white, black);
+        let gradient = Pattern::new_gradient_pattern(red, white);
+        let lerp = Pattern::new_blended_pattern(checkers.clone(), gradient.clone(), BlendMode::Lerp(0.5));
+        assert_eq!(lerp.pattern_at(p1), create_color(1.0, 0.625, 0.625));
+
+        // Scenario: Adding and multiplying patterns
+        let add = BlendedPattern::new(checkers.clone(), gradient.clone(), BlendMode::Add);
+        assert_eq!(add.pattern_at(p1), create_color(2.0, 1.25, 1.25));
+        let multiply = BlendedPattern::new(checkers.clone(), gradient, BlendMode::Multiply);
+        assert_eq!(multiply.pattern_at(p1), create_color(1.0, 0.25, 0.25));
+        assert_eq!(multiply.pattern_at(create_point(1.25, 0.0, 0.0)), black);
+
+        // Scenario: Each blended pattern has its own transform
+        let mut shifted = Pattern::new_stripe_pattern(white, black);
+        shifted.set_pattern_transform(Matrix::translation(1.0, 0.0, 0.0));
+        let blend = Pattern::new_blended_pattern(Pattern::new_stripe_pattern(white, black), shifted,
+            BlendMode::Lerp(0.5));
+        assert_eq!(blend.pattern_at(p1), create_color(0.5, 0.5, 0.5));
+        let s = Shape::new_sphere(1);
+        assert_eq!(blend.pattern_at_shape(&s, create_point(1.25, 0.0, 0.0)), create_color(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_pattern_nested()
+    {
+        let black = create_color(0.0, 0.0, 0.0);
+        let white = create_color(1.0, 1.0, 1.0);
+        let red = create_color(1.0, 0.0, 0.0);
+        let blue = create_color(0.0, 0.0, 1.0);
+
+        // Scenario: Stripes whose stripes are rings
+        let rings = Pattern::new_ring_pattern(red, blue);
+        let stripes = Pattern::new_stripe_pattern(rings, white);
+        assert_eq!(stripes.pattern_at(create_point(0.5, 0.0, 0.0)), red);
+        assert_eq!(stripes.pattern_at(create_point(0.5, 0.0, 1.0)), blue);
+        assert_eq!(stripes.pattern_at(create_point(1.5, 0.0, 1.0)), white);
+
+        // Scenario: A nested pattern is seen through its own transform
+        let mut small = Pattern::new_checker_pattern(black, white);
+        small.set_pattern_transform(Matrix::scaling(0.5, 0.5, 0.5));
+        let outer = CheckerPattern::new(small, red);
+        assert_eq!(outer.pattern_at(create_point(0.25, 0.25, 0.25)), black);
+        assert_eq!(outer.pattern_at(create_point(0.75, 0.25, 0.25)), white);
+        assert_eq!(outer.pattern_at(create_point(1.25, 0.25, 0.25)), red);
+
+        // Scenario: A gradient between two patterns
+        let gradient = GradientPattern::new(Pattern::new_stripe_pattern(red, blue), white);
+        assert_eq!(gradient.pattern_at(create_point(0.5, 0.0, 0.0)), create_color(1.0, 0.5, 0.5));
+        assert_eq!(gradient.pattern_at(create_point(1.5, 0.0, 0.0)), create_color(0.5, 0.5, 1.0));
+
+        // Scenario: Patterns nest to any depth
+        let deep = Pattern::new_stripe_pattern(Pattern::new_stripe_pattern(
+            Pattern::new_solid_pattern(red), black), white);
+        assert_eq!(deep.pattern_at(create_point(0.0, 0.0, 0.0)), red);
+    }
+
+    #[test]
+    fn test_pattern_radial()
+    {
+        let black = create_color(0.0, 0.0, 0.0);
+        let white = create_color(1.0, 1.0, 1.0);
+
+        // Scenario: A radial gradient blends outward in x and z
+        let p1 = RadialGradientPattern::new(white, black);
+        assert_eq!(p1.pattern_at(create_point(0.0, 0.0, 0.0)), white);
+        assert_eq!(p1.pattern_at(create_point(0.25, 0.0, 0.0)), create_color(0.75, 0.75, 0.75));
+        assert_eq!(p1.pattern_at(create_point(0.0, 5.0, -0.5)), create_color(0.5, 0.5, 0.5));
+        assert_eq!(p1.pattern_at(create_point(0.6, 0.0, 0.8)), p1.pattern_at(create_point(1.0, 0.0, 0.0)));
+        assert_eq!(p1.pattern_at(create_point(1.25, 0.0, 0.0)), create_color(0.75, 0.75, 0.75));
+
+        // Scenario: A spiral's bands move outward as they go around
+        let p2 = SpiralPattern::new(white, black);
+        assert_eq!(p2.pattern_at(create_point(0.5, 0.0, 0.0)), white);
+        assert_eq!(p2.pattern_at(create_point(1.5, 0.0, 0.0)), black);
+        assert_eq!(p2.pattern_at(create_point(2.5, 0.0, 0.0)), white);
+        // a quarter turn counterclockwise moves the first band out by half
+        assert_eq!(p2.pattern_at(create_point(0.0, 0.0, 0.4)), black);
+        assert_eq!(p2.pattern_at(create_point(0.0, 0.0, 0.6)), white);
+        // and a half turn by a whole band, where the other arm is
+        assert_eq!(p2.pattern_at(create_point(-0.5, 0.0, 0.01)), black);
+        assert_eq!(p2.pattern_at(create_point(-1.5, 0.0, 0.01)), white);
+    }
 }
@@ -1,6 +1,7 @@
 use std::fmt;
-use std::f64::consts::PI;
+use crate::arithmetic::consts::PI;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::intersections::*;
 use crate::tuple::*;
 use crate::ray::*;
@@ -22,23 +23,40 @@ impl Plane
         Plane{}
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
     {
         let u = 0.0;
         let v = 0.0;
-        if ray.direction.get_vec()[1].abs() < EPSILON
+        if ray.direction.to_array()[1].abs() < EPSILON
         {
             // empty set -- no intersection
-            return Vec::new();
+            return LocalHits::new();
         }
-        let t = -ray.origin.get_vec()[1] / ray.direction.get_vec()[1];
-        return vec![(t, u, v)];
+        let t = -ray.origin.to_array()[1] / ray.direction.to_array()[1];
+        return [(t, u, v)].into();
     }
 
-    pub fn local_normal_at(&self, point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
         create_vector(0.0, 1.0, 0.0)
     }
+
+    // The direction u grows in the planar mapping
+    pub fn local_tangent_at(&self, point: Tuple) -> Tuple
+    {
+        create_vector(1.0, 0.0, 0.0)
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        BoundingBox::new_with_corners(create_point(Scalar::NEG_INFINITY, 0.0, Scalar::NEG_INFINITY),
+            create_point(Scalar::INFINITY, 0.0, Scalar::INFINITY))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::infinite()
+    }
 }
 
 impl fmt::Display for Plane
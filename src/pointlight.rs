@@ -4,14 +4,34 @@ use crate::tuple::*;
 pub struct PointLight
 {
     pub position: Tuple,
-    pub intensity: Tuple
+    pub intensity: Tuple,
+    // For a directional light, like the sun, the way its light travels.
+    // It is so far away that every ray of it is parallel and the position
+    // is not used.
+    pub direction: Option<Tuple>,
 }
 
 impl PointLight
 {
     pub fn new(position: Tuple, intensity: Tuple) -> Self
     {
-        PointLight{position: position, intensity: intensity}
+        PointLight{position: position, intensity: intensity, direction: None}
+    }
+
+    pub fn new_directional(direction: Tuple, intensity: Tuple) -> Self
+    {
+        PointLight{position: create_point(0.0, 0.0, 0.0), intensity: intensity,
+            direction: Some(direction.normalize())}
+    }
+
+    // The unit vector from a point towards the light
+    pub fn direction_from(&self, point: Tuple) -> Tuple
+    {
+        match self.direction
+        {
+            Some(d) => d.negate(),
+            None => self.position.sub(point).normalize(),
+        }
     }
 }
 
@@ -29,5 +49,12 @@ mod tests
         let light1 = PointLight::new(position1, intensity1);
         assert_eq!(light1.position, position1);
         assert_eq!(light1.intensity, intensity1);
+        assert_eq!(light1.direction_from(create_point(0.0, 0.0, -2.0)), create_vector(0.0, 0.0, 1.0));
+
+        // Scenario: A directional light comes from the same direction everywhere
+        let light2 = PointLight::new_directional(create_vector(0.0, -2.0, 0.0), intensity1);
+        assert_eq!(light2.direction, Some(create_vector(0.0, -1.0, 0.0)));
+        assert_eq!(light2.direction_from(create_point(5.0, 0.0, -3.0)), create_vector(0.0, 1.0, 0.0));
+        assert_eq!(light2.direction_from(create_point(0.0, 100.0, 0.0)), create_vector(0.0, 1.0, 0.0));
     }
 }
@@ -0,0 +1,357 @@
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::tuple::*;
+
+// Makes bright parts of an image glow by blurring the light over a
+// threshold and adding it back, as a camera lens would scatter it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom
+{
+    // only the light above this, in the brightest channel, glows
+    pub threshold: Scalar,
+    // the standard deviation of the blur, in pixels
+    pub radius: Scalar,
+    // how much of the blurred light is added back
+    pub strength: Scalar,
+}
+
+impl Bloom
+{
+    pub fn new() -> Self
+    {
+        Bloom{threshold: 1.0, radius: 4.0, strength: 0.5}
+    }
+
+    pub fn apply(&self, image: &Canvas) -> Canvas
+    {
+        let mut bright = image.clone();
+        for pixel in bright.pixels.iter_mut()
+        {
+            *pixel = bright_part(*pixel, self.threshold);
+        }
+        let glow = gaussian_blur(&bright, self.radius);
+        let mut result = image.clone();
+        for (pixel, glow) in result.pixels.iter_mut().zip(glow.pixels.iter())
+        {
+            *pixel = pixel.add(glow.multiply(self.strength));
+        }
+        result
+    }
+}
+
+// Darkens the image toward its corners, as light falls off across a lens
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vignette
+{
+    // the share of the light lost at the corners
+    pub strength: Scalar,
+}
+
+impl Vignette
+{
+    pub fn apply(&self, image: &Canvas) -> Canvas
+    {
+        let mut result = image.clone();
+        for y in 0..image.height
+        {
+            for x in 0..image.width
+            {
+                let (dx, dy) = offset_from_center(image, x as Scalar + 0.5, y as Scalar + 0.5);
+                let r2 = dx * dx + dy * dy;
+                let factor = (1.0 - self.strength * r2).max(0.0);
+                result.write_pixel(x, y, image.pixel_at(x, y).multiply(factor));
+            }
+        }
+        result
+    }
+}
+
+// Spreads the red and blue channels apart toward the edges of the image,
+// as a lens that bends colors by different amounts would
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChromaticAberration
+{
+    // how far red and blue are moved, in pixels, at the corners
+    pub amount: Scalar,
+}
+
+impl ChromaticAberration
+{
+    pub fn apply(&self, image: &Canvas) -> Canvas
+    {
+        let cx = image.width as Scalar / 2.0;
+        let cy = image.height as Scalar / 2.0;
+        let half_diagonal = (cx * cx + cy * cy).sqrt().max(1.0);
+        let scale = self.amount / half_diagonal;
+        let mut result = image.clone();
+        for y in 0..image.height
+        {
+            for x in 0..image.width
+            {
+                let (px, py) = (x as Scalar + 0.5 - cx, y as Scalar + 0.5 - cy);
+                let red = sample(image, cx + px * (1.0 + scale), cy + py * (1.0 + scale)).get_vec()[0];
+                let green = image.pixel_at(x, y).get_vec()[1];
+                let blue = sample(image, cx + px * (1.0 - scale), cy + py * (1.0 - scale)).get_vec()[2];
+                result.write_pixel(x, y, create_color(red, green, blue));
+            }
+        }
+        result
+    }
+}
+
+// Brings the light in an image down to what can be shown, rolling bright
+// highlights off smoothly instead of clipping them to white
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMap
+{
+    // channels over 1 are clipped when the image is written
+    None,
+    // scales each color by L / (1 + L) of its luminance, which keeps its
+    // hue and never quite reaches white
+    Reinhard,
+    // the filmic curve of the Academy Color Encoding System, as fitted by
+    // Krzysztof Narkowicz, with more contrast and saturated highlights
+    Aces,
+}
+
+impl ToneMap
+{
+    pub fn map_color(&self, color: Tuple) -> Tuple
+    {
+        let c = color.to_array();
+        match self
+        {
+            ToneMap::None => color,
+            ToneMap::Reinhard =>
+            {
+                let luminance = 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2];
+                match luminance > 0.0
+                {
+                    true => color.multiply(1.0 / (1.0 + luminance)),
+                    false => color,
+                }
+            },
+            ToneMap::Aces =>
+            {
+                let curve = |x: Scalar| ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0);
+                create_color(curve(c[0]), curve(c[1]), curve(c[2]))
+            },
+        }
+    }
+
+    pub fn apply(&self, image: &Canvas) -> Canvas
+    {
+        let mut result = image.clone();
+        result.pixels.iter_mut().for_each(|p| *p = self.map_color(*p));
+        result
+    }
+}
+
+// The post effects a camera applies to its finished images, in the order
+// bloom, chromatic aberration and vignette, and then the tone map. The
+// camera's exposure is applied before any of them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PostEffects
+{
+    pub bloom: Option<Bloom>,
+    pub aberration: Option<ChromaticAberration>,
+    pub vignette: Option<Vignette>,
+    pub tone_map: ToneMap,
+}
+
+impl PostEffects
+{
+    pub fn new() -> Self
+    {
+        PostEffects{bloom: None, aberration: None, vignette: None, tone_map: ToneMap::None}
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.bloom.is_none() && self.aberration.is_none() && self.vignette.is_none() &&
+            self.tone_map == ToneMap::None
+    }
+
+    pub fn apply(&self, image: &Canvas) -> Canvas
+    {
+        let mut result = image.clone();
+        if let Some(bloom) = &self.bloom
+        {
+            result = bloom.apply(&result);
+        }
+        if let Some(aberration) = &self.aberration
+        {
+            result = aberration.apply(&result);
+        }
+        if let Some(vignette) = &self.vignette
+        {
+            result = vignette.apply(&result);
+        }
+        if self.tone_map != ToneMap::None
+        {
+            result = self.tone_map.apply(&result);
+        }
+        result
+    }
+}
+
+// How far a point is from the center of the image, scaled so that the
+// corners are at distance 1
+fn offset_from_center(image: &Canvas, x: Scalar, y: Scalar) -> (Scalar, Scalar)
+{
+    let cx = image.width as Scalar / 2.0;
+    let cy = image.height as Scalar / 2.0;
+    let half_diagonal = (cx * cx + cy * cy).sqrt().max(1.0);
+    ((x - cx) / half_diagonal, (y - cy) / half_diagonal)
+}
+
+// The color at a point between pixel centers, blending the four nearest
+// pixels. Points past the edges take the edge pixels.
+fn sample(image: &Canvas, x: Scalar, y: Scalar) -> Tuple
+{
+    let fx = (x - 0.5).clamp(0.0, (image.width - 1) as Scalar);
+    let fy = (y - 0.5).clamp(0.0, (image.height - 1) as Scalar);
+    let (x0, y0) = (fx.floor() as usize, fy.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(image.width - 1), (y0 + 1).min(image.height - 1));
+    let (tx, ty) = (fx - x0 as Scalar, fy - y0 as Scalar);
+    let top = image.pixel_at(x0, y0).multiply(1.0 - tx).add(image.pixel_at(x1, y0).multiply(tx));
+    let bottom = image.pixel_at(x0, y1).multiply(1.0 - tx).add(image.pixel_at(x1, y1).multiply(tx));
+    top.multiply(1.0 - ty).add(bottom.multiply(ty))
+}
+
+// The part of a color over the threshold, keeping its hue
+fn bright_part(color: Tuple, threshold: Scalar) -> Tuple
+{
+    let v = color.get_vec();
+    let brightest = v[0].max(v[1]).max(v[2]);
+    match brightest > threshold
+    {
+        true => color.multiply((brightest - threshold) / brightest),
+        false => create_color(0.0, 0.0, 0.0),
+    }
+}
+
+// Blurs across the rows and then down the columns, which gives the same
+// result as a 2D gaussian for far fewer samples. Pixels past the edges
+// repeat the edge pixels.
+pub fn gaussian_blur(image: &Canvas, sigma: Scalar) -> Canvas
+{
+    if sigma <= 0.0
+    {
+        return image.clone();
+    }
+    let radius = (sigma * 3.0).ceil() as isize;
+    let mut kernel: Vec<Scalar> = (-radius..=radius)
+        .map(|i| (-((i * i) as Scalar) / (2.0 * sigma * sigma)).exp()).collect();
+    let total: Scalar = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k = *k / total);
+
+    let blur = |source: &Canvas, dx: isize, dy: isize| -> Canvas
+    {
+        let mut result = Canvas::new(source.width, source.height);
+        for y in 0..source.height
+        {
+            for x in 0..source.width
+            {
+                let mut sum = create_color(0.0, 0.0, 0.0);
+                for (k, weight) in kernel.iter().enumerate()
+                {
+                    let offset = k as isize - radius;
+                    let sx = (x as isize + offset * dx).clamp(0, source.width as isize - 1) as usize;
+                    let sy = (y as isize + offset * dy).clamp(0, source.height as isize - 1) as usize;
+                    sum = sum.add(source.pixel_at(sx, sy).multiply(*weight));
+                }
+                result.write_pixel(x, y, sum);
+            }
+        }
+        result
+    };
+    blur(&blur(image, 1, 0), 0, 1)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_postprocess_tone_map()
+    {
+        // Scenario: Reinhard halves a color with a luminance of 1 and
+        // keeps its hue
+        let gray = create_color(1.0, 1.0, 1.0);
+        assert_eq!(ToneMap::Reinhard.map_color(gray), create_color(0.5, 0.5, 0.5));
+        let c1 = ToneMap::Reinhard.map_color(create_color(4.0, 2.0, 0.0)).to_array();
+        assert!(fuzzy_equal(c1[1], c1[0] * 0.5) && c1[0] < 4.0);
+        assert_eq!(ToneMap::Reinhard.map_color(create_color(0.0, 0.0, 0.0)), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: ACES leaves black alone and rolls bright light off to
+        // white
+        assert_eq!(ToneMap::Aces.map_color(create_color(0.0, 0.0, 0.0)), create_color(0.0, 0.0, 0.0));
+        let c2 = ToneMap::Aces.map_color(create_color(0.18, 1.0, 100.0)).to_array();
+        assert!(c2[0] > 0.2 && c2[0] < 0.3);
+        assert!(c2[1] > 0.75 && c2[1] < 0.85);
+        assert_eq!(c2[2], 1.0);
+
+        // Scenario: Tone mapping is the last of the effects
+        let mut canvas1 = Canvas::new(1, 1);
+        canvas1.write_pixel(0, 0, create_color(3.0, 3.0, 3.0));
+        let mut effects = PostEffects::new();
+        assert_eq!(effects.apply(&canvas1), canvas1);
+        effects.tone_map = ToneMap::Reinhard;
+        assert!(!effects.is_empty());
+        assert_eq!(effects.apply(&canvas1).pixel_at(0, 0), create_color(0.75, 0.75, 0.75));
+    }
+
+    #[test]
+    fn test_postprocess_feature()
+    {
+        // Scenario: Blurring keeps the total light and spreads it out
+        let mut canvas1 = Canvas::new(9, 9);
+        canvas1.write_pixel(4, 4, create_color(1.0, 1.0, 1.0));
+        let blurred1 = gaussian_blur(&canvas1, 1.0);
+        let total: Scalar = blurred1.pixels.iter().map(|p| p.get_vec()[0]).sum();
+        assert!(fuzzy_equal(total, 1.0));
+        assert!(blurred1.pixel_at(4, 4).get_vec()[0] < 0.2);
+        assert_eq!(blurred1.pixel_at(3, 4), blurred1.pixel_at(4, 5));
+        assert!(blurred1.pixel_at(3, 4).get_vec()[0] > blurred1.pixel_at(2, 4).get_vec()[0]);
+
+        // Scenario: Only light over the threshold blooms
+        let mut canvas2 = Canvas::new(9, 9);
+        canvas2.write_pixel(1, 1, create_color(0.9, 0.9, 0.9));
+        canvas2.write_pixel(7, 7, create_color(4.0, 2.0, 0.0));
+        let bloom = Bloom{threshold: 1.0, radius: 1.0, strength: 1.0};
+        let result2 = bloom.apply(&canvas2);
+        assert_eq!(result2.pixel_at(1, 0), create_color(0.0, 0.0, 0.0));
+        assert_eq!(result2.pixel_at(1, 1), create_color(0.9, 0.9, 0.9));
+        let glow = result2.pixel_at(6, 7).get_vec();
+        assert!(glow[0] > 0.0 && fuzzy_equal(glow[1], glow[0] * 0.5) && glow[2] == 0.0);
+
+        // Scenario: A vignette darkens the corners but not the center
+        let mut canvas3 = Canvas::new(10, 10);
+        canvas3.pixels.iter_mut().for_each(|p| *p = create_color(1.0, 1.0, 1.0));
+        let result3 = Vignette{strength: 0.5}.apply(&canvas3);
+        assert!(result3.pixel_at(5, 5).get_vec()[0] > 0.98);
+        assert!(result3.pixel_at(0, 0).get_vec()[0] < 0.6);
+        assert_eq!(result3.pixel_at(0, 0), result3.pixel_at(9, 9));
+
+        // Scenario: Chromatic aberration moves red out and blue in, and
+        // leaves the center alone
+        let mut canvas4 = Canvas::new(11, 1);
+        canvas4.write_pixel(9, 0, create_color(1.0, 1.0, 1.0));
+        canvas4.write_pixel(5, 0, create_color(0.5, 0.5, 0.5));
+        let result4 = ChromaticAberration{amount: 1.0}.apply(&canvas4);
+        assert_eq!(result4.pixel_at(5, 0), create_color(0.5, 0.5, 0.5));
+        assert_eq!(result4.pixel_at(9, 0).get_vec()[1], 1.0);
+        assert!(result4.pixel_at(8, 0).get_vec()[0] > 0.0);
+        assert_eq!(result4.pixel_at(10, 0).get_vec()[0], 0.0);
+        assert!(result4.pixel_at(10, 0).get_vec()[2] > 0.0);
+
+        // Scenario: No post effects leave the image as it was
+        let mut effects = PostEffects::new();
+        assert!(effects.is_empty());
+        assert_eq!(effects.apply(&canvas2), canvas2);
+        effects.vignette = Some(Vignette{strength: 0.5});
+        assert_eq!(effects.apply(&canvas3), result3);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::intersections::*;
+use crate::tuple::*;
+use crate::ray::*;
+
+// The surface where
+//   a x² + b y² + c z² + d xy + e xz + f yz + g x + h y + i z + j = 0
+// which, depending on the coefficients, is an ellipsoid, paraboloid,
+// hyperboloid, cone, cylinder or pair of planes. Most of these go on for
+// ever, so the parts outside the limits are cut away.
+#[derive(Clone, Debug)]
+pub struct Quadric
+{
+    // a to j, in the order of the equation
+    pub coefficients: [Scalar; 10],
+    pub limits: BoundingBox,
+}
+
+impl Quadric
+{
+    pub fn new(coefficients: [Scalar; 10], limits: BoundingBox) -> Self
+    {
+        Quadric{coefficients, limits}
+    }
+
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
+    {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coefficients;
+        let o = ray.origin.to_array();
+        let v = ray.direction.to_array();
+        // putting origin + t * direction into the equation gives a
+        // quadratic in t
+        let qa = a * v[0] * v[0] + b * v[1] * v[1] + c * v[2] * v[2] +
+            d * v[0] * v[1] + e * v[0] * v[2] + f * v[1] * v[2];
+        let qb = 2.0 * (a * o[0] * v[0] + b * o[1] * v[1] + c * o[2] * v[2]) +
+            d * (o[0] * v[1] + o[1] * v[0]) + e * (o[0] * v[2] + o[2] * v[0]) +
+            f * (o[1] * v[2] + o[2] * v[1]) + g * v[0] + h * v[1] + i * v[2];
+        let qc = a * o[0] * o[0] + b * o[1] * o[1] + c * o[2] * o[2] +
+            d * o[0] * o[1] + e * o[0] * o[2] + f * o[1] * o[2] +
+            g * o[0] + h * o[1] + i * o[2] + j;
+
+        let mut xs = LocalHits::new();
+        if qa.abs() < EPSILON
+        {
+            // the ray is parallel to the surface's axis, and crosses it
+            // at most once
+            if qb.abs() >= EPSILON
+            {
+                self.push_within_limits(ray, -qc / qb, &mut xs);
+            }
+            return xs;
+        }
+        let discriminant = qb * qb - 4.0 * qa * qc;
+        if discriminant < 0.0
+        {
+            return xs;
+        }
+        // worked out this way to avoid subtracting nearly equal numbers
+        let q = -0.5 * (qb + qb.signum() * discriminant.sqrt());
+        let (t0, t1) = match q == 0.0
+        {
+            true => (0.0, 0.0),
+            false => (q / qa, qc / q),
+        };
+        self.push_within_limits(ray, t0.min(t1), &mut xs);
+        self.push_within_limits(ray, t0.max(t1), &mut xs);
+        xs
+    }
+
+    fn push_within_limits(&self, ray: Ray, t: Scalar, xs: &mut LocalHits)
+    {
+        let p = ray.position(t).get_vec();
+        let min = self.limits.min.get_vec();
+        let max = self.limits.max.get_vec();
+        if (0..3).all(|k| min[k] - EPSILON <= p[k] && p[k] <= max[k] + EPSILON)
+        {
+            xs.push((t, 0.0, 0.0));
+        }
+    }
+
+    // The gradient of the equation, which points away from the surface
+    pub fn local_normal_at(&self, point: Tuple, _hit_uv: (Scalar, Scalar)) -> Tuple
+    {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients;
+        let p = point.to_array();
+        create_vector(2.0 * a * p[0] + d * p[1] + e * p[2] + g,
+            2.0 * b * p[1] + d * p[0] + f * p[2] + h,
+            2.0 * c * p[2] + e * p[0] + f * p[1] + i).normalize()
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        self.limits
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::from_box(&self.limits)
+    }
+}
+
+impl fmt::Display for Quadric
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "quadric {:?}", self.coefficients)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_quadric_feature()
+    {
+        // Scenario: The unit sphere as a quadric, x² + y² + z² - 1 = 0
+        let sphere1 = Quadric::new([1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0], BoundingBox::infinite());
+        let xs1 = sphere1.local_intersect(Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0)));
+        assert_eq!(xs1.len(), 2);
+        assert!(fuzzy_equal(xs1[0].0, 4.0) && fuzzy_equal(xs1[1].0, 6.0));
+        assert_eq!(sphere1.local_intersect(Ray::new(create_point(0.0, 2.0, -5.0),
+            create_vector(0.0, 0.0, 1.0))).len(), 0);
+        assert_eq!(sphere1.local_normal_at(create_point(0.0, 0.0, -1.0), (0.0, 0.0)),
+            create_vector(0.0, 0.0, -1.0));
+
+        // Scenario: A paraboloid, y = x² + z², crossed once along its axis
+        let mut paraboloid2 = Quadric::new([1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0], BoundingBox::infinite());
+        let xs2 = paraboloid2.local_intersect(Ray::new(create_point(0.0, 5.0, 0.0), create_vector(0.0, -1.0, 0.0)));
+        assert_eq!(xs2.len(), 1);
+        assert!(fuzzy_equal(xs2[0].0, 5.0));
+        assert_eq!(paraboloid2.local_normal_at(create_point(0.0, 0.0, 0.0), (0.0, 0.0)),
+            create_vector(0.0, -1.0, 0.0));
+        let ray2 = Ray::new(create_point(-5.0, 1.0, 0.0), create_vector(1.0, 0.0, 0.0));
+        let xs2b = paraboloid2.local_intersect(ray2);
+        assert_eq!(xs2b.len(), 2);
+        assert!(fuzzy_equal(xs2b[0].0, 4.0) && fuzzy_equal(xs2b[1].0, 6.0));
+
+        // Scenario: Limits cut a quadric down to size
+        paraboloid2.limits = BoundingBox::new_with_corners(create_point(-1.0, 0.0, -1.0),
+            create_point(0.5, 1.0, 1.0));
+        let xs3 = paraboloid2.local_intersect(ray2);
+        assert_eq!(xs3.len(), 1);
+        assert!(fuzzy_equal(xs3[0].0, 4.0));
+        assert_eq!(paraboloid2.bounds().max, create_point(0.5, 1.0, 1.0));
+
+        // Scenario: A hyperboloid of one sheet, x² - y² + z² - 1 = 0, has
+        // a waist of radius 1 and widens away from it
+        let hyperboloid4 = Quadric::new([1.0, -1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0], BoundingBox::infinite());
+        let xs4 = hyperboloid4.local_intersect(Ray::new(create_point(-5.0, 1.0, 0.0), create_vector(1.0, 0.0, 0.0)));
+        assert_eq!(xs4.len(), 2);
+        assert!(fuzzy_equal(xs4[0].0, 5.0 - (2.0 as Scalar).sqrt()));
+        assert!(hyperboloid4.bounds().is_infinite());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::arithmetic::*;
+use crate::matrix::*;
+use crate::tuple::*;
+
+// A rotation, stored as w + xi + yj + zk with a length of one. Unlike
+// angles around the x, y and z axes, two rotations can be blended
+// smoothly with slerp, as animation needs.
+#[derive(Copy, Clone, Debug)]
+pub struct Quaternion
+{
+    pub w: Scalar,
+    pub x: Scalar,
+    pub y: Scalar,
+    pub z: Scalar,
+}
+
+impl Quaternion
+{
+    pub fn new(w: Scalar, x: Scalar, y: Scalar, z: Scalar) -> Self
+    {
+        Quaternion{w, x, y, z}
+    }
+
+    // No rotation at all
+    pub fn identity() -> Self
+    {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    // A rotation by an angle around an axis, turning the same way as
+    // Matrix::rotation_axis
+    pub fn from_axis_angle(axis: Tuple, angle: Scalar) -> Self
+    {
+        let a = axis.normalize().to_array();
+        let (s, c) = ((angle / 2.0).sin(), (angle / 2.0).cos());
+        Quaternion::new(c, a[0] * s, a[1] * s, a[2] * s)
+    }
+
+    pub fn dot(&self, b: Quaternion) -> Scalar
+    {
+        self.w * b.w + self.x * b.x + self.y * b.y + self.z * b.z
+    }
+
+    pub fn normalize(&self) -> Quaternion
+    {
+        let m = self.dot(*self).sqrt();
+        Quaternion::new(self.w / m, self.x / m, self.y / m, self.z / m)
+    }
+
+    // The rotation that undoes this one
+    pub fn conjugate(&self) -> Quaternion
+    {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    // Rotating by b and then by this
+    pub fn multiply(&self, b: Quaternion) -> Quaternion
+    {
+        Quaternion::new(
+            self.w * b.w - self.x * b.x - self.y * b.y - self.z * b.z,
+            self.w * b.x + self.x * b.w + self.y * b.z - self.z * b.y,
+            self.w * b.y - self.x * b.z + self.y * b.w + self.z * b.x,
+            self.w * b.z + self.x * b.y - self.y * b.x + self.z * b.w)
+    }
+
+    // Rotates a point or vector
+    pub fn rotate(&self, t: Tuple) -> Tuple
+    {
+        self.to_matrix().multiply_tuple(t)
+    }
+
+    pub fn to_matrix(&self) -> Matrix
+    {
+        let q = self.normalize();
+        let (w, x, y, z) = (q.w, q.x, q.y, q.z);
+        Matrix::new(4, 4, &vec![
+            1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0,
+            2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0,
+            2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0,
+            0.0, 0.0, 0.0, 1.0])
+    }
+
+    // The rotation a share t of the way from this one to b, turning at an
+    // even speed along the shortest path
+    pub fn slerp(&self, b: Quaternion, t: Scalar) -> Quaternion
+    {
+        // q and -q are the same rotation; take the one nearer this
+        let mut cos = self.dot(b);
+        let mut b = b;
+        if cos < 0.0
+        {
+            cos = -cos;
+            b = Quaternion::new(-b.w, -b.x, -b.y, -b.z);
+        }
+        let (s0, s1) = match cos > 1.0 - EPSILON
+        {
+            // too close to divide by the sine, and a straight line is as good
+            true => (1.0 - t, t),
+            false =>
+            {
+                let angle = cos.acos();
+                let sin = angle.sin();
+                (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+            },
+        };
+        Quaternion::new(self.w * s0 + b.w * s1, self.x * s0 + b.x * s1,
+            self.y * s0 + b.y * s1, self.z * s0 + b.z * s1).normalize()
+    }
+}
+
+impl PartialEq for Quaternion
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        fuzzy_equal(self.w, other.w) && fuzzy_equal(self.x, other.x) &&
+            fuzzy_equal(self.y, other.y) && fuzzy_equal(self.z, other.z)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+
+    #[test]
+    fn test_quaternion_feature()
+    {
+        // Scenario: A quaternion gives the same matrix as an axis rotation
+        let axis1 = create_vector(1.0, 2.0, 3.0);
+        let q1 = Quaternion::from_axis_angle(axis1, 0.7);
+        assert_eq!(q1.to_matrix(), Matrix::rotation_axis(axis1, 0.7));
+        assert_eq!(Quaternion::from_axis_angle(create_vector(0.0, 1.0, 0.0), PI / 2.0)
+            .rotate(create_point(0.0, 0.0, 1.0)), create_point(1.0, 0.0, 0.0));
+        assert_eq!(Quaternion::identity().to_matrix(), Matrix::identity(4));
+
+        // Scenario: Multiplying quaternions combines their rotations
+        let q2 = Quaternion::from_axis_angle(create_vector(0.0, 0.0, 1.0), 0.4);
+        assert_eq!(q1.multiply(q2).to_matrix(), q1.to_matrix().multiply(&q2.to_matrix()));
+        assert_eq!(q1.multiply(q1.conjugate()), Quaternion::identity());
+
+        // Scenario: Slerp turns evenly between two rotations
+        let x3 = create_vector(1.0, 0.0, 0.0);
+        let start3 = Quaternion::from_axis_angle(x3, 0.2);
+        let end3 = Quaternion::from_axis_angle(x3, 1.4);
+        assert_eq!(start3.slerp(end3, 0.0), start3);
+        assert_eq!(start3.slerp(end3, 1.0), end3);
+        assert_eq!(start3.slerp(end3, 0.25), Quaternion::from_axis_angle(x3, 0.5));
+        assert_eq!(start3.slerp(start3, 0.5), start3);
+
+        // Scenario: Slerp takes the shorter way round
+        let end4 = Quaternion::from_axis_angle(x3, 2.0 * PI - 0.2);
+        assert_eq!(Quaternion::identity().slerp(end4, 0.5).to_matrix(), Matrix::rotation_x(-0.1));
+    }
+}
@@ -1,22 +1,56 @@
 use std::fmt;
+use crate::arithmetic::*;
 use crate::tuple::*;
 use crate::matrix::*;
 
+// The reason a ray was cast, so that shapes can choose which rays see them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RayKind
+{
+    Camera,
+    Reflection,
+    Refraction,
+    Shadow,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Ray
 {
     pub origin: Tuple,
-    pub direction: Tuple
+    pub direction: Tuple,
+    pub kind: RayKind,
+    // hits further along the ray than this are ignored
+    pub max_t: Scalar,
+    // when during the camera's shutter the ray was cast, from 0 to 1,
+    // which moving shapes are seen at
+    pub time: Scalar,
 }
 
 impl Ray
 {
     pub fn new(origin: Tuple, direction: Tuple) -> Self
     {
-        Ray{origin: origin, direction: direction}
+        Ray{origin: origin, direction: direction, kind: RayKind::Camera, max_t: Scalar::INFINITY, time: 0.0}
+    }
+
+    pub fn new_with_kind(origin: Tuple, direction: Tuple, kind: RayKind) -> Self
+    {
+        Ray{origin, direction, kind, max_t: Scalar::INFINITY, time: 0.0}
+    }
+
+    // The same ray, cast at another time
+    pub fn with_time(&self, time: Scalar) -> Ray
+    {
+        Ray{time, ..*self}
     }
 
-    pub fn position(&self, t: f64) -> Tuple
+    // The same ray, ignoring hits beyond t
+    pub fn with_max_t(&self, t: Scalar) -> Ray
+    {
+        Ray{max_t: t, ..*self}
+    }
+
+    pub fn position(&self, t: Scalar) -> Tuple
     {
         let ov = self.origin.get_vec();
         let dv = self.direction.get_vec();
@@ -25,10 +59,13 @@ impl Ray
             ov[2] + t * dv[2])
     }
 
-    pub fn transform(&self, m: Matrix) -> Ray
+    pub fn transform(&self, m: &Matrix) -> Ray
     {
         Ray{origin: m.multiply_tuple(self.origin),
-            direction: m.multiply_tuple(self.direction)}
+            direction: m.multiply_tuple(self.direction),
+            kind: self.kind,
+            max_t: self.max_t,
+            time: self.time}
     }
 }
 
@@ -73,15 +110,25 @@ mod tests
         // p.69 Scenario: Translating a ray
         let r3 = Ray::new(create_point(1.0, 2.0, 3.0), create_vector(0.0, 1.0, 0.0));
         let m3 = Matrix::translation(3.0, 4.0, 5.0);
-        let r3t = r3.transform(m3);
+        let r3t = r3.transform(&m3);
         assert_eq!(r3t.origin, create_point(4.0, 6.0, 8.0));
         assert_eq!(r3t.direction, create_vector(0.0, 1.0, 0.0));
 
         // p.69 Scenario: Scaling a ray
         let r4 = Ray::new(create_point(1.0, 2.0, 3.0), create_vector(0.0, 1.0, 0.0));
         let m4 = Matrix::scaling(2.0, 3.0, 4.0);
-        let r4t = r4.transform(m4);
+        let r4t = r4.transform(&m4);
         assert_eq!(r4t.origin, create_point(2.0, 6.0, 12.0));
         assert_eq!(r4t.direction, create_vector(0.0, 3.0, 0.0));
+
+        // Scenario: A ray's maximum distance is kept when it is transformed
+        assert_eq!(r4.max_t, Scalar::INFINITY);
+        let r5 = r4.with_max_t(7.5).transform(&m4);
+        assert_eq!(r5.max_t, 7.5);
+        assert_eq!(r5.position(r5.max_t), m4.multiply_tuple(r4.position(7.5)));
+
+        // Scenario: A ray's time is kept when it is transformed
+        assert_eq!(r4.time, 0.0);
+        assert_eq!(r4.with_time(0.25).transform(&m4).with_max_t(2.0).time, 0.25);
     }
 }
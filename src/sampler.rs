@@ -0,0 +1,439 @@
+use std::fmt;
+use crate::arithmetic::*;
+
+// Sequences of points in the unit square that cover it more evenly than
+// uniform random numbers, so that averaging over them (for anti-aliasing,
+// depth of field or soft shadows) converges with fewer samples
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SequenceKind
+{
+    // radical inverses in bases 2 and 3
+    Halton,
+    // the first two dimensions of the Sobol sequence, which are evenly
+    // spread for any power of two number of points
+    Sobol,
+}
+
+// The points of a low-discrepancy sequence in turn, all shifted by the
+// same offset (wrapping around) so that neighbouring pixels can use the
+// same sequence without their samples lining up
+#[derive(Clone, Debug)]
+pub struct LowDiscrepancy
+{
+    kind: SequenceKind,
+    index: u32,
+    offset: (Scalar, Scalar),
+}
+
+impl LowDiscrepancy
+{
+    pub fn new(kind: SequenceKind) -> Self
+    {
+        LowDiscrepancy{kind, index: 0, offset: (0.0, 0.0)}
+    }
+
+    // A sequence shifted by an offset made from the seed, such as the
+    // index of a pixel
+    pub fn new_with_seed(kind: SequenceKind, seed: u32) -> Self
+    {
+        let offset = (radical_inverse(hash(seed), 2), radical_inverse(hash(seed ^ 0x5bd1e995), 3));
+        LowDiscrepancy{kind, index: 0, offset}
+    }
+
+    pub fn get_kind(&self) -> SequenceKind
+    {
+        self.kind
+    }
+
+    // Starts the sequence again from its first point
+    pub fn reset(&mut self)
+    {
+        self.index = 0;
+    }
+
+    pub fn next_2d(&mut self) -> (Scalar, Scalar)
+    {
+        let (x, y) = sample_2d(self.kind, self.index);
+        self.index = self.index.wrapping_add(1);
+        (wrap(x + self.offset.0), wrap(y + self.offset.1))
+    }
+}
+
+// A choice of points in the unit square for averaging over, such as the
+// points of a pixel that rays are sent through. Each set of points is
+// made from a seed, so that the same seed always gives the same points.
+pub trait Sampler: fmt::Debug + Send
+{
+    // Starts a new set of points. Some samplers spread the points out
+    // better when they know how many will be taken.
+    fn start(&mut self, seed: u64, count: u32);
+
+    fn next_2d(&mut self) -> (Scalar, Scalar);
+}
+
+// The kinds of sampler, to choose one without making it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerKind
+{
+    Uniform,
+    Jittered,
+    Stratified,
+    Sequence(SequenceKind),
+}
+
+impl SamplerKind
+{
+    pub fn create(&self) -> Box<dyn Sampler>
+    {
+        match self
+        {
+            SamplerKind::Uniform => Box::new(UniformSampler::new()),
+            SamplerKind::Jittered => Box::new(JitteredSampler::new()),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new()),
+            SamplerKind::Sequence(kind) => Box::new(LowDiscrepancy::new(*kind)),
+        }
+    }
+}
+
+impl Sampler for LowDiscrepancy
+{
+    // the seed's two halves are mixed down to the 32 bits the offset is
+    // made from
+    fn start(&mut self, seed: u64, _count: u32)
+    {
+        *self = LowDiscrepancy::new_with_seed(self.kind, (seed ^ (seed >> 32)) as u32);
+    }
+
+    fn next_2d(&mut self) -> (Scalar, Scalar)
+    {
+        LowDiscrepancy::next_2d(self)
+    }
+}
+
+// A small, fast random number generator (PCG32) that gives the same
+// numbers for the same seed on every machine
+#[derive(Clone, Debug, PartialEq)]
+pub struct Random
+{
+    state: u64,
+}
+
+impl Random
+{
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Self
+    {
+        let mut random = Random{state: 0};
+        random.next_u32();
+        random.state = random.state.wrapping_add(seed);
+        random.next_u32();
+        random
+    }
+
+    pub fn next_u32(&mut self) -> u32
+    {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(Self::INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    // A number from 0 up to but not including 1
+    pub fn next_scalar(&mut self) -> Scalar
+    {
+        // 24 bits, so that f32 can't round up to 1
+        (self.next_u32() >> 8) as Scalar / 16777216.0
+    }
+
+    // A whole number from 0 up to but not including n
+    pub fn below(&mut self, n: u32) -> u32
+    {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+}
+
+// Points anywhere in the square, independently of each other, which may
+// clump together or leave gaps
+#[derive(Clone, Debug)]
+pub struct UniformSampler
+{
+    random: Random,
+}
+
+impl UniformSampler
+{
+    pub fn new() -> Self
+    {
+        UniformSampler{random: Random::new(0)}
+    }
+}
+
+impl Sampler for UniformSampler
+{
+    fn start(&mut self, seed: u64, _count: u32)
+    {
+        self.random = Random::new(seed);
+    }
+
+    fn next_2d(&mut self) -> (Scalar, Scalar)
+    {
+        (self.random.next_scalar(), self.random.next_scalar())
+    }
+}
+
+// The square is cut into a grid with about as many cells as points, and
+// each point is put somewhere random in the next cell, so that no part
+// of the square is missed. Points past the count start the grid again.
+#[derive(Clone, Debug)]
+pub struct JitteredSampler
+{
+    random: Random,
+    side: u32,
+    index: u32,
+}
+
+impl JitteredSampler
+{
+    pub fn new() -> Self
+    {
+        JitteredSampler{random: Random::new(0), side: 1, index: 0}
+    }
+}
+
+impl Sampler for JitteredSampler
+{
+    fn start(&mut self, seed: u64, count: u32)
+    {
+        self.random = Random::new(seed);
+        self.side = ((count.max(1) as Scalar).sqrt().ceil() as u32).max(1);
+        self.index = 0;
+    }
+
+    fn next_2d(&mut self) -> (Scalar, Scalar)
+    {
+        let cell = self.index % (self.side * self.side);
+        self.index = self.index.wrapping_add(1);
+        let size = 1.0 / self.side as Scalar;
+        let x = ((cell % self.side) as Scalar + self.random.next_scalar()) * size;
+        let y = ((cell / self.side) as Scalar + self.random.next_scalar()) * size;
+        (x, y)
+    }
+}
+
+// Each of the points is in its own column and its own row of a count by
+// count grid (a Latin hypercube), so the points are spread evenly along
+// each axis for any count, not just square numbers
+#[derive(Clone, Debug)]
+pub struct StratifiedSampler
+{
+    random: Random,
+    rows: Vec<u32>,
+    index: u32,
+}
+
+impl StratifiedSampler
+{
+    pub fn new() -> Self
+    {
+        StratifiedSampler{random: Random::new(0), rows: vec![0], index: 0}
+    }
+}
+
+impl Sampler for StratifiedSampler
+{
+    fn start(&mut self, seed: u64, count: u32)
+    {
+        self.random = Random::new(seed);
+        self.rows = (0..count.max(1)).collect();
+        // a Fisher-Yates shuffle picks a different row for each column
+        for i in (1..self.rows.len()).rev()
+        {
+            let j = self.random.below(i as u32 + 1) as usize;
+            self.rows.swap(i, j);
+        }
+        self.index = 0;
+    }
+
+    fn next_2d(&mut self) -> (Scalar, Scalar)
+    {
+        let n = self.rows.len() as u32;
+        let column = self.index % n;
+        self.index = self.index.wrapping_add(1);
+        let size = 1.0 / n as Scalar;
+        let x = (column as Scalar + self.random.next_scalar()) * size;
+        let y = (self.rows[column as usize] as Scalar + self.random.next_scalar()) * size;
+        (x, y)
+    }
+}
+
+// The point at an index of a sequence, without any offset
+pub fn sample_2d(kind: SequenceKind, index: u32) -> (Scalar, Scalar)
+{
+    match kind
+    {
+        SequenceKind::Halton => (radical_inverse(index, 2), radical_inverse(index, 3)),
+        SequenceKind::Sobol => (sobol(index, 0), sobol(index, 1)),
+    }
+}
+
+// The digits of the index in a base, mirrored around the decimal point
+pub fn radical_inverse(index: u32, base: u32) -> Scalar
+{
+    let inverse_base = 1.0 / base as Scalar;
+    let mut n = index;
+    let mut scale = inverse_base;
+    let mut result = 0.0;
+    while n > 0
+    {
+        result = result + (n % base) as Scalar * scale;
+        n = n / base;
+        scale = scale * inverse_base;
+    }
+    result
+}
+
+// A dimension (0 or 1) of the Sobol sequence. The first is the radical
+// inverse in base 2; the second uses the direction numbers of the
+// primitive polynomial x + 1.
+pub fn sobol(index: u32, dimension: usize) -> Scalar
+{
+    let mut bits = 0u32;
+    let mut direction = 1u32 << 31;
+    let mut n = index;
+    while n > 0
+    {
+        if n & 1 == 1
+        {
+            bits = bits ^ direction;
+        }
+        n = n >> 1;
+        direction = match dimension
+        {
+            0 => direction >> 1,
+            _ => direction ^ (direction >> 1),
+        };
+    }
+    bits as Scalar / 4294967296.0
+}
+
+fn wrap(value: Scalar) -> Scalar
+{
+    if value >= 1.0 { value - 1.0 } else { value }
+}
+
+// Mixes the bits of a number, so that nearby seeds give unrelated offsets
+fn hash(seed: u32) -> u32
+{
+    let mut h = seed.wrapping_mul(0x9e3779b9);
+    h = (h ^ (h >> 16)).wrapping_mul(0x85ebca6b);
+    h ^ (h >> 13)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_sampler_feature()
+    {
+        // Scenario: The radical inverse mirrors the digits of the index
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!(fuzzy_equal(radical_inverse(5, 3), 7.0 / 9.0));
+
+        // Scenario: The first points of the Halton and Sobol sequences
+        assert_eq!(sample_2d(SequenceKind::Halton, 0), (0.0, 0.0));
+        assert!(fuzzy_equal(sample_2d(SequenceKind::Halton, 2).1, 2.0 / 3.0));
+        let sobol1: Vec<(Scalar, Scalar)> = (0..4).map(|i| sample_2d(SequenceKind::Sobol, i)).collect();
+        assert_eq!(sobol1, vec![(0.0, 0.0), (0.5, 0.5), (0.25, 0.75), (0.75, 0.25)]);
+
+        // Scenario: Four Sobol points put one in each quarter of the square
+        let mut quarters = [false; 4];
+        for (x, y) in sobol1
+        {
+            quarters[(x * 2.0) as usize + 2 * (y * 2.0) as usize] = true;
+        }
+        assert_eq!(quarters, [true; 4]);
+
+        // Scenario: A shifted sequence stays in the unit square and can restart
+        let mut sequence = LowDiscrepancy::new_with_seed(SequenceKind::Halton, 42);
+        let first = sequence.next_2d();
+        for _ in 0..100
+        {
+            let (x, y) = sequence.next_2d();
+            assert!(x >= 0.0 && x < 1.0 && y >= 0.0 && y < 1.0);
+        }
+        sequence.reset();
+        assert_eq!(sequence.next_2d(), first);
+    }
+
+    #[test]
+    fn test_sampler_seeded()
+    {
+        // Scenario: The same seed gives the same random numbers
+        let mut random1 = Random::new(7);
+        let mut random2 = Random::new(7);
+        let first: Vec<u32> = (0..8).map(|_| random1.next_u32()).collect();
+        assert_eq!(first, (0..8).map(|_| random2.next_u32()).collect::<Vec<u32>>());
+        assert_ne!(Random::new(8).next_u32(), first[0]);
+        assert!((0..1000).all(|_| { let v = random1.next_scalar(); v >= 0.0 && v < 1.0 }));
+        assert!((0..1000).all(|_| random1.below(3) < 3));
+
+        for kind in [SamplerKind::Uniform, SamplerKind::Jittered, SamplerKind::Stratified,
+            SamplerKind::Sequence(SequenceKind::Sobol)]
+        {
+            // Scenario: Every sampler repeats its points for the same seed
+            let mut sampler = kind.create();
+            sampler.start(99, 16);
+            let points: Vec<(Scalar, Scalar)> = (0..16).map(|_| sampler.next_2d()).collect();
+            assert!(points.iter().all(|(x, y)| *x >= 0.0 && *x < 1.0 && *y >= 0.0 && *y < 1.0));
+            sampler.start(99, 16);
+            assert_eq!(points, (0..16).map(|_| sampler.next_2d()).collect::<Vec<(Scalar, Scalar)>>());
+            sampler.start(100, 16);
+            assert_ne!(points[0], sampler.next_2d());
+        }
+
+        // Scenario: Jittered points fall one in each cell of a grid
+        let mut jittered = JitteredSampler::new();
+        jittered.start(3, 4);
+        let mut quarters = [0; 4];
+        for _ in 0..4
+        {
+            let (x, y) = jittered.next_2d();
+            quarters[(x * 2.0) as usize + 2 * (y * 2.0) as usize] += 1;
+        }
+        assert_eq!(quarters, [1; 4]);
+
+        // Scenario: Stratified points fall one in each row and column
+        let mut stratified = StratifiedSampler::new();
+        stratified.start(3, 5);
+        let (mut columns, mut rows) = ([0; 5], [0; 5]);
+        for _ in 0..5
+        {
+            let (x, y) = stratified.next_2d();
+            columns[(x * 5.0) as usize] += 1;
+            rows[(y * 5.0) as usize] += 1;
+        }
+        assert_eq!((columns, rows), ([1; 5], [1; 5]));
+    }
+
+    #[test]
+    fn test_sampler_converges()
+    {
+        // Scenario: Estimating the area of a quarter circle with 1024 points
+        for kind in [SequenceKind::Halton, SequenceKind::Sobol]
+        {
+            let mut sequence = LowDiscrepancy::new(kind);
+            let inside = (0..1024).filter(|_|
+            {
+                let (x, y) = sequence.next_2d();
+                x * x + y * y < 1.0
+            }).count();
+            let area = inside as Scalar / 1024.0;
+            assert!((area - consts::PI / 4.0).abs() < 0.005);
+        }
+    }
+}
@@ -0,0 +1,1413 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::arithmetic::*;
+use crate::background::*;
+use crate::bounds::*;
+use crate::bump::*;
+use crate::camera::*;
+use crate::canvas::*;
+use crate::colors::*;
+use crate::csg::*;
+use crate::fog::*;
+use crate::integrator::*;
+use crate::material::*;
+use crate::matrix::*;
+use crate::metaballs::*;
+use crate::normalmap::*;
+use crate::objfile::*;
+use crate::pattern::*;
+use crate::pointlight::*;
+use crate::postprocess::*;
+use crate::sampler::*;
+use crate::shape::*;
+use crate::texturemap::*;
+use crate::tuple::*;
+use crate::world::*;
+use crate::yaml::*;
+
+// Problems found while loading a scene file
+#[derive(Debug)]
+pub enum SceneError
+{
+    Io(io::Error),
+    Syntax(YamlError),
+    // the file is valid YAML, but does not describe a scene
+    Invalid(String),
+    // a model placed with "add: obj" could not be loaded
+    Obj{file: String, error: ObjError},
+}
+
+impl fmt::Display for SceneError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Syntax(e) => write!(f, "{}", e),
+            SceneError::Invalid(message) => write!(f, "{}", message),
+            SceneError::Obj{file, error} => write!(f, "{}: {}", file, error),
+        }
+    }
+}
+
+impl error::Error for SceneError
+{
+}
+
+impl From<io::Error> for SceneError
+{
+    fn from(e: io::Error) -> Self
+    {
+        SceneError::Io(e)
+    }
+}
+
+impl From<YamlError> for SceneError
+{
+    fn from(e: YamlError) -> Self
+    {
+        SceneError::Syntax(e)
+    }
+}
+
+fn invalid(message: String) -> SceneError
+{
+    SceneError::Invalid(message)
+}
+
+// The world and camera described by a scene file
+#[derive(Clone, Debug)]
+pub struct Scene
+{
+    pub world: World,
+    pub camera: Option<Camera>,
+}
+
+impl Scene
+{
+    // Loads a YAML or JSON scene file. Files it refers to are found
+    // relative to the scene file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError>
+    {
+        let text = fs::read_to_string(path.as_ref())?;
+        let base_dir = match path.as_ref().parent()
+        {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::new(),
+        };
+        parse_scene(&text, &base_dir)
+    }
+}
+
+// Builds a scene from the list of directives in a scene file
+struct SceneBuilder
+{
+    base_dir: PathBuf,
+    defines: HashMap<String, Value>,
+    next_id: i32,
+    world: World,
+    camera: Option<Camera>,
+}
+
+fn number(value: Option<&Value>, what: &str) -> Result<Scalar, SceneError>
+{
+    match value.and_then(|v| v.as_scalar())
+    {
+        Some(n) => Ok(n),
+        None => Err(invalid(format!("'{}' must be a number", what))),
+    }
+}
+
+fn triple(value: Option<&Value>, what: &str) -> Result<[Scalar; 3], SceneError>
+{
+    let items = value.and_then(|v| v.as_list());
+    match items.map(|list| list.iter().filter_map(|v| v.as_scalar()).collect::<Vec<Scalar>>())
+    {
+        Some(n) if n.len() == 3 && items.unwrap().len() == 3 => Ok([n[0], n[1], n[2]]),
+        _ => Err(invalid(format!("'{}' must be a list of three numbers", what))),
+    }
+}
+
+// A color as a list of three numbers, or a string with a hex color, a
+// color name or a temperature such as "3200K"
+fn color(value: Option<&Value>, what: &str) -> Result<Tuple, SceneError>
+{
+    if let Some(text) = value.and_then(|v| v.as_str())
+    {
+        return parse_color(text).ok_or_else(|| invalid(format!("'{}' is not a color", text)));
+    }
+    let c = triple(value, what)?;
+    Ok(create_color(c[0], c[1], c[2]))
+}
+
+fn uv_mapping(value: Option<&Value>) -> Result<UvMapping, SceneError>
+{
+    match value.and_then(|v| v.as_str())
+    {
+        Some("spherical") => Ok(UvMapping::Spherical),
+        Some("planar") => Ok(UvMapping::Planar),
+        Some("cylindrical") => Ok(UvMapping::Cylindrical),
+        Some("cube") => Ok(UvMapping::Cube),
+        _ => Err(invalid(String::from("'mapping' must be spherical, planar, cylindrical or cube"))),
+    }
+}
+
+fn names(value: &Value, what: &str) -> Result<Vec<String>, SceneError>
+{
+    let error = || invalid(format!("'{}' must be a list of names", what));
+    let list = value.as_list().ok_or_else(error)?;
+    list.iter().map(|v| v.as_str().map(String::from).ok_or_else(error)).collect()
+}
+
+fn point(value: Option<&Value>, what: &str) -> Result<Tuple, SceneError>
+{
+    let p = triple(value, what)?;
+    Ok(create_point(p[0], p[1], p[2]))
+}
+
+fn vector(value: Option<&Value>, what: &str) -> Result<Tuple, SceneError>
+{
+    let v = triple(value, what)?;
+    Ok(create_vector(v[0], v[1], v[2]))
+}
+
+impl SceneBuilder
+{
+    fn new(base_dir: &Path) -> Self
+    {
+        let light = PointLight::new(create_point(-10.0, 10.0, -10.0),
+            create_color(1.0, 1.0, 1.0));
+        SceneBuilder{base_dir: base_dir.to_path_buf(), defines: HashMap::new(),
+            next_id: 1, world: World::new(light, Vec::new()), camera: None}
+    }
+
+    fn take_id(&mut self) -> i32
+    {
+        let id = self.next_id;
+        self.next_id = self.next_id + 1;
+        id
+    }
+
+    // Replaces the name of a define with its value
+    fn resolve<'a>(&'a self, value: &'a Value) -> Result<&'a Value, SceneError>
+    {
+        match value
+        {
+            Value::Text(name) => match self.defines.get(name)
+            {
+                Some(v) => Ok(v),
+                None => Err(invalid(format!("'{}' has not been defined", name))),
+            },
+            v => Ok(v),
+        }
+    }
+
+    fn add_item(&mut self, item: &Value) -> Result<(), SceneError>
+    {
+        if let Some(name) = item.get("define").and_then(|v| v.as_str())
+        {
+            return self.add_define(name, item);
+        }
+        match item.get("add").and_then(|v| v.as_str())
+        {
+            Some("camera") => self.add_camera(item),
+            Some("light") =>
+            {
+                let intensity = color(item.get("intensity"), "intensity")?;
+                self.world.light = match item.get("direction")
+                {
+                    // a light like the sun, infinitely far off
+                    Some(v) => PointLight::new_directional(vector(Some(v), "direction")?, intensity),
+                    None => PointLight::new(point(item.get("at"), "at")?, intensity),
+                };
+                Ok(())
+            },
+            Some("ambient") =>
+            {
+                self.world.ambient = color(item.get("intensity"), "intensity")?;
+                Ok(())
+            },
+            Some("fog") =>
+            {
+                let fog_color = color(item.get("color"), "color")?;
+                self.world.fog = match item.get("density")
+                {
+                    Some(d) => Some(Fog::Exponential{color: fog_color, density: number(Some(d), "density")?}),
+                    None => Some(Fog::Linear{color: fog_color, start: number(item.get("start"), "start")?,
+                        end: number(item.get("end"), "end")?}),
+                };
+                Ok(())
+            },
+            Some("background") =>
+            {
+                self.world.background = match (item.get("color"), item.get("environment"))
+                {
+                    (Some(c), _) => Background::Color(color(Some(c), "color")?),
+                    (None, Some(file)) =>
+                    {
+                        let file = file.as_str().ok_or_else(|| invalid(String::from("environment must be a file name")))?;
+                        let image = Canvas::from_ppm(io::BufReader::new(fs::File::open(self.base_dir.join(file))?))?;
+                        Background::Environment(Arc::new(image))
+                    },
+                    (None, None) => Background::Gradient{top: color(item.get("top"), "top")?,
+                        bottom: color(item.get("bottom"), "bottom")?},
+                };
+                Ok(())
+            },
+            Some("settings") =>
+            {
+                if let Some(depth) = item.get("max-depth")
+                {
+                    self.world.set_max_depth(number(Some(depth), "max-depth")? as i32);
+                }
+                if let Some(distance) = item.get("max-distance")
+                {
+                    self.world.set_max_distance(number(Some(distance), "max-distance")?);
+                }
+                let mut limits = self.world.get_sample_limits();
+                if let Some(value) = item.get("max-sample-value")
+                {
+                    limits.max_value = number(Some(value), "max-sample-value")?;
+                }
+                if let Some(value) = item.get("max-indirect-value")
+                {
+                    limits.max_indirect = number(Some(value), "max-indirect-value")?;
+                }
+                self.world.set_sample_limits(limits);
+                let mut tolerance = self.world.get_tolerance();
+                if let Some(value) = item.get("epsilon")
+                {
+                    tolerance.absolute = number(Some(value), "epsilon")?;
+                }
+                if let Some(value) = item.get("relative-epsilon")
+                {
+                    tolerance.relative = number(Some(value), "relative-epsilon")?;
+                }
+                self.world.set_tolerance(tolerance);
+                match item.get("integrator").map(|v| v.as_str())
+                {
+                    None => (),
+                    Some(Some("whitted")) => self.world.set_integrator(WhittedIntegrator),
+                    Some(Some("normals")) => self.world.set_integrator(NormalIntegrator),
+                    Some(Some("depth")) => self.world.set_integrator(DepthIntegrator{far: number(item.get("far"), "far")?}),
+                    Some(Some("path")) =>
+                    {
+                        let mut path = PathIntegrator::new();
+                        if let Some(samples) = item.get("samples")
+                        {
+                            path.samples = number(Some(samples), "samples")? as u32;
+                        }
+                        self.world.set_integrator(path);
+                    },
+                    Some(_) => return Err(invalid(String::from("'integrator' must be whitted, normals, depth or path"))),
+                }
+                let mut layers = self.world.get_layers().clone();
+                if let Some(include) = item.get("layers")
+                {
+                    layers.include = Some(names(include, "layers")?);
+                }
+                if let Some(exclude) = item.get("exclude-layers")
+                {
+                    layers.exclude = names(exclude, "exclude-layers")?;
+                }
+                self.world.set_layers(layers);
+                Ok(())
+            },
+            Some(_) =>
+            {
+                let shape = self.build_shape(item)?;
+                match item.get("name").and_then(|v| v.as_str())
+                {
+                    Some(name) => { self.world.add_named(name, shape); },
+                    None => self.world.objects.push(shape),
+                }
+                Ok(())
+            },
+            None => Err(invalid(String::from("each entry needs an 'add' or 'define' key"))),
+        }
+    }
+
+    // A define names a value for later use. Maps can extend another
+    // define, replacing or adding some of its keys.
+    fn add_define(&mut self, name: &str, item: &Value) -> Result<(), SceneError>
+    {
+        let value = match item.get("value")
+        {
+            Some(v) => v.clone(),
+            None => return Err(invalid(format!("define '{}' has no value", name))),
+        };
+        let value = match (item.get("extend"), value)
+        {
+            (Some(base), Value::Map(entries)) =>
+            {
+                let mut merged = match self.resolve(base)?
+                {
+                    Value::Map(base_entries) => base_entries.clone(),
+                    _ => return Err(invalid(format!("define '{}' can only extend a map", name))),
+                };
+                for (key, v) in entries
+                {
+                    merged.retain(|e| e.0 != key);
+                    merged.push((key, v));
+                }
+                Value::Map(merged)
+            },
+            (Some(_), _) => return Err(invalid(format!("define '{}' extends a value that is not a map", name))),
+            (None, v) => v,
+        };
+        self.defines.insert(String::from(name), value);
+        Ok(())
+    }
+
+    fn add_camera(&mut self, item: &Value) -> Result<(), SceneError>
+    {
+        let width = number(item.get("width"), "width")?;
+        let height = number(item.get("height"), "height")?;
+        let field_of_view = number(item.get("field-of-view"), "field-of-view")?;
+        if width < 1.0 || height < 1.0 || width > Scalar::from(u16::MAX) || height > Scalar::from(u16::MAX)
+        {
+            return Err(invalid(String::from("camera width and height must be between 1 and 65535")));
+        }
+        let mut camera = Camera::new(width as u16, height as u16, field_of_view);
+        camera.transform = Matrix::view_transform(point(item.get("from"), "from")?,
+            point(item.get("to"), "to")?, vector(item.get("up"), "up")?);
+        if let Some(exposure) = item.get("exposure")
+        {
+            camera.exposure = number(Some(exposure), "exposure")?;
+        }
+        if let Some(balance) = item.get("white-balance")
+        {
+            camera.white_balance = color(Some(balance), "white-balance")?;
+        }
+        if let Some(strength) = item.get("vignette")
+        {
+            camera.effects.vignette = Some(Vignette{strength: number(Some(strength), "vignette")?});
+        }
+        if let Some(amount) = item.get("chromatic-aberration")
+        {
+            camera.effects.aberration = Some(ChromaticAberration{amount: number(Some(amount),
+                "chromatic-aberration")?});
+        }
+        if let Some(tone_map) = item.get("tone-map")
+        {
+            camera.effects.tone_map = match tone_map.as_str()
+            {
+                Some("none") => ToneMap::None,
+                Some("reinhard") => ToneMap::Reinhard,
+                Some("aces") => ToneMap::Aces,
+                _ => return Err(invalid(String::from("'tone-map' must be none, reinhard or aces"))),
+            };
+        }
+        if let Some(samples) = item.get("min-samples")
+        {
+            camera.sampling.get_or_insert(AdaptiveSampling::new()).min_samples =
+                number(Some(samples), "min-samples")? as u32;
+        }
+        if let Some(samples) = item.get("max-samples")
+        {
+            camera.sampling.get_or_insert(AdaptiveSampling::new()).max_samples =
+                number(Some(samples), "max-samples")? as u32;
+        }
+        if let Some(threshold) = item.get("sample-threshold")
+        {
+            camera.sampling.get_or_insert(AdaptiveSampling::new()).threshold =
+                number(Some(threshold), "sample-threshold")?;
+        }
+        if let Some(projection) = item.get("projection")
+        {
+            camera.projection = match projection.as_str()
+            {
+                Some("perspective") => Projection::Perspective,
+                Some("fisheye") => Projection::Fisheye,
+                Some("equirectangular") => Projection::Equirectangular,
+                _ => return Err(invalid(String::from(
+                    "'projection' must be perspective, fisheye or equirectangular"))),
+            };
+        }
+        if let Some(aperture) = item.get("aperture")
+        {
+            camera.aperture = number(Some(aperture), "aperture")?;
+        }
+        if let Some(distance) = item.get("focal-distance")
+        {
+            camera.focal_distance = number(Some(distance), "focal-distance")?;
+        }
+        if let Some(open) = item.get("shutter-open")
+        {
+            camera.shutter_open = number(Some(open), "shutter-open")?;
+        }
+        if let Some(close) = item.get("shutter-close")
+        {
+            camera.shutter_close = number(Some(close), "shutter-close")?;
+        }
+        if let Some(samples) = item.get("blur-samples")
+        {
+            camera.blur_samples = number(Some(samples), "blur-samples")? as u32;
+        }
+        if let Some(crop) = item.get("crop")
+        {
+            let error = || invalid("'crop' must be a list of four pixel numbers".to_string());
+            let numbers = crop.as_list().ok_or_else(error)?.iter()
+                .map(|v| v.as_scalar().filter(|n| *n >= 0.0 && *n <= Scalar::from(u16::MAX)).map(|n| n as u16))
+                .collect::<Option<Vec<u16>>>().ok_or_else(error)?;
+            match numbers[..]
+            {
+                [x0, y0, x1, y1] => camera.set_crop(x0, y0, x1, y1),
+                _ => return Err(error()),
+            }
+        }
+        if let Some(distance) = item.get("interocular")
+        {
+            camera.interocular = number(Some(distance), "interocular")?;
+        }
+        if let Some(sampler) = item.get("sampler")
+        {
+            camera.sampler = match sampler.as_str()
+            {
+                Some("uniform") => SamplerKind::Uniform,
+                Some("jittered") => SamplerKind::Jittered,
+                Some("stratified") => SamplerKind::Stratified,
+                Some("halton") => SamplerKind::Sequence(SequenceKind::Halton),
+                Some("sobol") => SamplerKind::Sequence(SequenceKind::Sobol),
+                _ => return Err(invalid(String::from(
+                    "'sampler' must be uniform, jittered, stratified, halton or sobol"))),
+            };
+        }
+        if let Some(seed) = item.get("seed")
+        {
+            let seed = number(Some(seed), "seed")?;
+            if seed < 0.0 || seed.fract() != 0.0
+            {
+                return Err(invalid(String::from("'seed' must be a whole number")));
+            }
+            camera.seed = seed as u64;
+        }
+        if let Some(dither) = item.get("dither")
+        {
+            camera.dither = match dither.as_str()
+            {
+                Some("none") => Dither::None,
+                Some("ordered") => Dither::Ordered,
+                Some("floyd-steinberg") => Dither::FloydSteinberg,
+                _ => return Err(invalid(String::from("'dither' must be none, ordered or floyd-steinberg"))),
+            };
+        }
+        if let Some(bits) = item.get("bit-depth")
+        {
+            camera.bit_depth = match bits.as_scalar()
+            {
+                Some(b) if b == 8.0 => BitDepth::Eight,
+                Some(b) if b == 16.0 => BitDepth::Sixteen,
+                _ => return Err(invalid(String::from("'bit-depth' must be 8 or 16"))),
+            };
+        }
+        if let Some(gamma) = item.get("gamma")
+        {
+            camera.gamma = match (gamma.as_str(), gamma.as_scalar())
+            {
+                (Some("linear"), _) => Gamma::Linear,
+                (Some("srgb"), _) => Gamma::Srgb,
+                (_, Some(power)) if power > 0.0 => Gamma::Power(power),
+                _ => return Err(invalid(String::from("'gamma' must be linear, srgb or a positive number"))),
+            };
+        }
+        self.camera = Some(camera);
+        Ok(())
+    }
+
+    fn build_shape(&mut self, item: &Value) -> Result<Shape, SceneError>
+    {
+        let kind = match item.get("add").and_then(|v| v.as_str())
+        {
+            Some(k) => k,
+            None => return Err(invalid(String::from("a shape needs an 'add' key"))),
+        };
+        let id = self.take_id();
+        let mut shape = match kind
+        {
+            "sphere" => Shape::new_sphere(id),
+            "plane" => Shape::new_plane(id),
+            "cube" => Shape::new_cube(id),
+            "quadric" =>
+            {
+                let list = item.get("coefficients").and_then(|v| v.as_list());
+                let coefficients: Vec<Scalar> = list.iter().flat_map(|l| l.iter())
+                    .filter_map(|v| v.as_scalar()).collect();
+                if coefficients.len() != 10 || list.map_or(0, |l| l.len()) != 10
+                {
+                    return Err(invalid(String::from("'coefficients' must be a list of ten numbers")));
+                }
+                let mut limits = BoundingBox::infinite();
+                if let Some(v) = item.get("min")
+                {
+                    let min = triple(Some(v), "min")?;
+                    limits.min = create_point(min[0], min[1], min[2]);
+                }
+                if let Some(v) = item.get("max")
+                {
+                    let max = triple(Some(v), "max")?;
+                    limits.max = create_point(max[0], max[1], max[2]);
+                }
+                Shape::new_quadric(id, coefficients.try_into().unwrap(), limits)
+            },
+            "metaballs" =>
+            {
+                let error = || invalid(String::from("'balls' must be a list of balls with a center and radius"));
+                let list = item.get("balls").and_then(|v| v.as_list()).filter(|l| !l.is_empty()).ok_or_else(error)?;
+                let mut balls = Vec::with_capacity(list.len());
+                for ball in list
+                {
+                    if ball.get("center").is_none() || ball.get("radius").is_none()
+                    {
+                        return Err(error());
+                    }
+                    let mut metaball = Metaball::new(point(ball.get("center"), "center")?, number(ball.get("radius"), "radius")?);
+                    if let Some(v) = ball.get("strength")
+                    {
+                        metaball.strength = number(Some(v), "strength")?;
+                    }
+                    balls.push(metaball);
+                }
+                let threshold = match item.get("threshold")
+                {
+                    Some(v) => number(Some(v), "threshold")?,
+                    None => 0.5,
+                };
+                Shape::new_metaballs(id, balls, threshold)
+            },
+            "disc" => match item.get("inner")
+            {
+                Some(v) => Shape::new_disc(id, number(Some(v), "inner")?),
+                None => Shape::new_disc(id, 0.0),
+            },
+            "cylinder" | "cone" =>
+            {
+                let minimum = match item.get("min")
+                {
+                    Some(v) => number(Some(v), "min")?,
+                    None => Scalar::NEG_INFINITY,
+                };
+                let maximum = match item.get("max")
+                {
+                    Some(v) => number(Some(v), "max")?,
+                    None => Scalar::INFINITY,
+                };
+                let closed = item.get("closed").and_then(|v| v.as_bool()).unwrap_or(false);
+                match kind
+                {
+                    "cylinder" => Shape::new_cylinder(id, closed, minimum, maximum),
+                    _ => Shape::new_cone(id, closed, minimum, maximum),
+                }
+            },
+            "group" =>
+            {
+                let mut group = Shape::new_group(id);
+                if let Some(children) = item.get("children")
+                {
+                    let children = match children.as_list()
+                    {
+                        Some(c) => c,
+                        None => return Err(invalid(String::from("'children' must be a list"))),
+                    };
+                    for child in children
+                    {
+                        let c = self.build_shape(child)?;
+                        group.add_child(c);
+                    }
+                }
+                group
+            },
+            "csg" =>
+            {
+                let operation = match item.get("operation").and_then(|v| v.as_str())
+                {
+                    Some("union") => CsgOperation::Union,
+                    Some("intersection") => CsgOperation::Intersection,
+                    Some("difference") => CsgOperation::Difference,
+                    _ => return Err(invalid(String::from(
+                        "'operation' must be union, intersection or difference"))),
+                };
+                let (left, right) = match (item.get("left"), item.get("right"))
+                {
+                    (Some(l), Some(r)) => (l, r),
+                    _ => return Err(invalid(String::from("csg needs a 'left' and a 'right' shape"))),
+                };
+                let left = self.build_shape(left)?;
+                let right = self.build_shape(right)?;
+                Shape::new_csg(id, operation, left, right)
+            },
+            "obj" => self.load_obj(id, item)?,
+            _ => return Err(invalid(format!("unknown shape '{}'", kind))),
+        };
+
+        if let Some(t) = item.get("transform")
+        {
+            let transform = self.transform(t)?;
+            shape.set_transform(transform.multiply(&shape.get_transform()));
+        }
+        if let Some(m) = item.get("material")
+        {
+            let material = self.material(m)?;
+            // the triangles of an OBJ model are all given its material
+            match kind
+            {
+                "obj" => shape.set_all_materials(Arc::new(material)),
+                _ => shape.set_material(material),
+            }
+        }
+        if let Some(layer) = item.get("layer")
+        {
+            shape.set_layer(layer.as_str().ok_or_else(|| invalid(String::from("'layer' must be a name")))?);
+        }
+        if let Some(motion) = item.get("motion")
+        {
+            shape.set_motion(vector(Some(motion), "motion")?);
+        }
+        if let Some(casts_shadow) = item.get("casts-shadow")
+        {
+            let mut visibility = shape.get_visibility();
+            visibility.casts_shadow = casts_shadow.as_bool()
+                .ok_or_else(|| invalid(String::from("'casts-shadow' must be true or false")))?;
+            shape.set_visibility(visibility);
+        }
+        Ok(shape)
+    }
+
+    // Places an OBJ model, with "file" relative to the scene file. The
+    // model can be scaled to fit the unit cube with "normalize: true".
+    fn load_obj(&mut self, id: i32, item: &Value) -> Result<Shape, SceneError>
+    {
+        let file = match item.get("file").and_then(|v| v.as_str())
+        {
+            Some(f) => f,
+            None => return Err(invalid(String::from("obj needs a 'file'"))),
+        };
+        let mut options = ObjOptions::new();
+        options.first_id = id;
+        let (obj, report) = match ObjFile::from_path_with_options(self.base_dir.join(file), &options)
+        {
+            Ok(loaded) => loaded,
+            Err(error) => return Err(SceneError::Obj{file: String::from(file), error}),
+        };
+        self.next_id = report.last_id + 1;
+        match item.get("normalize").and_then(|v| v.as_bool())
+        {
+            Some(true) => Ok(obj.normalized()),
+            _ => Ok(obj.obj_to_group()),
+        }
+    }
+
+    // A transform is a list of operations, applied in the order they are
+    // written. Defined names in the list are replaced by their operations.
+    fn transform(&self, value: &Value) -> Result<Matrix, SceneError>
+    {
+        let operations = match self.resolve(value)?.as_list()
+        {
+            Some(list) => list,
+            None => return Err(invalid(String::from("'transform' must be a list"))),
+        };
+        let mut transform = Matrix::identity(4);
+        for operation in operations
+        {
+            let matrix = match operation
+            {
+                Value::Text(_) => self.transform(operation)?,
+                Value::List(parts) => self.transform_operation(parts)?,
+                _ => return Err(invalid(String::from("each transform must be a list or a defined name"))),
+            };
+            transform = matrix.multiply(&transform);
+        }
+        Ok(transform)
+    }
+
+    fn transform_operation(&self, parts: &Vec<Value>) -> Result<Matrix, SceneError>
+    {
+        let name = parts.get(0).and_then(|v| v.as_str()).unwrap_or("");
+        let args: Vec<Scalar> = parts.iter().skip(1).filter_map(|v| v.as_scalar()).collect();
+        let expected = match name
+        {
+            "translate" | "scale" => 3,
+            "rotate-x" | "rotate-y" | "rotate-z" => 1,
+            // an axis and an angle
+            "rotate" => 4,
+            "shear" => 6,
+            _ => return Err(invalid(format!("unknown transform '{}'", name))),
+        };
+        if args.len() != expected || parts.len() != expected + 1
+        {
+            return Err(invalid(format!("'{}' needs {} numbers", name, expected)));
+        }
+        Ok(match name
+        {
+            "translate" => Matrix::translation(args[0], args[1], args[2]),
+            "scale" => Matrix::scaling(args[0], args[1], args[2]),
+            "rotate-x" => Matrix::rotation_x(args[0]),
+            "rotate-y" => Matrix::rotation_y(args[0]),
+            "rotate-z" => Matrix::rotation_z(args[0]),
+            "rotate" => Matrix::rotation_axis(create_vector(args[0], args[1], args[2]), args[3]),
+            _ => Matrix::shearing(args[0], args[1], args[2], args[3], args[4], args[5]),
+        })
+    }
+
+    fn material(&self, value: &Value) -> Result<Material, SceneError>
+    {
+        let entries = match self.resolve(value)?
+        {
+            Value::Map(entries) => entries,
+            _ => return Err(invalid(String::from("'material' must be a map or a defined name"))),
+        };
+        let mut material = Material::new();
+        let (mut metallic, mut roughness) = (None, None);
+        for (key, v) in entries
+        {
+            match key.as_str()
+            {
+                "color" => material.color = color(Some(v), key)?,
+                "ambient" => material.ambient = number(Some(v), key)?,
+                "diffuse" => material.diffuse = number(Some(v), key)?,
+                "specular" => material.specular = number(Some(v), key)?,
+                "shininess" => material.shininess = number(Some(v), key)?,
+                "reflective" => material.reflective = number(Some(v), key)?,
+                "transparency" => material.transparency = number(Some(v), key)?,
+                "refractive-index" => material.refractive_index = number(Some(v), key)?,
+                "refraction-roughness" => material.refraction_roughness = number(Some(v), key)?,
+                "refraction-samples" => material.refraction_samples = number(Some(v), key)? as u32,
+                "pattern" => material.pattern = Some(self.pattern(v)?),
+                "bump" => material.bump = Some(self.bump(v)?),
+                "normal-map" => material.normal_map = Some(self.normal_map(v)?),
+                "metallic" => metallic = Some(number(Some(v), key)?),
+                "roughness" => roughness = Some(number(Some(v), key)?),
+                _ => return Err(invalid(format!("unknown material setting '{}'", key))),
+            }
+        }
+        // either setting switches the material to physically based shading
+        if metallic.is_some() || roughness.is_some()
+        {
+            material.shading = Shading::Pbr{metallic: metallic.unwrap_or(0.0), roughness: roughness.unwrap_or(0.5)};
+        }
+        Ok(material)
+    }
+
+    fn normal_map(&self, value: &Value) -> Result<NormalMap, SceneError>
+    {
+        let value = self.resolve(value)?;
+        let file = value.get("file").and_then(|v| v.as_str())
+            .ok_or_else(|| invalid(String::from("a normal map needs a 'file'")))?;
+        // the normals are data, so they are read without gamma
+        let image = Canvas::from_ppm_with_gamma(io::BufReader::new(fs::File::open(self.base_dir.join(file))?),
+            Gamma::Linear)?;
+        // shapes with texture coordinates use those instead
+        let mapping = match value.get("mapping")
+        {
+            Some(m) => uv_mapping(Some(m))?,
+            None => UvMapping::Planar,
+        };
+        Ok(NormalMap::new(Arc::new(image), mapping))
+    }
+
+    fn bump(&self, value: &Value) -> Result<Bump, SceneError>
+    {
+        let value = self.resolve(value)?;
+        let amount = number(value.get("amount"), "amount")?;
+        match value.get("type").and_then(|v| v.as_str())
+        {
+            Some("noise") =>
+            {
+                let scale = match value.get("scale")
+                {
+                    Some(s) => number(Some(s), "scale")?,
+                    None => 1.0,
+                };
+                Ok(Bump::noise(scale, amount))
+            },
+            Some("pattern") => match value.get("pattern")
+            {
+                Some(p) => Ok(Bump::pattern(self.pattern(p)?, amount)),
+                None => Err(invalid(String::from("a pattern bump needs a 'pattern'"))),
+            },
+            _ => Err(invalid(String::from("bump 'type' must be noise or pattern"))),
+        }
+    }
+
+    fn pattern(&self, value: &Value) -> Result<Pattern, SceneError>
+    {
+        let value = self.resolve(value)?;
+        let kind = value.get("type").and_then(|v| v.as_str());
+        let mut pattern = if kind == Some("map")
+        {
+            let mapping = uv_mapping(value.get("mapping"))?;
+            Pattern::new_texture_map_pattern(self.uv_pattern(value, "uv-pattern")?, mapping)
+        }
+        else if kind == Some("cube-map")
+        {
+            Pattern::new_cube_map_pattern(CubeMapPattern::new(self.uv_pattern(value, "left")?,
+                self.uv_pattern(value, "front")?, self.uv_pattern(value, "right")?,
+                self.uv_pattern(value, "back")?, self.uv_pattern(value, "up")?, self.uv_pattern(value, "down")?))
+        }
+        else if kind == Some("blend")
+        {
+            let patterns = match value.get("patterns").and_then(|v| v.as_list())
+            {
+                Some(p) if p.len() == 2 => p,
+                _ => return Err(invalid(String::from("a blend pattern needs two 'patterns'"))),
+            };
+            let mode = match value.get("mode").and_then(|v| v.as_str())
+            {
+                Some("add") => BlendMode::Add,
+                Some("multiply") => BlendMode::Multiply,
+                Some("lerp") | None => match value.get("weight")
+                {
+                    Some(w) => BlendMode::Lerp(number(Some(w), "weight")?),
+                    None => BlendMode::Lerp(0.5),
+                },
+                _ => return Err(invalid(String::from("blend 'mode' must be add, multiply or lerp"))),
+            };
+            Pattern::new_blended_pattern(self.pattern(&patterns[0])?, self.pattern(&patterns[1])?, mode)
+        }
+        else if let Some(kind @ ("wood" | "marble" | "granite")) = kind
+        {
+            // a ramp of the colors, spread evenly
+            let colors = match value.get("colors").and_then(|v| v.as_list())
+            {
+                Some(c) if c.len() >= 2 => c.iter().map(|c| color(Some(c), "colors")).collect::<Result<Vec<_>, _>>()?,
+                _ => return Err(invalid(format!("a {} pattern needs at least two 'colors'", kind))),
+            };
+            let ramp = ColorRamp::even(&colors);
+            let scale = match value.get("scale")
+            {
+                Some(s) => number(Some(s), "scale")?,
+                None => 1.0,
+            };
+            match kind
+            {
+                "wood" => Pattern::new_wood_pattern(ramp, scale),
+                "marble" => Pattern::new_marble_pattern(ramp, scale),
+                _ => Pattern::new_granite_pattern(ramp, scale),
+            }
+        }
+        else
+        {
+            let colors = match value.get("colors").and_then(|v| v.as_list())
+            {
+                Some(c) if c.len() == 2 => c,
+                _ => return Err(invalid(String::from("a pattern needs two 'colors'"))),
+            };
+            let a = self.pattern_slot(&colors[0])?;
+            let b = self.pattern_slot(&colors[1])?;
+            match kind
+            {
+                Some("stripes") => Pattern::new_stripe_pattern(a, b),
+                Some("gradient") => Pattern::new_gradient_pattern(a, b),
+                Some("rings") => Pattern::new_ring_pattern(a, b),
+                Some("checkers") => Pattern::new_checker_pattern(a, b),
+                Some("radial-gradient") => Pattern::new_radial_gradient_pattern(a, b),
+                Some("spiral") => Pattern::new_spiral_pattern(a, b),
+                _ => return Err(invalid(String::from(concat!("pattern 'type' must be stripes, gradient, rings, ",
+                    "checkers, radial-gradient, spiral, map, cube-map, wood, marble, granite or blend")))),
+            }
+        };
+        if let Some(t) = value.get("transform")
+        {
+            pattern.set_pattern_transform(self.transform(t)?);
+        }
+        Ok(pattern)
+    }
+
+    // One of the two colors of a pattern, which may be a pattern itself
+    fn pattern_slot(&self, value: &Value) -> Result<Pattern, SceneError>
+    {
+        let value = match value
+        {
+            Value::Text(name) if self.defines.contains_key(name) => self.resolve(value)?,
+            v => v,
+        };
+        match value.get("type")
+        {
+            Some(_) => self.pattern(value),
+            None => Ok(Pattern::from(color(Some(value), "colors")?)),
+        }
+    }
+
+    // The UV pattern under a key of a map or cube-map pattern
+    fn uv_pattern(&self, pattern: &Value, key: &str) -> Result<UvPattern, SceneError>
+    {
+        let value = match pattern.get(key)
+        {
+            Some(v) => self.resolve(v)?,
+            None => return Err(invalid(format!("the pattern needs a '{}'", key))),
+        };
+        let colors = |count: usize| -> Result<Vec<Tuple>, SceneError>
+        {
+            match value.get("colors").and_then(|v| v.as_list())
+            {
+                Some(c) if c.len() == count => c.iter().map(|c| color(Some(c), "colors")).collect(),
+                _ => Err(invalid(format!("a {} uv-pattern needs {} 'colors'", key, count))),
+            }
+        };
+        match value.get("type").and_then(|v| v.as_str())
+        {
+            Some("checkers") =>
+            {
+                let c = colors(2)?;
+                Ok(uv_checkers(number(value.get("width"), "width")?, number(value.get("height"), "height")?,
+                    c[0], c[1]))
+            },
+            Some("align-check") =>
+            {
+                // the main color, then the upper left, upper right, bottom
+                // left and bottom right corners
+                let c = colors(5)?;
+                Ok(uv_align_check(c[0], c[1], c[2], c[3], c[4]))
+            },
+            Some("image") =>
+            {
+                let file = value.get("file").and_then(|v| v.as_str())
+                    .ok_or_else(|| invalid(String::from("an image uv-pattern needs a 'file'")))?;
+                let image = Canvas::from_ppm(io::BufReader::new(fs::File::open(self.base_dir.join(file))?))?;
+                Ok(UvPattern::Image(Arc::new(image)))
+            },
+            _ => Err(invalid(String::from("uv-pattern 'type' must be image, checkers or align-check"))),
+        }
+    }
+}
+
+// Builds a scene from the text of a YAML or JSON scene file, which is a
+// list of "add" and "define" entries
+pub fn parse_scene(text: &str, base_dir: &Path) -> Result<Scene, SceneError>
+{
+    let document = parse_yaml(text)?;
+    let items = match document.as_list()
+    {
+        Some(items) => items,
+        None => return Err(invalid(String::from("a scene must be a list of entries"))),
+    };
+    let mut builder = SceneBuilder::new(base_dir);
+    for (index, item) in items.iter().enumerate()
+    {
+        if let Err(e) = builder.add_item(item)
+        {
+            // name the entry, as the values no longer know their lines
+            return Err(match e
+            {
+                SceneError::Invalid(message) => invalid(format!("entry {}: {}", index + 1, message)),
+                other => other,
+            });
+        }
+    }
+    Ok(Scene{world: builder.world, camera: builder.camera})
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+    use crate::ray::*;
+
+    #[test]
+    fn test_scene_feature()
+    {
+        // Scenario: Building a world and camera from a scene file
+        let text1 = "
+- add: camera
+  width: 100
+  height: 50
+  field-of-view: 0.785
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+  exposure: -0.5
+  vignette: 0.3
+  chromatic-aberration: 1.5
+  tone-map: aces
+  gamma: 2.2
+  bit-depth: 16
+  dither: floyd-steinberg
+  max-samples: 16
+  sampler: stratified
+  seed: 42
+  aperture: 0.1
+  focal-distance: 5
+  projection: fisheye
+  shutter-close: 1
+  interocular: 0.1
+  crop: [10, 20, 30, 40]
+
+- add: light
+  at: [-10, 10, -10]
+  intensity: [1, 1, 1]
+
+- define: white-material
+  value:
+    color: [1, 1, 1]
+    diffuse: 0.7
+
+- define: blue-material
+  extend: white-material
+  value:
+    color: [0.1, 0.1, 1]
+
+- define: standard-transform
+  value:
+    - [translate, 1, -1, 1]
+    - [scale, 0.5, 0.5, 0.5]
+
+- add: cube
+  material: blue-material
+  transform:
+    - standard-transform
+    - [rotate-y, 1.5707963267948966]
+
+- add: group
+  children:
+    - add: cylinder
+      min: 0
+      max: 1
+      closed: true
+    - add: sphere
+      material:
+        pattern:
+          type: stripes
+          colors: [[1, 0, 0], [0, 0, 1]]
+";
+        let scene1 = parse_scene(text1, Path::new(".")).unwrap();
+        let camera1 = scene1.camera.unwrap();
+        assert_eq!(camera1.hsize, 100);
+        assert_eq!(camera1.exposure, -0.5);
+        assert_eq!(camera1.white_balance, create_color(1.0, 1.0, 1.0));
+        assert_eq!(camera1.effects.vignette, Some(Vignette{strength: 0.3}));
+        assert_eq!(camera1.effects.aberration, Some(ChromaticAberration{amount: 1.5}));
+        assert_eq!(camera1.effects.bloom, None);
+        assert_eq!(camera1.effects.tone_map, ToneMap::Aces);
+        assert_eq!(camera1.gamma, Gamma::Power(2.2));
+        assert_eq!(camera1.bit_depth, BitDepth::Sixteen);
+        assert_eq!(camera1.dither, Dither::FloydSteinberg);
+        assert_eq!(camera1.sampling, Some(AdaptiveSampling{max_samples: 16, ..AdaptiveSampling::new()}));
+        assert_eq!((camera1.sampler, camera1.seed), (SamplerKind::Stratified, 42));
+        assert_eq!((camera1.aperture, camera1.focal_distance, camera1.blur_samples), (0.1, 5.0, 16));
+        assert_eq!(camera1.interocular, 0.1);
+        assert_eq!(camera1.crop, Some([10, 20, 30, 40]));
+        assert_eq!((camera1.shutter_open, camera1.shutter_close), (0.0, 1.0));
+        assert_eq!(camera1.projection, Projection::Fisheye);
+        assert_eq!(camera1.transform, Matrix::view_transform(create_point(0.0, 1.5, -5.0),
+            create_point(0.0, 1.0, 0.0), create_vector(0.0, 1.0, 0.0)));
+        assert_eq!(scene1.world.objects.len(), 2);
+        let cube1 = &scene1.world.objects[0];
+        assert_eq!(cube1.get_material().color, create_color(0.1, 0.1, 1.0));
+        assert_eq!(cube1.get_material().diffuse, 0.7);
+        assert_eq!(cube1.get_transform(), Matrix::rotation_y(PI / 2.0)
+            .multiply(&Matrix::scaling(0.5, 0.5, 0.5))
+            .multiply(&Matrix::translation(1.0, -1.0, 1.0)));
+        let children1 = scene1.world.objects[1].get_children();
+        assert_eq!(children1.len(), 2);
+        assert!(children1[1].get_material().pattern.is_some());
+        assert_ne!(children1[0].get_id(), children1[1].get_id());
+
+        // Scenario: Scene files can be written as JSON
+        let text2 = "[{\"add\": \"light\", \"at\": [0, 5, 0], \"intensity\": [1, 1, 1]},
+            {\"add\": \"plane\", \"material\": {\"reflective\": 0.5}}]";
+        let scene2 = parse_scene(text2, Path::new(".")).unwrap();
+        assert_eq!(scene2.world.light.position, create_point(0.0, 5.0, 0.0));
+        assert_eq!(scene2.world.objects[0].get_material().reflective, 0.5);
+        assert!(scene2.camera.is_none());
+        assert_eq!(scene2.world.ambient, create_color(1.0, 1.0, 1.0));
+        let scene2b = parse_scene("- add: ambient\n  intensity: [0.5, 0.5, 0.5]\n", Path::new(".")).unwrap();
+        assert_eq!(scene2b.world.ambient, create_color(0.5, 0.5, 0.5));
+        let scene2e = parse_scene("- add: cube\n  name: box\n", Path::new(".")).unwrap();
+        assert_eq!(scene2e.world.get_node("box"), Some(&scene2e.world.objects[0]));
+        let scene2c = parse_scene("- add: settings\n  max-depth: 12\n  max-distance: 50\n  max-indirect-value: 4\n",
+            Path::new(".")).unwrap();
+        assert_eq!(scene2c.world.get_sample_limits(), SampleLimits{max_value: Scalar::INFINITY, max_indirect: 4.0});
+        assert_eq!(scene2c.world.get_max_depth(), 12);
+        assert_eq!(scene2c.world.get_max_distance(), 50.0);
+        assert_eq!(scene2c.world.get_tolerance(), Tolerance::new());
+        let scene2i = parse_scene("- add: settings\n  epsilon: 0.001\n  relative-epsilon: 0.0000001\n",
+            Path::new(".")).unwrap();
+        assert_eq!(scene2i.world.get_tolerance(), Tolerance{absolute: 0.001, relative: 0.0000001});
+        let scene2j = parse_scene("- add: light\n  at: [0, 5, 0]\n  intensity: 3200K\n- add: sphere\n  material:\n    color: \"#ffcc00\"\n- add: cube\n  material:\n    color: navy\n",
+            Path::new(".")).unwrap();
+        assert_eq!(scene2j.world.get_light().intensity, kelvin_to_color(3200.0));
+        let scene2p = parse_scene("- add: sphere\n  motion: [1, 0, 0]\n", Path::new(".")).unwrap();
+        assert_eq!(scene2p.world.objects[0].get_motion(), Some(create_vector(1.0, 0.0, 0.0)));
+        assert!(scene2p.world.objects[0].get_visibility().casts_shadow);
+        let scene2s = parse_scene("- add: sphere\n  casts-shadow: false\n", Path::new(".")).unwrap();
+        assert!(!scene2s.world.objects[0].get_visibility().casts_shadow);
+        let scene2n = parse_scene("- add: light\n  direction: [0, -3, 4]\n  intensity: [1, 1, 1]\n", Path::new(".")).unwrap();
+        assert_eq!(scene2n.world.get_light().direction, Some(create_vector(0.0, -0.6, 0.8)));
+        assert_eq!(scene2j.world.objects[0].get_material().color, create_color(1.0, 0.8, 0.0));
+        assert_eq!(scene2j.world.objects[1].get_material().color, create_color(0.0, 0.0, 128.0 / 255.0));
+        let scene2h = parse_scene("- add: settings\n  integrator: depth\n  far: 8\n- add: sphere\n",
+            Path::new(".")).unwrap();
+        let ray2h = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(scene2h.world.color_for_ray(ray2h), create_color(0.5, 0.5, 0.5));
+        let scene2o = parse_scene("- add: settings\n  integrator: path\n  samples: 4\n  max-depth: 0\n- add: sphere\n",
+            Path::new(".")).unwrap();
+        assert_eq!(format!("{:?}", scene2o.world.get_integrator()), "PathIntegrator { samples: 4 }");
+        assert_eq!(scene2o.world.color_for_ray(ray2h), PathIntegrator{samples: 1}.li(ray2h, &scene2o.world, 0));
+        let scene2f = parse_scene("- add: settings\n  exclude-layers: [props]\n- add: cube\n  layer: props\n",
+            Path::new(".")).unwrap();
+        assert!(!scene2f.world.get_layers().allows(scene2f.world.objects[0].get_layer()));
+        let scene2g = parse_scene("- add: fog\n  color: [1, 1, 1]\n  density: 0.05\n", Path::new(".")).unwrap();
+        assert_eq!(scene2g.world.fog, Some(Fog::Exponential{color: create_color(1.0, 1.0, 1.0), density: 0.05}));
+        let scene2d = parse_scene("- add: background\n  top: [0, 0, 1]\n  bottom: [1, 1, 1]\n",
+            Path::new(".")).unwrap();
+        assert_eq!(scene2d.world.background, Background::Gradient{top: create_color(0.0, 0.0, 1.0),
+            bottom: create_color(1.0, 1.0, 1.0)});
+        let scene2h = parse_scene("- add: cube\n  transform: [[rotate, 0, 2, 0, 0.5]]\n", Path::new(".")).unwrap();
+        assert_eq!(scene2h.world.objects[0].get_transform(), Matrix::rotation_y(0.5));
+        let scene2l = parse_scene("- add: quadric\n  coefficients: [1, 0, 1, 0, 0, 0, 0, -1, 0, 0]\n  max: [2, 4, 2]\n",
+            Path::new(".")).unwrap();
+        assert_eq!(format!("{}", scene2l.world.objects[0]), "quadric 1");
+        assert_eq!(scene2l.world.objects[0].bounds().max, create_point(2.0, 4.0, 2.0));
+        assert!(scene2l.world.objects[0].bounds().is_infinite());
+        assert!(parse_scene("- add: quadric\n  coefficients: [1, 2, 3]\n", Path::new(".")).is_err());
+        let scene2m = parse_scene("- add: metaballs\n  threshold: 0.25\n  balls:\n    - center: [-1, 0, 0]\n      radius: 1.5\n    - center: [1, 0, 0]\n      radius: 1.5\n      strength: 2\n",
+            Path::new(".")).unwrap();
+        match scene2m.world.objects[0].get_specific()
+        {
+            ShapeSpecific::Metaballs(m) =>
+            {
+                assert_eq!(m.balls.len(), 2);
+                assert_eq!(m.balls[1].strength, 2.0);
+                assert_eq!(m.threshold, 0.25);
+            },
+            _ => panic!("expected metaballs"),
+        }
+        assert!(parse_scene("- add: metaballs\n  balls:\n    - radius: 1\n", Path::new(".")).is_err());
+        let scene2k = parse_scene("- add: disc\n  inner: 0.5\n", Path::new(".")).unwrap();
+        assert_eq!(format!("{}", scene2k.world.objects[0]), "disc 1");
+        match scene2k.world.objects[0].get_specific()
+        {
+            ShapeSpecific::Disc(d) => assert_eq!(d.inner_radius, 0.5),
+            _ => panic!("expected a disc"),
+        }
+
+        // Scenario: Texture mapped patterns in a scene file
+        let text2l = "
+- define: corners
+  value:
+    type: align-check
+    colors: [white, red, yellow, green, cyan]
+- add: plane
+  material:
+    pattern:
+      type: map
+      mapping: planar
+      uv-pattern:
+        type: checkers
+        width: 2
+        height: 2
+        colors: [black, white]
+- add: cube
+  material:
+    pattern:
+      type: cube-map
+      left: corners
+      front: corners
+      right: corners
+      back: corners
+      up: corners
+      down: corners
+";
+        let scene2l = parse_scene(text2l, Path::new(".")).unwrap();
+        let plane = scene2l.world.objects[0].get_material().pattern.clone().unwrap();
+        assert_eq!(plane.pattern_at(create_point(0.25, 0.0, 0.5)), create_color(1.0, 1.0, 1.0));
+        assert_eq!(plane.pattern_at(create_point(0.25, 0.0, 0.25)), create_color(0.0, 0.0, 0.0));
+        let cube = scene2l.world.objects[1].get_material().pattern.clone().unwrap();
+        assert_eq!(cube.pattern_at(create_point(-0.9, 0.9, 1.0)), create_color(1.0, 0.0, 0.0));
+        assert_eq!(cube.pattern_at(create_point(0.0, 1.0, 0.0)), create_color(1.0, 1.0, 1.0));
+
+        // Scenario: Procedural patterns in a scene file
+        let text2m = "
+- add: sphere
+  material:
+    pattern:
+      type: marble
+      colors: [white, gray, black]
+      scale: 3
+- add: sphere
+  material:
+    pattern:
+      type: wood
+      colors: [[0.6, 0.4, 0.2], [0.4, 0.2, 0.1]]
+";
+        let scene2m = parse_scene(text2m, Path::new(".")).unwrap();
+        match scene2m.world.objects[0].get_material().pattern.clone().unwrap().get_specific()
+        {
+            PatternSpecific::MarblePattern(m) =>
+            {
+                assert_eq!(m.scale, 3.0);
+                assert_eq!(m.ramp.stops.len(), 3);
+            },
+            _ => panic!("expected a marble pattern"),
+        }
+        match scene2m.world.objects[1].get_material().pattern.clone().unwrap().get_specific()
+        {
+            PatternSpecific::WoodPattern(w) => assert_eq!(w.scale, 1.0),
+            _ => panic!("expected a wood pattern"),
+        }
+        let text2n = "
+- define: soft-checkers
+  value:
+    type: checkers
+    colors: [white, black]
+- add: plane
+  material:
+    pattern:
+      type: blend
+      weight: 0.25
+      patterns:
+        - soft-checkers
+        - type: gradient
+          colors: [red, white]
+          transform:
+            - [scale, 2, 2, 2]
+";
+        let scene2n = parse_scene(text2n, Path::new(".")).unwrap();
+        let blend = scene2n.world.objects[0].get_material().pattern.clone().unwrap();
+        assert_eq!(blend.pattern_at(create_point(0.5, 0.0, 0.0)), create_color(1.0, 0.8125, 0.8125));
+
+        // Scenario: Patterns nested in a scene file
+        let text2o = "
+- define: rings
+  value:
+    type: rings
+    colors: [red, blue]
+- add: plane
+  material:
+    pattern:
+      type: radial-gradient
+      colors: [white, black]
+    bump:
+      type: noise
+      scale: 8
+      amount: 0.02
+    transparency: 0.9
+    refraction-roughness: 0.3
+    refraction-samples: 4
+- add: plane
+  material:
+    pattern:
+      type: stripes
+      colors:
+        - rings
+        - type: checkers
+          colors: [white, black]
+          transform:
+            - [scale, 0.5, 0.5, 0.5]
+";
+        let scene2o = parse_scene(text2o, Path::new(".")).unwrap();
+        let radial = scene2o.world.objects[0].get_material().pattern.clone().unwrap();
+        assert_eq!(radial.pattern_at(create_point(0.0, 0.0, 0.5)), create_color(0.5, 0.5, 0.5));
+        match &scene2o.world.objects[0].get_material().bump
+        {
+            Some(Bump{source: BumpSource::Noise{scale}, amount}) => assert_eq!((*scale, *amount), (8.0, 0.02)),
+            _ => panic!("expected a noise bump"),
+        }
+        let frosted = scene2o.world.objects[0].get_material();
+        assert_eq!((frosted.refraction_roughness, frosted.refraction_samples), (0.3, 4));
+        assert_eq!(frosted.shading, Shading::Phong);
+        let metal = parse_scene("- add: sphere\n  material:\n    metallic: 1\n", Path::new(".")).unwrap();
+        assert_eq!(metal.world.objects[0].get_material().shading, Shading::Pbr{metallic: 1.0, roughness: 0.5});
+        let nested = scene2o.world.objects[1].get_material().pattern.clone().unwrap();
+        assert_eq!(nested.pattern_at(create_point(0.5, 0.0, 1.5)), create_color(0.0, 0.0, 1.0));
+        assert_eq!(nested.pattern_at(create_point(1.25, 0.0, 0.0)), create_color(1.0, 1.0, 1.0));
+        assert_eq!(nested.pattern_at(create_point(1.75, 0.0, 0.0)), create_color(0.0, 0.0, 0.0));
+        let error8 = parse_scene("- add: plane\n  material:\n    pattern:\n      type: granite\n      colors: [red]\n",
+            Path::new(".")).unwrap_err();
+        assert_eq!(format!("{}", error8), "entry 1: a granite pattern needs at least two 'colors'");
+
+        // Scenario: Mistakes in a scene are reported
+        let error3 = parse_scene("- add: sphere\n  material: shiny\n", Path::new(".")).unwrap_err();
+        assert_eq!(format!("{}", error3), "entry 1: 'shiny' has not been defined");
+        let error4 = parse_scene("- add: sphere\n  transform: [[grow, 2]]\n", Path::new(".")).unwrap_err();
+        assert_eq!(format!("{}", error4), "entry 1: unknown transform 'grow'");
+        let error5 = parse_scene("- add: sphere\n  material:\n    color: warm\n", Path::new(".")).unwrap_err();
+        assert_eq!(format!("{}", error5), "entry 1: 'warm' is not a color");
+        let error7 = parse_scene("- add: cube\n  material:\n    pattern:\n      type: cube-map\n",
+            Path::new(".")).unwrap_err();
+        assert_eq!(format!("{}", error7), "entry 1: the pattern needs a 'left'");
+        let error6 = parse_scene("- add: plane\n  material:\n    pattern:\n      type: map\n      mapping: conical\n",
+            Path::new(".")).unwrap_err();
+        assert_eq!(format!("{}", error6), "entry 1: 'mapping' must be spherical, planar, cylindrical or cube");
+        match parse_scene("- add: sphere\n  - x", Path::new("."))
+        {
+            Err(SceneError::Syntax(e)) => assert_eq!(e.line, 2),
+            _ => panic!("expected a syntax error"),
+        }
+    }
+
+    #[test]
+    fn test_scene_obj()
+    {
+        // Scenario: Placing an OBJ model from a scene file
+        let dir = std::env::temp_dir().join("ray_tracer_test_scene_obj");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("triangle.obj"), "v 0 1 0\nv -1 0 0\nv 1 0 0\nf 1 2 3\n").unwrap();
+        std::fs::write(dir.join("scene.yml"), "
+- add: sphere
+- add: obj
+  file: triangle.obj
+  transform:
+    - [translate, 0, 0, 5]
+  material:
+    color: [1, 0, 0]
+- add: obj
+  file: triangle.obj
+  normalize: true
+- add: sphere
+  material:
+    pattern:
+      type: map
+      mapping: spherical
+      uv-pattern:
+        type: image
+        file: sky.ppm
+    normal-map:
+      file: sky.ppm
+      mapping: spherical
+- add: background
+  environment: sky.ppm
+").unwrap();
+        std::fs::write(dir.join("sky.ppm"), "P3\n2 1\n255\n0 0 255 255 255 255\n").unwrap();
+        let scene = Scene::from_path(dir.join("scene.yml")).unwrap();
+        assert_eq!(scene.world.background.color_at(create_vector(0.0, 0.0, -1.0)), create_color(0.0, 0.0, 1.0));
+        let objects = scene.world.objects;
+        assert_eq!(objects.len(), 4);
+        assert_eq!(objects[1].get_transform(), Matrix::translation(0.0, 0.0, 5.0));
+        let triangle = objects[1].get_children()[0].get_children()[0].clone();
+        assert!(triangle.is_triangle());
+        assert_eq!(triangle.get_material().color, create_color(1.0, 0.0, 0.0));
+        assert_eq!(objects[2].parent_space_bounds().max.get_vec()[0], 1.0);
+        // the left half of the image is wrapped around the right of the sphere
+        let pattern = objects[3].get_material().pattern.clone().unwrap();
+        assert_eq!(pattern.pattern_at(create_point(1.0, 0.0, 0.0)), create_color(0.0, 0.0, 1.0));
+        assert_eq!(pattern.pattern_at(create_point(-1.0, 0.0, 0.0)), create_color(1.0, 1.0, 1.0));
+        // a normal map is read without gamma
+        let normal_map = objects[3].get_material().normal_map.clone().unwrap();
+        assert_eq!(normal_map.mapping, UvMapping::Spherical);
+        assert_eq!(normal_map.image.gamma, Gamma::Linear);
+
+        // every shape has its own id, including those in the models
+        let mut ids = vec![objects[0].get_id(), objects[1].get_id(), triangle.get_id(),
+            objects[2].get_id(), objects[2].get_children()[0].get_children()[0].get_id(),
+            objects[3].get_id()];
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 6);
+
+        // Scenario: A missing model is reported with its file name
+        std::fs::write(dir.join("missing.yml"), "- add: obj\n  file: teapot.obj\n").unwrap();
+        match Scene::from_path(dir.join("missing.yml"))
+        {
+            Err(SceneError::Obj{file, error: ObjError::Io(_)}) => assert_eq!(file, "teapot.obj"),
+            _ => panic!("expected an OBJ error"),
+        }
+    }
+}
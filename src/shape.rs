@@ -1,13 +1,25 @@
+use std::collections::HashSet;
 use std::fmt;
+use std::sync::Arc;
 
+use crate::arena::*;
+use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::cone::*;
+use crate::csg::*;
 use crate::cube::*;
 use crate::cylinder::*;
+use crate::disc::*;
 use crate::sphere::*;
 use crate::group::*;
+use crate::heatmap::*;
+use crate::intersections::*;
 use crate::material::*;
 use crate::matrix::*;
+use crate::metaballs::*;
 use crate::plane::*;
+use crate::pointlight::*;
+use crate::quadric::*;
 use crate::ray::*;
 use crate::smoothtriangle::*;
 use crate::triangle::*;
@@ -21,9 +33,51 @@ pub enum ShapeSpecific
     Cube(Cube),
     Cylinder(Cylinder),
     Cone(Cone),
+    Disc(Disc),
+    Quadric(Quadric),
+    Metaballs(Metaballs),
     Group(Group),
     Triangle(Triangle),
     SmoothTriangle(SmoothTriangle),
+    Csg(Csg),
+}
+
+// Which kinds of rays can see a shape
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Visibility
+{
+    pub camera: bool,
+    pub reflection: bool,
+    pub refraction: bool,
+    // whether the shape casts shadows, such as glass that shouldn't darken
+    // what is under it or a light fitting around its own light
+    pub casts_shadow: bool,
+}
+
+impl Visibility
+{
+    pub fn new() -> Self
+    {
+        Visibility{camera: true, reflection: true, refraction: true, casts_shadow: true}
+    }
+
+    pub fn allows(&self, kind: RayKind) -> bool
+    {
+        match kind
+        {
+            RayKind::Camera => self.camera,
+            RayKind::Reflection => self.reflection,
+            RayKind::Refraction => self.refraction,
+            RayKind::Shadow => self.casts_shadow,
+        }
+    }
+
+    // Visible only to the rays that can see both
+    pub fn and(&self, other: Visibility) -> Visibility
+    {
+        Visibility{camera: self.camera && other.camera, reflection: self.reflection && other.reflection,
+            refraction: self.refraction && other.refraction, casts_shadow: self.casts_shadow && other.casts_shadow}
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -31,9 +85,20 @@ pub struct Shape
 {
     id: i32,
     transform: Matrix,
-    material: Material,
-    saved_ray: Ray,
-    parent: Option<Box<Shape>>,
+    // cached so that the inverse isn't recomputed for every ray
+    inverse: Matrix,
+    inverse_transpose: Matrix,
+    // takes a point from the shape's parent space to its pattern's space
+    pattern_inverse: Matrix,
+    // shared between shapes given the same material, and copied on write
+    material: Arc<Material>,
+    visibility: Visibility,
+    // the render layer, which shapes in a group without one of their own
+    // take from the group
+    layer: Option<String>,
+    // how far the shape moves in its parent's space from time 0 to time
+    // 1, for motion blur
+    motion: Option<Tuple>,
     specific: ShapeSpecific,
 }
 
@@ -41,58 +106,63 @@ impl Shape
 {
     pub fn new_sphere(id: i32) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Sphere(Sphere::new())}
     }
 
     pub fn glass_sphere(id: i32) -> Shape
     {
         let mut sphere = Self::new_sphere(id);
-        let mut material = sphere.material;
-        material.transparency = 1.0;
-        material.refractive_index = 1.5;
-        sphere.material = material;
+        sphere.update_material(|material|
+        {
+            material.transparency = 1.0;
+            material.refractive_index = 1.5;
+        });
         sphere
     }
 
     pub fn new_plane(id: i32) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Plane(Plane::new())}
     }
 
     pub fn new_cube(id: i32) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Cube(Cube::new())}
     }
 
     pub fn new_cylinder(id: i32, closed: bool,
-        minimum_y: f64, maximum_y: f64) -> Shape
+        minimum_y: Scalar, maximum_y: Scalar) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
         let mut cylinder = Cylinder::new();
         cylinder.closed = closed;
         cylinder.minimum = minimum_y;
@@ -100,17 +170,19 @@ impl Shape
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Cylinder(cylinder)}
     }
 
     pub fn new_cone(id: i32, closed: bool,
-        minimum_y: f64, maximum_y: f64) -> Shape
+        minimum_y: Scalar, maximum_y: Scalar) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
         let mut cone = Cone::new();
         cone.closed = closed;
         cone.minimum = minimum_y;
@@ -118,59 +190,180 @@ impl Shape
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Cone(cone)}
     }
 
+    // A disc of radius 1, or a ring if the inner radius is more than 0
+    pub fn new_disc(id: i32, inner_radius: Scalar) -> Shape
+    {
+        let mut disc = Disc::new();
+        disc.inner_radius = inner_radius;
+
+        Shape{id: id,
+            transform: Matrix::identity(4),
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
+            specific: ShapeSpecific::Disc(disc)}
+    }
+
+    // The surface where the general quadric equation with the ten
+    // coefficients is zero, as described in quadric.rs, cut off outside
+    // the limits
+    pub fn new_quadric(id: i32, coefficients: [Scalar; 10], limits: BoundingBox) -> Shape
+    {
+        Shape{id: id,
+            transform: Matrix::identity(4),
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
+            specific: ShapeSpecific::Quadric(Quadric::new(coefficients, limits))}
+    }
+
+    // A blob around the balls, where their influences add up to the
+    // threshold, as described in metaballs.rs
+    pub fn new_metaballs(id: i32, balls: Vec<Metaball>, threshold: Scalar) -> Shape
+    {
+        Shape{id: id,
+            transform: Matrix::identity(4),
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
+            specific: ShapeSpecific::Metaballs(Metaballs::new(balls, threshold))}
+    }
+
     pub fn new_group(id: i32) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
         let group = Group::new();
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Group(group)}
     }
 
     pub fn new_triangle(id: i32, p1: Tuple, p2: Tuple, p3: Tuple) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
         let triangle = Triangle::new(p1, p2, p3);
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::Triangle(triangle)}
     }
 
     pub fn new_smooth_triangle(id: i32, p1: Tuple, p2: Tuple, p3: Tuple,
         n1: Tuple, n2: Tuple, n3: Tuple) -> Shape
     {
-        let zero_point = create_point(0.0, 0.0, 0.0);
-        let zero_vector = create_vector(0.0, 0.0, 0.0);
         let triangle = SmoothTriangle::new(p1, p2, p3, n1, n2, n3);
 
         Shape{id: id,
             transform: Matrix::identity(4),
-            material: Material::new(),
-            saved_ray: Ray::new(zero_point, zero_vector),
-            parent: None::<Box<Shape>>,
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
             specific: ShapeSpecific::SmoothTriangle(triangle)}
     }
 
+    pub fn new_csg(id: i32, operation: CsgOperation,
+        left: Shape, right: Shape) -> Shape
+    {
+        let mut csg = Csg::new(operation);
+        csg.arena.insert(None, left);
+        csg.arena.insert(None, right);
+        Shape{id: id,
+            transform: Matrix::identity(4),
+            inverse: Matrix::identity(4),
+            inverse_transpose: Matrix::identity(4),
+            pattern_inverse: Matrix::identity(4),
+            material: Arc::new(Material::new()),
+            visibility: Visibility::new(),
+            layer: None,
+            motion: None,
+            specific: ShapeSpecific::Csg(csg)}
+    }
+
     pub fn test_shape(id: i32) -> Shape
     {
         Self::new_sphere(id)
     }
 
+    pub fn get_id(&self) -> i32
+    {
+        self.id
+    }
+
+    pub fn set_id(&mut self, id: i32)
+    {
+        self.id = id;
+    }
+
+    // Adds the ids of the shape and of every shape inside it
+    pub fn collect_ids(&self, ids: &mut HashSet<i32>)
+    {
+        ids.insert(self.id);
+        if let Some(arena) = self.get_arena()
+        {
+            for shape in arena.shapes()
+            {
+                shape.collect_ids(ids);
+            }
+        }
+    }
+
+    // Gives the shape and every shape inside it the id returned for its
+    // old one
+    pub fn renumber_ids<F: FnMut(i32) -> i32>(&mut self, renumber: &mut F)
+    {
+        self.id = renumber(self.id);
+        if let Some(arena) = self.get_arena_mut()
+        {
+            for shape in arena.shapes_mut()
+            {
+                shape.renumber_ids(renumber);
+            }
+        }
+    }
+
+    pub fn get_specific(&self) -> ShapeSpecific
+    {
+        self.specific.clone()
+    }
+
     pub fn get_transform(&self) -> Matrix
     {
         self.transform.clone()
@@ -178,92 +371,505 @@ impl Shape
 
     pub fn set_transform(&mut self, transform: Matrix)
     {
+        self.inverse = transform.inverse();
+        self.inverse_transpose = self.inverse.transpose();
         self.transform = transform;
+        self.update_pattern_inverse();
+    }
+
+    pub fn get_inverse_transform(&self) -> &Matrix
+    {
+        &self.inverse
+    }
+
+    // Takes a shape out of the groups containing it, by folding their
+    // transform into its own. Its pattern stays where it was, since
+    // patterns are placed in the space of the shape's parent.
+    pub fn bake_transform(&mut self, parent_transform: &Matrix)
+    {
+        let pattern_inverse = self.pattern_inverse.clone();
+        self.set_transform(parent_transform.multiply(&self.transform));
+        self.pattern_inverse = pattern_inverse;
+        self.motion = self.motion.map(|m| parent_transform.multiply_tuple(m));
+    }
+
+    // Adds the motion of the groups a shape was taken out of, already in
+    // the space the shape's transform was baked into
+    pub fn inherit_motion(&mut self, parent_motion: Option<Tuple>)
+    {
+        if let Some(p) = parent_motion
+        {
+            self.motion = Some(self.motion.map_or(p, |m| m.add(p)));
+        }
+    }
+
+    // Copies of the shapes a ray can hit, with every group taken away and
+    // its transform baked into the shapes inside it. CSG shapes are kept
+    // whole, since their children are intersected together.
+    pub fn flatten(&self) -> Vec<Shape>
+    {
+        self.flatten_in(&Matrix::identity(4))
+    }
+
+    // As flatten, for a shape inside a group with the given transform
+    pub fn flatten_in(&self, parent_transform: &Matrix) -> Vec<Shape>
+    {
+        let mut list = Vec::new();
+        match self.get_arena()
+        {
+            Some(arena) if self.is_group() =>
+            {
+                let transform = parent_transform.multiply(&self.transform);
+                let motion = self.motion.map(|m| parent_transform.multiply_tuple(m));
+                for &root in arena.get_roots()
+                {
+                    arena.flatten(root, &transform, motion, self.get_layer(), self.visibility, &mut list);
+                }
+            },
+            _ =>
+            {
+                let mut shape = self.clone();
+                shape.bake_transform(parent_transform);
+                list.push(shape);
+            },
+        }
+        list
     }
 
-    pub fn get_material(&self) -> Material
+    pub fn get_material(&self) -> &Material
     {
-        self.material.clone()
+        &self.material
+    }
+
+    pub fn get_shared_material(&self) -> Arc<Material>
+    {
+        Arc::clone(&self.material)
     }
 
     pub fn set_material(&mut self, material: Material)
+    {
+        self.set_shared_material(Arc::new(material));
+    }
+
+    // Gives the shape a material without copying it, so that many shapes
+    // can share one
+    pub fn set_shared_material(&mut self, material: Arc<Material>)
     {
         self.material = material;
+        self.update_pattern_inverse();
+    }
+
+    // Gives the shape and every shape inside it the same material, in
+    // place of any they had of their own
+    pub fn set_all_materials(&mut self, material: Arc<Material>)
+    {
+        if let Some(arena) = self.get_arena_mut()
+        {
+            arena.set_all_materials(&material);
+        }
+        self.set_shared_material(material);
+    }
+
+    // Changes the shape's own material, copying it first if it is shared
+    // with other shapes
+    pub fn update_material<F: FnOnce(&mut Material)>(&mut self, update: F)
+    {
+        update(Arc::make_mut(&mut self.material));
+        self.update_pattern_inverse();
+    }
+
+    fn update_pattern_inverse(&mut self)
+    {
+        if let Some(pattern) = &self.material.pattern
+        {
+            self.pattern_inverse = pattern.get_inverse_transform().multiply(&self.inverse);
+        }
+    }
+
+    // Material::lighting with the shape's own material, using the cached
+    // transform into its pattern's space, and the ambient term scaled by
+    // the world's ambient light
+    pub fn lighting(&self, ambient_light: Tuple, light: PointLight, point: Tuple, eyev: Tuple,
+        normalv: Tuple, in_shadow: bool) -> Tuple
+    {
+        let color = match &self.material.pattern
+        {
+            Some(p) if !p.is_test_pattern() =>
+                p.pattern_at(self.pattern_inverse.multiply_tuple(point)),
+            _ => self.material.color_at(self, point),
+        };
+        self.material.lighting_with_ambient(color, ambient_light, light, point, eyev, normalv, in_shadow)
+    }
+
+    pub fn get_visibility(&self) -> Visibility
+    {
+        self.visibility
+    }
+
+    pub fn set_visibility(&mut self, visibility: Visibility)
+    {
+        self.visibility = visibility;
+    }
+
+    pub fn get_layer(&self) -> Option<&str>
+    {
+        self.layer.as_deref()
+    }
+
+    pub fn set_layer(&mut self, layer: &str)
+    {
+        self.layer = Some(String::from(layer));
+    }
+
+    pub fn get_motion(&self) -> Option<Tuple>
+    {
+        self.motion
+    }
+
+    // Makes the shape move steadily by the vector, in its parent's space,
+    // over the camera's shutter, so that it is blurred along its path
+    pub fn set_motion(&mut self, motion: Tuple)
+    {
+        self.motion = Some(motion);
+    }
+
+    // Grows a box in the shape's parent space to cover everywhere the
+    // shape moves through
+    pub fn sweep_bounds(&self, bounds: BoundingBox) -> BoundingBox
+    {
+        match self.motion
+        {
+            Some(m) if !bounds.is_empty() && !bounds.is_infinite() =>
+            {
+                let mut swept = bounds;
+                swept.add_point(bounds.min.add(m));
+                swept.add_point(bounds.max.add(m));
+                swept
+            },
+            _ => bounds,
+        }
+    }
+
+    // Grows a sphere in the shape's parent space to cover everywhere the
+    // shape moves through
+    pub fn sweep_sphere(&self, sphere: BoundingSphere) -> BoundingSphere
+    {
+        match self.motion
+        {
+            Some(m) if !sphere.is_empty() && !sphere.is_infinite() =>
+                BoundingSphere::new(sphere.center.add(m.multiply(0.5)), sphere.radius + m.magnitude() / 2.0),
+            _ => sphere,
+        }
+    }
+
+    // Gives the shape a layer if it has none, for shapes taken out of a
+    // group that is in the layer
+    pub fn inherit_layer(&mut self, layer: Option<&str>)
+    {
+        if self.layer.is_none()
+        {
+            self.layer = layer.map(String::from);
+        }
+    }
+
+    // Hides the shape from the rays that can't see the groups it was
+    // taken out of
+    pub fn inherit_visibility(&mut self, visibility: Visibility)
+    {
+        self.visibility = self.visibility.and(visibility);
+    }
+
+    // Whether the shape itself is visible to a kind of ray. Intersections
+    // also check the groups containing the shape.
+    pub fn is_visible_to(&self, kind: RayKind) -> bool
+    {
+        self.visibility.allows(kind)
     }
 
-    pub fn intersect(&mut self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn intersect(&self, ray: Ray) -> Vec<Intersection<'_>>
     {
-        let local_ray = ray.transform(self.transform.inverse());
-        self.saved_ray = local_ray.clone();
-        match self.specific.clone()
+        count_test();
+        let local_ray = self.local_ray(ray);
+        let tuvs = match &self.specific
         {
             ShapeSpecific::Sphere(s) => s.local_intersect(local_ray),
             ShapeSpecific::Plane(p) => p.local_intersect(local_ray),
             ShapeSpecific::Cube(c) => c.local_intersect(local_ray),
             ShapeSpecific::Cylinder(c) => c.local_intersect(local_ray),
             ShapeSpecific::Cone(c) => c.local_intersect(local_ray),
-            ShapeSpecific::Group(g) => g.local_intersect(local_ray),
+            ShapeSpecific::Disc(d) => d.local_intersect(local_ray),
+            ShapeSpecific::Quadric(q) => q.local_intersect(local_ray),
+            ShapeSpecific::Metaballs(m) =>
+            {
+                // blobs can cross a ray any number of times
+                let tuvs = m.local_intersect(local_ray);
+                return tuvs.iter().map(|tuv| Intersection::new_with_uv(tuv.0, self, tuv.1, tuv.2)).collect();
+            },
             ShapeSpecific::Triangle(t) => t.local_intersect(local_ray),
             ShapeSpecific::SmoothTriangle(t) => t.local_intersect(local_ray),
+            ShapeSpecific::Group(g) =>
+            {
+                let xs = g.local_intersect(local_ray);
+                return self.adopt_intersections(xs);
+            },
+            ShapeSpecific::Csg(c) =>
+            {
+                let xs = c.local_intersect(local_ray);
+                return self.adopt_intersections(xs);
+            },
+        };
+        tuvs.iter().map(|tuv| Intersection::new_with_uv(tuv.0, self, tuv.1, tuv.2)).collect()
+    }
+
+    // Make this group (or CSG) the outermost parent of the shapes
+    // intersected inside it, so that normals are transformed through
+    // every level of the hierarchy.
+    fn adopt_intersections<'a>(&'a self, intersections: Vec<Intersection<'a>>) -> Vec<Intersection<'a>>
+    {
+        let mut xs = intersections;
+        for x in xs.iter_mut()
+        {
+            x.parents.insert(0, self);
+        }
+        xs
+    }
+
+    // The ray in the shape's object space
+    pub fn local_ray(&self, ray: Ray) -> Ray
+    {
+        match self.motion
+        {
+            // moving the ray back is the same as moving the shape forward
+            Some(m) if ray.time != 0.0 => Ray{origin: ray.origin.sub(m.multiply(ray.time)), ..ray}
+                .transform(&self.inverse),
+            _ => ray.transform(&self.inverse),
         }
     }
 
-    pub fn get_saved_ray(&self) -> Ray
+    pub fn normal_at(&self, world_point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
+    {
+        self.normal_at_in(&[], world_point, hit_uv, 0.0)
+    }
+
+    // Like normal_at, for a shape inside the given groups (outermost
+    // first), where moving shapes are at the given time
+    pub fn normal_at_in(&self, parents: &[&Shape], world_point: Tuple, hit_uv: (Scalar, Scalar),
+        time: Scalar) -> Tuple
     {
-        self.saved_ray
+        let local_point = self.world_to_object_in(parents, world_point, time);
+        let local_normal = self.local_normal_at(local_point, hit_uv);
+        self.normal_to_world_in(parents, local_normal)
     }
 
-    pub fn normal_at(&self, world_point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    fn local_normal_at(&self, local_point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
-        let local_point = self.world_to_object(world_point);
-        let local_normal = match self.specific.clone()
+        match &self.specific
         {
             ShapeSpecific::Sphere(s) => s.local_normal_at(local_point, hit_uv),
             ShapeSpecific::Plane(p) => p.local_normal_at(local_point, hit_uv),
             ShapeSpecific::Cube(c) => c.local_normal_at(local_point, hit_uv),
             ShapeSpecific::Cylinder(c) => c.local_normal_at(local_point, hit_uv),
             ShapeSpecific::Cone(c) => c.local_normal_at(local_point, hit_uv),
+            ShapeSpecific::Disc(d) => d.local_normal_at(local_point, hit_uv),
+            ShapeSpecific::Quadric(q) => q.local_normal_at(local_point, hit_uv),
+            ShapeSpecific::Metaballs(m) => m.local_normal_at(local_point, hit_uv),
             ShapeSpecific::Group(g) => g.local_normal_at(local_point, hit_uv),
             ShapeSpecific::Triangle(t) => t.local_normal_at(local_point, hit_uv),
             ShapeSpecific::SmoothTriangle(t) => t.local_normal_at(local_point, hit_uv),
+            ShapeSpecific::Csg(c) => c.local_normal_at(local_point, hit_uv),
+        }
+    }
+
+    // The direction of the u of the shape's texture mapping at a point, in
+    // world space, for the shapes that have one. It need not be at right
+    // angles to the normal.
+    pub fn tangent_at_in(&self, parents: &[&Shape], world_point: Tuple, time: Scalar) -> Option<Tuple>
+    {
+        let local_point = self.world_to_object_in(parents, world_point, time);
+        let mut tangent = match &self.specific
+        {
+            ShapeSpecific::Sphere(s) => s.local_tangent_at(local_point),
+            ShapeSpecific::Plane(p) => p.local_tangent_at(local_point),
+            ShapeSpecific::Triangle(t) => t.local_tangent_at(),
+            ShapeSpecific::SmoothTriangle(t) => t.local_tangent_at(),
+            _ => return None,
         };
-        return self.normal_to_world(local_normal);
+        // directions along the surface move with the shape, unlike normals
+        for shape in std::iter::once(self).chain(parents.iter().rev().copied())
+        {
+            tangent = shape.transform.multiply_tuple(tangent);
+        }
+        Some(tangent)
+    }
+
+    // Bounding box of the shape in its own object space
+    pub fn bounds(&self) -> BoundingBox
+    {
+        match &self.specific
+        {
+            ShapeSpecific::Sphere(s) => s.bounds(),
+            ShapeSpecific::Plane(p) => p.bounds(),
+            ShapeSpecific::Cube(c) => c.bounds(),
+            ShapeSpecific::Cylinder(c) => c.bounds(),
+            ShapeSpecific::Cone(c) => c.bounds(),
+            ShapeSpecific::Disc(d) => d.bounds(),
+            ShapeSpecific::Quadric(q) => q.bounds(),
+            ShapeSpecific::Metaballs(m) => m.bounds(),
+            ShapeSpecific::Group(g) => g.bounds(),
+            ShapeSpecific::Triangle(t) => t.bounds(),
+            ShapeSpecific::SmoothTriangle(t) => t.bounds(),
+            ShapeSpecific::Csg(c) => c.bounds(),
+        }
+    }
+
+    // Bounding box of the shape in the space of its parent group
+    // (or world space, if the shape has no parent)
+    pub fn parent_space_bounds(&self) -> BoundingBox
+    {
+        self.sweep_bounds(self.bounds().transform(&self.transform))
     }
 
-    pub fn get_parent(&self) -> Option<Box<Shape>>
+    // A copy of the shape scaled and moved to fit within the cube from
+    // -1 to 1 on each axis, keeping its proportions. Shapes with empty or
+    // infinite bounds are returned unchanged.
+    pub fn normalized(&self) -> Shape
     {
-        self.parent.clone()
+        let mut shape = self.clone();
+        let bounds = self.parent_space_bounds();
+        if bounds.is_empty() || bounds.is_infinite()
+        {
+            return shape;
+        }
+        let size = bounds.max.sub(bounds.min).get_vec();
+        let largest = size[0].max(size[1]).max(size[2]);
+        if largest <= 0.0
+        {
+            return shape;
+        }
+        let center = bounds.center().get_vec();
+        let scale = 2.0 / largest;
+        let translation = Matrix::translation(-center[0], -center[1], -center[2]);
+        let transform = Matrix::scaling(scale, scale, scale)
+            .multiply(&translation).multiply(&self.transform);
+        shape.set_transform(transform);
+        shape
     }
 
-    pub fn set_parent(&mut self, parent: Shape)
+    // Bounding sphere of the shape in the space of its parent group
+    // (or world space, if the shape has no parent)
+    pub fn bounding_sphere(&self) -> BoundingSphere
     {
-        self.parent = Some(Box::new(parent));
+        let local_sphere = match &self.specific
+        {
+            ShapeSpecific::Sphere(s) => s.bounding_sphere(),
+            ShapeSpecific::Plane(p) => p.bounding_sphere(),
+            ShapeSpecific::Cube(c) => c.bounding_sphere(),
+            ShapeSpecific::Cylinder(c) => c.bounding_sphere(),
+            ShapeSpecific::Cone(c) => c.bounding_sphere(),
+            ShapeSpecific::Disc(d) => d.bounding_sphere(),
+            ShapeSpecific::Quadric(q) => q.bounding_sphere(),
+            ShapeSpecific::Metaballs(m) => m.bounding_sphere(),
+            ShapeSpecific::Group(g) => g.bounding_sphere(),
+            ShapeSpecific::Triangle(t) => t.bounding_sphere(),
+            ShapeSpecific::SmoothTriangle(t) => t.bounding_sphere(),
+            ShapeSpecific::Csg(c) => c.bounding_sphere(),
+        };
+        self.sweep_sphere(local_sphere.transform(&self.transform))
     }
 
+    // Copies of the children of a group or CSG shape
     pub fn get_children(&self) -> Vec<Shape>
+    {
+        match self.get_arena()
+        {
+            Some(arena) => arena.get_roots().iter().map(|&id| arena.extract(id)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Moves a shape into this group, returning its handle in the group
+    pub fn add_child(&mut self, child: Shape) -> ShapeId
+    {
+        match &mut self.specific
+        {
+            ShapeSpecific::Group(g) => g.arena.insert(None, child),
+            _ => panic!("Cannot add child to this type of Shape"),
+        }
+    }
+
+    // Moves a shape into a group that is already inside this group
+    pub fn add_child_to(&mut self, parent: ShapeId, child: Shape) -> ShapeId
+    {
+        match &mut self.specific
+        {
+            ShapeSpecific::Group(g) if g.arena.get(parent).is_group() =>
+                g.arena.insert(Some(parent), child),
+            _ => panic!("Cannot add child to this type of Shape"),
+        }
+    }
+
+    // The shapes inside a group or CSG shape
+    pub fn get_arena(&self) -> Option<&ShapeArena>
     {
         match &self.specific
         {
-            ShapeSpecific::Group(g) => g.child_shapes.clone(),
-            _ => Vec::new(),
+            ShapeSpecific::Group(g) => Some(&g.arena),
+            ShapeSpecific::Csg(c) => Some(&c.arena),
+            _ => None,
         }
     }
 
-    pub fn add_child(&mut self, child: &mut Shape)
+    pub fn get_arena_mut(&mut self) -> Option<&mut ShapeArena>
     {
-        let parent = self.clone();
         match &mut self.specific
         {
-            ShapeSpecific::Group(g) =>
-            {
-                // TODO use reference (Rc/RefCell), not copy, for parent-child
-                child.set_parent(parent);
-                g.child_shapes.push(child.clone());
-            },
-            _ =>
-            {
-                panic!("Cannot add child to this type of Shape");
-            },
+            ShapeSpecific::Group(g) => Some(&mut g.arena),
+            ShapeSpecific::Csg(c) => Some(&mut c.arena),
+            _ => None,
+        }
+    }
+
+    // Moves the shapes inside a group or CSG shape out of it, leaving it empty
+    pub fn take_arena(&mut self) -> Option<ShapeArena>
+    {
+        self.get_arena_mut().map(|arena| std::mem::replace(arena, ShapeArena::new()))
+    }
+
+    pub fn get_descendant(&self, id: ShapeId) -> &Shape
+    {
+        self.get_arena().expect("Shape has no children").get(id)
+    }
+
+    // The groups and CSG shapes containing a shape inside this one,
+    // outermost (this shape) first
+    pub fn get_descendant_parents(&self, id: ShapeId) -> Vec<&Shape>
+    {
+        let arena = self.get_arena().expect("Shape has no children");
+        std::iter::once(self)
+            .chain(arena.ancestors(id).into_iter().map(|a| arena.get(a)))
+            .collect()
+    }
+
+    pub fn is_composite(&self) -> bool
+    {
+        self.get_arena().is_some()
+    }
+
+    pub fn is_group(&self) -> bool
+    {
+        match self.specific
+        {
+            ShapeSpecific::Group(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn get_csg_operation(&self) -> Option<CsgOperation>
+    {
+        match &self.specific
+        {
+            ShapeSpecific::Csg(c) => Some(c.operation),
+            _ => None,
         }
     }
 
@@ -294,6 +900,27 @@ impl Shape
         }
     }
 
+    pub fn set_texture_uvs(&mut self, uvs: [(Scalar, Scalar); 3])
+    {
+        match &mut self.specific
+        {
+            ShapeSpecific::Triangle(t) => t.uvs = Some(uvs),
+            ShapeSpecific::SmoothTriangle(t) => t.uvs = Some(uvs),
+            _ => panic!("Only triangles have texture coordinates"),
+        }
+    }
+
+    // Texture coordinates at a hit, for triangles that have them
+    pub fn texture_uv_at(&self, hit_uv: (Scalar, Scalar)) -> Option<(Scalar, Scalar)>
+    {
+        match &self.specific
+        {
+            ShapeSpecific::Triangle(t) => interpolate_uv(t.uvs, hit_uv),
+            ShapeSpecific::SmoothTriangle(t) => interpolate_uv(t.uvs, hit_uv),
+            _ => None,
+        }
+    }
+
     pub fn get_smooth_triangle(&self) -> SmoothTriangle
     {
         match &self.specific
@@ -303,34 +930,51 @@ impl Shape
         }
     }
 
+    pub fn get_csg(&self) -> Csg
+    {
+        match &self.specific
+        {
+            ShapeSpecific::Csg(c) => c.clone(),
+            _ => panic!("Shape is not a CSG"),
+        }
+    }
+
     pub fn world_to_object(&self, world_point: Tuple) -> Tuple
+    {
+        self.world_to_object_in(&[], world_point, 0.0)
+    }
+
+    // Converts a point to object space through the given groups
+    // (outermost first) and then the shape's own transform, with moving
+    // shapes where they are at the time
+    pub fn world_to_object_in(&self, parents: &[&Shape], world_point: Tuple, time: Scalar) -> Tuple
     {
         let mut point = world_point;
-        match &self.parent
+        for shape in parents.iter().copied().chain(std::iter::once(self))
         {
-            Some(parent_group) =>
+            if let Some(m) = shape.motion
             {
-                point = parent_group.world_to_object(world_point);
-            },
-            None => (),
+                point = point.sub(m.multiply(time));
+            }
+            point = shape.inverse.multiply_tuple(point);
         }
-        return self.transform.inverse().multiply_tuple(point);
+        point
     }
 
     pub fn normal_to_world(&self, normal: Tuple) -> Tuple
     {
-        let mut normal = self.transform.inverse().transpose().multiply_tuple(normal);
-        let v = normal.get_vec();
-        normal = create_vector(v[0], v[1], v[2]).normalize();
-        match &self.parent
+        self.normal_to_world_in(&[], normal)
+    }
+
+    pub fn normal_to_world_in(&self, parents: &[&Shape], normal: Tuple) -> Tuple
+    {
+        let mut normal = normal;
+        for shape in std::iter::once(self).chain(parents.iter().rev().copied())
         {
-            Some(parent_group) =>
-            {
-                normal = parent_group.normal_to_world(normal);
-            },
-            None => (),
+            let v = shape.inverse_transpose.multiply_tuple(normal).get_vec();
+            normal = create_vector(v[0], v[1], v[2]).normalize();
         }
-        return normal;
+        normal
     }
 }
 
@@ -380,6 +1024,30 @@ impl PartialEq for Shape
                     _ => false,
                 }
             },
+            ShapeSpecific::Disc(_) =>
+            {
+                match other.specific
+                {
+                    ShapeSpecific::Disc(_) => self.id == other.id,
+                    _ => false,
+                }
+            },
+            ShapeSpecific::Quadric(_) =>
+            {
+                match other.specific
+                {
+                    ShapeSpecific::Quadric(_) => self.id == other.id,
+                    _ => false,
+                }
+            },
+            ShapeSpecific::Metaballs(_) =>
+            {
+                match other.specific
+                {
+                    ShapeSpecific::Metaballs(_) => self.id == other.id,
+                    _ => false,
+                }
+            },
             ShapeSpecific::Group(_) =>
             {
                 match other.specific
@@ -404,6 +1072,14 @@ impl PartialEq for Shape
                     _ => false,
                 }
             },
+            ShapeSpecific::Csg(_) =>
+            {
+                match other.specific
+                {
+                    ShapeSpecific::Csg(_) => self.id == other.id,
+                    _ => false,
+                }
+            },
         }
     }
 }
@@ -419,9 +1095,13 @@ impl fmt::Display for Shape
             ShapeSpecific::Cube(_) => write!(f, "cube {}", self.id),
             ShapeSpecific::Cylinder(_) => write!(f, "cylinder {}", self.id),
             ShapeSpecific::Cone(_) => write!(f, "cone {}", self.id),
+            ShapeSpecific::Disc(_) => write!(f, "disc {}", self.id),
+            ShapeSpecific::Quadric(_) => write!(f, "quadric {}", self.id),
+            ShapeSpecific::Metaballs(_) => write!(f, "metaballs {}", self.id),
             ShapeSpecific::Group(g) => write!(f, "group {} {}", self.id, g),
             ShapeSpecific::Triangle(_) => write!(f, "triangle {}", self.id),
             ShapeSpecific::SmoothTriangle(_) => write!(f, "smoothtriangle {}", self.id),
+            ShapeSpecific::Csg(c) => write!(f, "csg {} {}", self.id, c),
         }
     }
 }
@@ -430,6 +1110,7 @@ impl fmt::Display for Shape
 mod tests
 {
     use super::*;
+    use crate::pattern::*;
 
     #[test]
     fn test_shape_feature()
@@ -445,29 +1126,153 @@ mod tests
 
         // p.119 Scenario: The default material
         let s3 = Shape::test_shape(3);
-        assert_eq!(s3.get_material(), Material::new());
+        assert_eq!(*s3.get_material(), Material::new());
 
         // p.119 Scenario: Assigning a material
         let mut s4 = Shape::test_shape(4);
         let mut m4 = Material::new();
         m4.ambient = 1.0;
         s4.set_material(m4.clone());
-        assert_eq!(s4.get_material(), m4);
+        assert_eq!(*s4.get_material(), m4);
 
         // p.120 Scenario: Intersecting a scaled shape with a ray
         let r5 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let mut s5 = Shape::test_shape(5);
         s5.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
-        let _xs5 = s5.intersect(r5);
-        assert_eq!(s5.get_saved_ray().origin, create_point(0.0, 0.0, -2.5));
-        assert_eq!(s5.get_saved_ray().direction, create_vector(0.0, 0.0, 0.5));
+        let local5 = s5.local_ray(r5);
+        assert_eq!(local5.origin, create_point(0.0, 0.0, -2.5));
+        assert_eq!(local5.direction, create_vector(0.0, 0.0, 0.5));
 
         // p.120 Scenario: Intersecting a translated shape with a ray
         let r6 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let mut s6 = Shape::test_shape(6);
         s6.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-        let _xs6 = s6.intersect(r6);
-        assert_eq!(s6.get_saved_ray().origin, create_point(-5.0, 0.0, -5.0));
-        assert_eq!(s6.get_saved_ray().direction, create_vector(0.0, 0.0, 1.0));
+        let local6 = s6.local_ray(r6);
+        assert_eq!(local6.origin, create_point(-5.0, 0.0, -5.0));
+        assert_eq!(local6.direction, create_vector(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_shape_cached_inverse()
+    {
+        // Scenario: Setting a transform caches its inverse
+        let mut s1 = Shape::test_shape(1);
+        assert_eq!(*s1.get_inverse_transform(), Matrix::identity(4));
+        let t1 = Matrix::translation(1.0, 2.0, 3.0).multiply(&Matrix::scaling(2.0, 2.0, 2.0));
+        s1.set_transform(t1.clone());
+        assert_eq!(*s1.get_inverse_transform(), t1.inverse());
+        assert_eq!(s1.clone().get_inverse_transform(), s1.get_inverse_transform());
+    }
+
+    #[test]
+    fn test_shape_cached_pattern_transform()
+    {
+        // Scenario: Lighting a shape uses its pattern and transform, whichever
+        // was set last
+        let mut pattern = Pattern::new_stripe_pattern(create_color(1.0, 1.0, 1.0),
+            create_color(0.0, 0.0, 0.0));
+        pattern.set_pattern_transform(Matrix::translation(0.5, 0.0, 0.0));
+        let mut material = Material::new();
+        material.pattern = Some(pattern);
+        material.ambient = 1.0;
+        material.diffuse = 0.0;
+        material.specular = 0.0;
+        let light = PointLight::new(create_point(0.0, 0.0, -10.0), create_color(1.0, 1.0, 1.0));
+        let eyev = create_vector(0.0, 0.0, -1.0);
+        let normalv = create_vector(0.0, 0.0, -1.0);
+
+        let mut s1 = Shape::new_sphere(1);
+        s1.set_material(material.clone());
+        s1.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
+        let mut s2 = Shape::new_sphere(2);
+        s2.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
+        s2.set_material(material.clone());
+        for x in [0.5, 1.5, 2.5, 3.5]
+        {
+            let point = create_point(x, 0.0, 0.0);
+            let expected = material.lighting(&s1, light, point, eyev, normalv, false);
+            let white = create_color(1.0, 1.0, 1.0);
+            assert_eq!(s1.lighting(white, light, point, eyev, normalv, false), expected);
+            assert_eq!(s2.lighting(white, light, point, eyev, normalv, false), expected);
+        }
+    }
+    #[test]
+    fn test_shape_shared_material()
+    {
+        // Scenario: Shapes given the same material share one copy of it
+        let mut material = Material::new();
+        material.color = create_color(1.0, 0.0, 0.0);
+        let shared = Arc::new(material);
+        let mut s1 = Shape::new_sphere(1);
+        s1.set_shared_material(Arc::clone(&shared));
+        let mut s2 = Shape::new_sphere(2);
+        s2.set_shared_material(Arc::clone(&shared));
+        assert!(Arc::ptr_eq(&s1.get_shared_material(), &s2.get_shared_material()));
+
+        // Scenario: Changing a shared material copies it first
+        s1.update_material(|m| m.color = create_color(0.0, 0.0, 1.0));
+        assert_eq!(s1.get_material().color, create_color(0.0, 0.0, 1.0));
+        assert_eq!(s2.get_material().color, create_color(1.0, 0.0, 0.0));
+        assert!(Arc::ptr_eq(&s2.get_shared_material(), &shared));
+
+        // Scenario: A group's material is not given to its children
+        let mut g = Shape::new_group(3);
+        g.add_child(Shape::new_sphere(4));
+        g.add_child(Shape::new_cube(5));
+        g.set_shared_material(Arc::clone(&shared));
+        let arena = g.get_arena().unwrap();
+        for &id in arena.get_roots()
+        {
+            assert_eq!(arena.get(id).get_material().color, create_color(1.0, 1.0, 1.0));
+        }
+
+        // Scenario: Setting all the materials of a group shares one with
+        // its children
+        g.set_all_materials(Arc::clone(&shared));
+        let arena = g.get_arena().unwrap();
+        for &id in arena.get_roots()
+        {
+            assert!(Arc::ptr_eq(&arena.get(id).get_shared_material(), &shared));
+        }
+    }
+
+    #[test]
+    fn test_shape_motion_feature()
+    {
+        // Scenario: A moving shape is hit where it is at the ray's time
+        let mut s1 = Shape::new_sphere(1);
+        s1.set_motion(create_vector(2.0, 0.0, 0.0));
+        let r1 = Ray::new(create_point(2.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(s1.intersect(r1).len(), 0);
+        let xs1 = s1.intersect(r1.with_time(1.0));
+        assert_eq!(xs1.len(), 2);
+        assert!(fuzzy_equal(xs1[0].t, 4.0));
+        assert_eq!(s1.intersect(r1.with_time(0.25)).len(), 0);
+        assert_eq!(s1.intersect(r1.with_time(0.75)).len(), 2);
+
+        // Scenario: Its normal is worked out where it is at the time
+        let n1 = s1.normal_at_in(&[], create_point(2.0, 0.0, -1.0), (0.0, 0.0), 1.0);
+        assert_eq!(n1, create_vector(0.0, 0.0, -1.0));
+
+        // Scenario: Its bounds cover its whole path
+        let b1 = s1.parent_space_bounds();
+        assert_eq!(b1.min, create_point(-1.0, -1.0, -1.0));
+        assert_eq!(b1.max, create_point(3.0, 1.0, 1.0));
+        let sphere1 = s1.bounding_sphere();
+        assert_eq!(sphere1.center, create_point(1.0, 0.0, 0.0));
+        assert!(fuzzy_equal(sphere1.radius, 2.0));
+
+        // Scenario: Flattening a moving group moves the shapes in it
+        let mut g2 = Shape::new_group(2);
+        g2.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
+        g2.set_motion(create_vector(0.0, 1.0, 0.0));
+        let mut s2 = Shape::new_sphere(3);
+        s2.set_motion(create_vector(1.0, 0.0, 0.0));
+        g2.add_child(s2);
+        let flat2 = g2.flatten();
+        assert_eq!(flat2[0].get_motion(), Some(create_vector(2.0, 1.0, 0.0)));
+        let r2 = Ray::new(create_point(2.0, 1.0, -5.0), create_vector(0.0, 0.0, 1.0)).with_time(1.0);
+        assert_eq!(g2.intersect(r2).len(), 2);
+        assert_eq!(flat2[0].intersect(r2).len(), 2);
     }
 }
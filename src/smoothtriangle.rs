@@ -1,6 +1,7 @@
 use std::fmt;
 use std::cmp;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::intersections::*;
 use crate::tuple::*;
 use crate::ray::*;
@@ -8,6 +9,7 @@ use crate::matrix::*;
 use crate::material::*;
 use crate::ray::*;
 use crate::shape::*;
+use crate::triangle::*;
 
 #[derive(Clone, Debug)]
 pub struct SmoothTriangle
@@ -20,6 +22,8 @@ pub struct SmoothTriangle
     pub n1: Tuple,
     pub n2: Tuple,
     pub n3: Tuple,
+    // texture coordinates at p1, p2 and p3, if the triangle has them
+    pub uvs: Option<[(Scalar, Scalar); 3]>,
 }
 
 impl SmoothTriangle
@@ -30,39 +34,58 @@ impl SmoothTriangle
         let e1 = p2.sub(p1);
         let e2 = p3.sub(p1);
         SmoothTriangle{p1: p1, p2: p2, p3: p3,
-            e1: e1, e2: e2, n1: n1, n2: n2, n3: n3}
+            e1: e1, e2: e2, n1: n1, n2: n2, n3: n3, uvs: None}
     }
 
-    pub fn local_normal_at(&self, point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
         self.n2.multiply(hit_uv.0)
             .add(self.n3.multiply(hit_uv.1))
             .add(self.n1.multiply(1.0 - hit_uv.0 - hit_uv.1))
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_tangent_at(&self) -> Tuple
+    {
+        uv_tangent(self.e1, self.e2, self.uvs)
+    }
+
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
     {
         let dir_cross_e2 = ray.direction.cross_product(self.e2);
         let det = self.e1.dot_product(dir_cross_e2);
         if det.abs() < EPSILON
         {
-            return Vec::new();
+            return LocalHits::new();
         }
         let f = 1.0 / det;
         let p1_to_origin = ray.origin.sub(self.p1);
         let u = f * p1_to_origin.dot_product(dir_cross_e2);
         if u < 0.0 || u > 1.0
         {
-            return Vec::new();
+            return LocalHits::new();
         }
         let origin_cross_e1 = p1_to_origin.cross_product(self.e1);
         let v = f * ray.direction.dot_product(origin_cross_e1);
         if v < 0.0 || u + v > 1.0
         {
-            return Vec::new();
+            return LocalHits::new();
         }
         let t = f * self.e2.dot_product(origin_cross_e1);
-        vec![(t, u, v)]
+        [(t, u, v)].into()
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        let mut bounds = BoundingBox::new();
+        bounds.add_point(self.p1);
+        bounds.add_point(self.p2);
+        bounds.add_point(self.p3);
+        bounds
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::from_box(&self.bounds())
     }
 }
 
@@ -114,7 +137,7 @@ mod tests
     {
         // p.221 Scenario: An intersection can encapsulate u and v
         let t15 = create_tri();
-        let i15 = Intersection::new_with_uv(3.5, t15, 0.2, 0.4);
+        let i15 = Intersection::new_with_uv(3.5, &t15, 0.2, 0.4);
         assert!(fuzzy_equal(i15.u, 0.2));
         assert!(fuzzy_equal(i15.v, 0.4));
     }
@@ -128,8 +151,8 @@ mod tests
         let mut t16 = create_tri();
         let i16 = t16.intersect(r16);
         assert_eq!(i16.len(), 1);
-        assert!(fuzzy_equal(i16[0].1, 0.45));
-        assert!(fuzzy_equal(i16[0].2, 0.25));
+        assert!(fuzzy_equal(i16[0].u, 0.45));
+        assert!(fuzzy_equal(i16[0].v, 0.25));
     }
 
     #[test]
@@ -137,7 +160,7 @@ mod tests
     {
         // p.222 Scenario: A smooth triangle uses  u/v to interpolate the normal
         let mut t17 = create_tri();
-        let i17 = Intersection::new_with_uv(1.0, t17.clone(), 0.45, 0.25);
+        let i17 = Intersection::new_with_uv(1.0, &t17, 0.45, 0.25);
         let n17 = t17.normal_at(create_point(0.0, 0.0, 0.0), (i17.u, i17.v));
         assert_eq!(n17, create_vector(-0.5547, 0.83205, 0.0));
     }
@@ -149,7 +172,7 @@ mod tests
         let r18 = Ray::new(create_point(-0.2, 0.3, -2.0),
             create_vector(0.0, 0.0, 1.0));
         let mut t18 = create_tri();
-        let i18 = Intersection::new_with_uv(1.0, t18.clone(), 0.45, 0.25);
+        let i18 = Intersection::new_with_uv(1.0, &t18, 0.45, 0.25);
         let comps18 = i18.prepare_computations(r18,
             Intersections::new(vec![i18.clone()]));
         assert_eq!(comps18.normalv, create_vector(-0.5547, 0.83205, 0.0));
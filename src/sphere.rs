@@ -1,5 +1,8 @@
 use std::fmt;
-use std::f64::consts::PI;
+use crate::arithmetic::*;
+use crate::bounds::*;
+use crate::intersections::*;
+use crate::arithmetic::consts::PI;
 use crate::tuple::*;
 use crate::ray::*;
 use crate::matrix::*;
@@ -19,7 +22,7 @@ impl Sphere
         Sphere{}
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
     {
         let ray2 = ray;
 
@@ -35,21 +38,40 @@ impl Sphere
 
         if discriminant < 0.0
         {
-            return Vec::new();
+            return LocalHits::new();
         }
 
         let t1 = (-b - discriminant.sqrt()) / (2.0 * a);
         let t2 = (-b + discriminant.sqrt()) / (2.0 * a);
         let u = 0.0;
         let v = 0.0;
-        return vec![(t1, u, v), (t2, u, v)];
+        return [(t1, u, v), (t2, u, v)].into();
     }
 
-    pub fn local_normal_at(&self, local_point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, local_point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
         let local_normal = local_point.sub(create_point(0.0, 0.0, 0.0));
         local_normal
     }
+
+    // The direction u grows in the spherical mapping, around the y axis,
+    // which is zero at the poles
+    pub fn local_tangent_at(&self, local_point: Tuple) -> Tuple
+    {
+        let v = local_point.get_vec();
+        create_vector(-v[2], 0.0, v[0])
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        BoundingBox::new_with_corners(create_point(-1.0, -1.0, -1.0),
+            create_point(1.0, 1.0, 1.0))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::new(create_point(0.0, 0.0, 0.0), 1.0)
+    }
 }
 
 impl fmt::Display for Sphere
@@ -78,16 +100,16 @@ mod tests
         let mut s3 = Shape::new_sphere(3);
         let xs3 = s3.intersect(r3);
         assert_eq!(xs3.len(), 2);
-        assert_eq!(xs3[0].0, 4.0);
-        assert_eq!(xs3[1].0, 6.0);
+        assert_eq!(xs3[0].t, 4.0);
+        assert_eq!(xs3[1].t, 6.0);
 
         // p.60 Scenario: A ray intersects a sphere at a tangent
         let r4 = Ray::new(create_point(0.0, 1.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let mut s4 = Shape::new_sphere(4);
         let xs4 = s4.intersect(r4);
         assert_eq!(xs4.len(), 2);
-        assert_eq!(xs4[0].0, 5.0);
-        assert_eq!(xs4[1].0, 5.0);
+        assert_eq!(xs4[0].t, 5.0);
+        assert_eq!(xs4[1].t, 5.0);
 
         // p.60 Scenario: A ray misses a sphere
         let r5 = Ray::new(create_point(0.0, 2.0, -5.0), create_vector(0.0, 0.0, 1.0));
@@ -100,16 +122,16 @@ mod tests
         let mut s6 = Shape::new_sphere(6);
         let xs6 = s6.intersect(r6);
         assert_eq!(xs6.len(), 2);
-        assert_eq!(xs6[0].0, -1.0);
-        assert_eq!(xs6[1].0, 1.0);
+        assert_eq!(xs6[0].t, -1.0);
+        assert_eq!(xs6[1].t, 1.0);
 
         // p.62 Scenario: A sphere is behind a ray
         let r7 = Ray::new(create_point(0.0, 0.0, 5.0), create_vector(0.0, 0.0, 1.0));
         let mut s7 = Shape::new_sphere(7);
         let xs7 = s7.intersect(r7);
         assert_eq!(xs7.len(), 2);
-        assert_eq!(xs7[0].0, -6.0);
-        assert_eq!(xs7[1].0, -4.0);
+        assert_eq!(xs7[0].t, -6.0);
+        assert_eq!(xs7[1].t, -4.0);
 
         // p.69 Scenario: A sphere's default transformation
         let s8 = Shape::new_sphere(8);
@@ -127,8 +149,8 @@ mod tests
         s10.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
         let xs10 = s10.intersect(r10);
         assert_eq!(xs10.len(), 2);
-        assert_eq!(xs10[0].0, 3.0);
-        assert_eq!(xs10[1].0, 7.0);
+        assert_eq!(xs10[0].t, 3.0);
+        assert_eq!(xs10[1].t, 7.0);
 
         // p.70 Scenario: Intersecting a translated sphere with a ray
         let r11 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
@@ -157,13 +179,13 @@ mod tests
         assert_eq!(n3, create_vector(0.0, 0.0, 1.0));
 
         // p.78 Scenario: The normal on a sphere at a nonaxial point
-        let position4 = 3.0_f64.sqrt() / 3.0;
+        let position4 = Scalar::sqrt(3.0) / 3.0;
         let s4 = Shape::new_sphere(4);
         let n4 = s4.normal_at(create_point(position4, position4, position4), (0.0, 0.0));
         assert_eq!(n4, create_vector(position4, position4, position4));
 
         // p.78 Scenario: The normal is a normalized vector
-        let position5 = 3.0_f64.sqrt() / 3.0;
+        let position5 = Scalar::sqrt(3.0) / 3.0;
         let s5 = Shape::new_sphere(5);
         let n5 = s5.normal_at(create_point(position5, position5, position5), (0.0, 0.0));
         assert_eq!(n5.normalize(), create_vector(position5, position5, position5));
@@ -176,20 +198,20 @@ mod tests
 
         // p.80 Scenario: Computing the normal on a transformed sphere
         let mut s7 = Shape::new_sphere(7);
-        s7.set_transform(Matrix::scaling(1.0, 0.5, 1.0).multiply(&Matrix::rotation_z(PI / 5.0_f64)));
-        let position7 = 2.0_f64.sqrt() / 2.0_f64;
+        s7.set_transform(Matrix::scaling(1.0, 0.5, 1.0).multiply(&Matrix::rotation_z(PI / 5.0)));
+        let position7 = Scalar::sqrt(2.0) / 2.0;
         let n7 = s7.normal_at(create_point(0.0, position7, -position7), (0.0, 0.0));
         assert_eq!(n7.normalize(), create_vector(0.0, 0.97014, -0.24254));
 
         // p.85 Scenario: The default material
         let s8 = Shape::new_sphere(8);
-        assert_eq!(s8.get_material(), Material::new());
+        assert_eq!(*s8.get_material(), Material::new());
 
         // p.85 Scenario: A sphere may be assigned a material
         let mut s9 = Shape::new_sphere(9);
         let mut m9 = Material::new();
         m9.ambient = 1.0;
         s9.set_material(m9.clone());
-        assert_eq!(s9.get_material(), m9);
+        assert_eq!(*s9.get_material(), m9);
     }
 }
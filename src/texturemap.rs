@@ -0,0 +1,396 @@
+use std::sync::Arc;
+use crate::arithmetic::consts::PI;
+use crate::arithmetic::*;
+use crate::canvas::*;
+use crate::tuple::*;
+
+// A pattern on a flat square, from (0, 0) at the bottom left to (1, 1) at
+// the top right, to be wrapped onto a shape by a mapping
+#[derive(Clone, Debug)]
+pub enum UvPattern
+{
+    // an image stretched over the square
+    Image(Arc<Canvas>),
+    // width by height squares of two colors, with a at the bottom left
+    Checkers{width: Scalar, height: Scalar, a: Tuple, b: Tuple},
+    // one color with a different one in each corner, to show which way up
+    // and which way round a mapping puts the square
+    AlignCheck{main: Tuple, ul: Tuple, ur: Tuple, bl: Tuple, br: Tuple},
+}
+
+pub fn uv_checkers(width: Scalar, height: Scalar, a: Tuple, b: Tuple) -> UvPattern
+{
+    UvPattern::Checkers{width, height, a, b}
+}
+
+pub fn uv_align_check(main: Tuple, ul: Tuple, ur: Tuple, bl: Tuple, br: Tuple) -> UvPattern
+{
+    UvPattern::AlignCheck{main, ul, ur, bl, br}
+}
+
+impl UvPattern
+{
+    pub fn uv_pattern_at(&self, u: Scalar, v: Scalar) -> Tuple
+    {
+        match self
+        {
+            UvPattern::Image(canvas) => image_at(canvas, u, v),
+            UvPattern::Checkers{width, height, a, b} =>
+            {
+                let u2 = (u * width).floor() as i64;
+                let v2 = (v * height).floor() as i64;
+                if (u2 + v2) % 2 == 0 { *a } else { *b }
+            },
+            UvPattern::AlignCheck{main, ul, ur, bl, br} =>
+            {
+                // the corners are a fifth of the square across
+                if v > 0.8 && u < 0.2
+                {
+                    *ul
+                }
+                else if v > 0.8 && u > 0.8
+                {
+                    *ur
+                }
+                else if v < 0.2 && u < 0.2
+                {
+                    *bl
+                }
+                else if v < 0.2 && u > 0.8
+                {
+                    *br
+                }
+                else
+                {
+                    *main
+                }
+            },
+        }
+    }
+}
+
+// The pixel of an image nearest to (u, v), with (0, 0) at the bottom left
+pub fn image_at(canvas: &Canvas, u: Scalar, v: Scalar) -> Tuple
+{
+    // v goes up, while the rows of the image go down
+    let x = (u * (canvas.width - 1) as Scalar).round() as usize;
+    let y = ((1.0 - v) * (canvas.height - 1) as Scalar).round() as usize;
+    canvas.pixel_at(x.min(canvas.width - 1), y.min(canvas.height - 1))
+}
+
+// How a point on a shape is turned into the (u, v) of a UV pattern
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UvMapping
+{
+    // longitude and latitude around the origin, for spheres
+    Spherical,
+    // the x and z of the point, repeating every unit, for planes
+    Planar,
+    // around the y axis and up it, repeating every unit, for cylinders
+    Cylindrical,
+    // each face of the unit cube has the whole square, for cubes
+    Cube,
+}
+
+impl UvMapping
+{
+    pub fn map(&self, point: Tuple) -> (Scalar, Scalar)
+    {
+        match self
+        {
+            UvMapping::Spherical => spherical_map(point),
+            UvMapping::Planar => planar_map(point),
+            UvMapping::Cylindrical => cylindrical_map(point),
+            UvMapping::Cube => cube_map(point).1,
+        }
+    }
+}
+
+pub fn spherical_map(point: Tuple) -> (Scalar, Scalar)
+{
+    let p = point.to_array();
+    // the azimuthal angle, from -π to π, which goes clockwise looking down
+    let theta = p[0].atan2(p[2]);
+    let radius = create_vector(p[0], p[1], p[2]).magnitude();
+    // the polar angle, from 0 at the top to π at the bottom
+    let phi = (p[1] / radius).acos();
+    let raw_u = theta / (2.0 * PI);
+    // flipped so that u goes counterclockwise looking down
+    let u = 1.0 - (raw_u + 0.5);
+    let v = 1.0 - phi / PI;
+    (u, v)
+}
+
+pub fn planar_map(point: Tuple) -> (Scalar, Scalar)
+{
+    let p = point.to_array();
+    (p[0].rem_euclid(1.0), p[2].rem_euclid(1.0))
+}
+
+pub fn cylindrical_map(point: Tuple) -> (Scalar, Scalar)
+{
+    let p = point.to_array();
+    let theta = p[0].atan2(p[2]);
+    let raw_u = theta / (2.0 * PI);
+    let u = 1.0 - (raw_u + 0.5);
+    (u, p[1].rem_euclid(1.0))
+}
+
+// The faces of a cube, named as seen from the front, looking toward +z
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CubeFace
+{
+    Left,
+    Right,
+    Front,
+    Back,
+    Up,
+    Down,
+}
+
+// The face the point is on, which is the one its largest coordinate
+// points to
+pub fn face_from_point(point: Tuple) -> CubeFace
+{
+    let p = point.to_array();
+    let coord = p[0].abs().max(p[1].abs()).max(p[2].abs());
+    if coord == p[0]
+    {
+        CubeFace::Right
+    }
+    else if coord == -p[0]
+    {
+        CubeFace::Left
+    }
+    else if coord == p[1]
+    {
+        CubeFace::Up
+    }
+    else if coord == -p[1]
+    {
+        CubeFace::Down
+    }
+    else if coord == p[2]
+    {
+        CubeFace::Front
+    }
+    else
+    {
+        CubeFace::Back
+    }
+}
+
+// The face a point is on and where on that face it is. Each face is
+// seen from outside the cube, with the up face's top edge at the back and
+// the down face's at the front.
+pub fn cube_map(point: Tuple) -> (CubeFace, (Scalar, Scalar))
+{
+    let p = point.to_array();
+    let wrap = |a: Scalar| a.rem_euclid(2.0) / 2.0;
+    let face = face_from_point(point);
+    let uv = match face
+    {
+        CubeFace::Front => (wrap(p[0] + 1.0), wrap(p[1] + 1.0)),
+        CubeFace::Back => (wrap(1.0 - p[0]), wrap(p[1] + 1.0)),
+        CubeFace::Left => (wrap(p[2] + 1.0), wrap(p[1] + 1.0)),
+        CubeFace::Right => (wrap(1.0 - p[2]), wrap(p[1] + 1.0)),
+        CubeFace::Up => (wrap(p[0] + 1.0), wrap(1.0 - p[2])),
+        CubeFace::Down => (wrap(p[0] + 1.0), wrap(p[2] + 1.0)),
+    };
+    (face, uv)
+}
+
+// Wraps a UV pattern onto a shape
+#[derive(Clone, Debug)]
+pub struct TextureMapPattern
+{
+    pub uv_pattern: UvPattern,
+    pub mapping: UvMapping,
+}
+
+impl TextureMapPattern
+{
+    pub fn new(uv_pattern: UvPattern, mapping: UvMapping) -> TextureMapPattern
+    {
+        TextureMapPattern{uv_pattern, mapping}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let (u, v) = self.mapping.map(point);
+        self.uv_pattern.uv_pattern_at(u, v)
+    }
+}
+
+// A UV pattern for each face of a cube
+#[derive(Clone, Debug)]
+pub struct CubeMapPattern
+{
+    pub left: UvPattern,
+    pub front: UvPattern,
+    pub right: UvPattern,
+    pub back: UvPattern,
+    pub up: UvPattern,
+    pub down: UvPattern,
+}
+
+impl CubeMapPattern
+{
+    pub fn new(left: UvPattern, front: UvPattern, right: UvPattern, back: UvPattern, up: UvPattern,
+        down: UvPattern) -> CubeMapPattern
+    {
+        CubeMapPattern{left, front, right, back, up, down}
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Tuple
+    {
+        let (face, (u, v)) = cube_map(point);
+        let pattern = match face
+        {
+            CubeFace::Left => &self.left,
+            CubeFace::Front => &self.front,
+            CubeFace::Right => &self.right,
+            CubeFace::Back => &self.back,
+            CubeFace::Up => &self.up,
+            CubeFace::Down => &self.down,
+        };
+        pattern.uv_pattern_at(u, v)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn assert_uv(actual: (Scalar, Scalar), expected: (Scalar, Scalar))
+    {
+        assert!(fuzzy_equal(actual.0, expected.0) && fuzzy_equal(actual.1, expected.1),
+            "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn test_texturemap_feature()
+    {
+        // Bonus Scenario: Using a spherical mapping on a 3D point
+        let h = (2.0 as Scalar).sqrt() / 2.0;
+        for (point, uv) in [((0.0, 0.0, -1.0), (0.0, 0.5)), ((1.0, 0.0, 0.0), (0.25, 0.5)),
+            ((0.0, 0.0, 1.0), (0.5, 0.5)), ((-1.0, 0.0, 0.0), (0.75, 0.5)), ((0.0, 1.0, 0.0), (0.5, 1.0)),
+            ((0.0, -1.0, 0.0), (0.5, 0.0)), ((h, h, 0.0), (0.25, 0.75))]
+        {
+            assert_uv(spherical_map(create_point(point.0, point.1, point.2)), uv);
+        }
+
+        // Bonus Scenario: Using a planar mapping on a 3D point
+        for (point, uv) in [((0.25, 0.0, 0.5), (0.25, 0.5)), ((0.25, 0.0, -0.25), (0.25, 0.75)),
+            ((0.25, 0.5, -0.25), (0.25, 0.75)), ((1.25, 0.0, 0.5), (0.25, 0.5)),
+            ((0.25, 0.0, -1.75), (0.25, 0.25)), ((1.0, 0.0, -1.0), (0.0, 0.0)), ((0.0, 0.0, 0.0), (0.0, 0.0))]
+        {
+            assert_uv(planar_map(create_point(point.0, point.1, point.2)), uv);
+        }
+
+        // Bonus Scenario: Using a cylindrical mapping on a 3D point
+        for (point, uv) in [((0.0, 0.0, -1.0), (0.0, 0.0)), ((0.0, 0.5, -1.0), (0.0, 0.5)),
+            ((0.0, 1.0, -1.0), (0.0, 0.0)), ((h, 0.5, -h), (0.125, 0.5)),
+            ((1.0, 0.5, 0.0), (0.25, 0.5)), ((h, 0.5, h), (0.375, 0.5)),
+            ((0.0, -0.25, 1.0), (0.5, 0.75)), ((-h, 0.5, h), (0.625, 0.5)),
+            ((-1.0, 1.25, 0.0), (0.75, 0.25)), ((-h, 0.5, -h), (0.875, 0.5))]
+        {
+            assert_uv(cylindrical_map(create_point(point.0, point.1, point.2)), uv);
+        }
+
+        // Bonus Scenario: Identifying the face of a cube from a point
+        for (point, face) in [((-1.0, 0.5, -0.25), CubeFace::Left), ((1.1, -0.75, 0.8), CubeFace::Right),
+            ((0.1, 0.6, 0.9), CubeFace::Front), ((-0.7, 0.0, -2.0), CubeFace::Back),
+            ((0.5, 1.0, 0.9), CubeFace::Up), ((-0.2, -1.3, 1.1), CubeFace::Down)]
+        {
+            assert_eq!(face_from_point(create_point(point.0, point.1, point.2)), face);
+        }
+
+        // Bonus Scenario: UV mapping each face of a cube
+        for (point, uv) in [((-0.5, 0.5, 1.0), (0.25, 0.75)), ((0.5, -0.5, 1.0), (0.75, 0.25)),
+            ((0.5, 0.5, -1.0), (0.25, 0.75)), ((-0.5, -0.5, -1.0), (0.75, 0.25)),
+            ((-1.0, 0.5, -0.5), (0.25, 0.75)), ((-1.0, -0.5, 0.5), (0.75, 0.25)),
+            ((1.0, 0.5, 0.5), (0.25, 0.75)), ((1.0, -0.5, -0.5), (0.75, 0.25)),
+            ((-0.5, 1.0, -0.5), (0.25, 0.75)), ((0.5, 1.0, 0.5), (0.75, 0.25)),
+            ((-0.5, -1.0, 0.5), (0.25, 0.75)), ((0.5, -1.0, -0.5), (0.75, 0.25))]
+        {
+            assert_uv(cube_map(create_point(point.0, point.1, point.2)).1, uv);
+        }
+
+        // Bonus Scenario: Checkers pattern in 2D
+        let black = create_color(0.0, 0.0, 0.0);
+        let white = create_color(1.0, 1.0, 1.0);
+        let checkers = uv_checkers(2.0, 2.0, black, white);
+        for (uv, expected) in [((0.0, 0.0), black), ((0.5, 0.0), white), ((0.0, 0.5), white),
+            ((0.5, 0.5), black), ((1.0, 1.0), black)]
+        {
+            assert_eq!(checkers.uv_pattern_at(uv.0, uv.1), expected);
+        }
+
+        // Bonus Scenario: Using a texture map pattern with a spherical map
+        let sphere_map = TextureMapPattern::new(uv_checkers(16.0, 8.0, black, white), UvMapping::Spherical);
+        for (point, expected) in [((0.4315, 0.4670, 0.7719), white), ((-0.9654, 0.2552, -0.0534), black),
+            ((0.1039, 0.7090, 0.6975), white), ((-0.4986, -0.7856, -0.3663), black),
+            ((-0.0317, -0.9395, 0.3411), black), ((0.4809, -0.7721, 0.4154), black),
+            ((0.0285, -0.9612, -0.2745), black), ((-0.5734, -0.2162, -0.7903), white),
+            ((0.7688, -0.1470, 0.6223), black), ((-0.7652, 0.2175, 0.6060), black)]
+        {
+            assert_eq!(sphere_map.pattern_at(create_point(point.0, point.1, point.2)), expected);
+        }
+
+        // Bonus Scenario: Layout of the "align check" pattern
+        let red = create_color(1.0, 0.0, 0.0);
+        let yellow = create_color(1.0, 1.0, 0.0);
+        let brown = create_color(1.0, 0.5, 0.0);
+        let green = create_color(0.0, 1.0, 0.0);
+        let cyan = create_color(0.0, 1.0, 1.0);
+        let blue = create_color(0.0, 0.0, 1.0);
+        let purple = create_color(1.0, 0.0, 1.0);
+        let align = uv_align_check(white, red, yellow, green, cyan);
+        for (uv, expected) in [((0.5, 0.5), white), ((0.1, 0.9), red), ((0.9, 0.9), yellow),
+            ((0.1, 0.1), green), ((0.9, 0.1), cyan)]
+        {
+            assert_eq!(align.uv_pattern_at(uv.0, uv.1), expected);
+        }
+
+        // Bonus Scenario: Finding the colors on a mapped cube
+        let cube = CubeMapPattern::new(uv_align_check(yellow, cyan, red, blue, brown),
+            uv_align_check(cyan, red, yellow, brown, green), uv_align_check(red, yellow, purple, green, white),
+            uv_align_check(green, purple, cyan, white, blue), uv_align_check(brown, cyan, purple, red, yellow),
+            uv_align_check(purple, brown, green, blue, white));
+        for (point, expected) in [((-1.0, 0.0, 0.0), yellow), ((-1.0, 0.9, -0.9), cyan), ((-1.0, 0.9, 0.9), red),
+            ((-1.0, -0.9, -0.9), blue), ((-1.0, -0.9, 0.9), brown), ((0.0, 0.0, 1.0), cyan),
+            ((-0.9, 0.9, 1.0), red), ((0.9, 0.9, 1.0), yellow), ((-0.9, -0.9, 1.0), brown),
+            ((0.9, -0.9, 1.0), green), ((1.0, 0.0, 0.0), red), ((1.0, 0.9, 0.9), yellow),
+            ((1.0, 0.9, -0.9), purple), ((1.0, -0.9, 0.9), green), ((1.0, -0.9, -0.9), white),
+            ((0.0, 0.0, -1.0), green), ((0.9, 0.9, -1.0), purple), ((-0.9, 0.9, -1.0), cyan),
+            ((0.9, -0.9, -1.0), white), ((-0.9, -0.9, -1.0), blue), ((0.0, 1.0, 0.0), brown),
+            ((-0.9, 1.0, -0.9), cyan), ((0.9, 1.0, -0.9), purple), ((-0.9, 1.0, 0.9), red),
+            ((0.9, 1.0, 0.9), yellow), ((0.0, -1.0, 0.0), purple), ((-0.9, -1.0, -0.9), blue),
+            ((0.9, -1.0, -0.9), white), ((-0.9, -1.0, 0.9), brown), ((0.9, -1.0, 0.9), green)]
+        {
+            assert_eq!(cube.pattern_at(create_point(point.0, point.1, point.2)), expected, "at {:?}", point);
+        }
+
+        // Scenario: An image as a UV pattern
+        let mut canvas = Canvas::new(10, 10);
+        for y in 0..10
+        {
+            for x in 0..10
+            {
+                canvas.write_pixel(x, y, create_color(x as Scalar / 10.0, y as Scalar / 10.0, 0.0));
+            }
+        }
+        let image = UvPattern::Image(Arc::new(canvas));
+        assert_eq!(image.uv_pattern_at(0.0, 0.0), create_color(0.0, 0.9, 0.0));
+        assert_eq!(image.uv_pattern_at(1.0, 1.0), create_color(0.9, 0.0, 0.0));
+        assert_eq!(image.uv_pattern_at(0.5, 0.5), create_color(0.5, 0.5, 0.0));
+
+        // Scenario: A texture map wraps a UV pattern with a mapping
+        let map = TextureMapPattern::new(image, UvMapping::Spherical);
+        assert_eq!(map.pattern_at(create_point(0.0, 0.0, -1.0)), create_color(0.0, 0.5, 0.0));
+        assert_eq!(map.pattern_at(create_point(0.0, 1.0, 0.0)), create_color(0.5, 0.0, 0.0));
+        assert_eq!(map.pattern_at(create_point(1.0, 0.0, 0.0)), map.pattern_at(create_point(2.0, 0.0, 0.0)));
+    }
+}
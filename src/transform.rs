@@ -0,0 +1,149 @@
+use crate::arithmetic::*;
+use crate::matrix::*;
+use crate::tuple::*;
+
+// Builds a transformation matrix one step at a time, in the order the
+// steps are applied to a shape, so that
+//
+//     Transform::identity().scale(2.0, 2.0, 2.0).translate(0.0, 1.0, 0.0)
+//
+// scales and then moves, without writing the matrices out backwards as
+// translation.multiply(&scaling)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform
+{
+    matrix: Matrix,
+}
+
+impl Transform
+{
+    pub fn identity() -> Self
+    {
+        Transform{matrix: Matrix::identity(4)}
+    }
+
+    // Applies another transformation after the steps so far
+    pub fn then(self, m: &Matrix) -> Self
+    {
+        Transform{matrix: m.multiply(&self.matrix)}
+    }
+
+    pub fn translate(self, x: Scalar, y: Scalar, z: Scalar) -> Self
+    {
+        self.then(&Matrix::translation(x, y, z))
+    }
+
+    pub fn scale(self, x: Scalar, y: Scalar, z: Scalar) -> Self
+    {
+        self.then(&Matrix::scaling(x, y, z))
+    }
+
+    pub fn rotate_x(self, r: Scalar) -> Self
+    {
+        self.then(&Matrix::rotation_x(r))
+    }
+
+    pub fn rotate_y(self, r: Scalar) -> Self
+    {
+        self.then(&Matrix::rotation_y(r))
+    }
+
+    pub fn rotate_z(self, r: Scalar) -> Self
+    {
+        self.then(&Matrix::rotation_z(r))
+    }
+
+    pub fn rotate_axis(self, axis: Tuple, r: Scalar) -> Self
+    {
+        self.then(&Matrix::rotation_axis(axis, r))
+    }
+
+    pub fn shear(self, xy: Scalar, xz: Scalar, yx: Scalar, yz: Scalar, zx: Scalar, zy: Scalar) -> Self
+    {
+        self.then(&Matrix::shearing(xy, xz, yx, yz, zx, zy))
+    }
+
+    // Places a shape standing on the y axis from y = 0 to y = 1, with a
+    // radius of 1, such as a cylinder or cone cut off at 0 and 1, so that
+    // it runs from one point to another with the given radius. Useful for
+    // the edges of wireframes and the bonds between atoms.
+    pub fn between(from: Tuple, to: Tuple, radius: Scalar) -> Self
+    {
+        let span = to.sub(from);
+        let length = span.magnitude();
+        let up = create_vector(0.0, 1.0, 0.0);
+        let direction = span.divide(length);
+        let axis = up.cross_product(direction);
+        let cos = up.dot_product(direction).clamp(-1.0, 1.0);
+        let mut transform = Transform::identity().scale(radius, length, radius);
+        if axis.magnitude() > EPSILON
+        {
+            transform = transform.rotate_axis(axis, cos.acos());
+        }
+        else if cos < 0.0
+        {
+            // pointing straight down, where any axis across y will do
+            transform = transform.rotate_x(consts::PI);
+        }
+        let f = from.to_array();
+        transform.translate(f[0], f[1], f[2])
+    }
+
+    pub fn get_matrix(&self) -> Matrix
+    {
+        self.matrix.clone()
+    }
+}
+
+impl From<Transform> for Matrix
+{
+    fn from(transform: Transform) -> Matrix
+    {
+        transform.matrix
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::arithmetic::consts::PI;
+
+    #[test]
+    fn test_transform_feature()
+    {
+        // p.54 Scenario: Chained transformations must be applied in reverse order
+        let p1 = create_point(1.0, 0.0, 1.0);
+        let t1: Matrix = Transform::identity().rotate_x(PI / 2.0).scale(5.0, 5.0, 5.0)
+            .translate(10.0, 5.0, 7.0).into();
+        assert_eq!(t1.multiply_tuple(p1), create_point(15.0, 0.0, 7.0));
+
+        // Scenario: A chain gives the same matrix as multiplying in reverse
+        let t2 = Transform::identity().scale(0.25, 1.0, 0.25).rotate_z(-PI / 2.0).rotate_y(-PI / 6.0)
+            .translate(0.0, 0.0, -1.0);
+        assert_eq!(t2.get_matrix(), Matrix::translation(0.0, 0.0, -1.0)
+            .multiply(&Matrix::rotation_y(-PI / 6.0))
+            .multiply(&Matrix::rotation_z(-PI / 2.0))
+            .multiply(&Matrix::scaling(0.25, 1.0, 0.25)));
+        assert_eq!(Transform::identity().get_matrix(), Matrix::identity(4));
+        assert_eq!(Transform::identity().shear(1.0, 0.0, 0.0, 0.0, 0.0, 0.0).then(&Matrix::rotation_x(0.3)),
+            Transform{matrix: Matrix::rotation_x(0.3).multiply(&Matrix::shearing(1.0, 0.0, 0.0, 0.0, 0.0, 0.0))});
+
+        // Scenario: A shape is placed to run between two points
+        let from3 = create_point(1.0, 2.0, 3.0);
+        let to3 = create_point(4.0, -2.0, 3.0);
+        let t3 = Transform::between(from3, to3, 0.5).get_matrix();
+        assert_eq!(t3.multiply_tuple(create_point(0.0, 0.0, 0.0)), from3);
+        assert_eq!(t3.multiply_tuple(create_point(0.0, 1.0, 0.0)), to3);
+        // the rim stays at the radius from the axis
+        let rim3 = t3.multiply_tuple(create_point(1.0, 0.0, 0.0)).sub(from3);
+        assert!(fuzzy_equal(rim3.magnitude(), 0.5));
+        assert!(fuzzy_equal(rim3.dot_product(to3.sub(from3)), 0.0));
+
+        // Scenario: Placing a shape straight up or straight down
+        let t4 = Transform::between(create_point(0.0, 1.0, 0.0), create_point(0.0, 3.0, 0.0), 2.0).get_matrix();
+        assert_eq!(t4, Matrix::translation(0.0, 1.0, 0.0).multiply(&Matrix::scaling(2.0, 2.0, 2.0)));
+        let t5 = Transform::between(create_point(0.0, 1.0, 0.0), create_point(0.0, -3.0, 0.0), 1.0).get_matrix();
+        assert_eq!(t5.multiply_tuple(create_point(0.0, 1.0, 0.0)), create_point(0.0, -3.0, 0.0));
+    }
+}
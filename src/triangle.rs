@@ -1,6 +1,7 @@
 use std::fmt;
 use std::cmp;
 use crate::arithmetic::*;
+use crate::bounds::*;
 use crate::intersections::*;
 use crate::tuple::*;
 use crate::ray::*;
@@ -18,6 +19,35 @@ pub struct Triangle
     pub e1: Tuple,
     pub e2: Tuple,
     pub normal: Tuple,
+    // texture coordinates at p1, p2 and p3, if the triangle has them
+    pub uvs: Option<[(Scalar, Scalar); 3]>,
+}
+
+// Interpolates per-vertex texture coordinates at a hit, where the hit's
+// u and v weight the second and third vertices
+pub fn interpolate_uv(uvs: Option<[(Scalar, Scalar); 3]>, hit_uv: (Scalar, Scalar)) -> Option<(Scalar, Scalar)>
+{
+    let t = uvs?;
+    let w = 1.0 - hit_uv.0 - hit_uv.1;
+    Some((w * t[0].0 + hit_uv.0 * t[1].0 + hit_uv.1 * t[2].0,
+        w * t[0].1 + hit_uv.0 * t[1].1 + hit_uv.1 * t[2].1))
+}
+
+// The direction the texture's u grows in across a triangle, or along its
+// first edge if it has no texture coordinates
+pub fn uv_tangent(e1: Tuple, e2: Tuple, uvs: Option<[(Scalar, Scalar); 3]>) -> Tuple
+{
+    if let Some(t) = uvs
+    {
+        let (du1, dv1) = (t[1].0 - t[0].0, t[1].1 - t[0].1);
+        let (du2, dv2) = (t[2].0 - t[0].0, t[2].1 - t[0].1);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() > EPSILON
+        {
+            return e1.multiply(dv2).sub(e2.multiply(dv1)).multiply(1.0 / det);
+        }
+    }
+    e1
 }
 
 impl Triangle
@@ -27,40 +57,57 @@ impl Triangle
         let e1 = p2.sub(p1);
         let e2 = p3.sub(p1);
         let normal = e2.cross_product(e1).normalize();
-        Triangle{p1: p1, p2: p2, p3: p3, e1: e1, e2: e2, normal: normal}
+        Triangle{p1: p1, p2: p2, p3: p3, e1: e1, e2: e2, normal: normal, uvs: None}
     }
 
-    pub fn local_normal_at(&self, point: Tuple, hit_uv: (f64, f64)) -> Tuple
+    pub fn local_normal_at(&self, point: Tuple, hit_uv: (Scalar, Scalar)) -> Tuple
     {
         self.normal
     }
 
-    pub fn local_intersect(&self, ray: Ray) -> Vec<(f64, f64, f64)>
+    pub fn local_tangent_at(&self) -> Tuple
+    {
+        uv_tangent(self.e1, self.e2, self.uvs)
+    }
+
+    pub fn local_intersect(&self, ray: Ray) -> LocalHits
     {
         let dir_cross_e2 = ray.direction.cross_product(self.e2);
         let det = self.e1.dot_product(dir_cross_e2);
         if det.abs() < EPSILON
         {
-            return Vec::new();
+            return LocalHits::new();
         }
         let f = 1.0 / det;
         let p1_to_origin = ray.origin.sub(self.p1);
         let u = f * p1_to_origin.dot_product(dir_cross_e2);
         if u < 0.0 || u > 1.0
         {
-            return Vec::new();
+            return LocalHits::new();
         }
         let origin_cross_e1 = p1_to_origin.cross_product(self.e1);
         let v = f * ray.direction.dot_product(origin_cross_e1);
         if v < 0.0 || u + v > 1.0
         {
-            return Vec::new();
+            return LocalHits::new();
         }
         let t = f * self.e2.dot_product(origin_cross_e1);
-        // u and v only implemented for smooth triangles
-        let u0 = 0.0;
-        let v0 = 0.0;
-        vec![(t, u0, v0)]
+        // u and v are kept for interpolating texture coordinates
+        [(t, u, v)].into()
+    }
+
+    pub fn bounds(&self) -> BoundingBox
+    {
+        let mut bounds = BoundingBox::new();
+        bounds.add_point(self.p1);
+        bounds.add_point(self.p2);
+        bounds.add_point(self.p3);
+        bounds
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere::from_box(&self.bounds())
     }
 }
 
@@ -174,4 +221,19 @@ mod tests
         assert_eq!(xs7.len(), 1);
         assert!(fuzzy_equal(xs7[0].0, 2.0));
     }
+
+    #[test]
+    fn test_triangles_texture_uv()
+    {
+        // Scenario: Texture coordinates are interpolated at a hit
+        let mut t8 = Triangle::new(create_point(0.0, 1.0, 0.0),
+            create_point(-1.0, 0.0, 0.0), create_point(1.0, 0.0, 0.0));
+        assert_eq!(interpolate_uv(t8.uvs, (0.25, 0.5)), None);
+        t8.uvs = Some([(0.5, 1.0), (0.0, 0.0), (1.0, 0.0)]);
+        let r8 = Ray::new(create_point(-0.2, 0.3, -2.0), create_vector(0.0, 0.0, 1.0));
+        let xs8 = t8.local_intersect(r8);
+        let uv8 = interpolate_uv(t8.uvs, (xs8[0].1, xs8[0].2)).unwrap();
+        assert!(fuzzy_equal(uv8.0, 0.4));
+        assert!(fuzzy_equal(uv8.1, 0.3));
+    }
 }
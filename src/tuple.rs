@@ -4,19 +4,25 @@ use crate::arithmetic::*;
 #[derive(Copy, Clone, Debug)]
 pub struct Tuple
 {
-    x: f64,
-    y: f64,
-    z: f64,
-    w: f64,
+    x: Scalar,
+    y: Scalar,
+    z: Scalar,
+    w: Scalar,
 }
 
 impl Tuple
 {
-    pub fn get_vec(self) -> Vec<f64>
+    pub fn get_vec(self) -> Vec<Scalar>
     {
         vec![self.x, self.y, self.z, self.w]
     }
 
+    // Like get_vec, without allocating
+    pub fn to_array(self) -> [Scalar; 4]
+    {
+        [self.x, self.y, self.z, self.w]
+    }
+
     pub fn add(&self, b: Tuple) -> Tuple
     {
         Tuple{x: self.x + b.x, y: self.y + b.y, z: self.z + b.z, w: self.w + b.w}
@@ -32,17 +38,17 @@ impl Tuple
         Tuple{x: -self.x, y: -self.y, z: -self.z, w: -self.w}
     }
 
-    pub fn multiply(&self, scalar: f64) -> Tuple
+    pub fn multiply(&self, scalar: Scalar) -> Tuple
     {
         Tuple{x: self.x * scalar, y: self.y * scalar, z: self.z * scalar, w: self.w * scalar}
     }
 
-    pub fn divide(&self, scalar: f64) -> Tuple
+    pub fn divide(&self, scalar: Scalar) -> Tuple
     {
         Tuple{x: self.x / scalar, y: self.y / scalar, z: self.z / scalar, w: self.w / scalar}
     }
 
-    pub fn magnitude(&self) -> f64
+    pub fn magnitude(&self) -> Scalar
     {
         let n = (self.x * self.x) + (self.y * self.y) + (self.z * self.z) + (self.w * self.w);
         n.sqrt()
@@ -54,7 +60,7 @@ impl Tuple
         Tuple{x: self.x / m, y: self.y / m, z: self.z / m, w: self.w / m}
     }
 
-    pub fn dot_product(&self, b: Tuple) -> f64
+    pub fn dot_product(&self, b: Tuple) -> Scalar
     {
         self.x * b.x + self.y * b.y + self.z * b.z + self.w * b.w
     }
@@ -95,22 +101,22 @@ impl fmt::Display for Tuple
     }
 }
 
-pub fn create_point(x: f64, y: f64, z: f64) -> Tuple
+pub fn create_point(x: Scalar, y: Scalar, z: Scalar) -> Tuple
 {
     Tuple{x: x, y: y, z: z, w: 1.0}
 }
 
-pub fn create_vector(x: f64, y: f64, z: f64) -> Tuple
+pub fn create_vector(x: Scalar, y: Scalar, z: Scalar) -> Tuple
 {
     Tuple{x: x, y: y, z: z, w: 0.0}
 }
 
-pub fn create_color(r: f64, g: f64, b: f64) -> Tuple
+pub fn create_color(r: Scalar, g: Scalar, b: Scalar) -> Tuple
 {
     Tuple{x: r, y: g, z: b, w: 0.0}
 }
 
-pub fn create_tuple(x: f64, y: f64, z: f64, w: f64) -> Tuple
+pub fn create_tuple(x: Scalar, y: Scalar, z: Scalar, w: Scalar) -> Tuple
 {
     Tuple{x: x, y: y, z: z, w: w}
 }
@@ -217,11 +223,11 @@ mod tests
 
         // p.9 Scenario: Computing the magnitude of vector(1, 2, 3)
         let m4 = create_vector(1.0, 2.0, 3.0).magnitude();
-        assert!(fuzzy_equal(m4, 14.0_f64.sqrt()));
+        assert!(fuzzy_equal(m4, Scalar::sqrt(14.0)));
 
         // p.9 Scenario: Computing the magnitude of vector(-1, -2, -3)
         let m5 = create_vector(-1.0, -2.0, -3.0).magnitude();
-        assert!(fuzzy_equal(m5, 14.0_f64.sqrt()));
+        assert!(fuzzy_equal(m5, Scalar::sqrt(14.0)));
 
         // p.10 Scenario: Normalizing the vector (4, 0, 0) gives (1, 0, 0)
         let no1 = create_vector(4.0, 0.0, 0.0).normalize();
@@ -287,7 +293,7 @@ mod tests
 
         // p.83 Scenario: Reflecting a vector off a slanted surface
         let v11 = create_vector(0.0, -1.0, 0.0);
-        let sqrt2 = 2.0_f64.sqrt();
+        let sqrt2 = Scalar::sqrt(2.0);
         let n11 = create_vector(sqrt2 / 2.0, sqrt2 / 2.0, 0.0);
         let r11 = v11.reflect(n11);
         assert_eq!(r11, create_vector(1.0, 0.0, 0.0));
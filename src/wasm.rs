@@ -0,0 +1,40 @@
+use std::path::Path;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+use crate::scene::*;
+
+// Entry points for running the ray tracer in a web browser. Built with
+//
+//     cargo build --release --target wasm32-unknown-unknown --features wasm
+//     wasm-bindgen --target web --out-dir www/pkg \
+//         target/wasm32-unknown-unknown/release/ray_tracer.wasm
+//
+// and used by www/index.html.
+
+// Renders a YAML or JSON scene into an HTML canvas, which is resized to
+// the scene's camera. There is no filesystem in a browser, so the scene
+// can't load OBJ files.
+#[wasm_bindgen]
+pub fn render_scene(text: &str, canvas: &HtmlCanvasElement) -> Result<(), JsValue>
+{
+    let mut scene = parse_scene(text, Path::new(""))
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let camera = match scene.camera
+    {
+        Some(c) => c,
+        None => return Err(JsValue::from_str("the scene has no camera")),
+    };
+    scene.world.compile();
+    let image = camera.render(scene.world);
+
+    canvas.set_width(image.width as u32);
+    canvas.set_height(image.height as u32);
+    let context = canvas.get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("the canvas has no 2d context"))?
+        .dyn_into::<CanvasRenderingContext2d>()?;
+    let rgba = image.to_rgba();
+    let data = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&rgba),
+        image.width as u32, image.height as u32)?;
+    context.put_image_data(&data, 0.0, 0.0)
+}
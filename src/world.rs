@@ -1,11 +1,28 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use crate::accel::*;
+use crate::aov::*;
+use crate::arena::*;
+use crate::background::*;
+use crate::arithmetic::consts::PI;
 use crate::arithmetic::*;
 use crate::computations::*;
+use crate::fog::*;
+use crate::heatmap::*;
+use crate::integrator::*;
 use crate::intersections::*;
 use crate::material::*;
 use crate::matrix::*;
+use crate::objwriter::*;
 use crate::pattern::*;
 use crate::pointlight::*;
 use crate::ray::*;
+use crate::sampler::*;
 use crate::shape::*;
 use crate::sphere::*;
 use crate::tuple::*;
@@ -14,13 +31,54 @@ use crate::tuple::*;
 pub struct World
 {
     pub light: PointLight,
+    // scales the ambient term of every material, white by default
+    pub ambient: Tuple,
+    // seen by rays that miss every object
+    pub background: Background,
+    // blends the colors of hits toward a fog color with distance
+    pub fog: Option<Fog>,
+    // the children of the world's root group
     pub objects: Vec<Shape>,
+    // the transform of the root group, which moves every object
+    transform: Matrix,
+    // named objects, or shapes inside them
+    names: HashMap<String, NodeRef>,
+    // which render layers rays can see
+    layers: RenderLayers,
+    // the number of reflections or refractions followed from each
+    // camera ray
+    max_depth: i32,
+    // how far rays look for hits, as a far clipping distance
+    max_distance: Scalar,
+    // works out the colors of camera rays
+    integrator: Arc<dyn Integrator>,
+    sample_limits: SampleLimits,
+    tolerance: Tolerance,
+    // the objects flattened by compile, which rays are tested against
+    // in their place
+    compiled: Option<Vec<Shape>>,
+    // where the shapes flattened from each object are in that list
+    compiled_ranges: Vec<Range<usize>>,
+    accelerator: Option<Accelerator>,
+    // the indices of the objects edited through get_object_mut since the
+    // changes were last taken
+    changed: Vec<usize>,
 }
 
 impl World
 {
     // maximum number of times to reflect rays, to avoid infinite recursion
     pub const REFLECTION_RECURSION: i32 = 4;
+    // the most a world's depth can be set to
+    pub const MAX_DEPTH: i32 = 64;
+
+    pub fn new(light: PointLight, objects: Vec<Shape>) -> Self
+    {
+        World{light, ambient: create_color(1.0, 1.0, 1.0), background: Background::new(), fog: None, objects,
+            transform: Matrix::identity(4), names: HashMap::new(), layers: RenderLayers::new(),
+            max_depth: Self::REFLECTION_RECURSION, max_distance: Scalar::INFINITY,
+            integrator: Arc::new(WhittedIntegrator), sample_limits: SampleLimits::new(), tolerance: Tolerance::new(), compiled: None, compiled_ranges: Vec::new(), accelerator: None, changed: Vec::new()}
+    }
 
     pub fn default_world() -> Self
     {
@@ -37,104 +95,703 @@ impl World
         let mut sphere2 = Shape::new_sphere(2);
         sphere2.set_transform(Matrix::scaling(0.5, 0.5, 0.5));
 
-        World{light: light, objects: vec![sphere1, sphere2]}
+        World::new(light, vec![sphere1, sphere2])
+    }
+
+    pub fn get_max_depth(&self) -> i32
+    {
+        self.max_depth
+    }
+
+    // Sets how many reflections or refractions color_for_ray follows,
+    // between 0 and MAX_DEPTH. Scenes with a lot of glass need more than
+    // the default.
+    pub fn set_max_depth(&mut self, depth: i32)
+    {
+        self.max_depth = depth.clamp(0, Self::MAX_DEPTH);
+    }
+
+    pub fn get_max_distance(&self) -> Scalar
+    {
+        self.max_distance
+    }
+
+    // Sets how far along any ray hits are found, so that distant objects
+    // are clipped away. Unlimited by default.
+    pub fn set_max_distance(&mut self, distance: Scalar)
+    {
+        self.max_distance = distance;
+    }
+
+    pub fn get_sample_limits(&self) -> SampleLimits
+    {
+        self.sample_limits
+    }
+
+    pub fn set_sample_limits(&mut self, limits: SampleLimits)
+    {
+        self.sample_limits = limits;
+    }
+
+    pub fn get_tolerance(&self) -> Tolerance
+    {
+        self.tolerance
+    }
+
+    // Very large or very small scenes may need a different tolerance to
+    // avoid shadow acne or light leaking through thin shapes
+    pub fn set_tolerance(&mut self, tolerance: Tolerance)
+    {
+        self.tolerance = tolerance;
+    }
+
+    pub fn get_integrator(&self) -> &dyn Integrator
+    {
+        self.integrator.as_ref()
+    }
+
+    // Chooses the shading algorithm color_for_ray uses, such as one that
+    // shows normals or depth for debugging. WhittedIntegrator by default.
+    pub fn set_integrator<I: Integrator + 'static>(&mut self, integrator: I)
+    {
+        self.integrator = Arc::new(integrator);
+    }
+
+    // An object to edit, which is remembered as changed so that an
+    // IncrementalRender only renders the parts of the image it affects
+    pub fn get_object_mut(&mut self, index: usize) -> &mut Shape
+    {
+        self.changed.push(index);
+        &mut self.objects[index]
+    }
+
+    pub fn get_light(&self) -> PointLight
+    {
+        self.light
+    }
+
+    pub fn set_light(&mut self, light: PointLight)
+    {
+        self.light = light;
     }
 
-    pub fn intersect_world(&self, ray: Ray) -> Intersections
+    // Adds an object, returning the id it can be found by. The object and
+    // the shapes inside it keep their own ids unless a shape already in
+    // the world, at any depth, has one, in which case it is given one
+    // greater than any in use.
+    pub fn add_shape(&mut self, shape: Shape) -> i32
     {
-        let mut intersections = Vec::new();
+        let mut shape = shape;
+        let mut used = HashSet::new();
         for object in &self.objects
         {
-            let xs = object.clone().intersect(ray);
-            for tuv in xs
+            object.collect_ids(&mut used);
+        }
+        let mut incoming = HashSet::new();
+        shape.collect_ids(&mut incoming);
+        let mut next = used.union(&incoming).copied().max().unwrap_or(0) + 1;
+        shape.renumber_ids(&mut |id| match used.contains(&id)
+        {
+            true =>
+            {
+                next += 1;
+                next - 1
+            },
+            false => id,
+        });
+        let id = shape.get_id();
+        self.objects.push(shape);
+        id
+    }
+
+    pub fn get_shape(&self, id: i32) -> Option<&Shape>
+    {
+        self.objects.iter().find(|o| o.get_id() == id)
+    }
+
+    // An object to edit, remembered as changed as with get_object_mut
+    pub fn get_shape_mut(&mut self, id: i32) -> Option<&mut Shape>
+    {
+        let index = self.index_of(id)?;
+        Some(self.get_object_mut(index))
+    }
+
+    // Puts a shape in the place of an object, giving it the object's id,
+    // and returns the object. Names of shapes inside the old object are
+    // forgotten.
+    pub fn replace_shape(&mut self, id: i32, shape: Shape) -> Option<Shape>
+    {
+        let index = self.index_of(id)?;
+        let mut shape = shape;
+        shape.set_id(id);
+        self.names.retain(|_, node| node.object != index || node.id.is_none());
+        Some(std::mem::replace(self.get_object_mut(index), shape))
+    }
+
+    // Takes an object out of the world, along with any names of it or of
+    // shapes inside it. A compiled world is compiled again.
+    pub fn remove_shape(&mut self, id: i32) -> Option<Shape>
+    {
+        let index = self.index_of(id)?;
+        self.names.retain(|_, node| node.object != index);
+        for node in self.names.values_mut()
+        {
+            if node.object > index
             {
-                intersections.push(Intersection::new_with_uv(tuv.0, object.clone(), tuv.1, tuv.2));
+                node.object -= 1;
             }
         }
-        Intersections::new(intersections)
+        self.changed.retain(|&i| i != index);
+        for i in self.changed.iter_mut()
+        {
+            if *i > index
+            {
+                *i -= 1;
+            }
+        }
+        let removed = self.objects.remove(index);
+        if self.is_compiled()
+        {
+            self.compile();
+        }
+        Some(removed)
     }
 
-    pub fn shade_hit(&self, comps: Computations, remaining: i32) -> Tuple
+    fn index_of(&self, id: i32) -> Option<usize>
+    {
+        self.objects.iter().position(|o| o.get_id() == id)
+    }
+
+    // The acceleration structure built by compile, if any
+    pub fn get_accelerator(&self) -> Option<&Accelerator>
+    {
+        self.accelerator.as_ref()
+    }
+
+    pub fn get_transform(&self) -> Matrix
+    {
+        self.transform.clone()
+    }
+
+    // Moves every object at once, such as to turn a whole model around.
+    // The transform is folded into the shapes by compile, so the world
+    // is compiled again here.
+    pub fn set_transform(&mut self, transform: Matrix)
+    {
+        self.transform = transform;
+        self.compile();
+    }
+
+    pub fn get_layers(&self) -> &RenderLayers
+    {
+        &self.layers
+    }
+
+    // Chooses which layers are rendered, such as to leave out some props
+    // or to render the foreground and background separately. Shapes in
+    // hidden layers cast no shadows either.
+    pub fn set_layers(&mut self, layers: RenderLayers)
+    {
+        self.layers = layers;
+    }
+
+    // Adds an object that can be found by name, returning its index
+    pub fn add_named(&mut self, name: &str, object: Shape) -> usize
+    {
+        self.objects.push(object);
+        let index = self.objects.len() - 1;
+        self.names.insert(String::from(name), NodeRef{object: index, id: None});
+        index
+    }
+
+    // Names an object, or a shape inside one (by the handle add_child
+    // returned), replacing any node with the same name
+    pub fn set_name(&mut self, name: &str, node: NodeRef)
     {
-        let comps2 = comps.clone();
-        let comps3 = comps.clone();
-        let comps4 = comps.clone();
-        let material = comps.clone().object.get_material();
+        self.names.insert(String::from(name), node);
+    }
 
-        let shadowed = self.is_shadowed(comps.over_point);
-        let surface = material.lighting(comps.object,
-            self.light, comps.point,
-            comps.eyev, comps.normalv, shadowed);
-        let reflected = self.reflected_color(comps2, remaining);
-        let refracted = self.refracted_color(comps3, remaining);
+    pub fn find(&self, name: &str) -> Option<NodeRef>
+    {
+        self.names.get(name).copied()
+    }
 
-        if material.reflective > 0.0 && material.transparency > 0.0
+    pub fn get_node(&self, name: &str) -> Option<&Shape>
+    {
+        let node = self.find(name)?;
+        let object = self.objects.get(node.object)?;
+        match node.id
         {
-            let reflectance = comps4.schlick();
-            return surface.add(reflected.multiply(reflectance))
-                .add(refracted.multiply(1.0 - reflectance));
+            Some(id) => Some(object.get_descendant(id)),
+            None => Some(object),
+        }
+    }
+
+    // A named node to edit, which marks its object as changed as
+    // get_object_mut does
+    pub fn get_node_mut(&mut self, name: &str) -> Option<&mut Shape>
+    {
+        let node = self.find(name)?;
+        if node.object >= self.objects.len()
+        {
+            return None;
+        }
+        let object = self.get_object_mut(node.object);
+        match node.id
+        {
+            Some(id) => Some(object.get_arena_mut()?.get_mut(id)),
+            None => Some(object),
+        }
+    }
+
+    // The transform from a named node's space to world space, through the
+    // root group and every group containing the node
+    pub fn get_node_world_transform(&self, name: &str) -> Option<Matrix>
+    {
+        let node = self.find(name)?;
+        let object = self.objects.get(node.object)?;
+        let mut transform = self.transform.clone();
+        if let Some(id) = node.id
+        {
+            for parent in object.get_descendant_parents(id)
+            {
+                transform = transform.multiply(&parent.get_transform());
+            }
+            transform = transform.multiply(&object.get_descendant(id).get_transform());
         }
         else
         {
-            return surface.add(reflected).add(refracted);
+            transform = transform.multiply(&object.get_transform());
+        }
+        Some(transform)
+    }
+
+    // The indices of the objects changed since this was last called, in
+    // order and without repeats
+    pub fn take_changed(&mut self) -> Vec<usize>
+    {
+        let mut changed = std::mem::take(&mut self.changed);
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+
+    pub fn get_acceleration(&self) -> Acceleration
+    {
+        match &self.accelerator
+        {
+            Some(a) => a.get_acceleration(),
+            None => Acceleration::None,
+        }
+    }
+
+    // Chooses how rays are intersected with the objects. Any structure is
+    // built from the objects as they are now, so this has to be called
+    // again after the objects are changed.
+    pub fn set_acceleration(&mut self, acceleration: Acceleration)
+    {
+        self.accelerator = Accelerator::new(acceleration, self.get_render_objects());
+    }
+
+    // Gets the objects ready for rendering, by flattening every group into
+    // the shapes inside it with their world transforms, so that rays no
+    // longer pass down through groups. The acceleration structure is
+    // rebuilt over the flattened shapes. Like set_acceleration, this has
+    // to be called again after the objects are changed.
+    pub fn compile(&mut self)
+    {
+        let mut list = Vec::new();
+        self.compiled_ranges.clear();
+        for object in &self.objects
+        {
+            let start = list.len();
+            list.extend(object.flatten_in(&self.transform));
+            self.compiled_ranges.push(start..list.len());
+        }
+        self.compiled = Some(list);
+        self.set_acceleration(self.get_acceleration());
+    }
+
+    // Brings the flattened objects and the acceleration structure up to
+    // date with the objects changed through get_object_mut, such as the
+    // moving objects of an animation, redoing only the work for those
+    // objects. Returns the indices of the changed objects.
+    pub fn update(&mut self) -> Vec<usize>
+    {
+        let changed = self.take_changed();
+        if self.compiled.is_some() && self.compiled_ranges.len() != self.objects.len()
+        {
+            // objects were added or removed
+            self.compile();
+            return changed;
+        }
+        if changed.is_empty()
+        {
+            return changed;
+        }
+
+        let mut render_changed = changed.clone();
+        if let Some(list) = &mut self.compiled
+        {
+            render_changed.clear();
+            for &i in &changed
+            {
+                let range = self.compiled_ranges[i].clone();
+                let flattened = self.objects[i].flatten_in(&self.transform);
+                if flattened.len() != range.len()
+                {
+                    self.compile();
+                    return changed;
+                }
+                for (slot, shape) in list[range.clone()].iter_mut().zip(flattened)
+                {
+                    *slot = shape;
+                }
+                render_changed.extend(range);
+            }
+        }
+        if let Some(mut accelerator) = self.accelerator.take()
+        {
+            accelerator.update(self.get_render_objects(), &render_changed);
+            self.accelerator = Some(accelerator);
         }
+        changed
+    }
+
+    pub fn is_compiled(&self) -> bool
+    {
+        self.compiled.is_some()
+    }
+
+    // The shapes rays are tested against
+    pub fn get_render_objects(&self) -> &[Shape]
+    {
+        match &self.compiled
+        {
+            Some(list) => list,
+            None => &self.objects,
+        }
+    }
+
+    // Writes the objects as an OBJ file, so the scene can be inspected in
+    // other tools. Planes and unbounded cylinders and cones are left out.
+    pub fn export_obj<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        let file = File::create(path)?;
+        ObjWriter::new(BufWriter::new(file)).write_shapes(&self.objects)
+    }
+
+    // The hits along a ray up to its max_t, or the world's maximum
+    // distance if that is closer
+    pub fn intersect_world(&self, ray: Ray) -> Intersections<'_>
+    {
+        self.intersect_world_ignoring(ray, &[])
+    }
+
+    // Like intersect_world, leaving out the shapes with the given ids and
+    // the shapes inside groups or CSG shapes with them. Groups are gone
+    // from compiled worlds, so only the shapes' own ids count there.
+    pub fn intersect_world_ignoring(&self, ray: Ray, ignore: &[i32]) -> Intersections<'_>
+    {
+        count_ray();
+        let ray = ray.with_max_t(ray.max_t.min(self.max_distance));
+        let objects = self.get_render_objects();
+        let mut intersections = match &self.accelerator
+        {
+            Some(a) => a.intersect(objects, ray),
+            None => objects.iter().flat_map(|o| o.intersect(ray)).collect(),
+        };
+        intersections.retain(|i| i.t <= ray.max_t && i.is_visible_to(ray.kind) &&
+            self.layers.allows(i.get_layer()) && !i.involves(ignore));
+        Intersections::new(intersections)
+    }
+
+    // The surface color plus the reflected and refracted colors. For
+    // materials that are both reflective and transparent the two are
+    // weighted by Computations::schlick, as on p.164 of the book.
+    pub fn shade_hit(&self, comps: Computations, remaining: i32) -> Tuple
+    {
+        let mut pending = Vec::new();
+        self.push_secondary_rays(&comps, create_color(1.0, 1.0, 1.0), remaining, false, &mut pending);
+        self.surface_color(&comps).add(self.trace(pending, None))
     }
 
     pub fn color_at(&self, ray: Ray, remaining: i32) -> Tuple
+    {
+        self.trace(vec![PendingRay{ray, throughput: create_color(1.0, 1.0, 1.0), remaining, indirect: false,
+            scattered: false}], None)
+    }
+
+    // Like color_at, also giving what the ray first hit
+    pub fn color_at_with_aovs(&self, ray: Ray, remaining: i32) -> (Tuple, Aovs)
+    {
+        let mut aovs = Aovs::new();
+        let color = self.trace(vec![PendingRay{ray, throughput: create_color(1.0, 1.0, 1.0), remaining,
+            indirect: false, scattered: false}], Some(&mut aovs));
+        (color, aovs)
+    }
+
+    // What a ray first hits, without shading it
+    pub fn aovs_at(&self, ray: Ray) -> Aovs
     {
         let intersections = self.intersect_world(ray);
         match intersections.hit()
         {
-            Some(intersection) =>
+            Some(hit) => Aovs::from_hit(&hit.prepare_computations_with(ray, intersections.clone(), &self.tolerance), ray),
+            None => Aovs::new(),
+        }
+    }
+
+    // The color seen along a camera ray, as worked out by the world's
+    // integrator following the world's number of bounces, within the
+    // world's sample limits
+    pub fn color_for_ray(&self, ray: Ray) -> Tuple
+    {
+        self.color_for_ray_with(self.integrator.as_ref(), ray)
+    }
+
+    // Like color_for_ray, worked out by another integrator, such as the
+    // one a camera is set to use
+    pub fn color_for_ray_with(&self, integrator: &dyn Integrator, ray: Ray) -> Tuple
+    {
+        limit_color(integrator.li(ray, self, self.max_depth), self.sample_limits.max_value)
+    }
+
+    // Like color_for_ray_with, also giving what the ray first hit
+    pub fn color_for_ray_with_aovs(&self, integrator: &dyn Integrator, ray: Ray) -> (Tuple, Aovs)
+    {
+        let (color, aovs) = integrator.li_with_aovs(ray, self, self.max_depth);
+        (limit_color(color, self.sample_limits.max_value), aovs)
+    }
+
+    // Follows rays, and the rays they reflect and refract into, until none
+    // are left, adding up the light each one brings back. The rays are kept
+    // on a list instead of the call stack, so deep reflections can't
+    // overflow it. The outputs of the first ray's hit are kept in aovs.
+    fn trace(&self, pending: Vec<PendingRay>, aovs: Option<&mut Aovs>) -> Tuple
+    {
+        let mut pending = pending;
+        let mut aovs = aovs;
+        let mut color = create_color(0.0, 0.0, 0.0);
+        while let Some(p) = pending.pop()
+        {
+            let intersections = self.intersect_world(p.ray);
+            let mut light = create_color(0.0, 0.0, 0.0);
+            if let Some(intersection) = intersections.hit()
             {
-                let comps = intersection.prepare_computations(ray, intersections);
-                self.shade_hit(comps, remaining)
-            },
-            None => create_color(0.0, 0.0, 0.0),
+                let comps = intersection.prepare_computations_with(p.ray, intersections, &self.tolerance);
+                if let Some(aovs) = aovs.take()
+                {
+                    *aovs = Aovs::from_hit(&comps, p.ray);
+                }
+                let mut throughput = p.throughput;
+                if let Some(fog) = &self.fog
+                {
+                    // the fog hides its share of everything seen at the
+                    // hit, reflections and refractions included
+                    let amount = fog.amount(comps.t * p.ray.direction.magnitude());
+                    light = fog.get_color().hadamard_product(throughput).multiply(amount);
+                    throughput = throughput.multiply(1.0 - amount);
+                }
+                light = light.add(self.surface_color(&comps).hadamard_product(throughput));
+                self.push_secondary_rays(&comps, throughput, p.remaining, p.scattered, &mut pending);
+            }
+            else
+            {
+                aovs = None;
+                light = self.background.color_at(p.ray.direction).hadamard_product(p.throughput);
+            }
+            if p.indirect
+            {
+                light = limit_color(light, self.sample_limits.max_indirect);
+            }
+            color = color.add(light);
         }
+        color
     }
 
-    pub fn is_shadowed(&self, point: Tuple) -> bool
+    // The light reflected straight from the light source at a hit
+    fn surface_color(&self, comps: &Computations) -> Tuple
     {
-        let v = self.light.position.sub(point);
-        let distance = v.magnitude();
-        let direction = v.normalize();
-        let r = Ray::new(point, direction);
-        let intersections = self.intersect_world(r);
-        let h = intersections.hit();
-        match h
+        let shadowed = self.occlusion_at(&self.light, comps.over_point, comps.time) >= 1.0;
+        comps.object.lighting(self.ambient, self.light, comps.point, comps.eyev, comps.normalv, shadowed)
+    }
+
+    // Adds the reflected and refracted rays leaving a hit to the list,
+    // with the share of the light they bring back. Surfaces that both
+    // reflect and refract divide it using the Schlick approximation. Rough
+    // refraction is split between the material's number of samples, but
+    // only for rays not already scattered, so that the number of rays
+    // grows with the depth instead of multiplying at every hit.
+    fn push_secondary_rays(&self, comps: &Computations, throughput: Tuple, remaining: i32, scattered: bool,
+        pending: &mut Vec<PendingRay>)
+    {
+        let material = comps.object.get_material();
+        let reflectance = match material.reflective > 0.0 && material.transparency > 0.0
+        {
+            true => Some(comps.schlick()),
+            false => None,
+        };
+        if let Some(ray) = self.reflect_ray(comps, remaining)
+        {
+            let share = material.reflective * reflectance.unwrap_or(1.0);
+            pending.push(PendingRay{ray, throughput: throughput.multiply(share), remaining: remaining - 1,
+                indirect: true, scattered});
+        }
+        if let Some(ray) = self.refract_ray(comps, remaining)
         {
-            Some(intersection) => intersection.t < distance,
-            None => false
+            let share = material.transparency * reflectance.map_or(1.0, |r| 1.0 - r);
+            if material.refraction_roughness > 0.0
+            {
+                let samples = if scattered { 1 } else { material.refraction_samples.max(1) };
+                let mut sequence = LowDiscrepancy::new_with_seed(SequenceKind::Halton, point_seed(comps.point));
+                for _ in 0..samples
+                {
+                    pending.push(PendingRay{ray: self.scatter_refraction(comps, ray, sequence.next_2d()),
+                        throughput: throughput.multiply(share / samples as Scalar), remaining: remaining - 1,
+                        indirect: true, scattered: true});
+                }
+            }
+            else
+            {
+                pending.push(PendingRay{ray, throughput: throughput.multiply(share), remaining: remaining - 1,
+                    indirect: true, scattered});
+            }
         }
     }
 
-    pub fn reflected_color(&self, comps: Computations, remaining: i32) -> Tuple
+    // A refracted ray turned to a direction in a cone around it, picked by
+    // two numbers from 0 to 1, as frosted glass scatters it. The cone is
+    // wider the rougher the material, and the ray is kept on the far side
+    // of the surface.
+    pub fn scatter_refraction(&self, comps: &Computations, ray: Ray, sample: (Scalar, Scalar)) -> Ray
     {
-        if remaining <= 0
+        let roughness = comps.object.get_material().refraction_roughness;
+        let direction = ray.direction.normalize();
+        let helper = match direction.to_array()[0].abs() > 0.9
         {
-            return create_color(0.0, 0.0, 0.0);
+            true => create_vector(0.0, 1.0, 0.0),
+            false => create_vector(1.0, 0.0, 0.0),
+        };
+        let tangent = helper.cross_product(direction).normalize();
+        let bitangent = direction.cross_product(tangent);
+        // a point in a disk across the ray, evenly spread over its area
+        let r = roughness * sample.0.sqrt();
+        let phi = 2.0 * PI * sample.1;
+        let scattered = direction.add(tangent.multiply(r * phi.cos())).add(bitangent.multiply(r * phi.sin()));
+        match scattered.dot_product(comps.normalv) < 0.0
+        {
+            true => Ray::new_with_kind(ray.origin, scattered.normalize(), RayKind::Refraction).with_time(ray.time),
+            false => ray,
         }
-        if fuzzy_equal(comps.object.get_material().reflective, 0.0)
+    }
+
+    // How much of a light is hidden from a point, from 0 for none of it
+    // to 1 for all of it. A point light is either seen or not.
+    pub fn occlusion(&self, light: &PointLight, point: Tuple) -> Scalar
+    {
+        self.occlusion_at(light, point, 0.0)
+    }
+
+    // Like occlusion, with moving shapes where they are at the time
+    pub fn occlusion_at(&self, light: &PointLight, point: Tuple, time: Scalar) -> Scalar
+    {
+        let ray = match light.direction
+        {
+            Some(d) => Ray::new_with_kind(point, d.negate().normalize(), RayKind::Shadow),
+            None => self.shadow_ray(light.position, point),
+        };
+        match self.any_hit(ray.with_time(time), &[])
         {
-            return create_color(0.0, 0.0, 0.0);
+            true => 1.0,
+            false => 0.0,
         }
+    }
+
+    // Whether something is between a point and a point on a light
+    pub fn is_shadowed(&self, light_position: Tuple, point: Tuple) -> bool
+    {
+        self.is_shadowed_ignoring(light_position, point, &[])
+    }
+
+    // Like is_shadowed, where the shapes with the given ids cast no
+    // shadow, such as the surface the point is on
+    pub fn is_shadowed_ignoring(&self, light_position: Tuple, point: Tuple, ignore: &[i32]) -> bool
+    {
+        self.any_hit(self.shadow_ray(light_position, point), ignore)
+    }
 
-        let reflect_ray = Ray::new(comps.over_point, comps.reflectv);
-        let color = self.color_at(reflect_ray, remaining - 1);
+    // The ray from a point to a point on a light, which stops there
+    fn shadow_ray(&self, light_position: Tuple, point: Tuple) -> Ray
+    {
+        let v = light_position.sub(point);
+        let distance = v.magnitude();
+        let direction = v.normalize();
+        Ray::new_with_kind(point, direction, RayKind::Shadow).with_max_t(distance)
+    }
+
+    // Whether anything is in the way from a point towards a light that
+    // is infinitely far off in a direction, so the shadow ray never stops
+    pub fn is_shadowed_in_direction(&self, direction: Tuple, point: Tuple) -> bool
+    {
+        let r = Ray::new_with_kind(point, direction.normalize(), RayKind::Shadow);
+        self.any_hit(r, &[])
+    }
+
+    // Whether the ray hits anything before its max_t, apart from the
+    // ignored shapes and those that cast no shadow. The search stops at
+    // the first shape found, so nothing needs to be collected or sorted.
+    fn any_hit(&self, ray: Ray, ignore: &[i32]) -> bool
+    {
+        count_ray();
+        let ray = ray.with_max_t(ray.max_t.min(self.max_distance));
+        let blocks = |i: &Intersection| i.t >= 0.0 && i.t < ray.max_t &&
+            i.is_visible_to(RayKind::Shadow) && self.layers.allows(i.get_layer()) && !i.involves(ignore);
+        let objects = self.get_render_objects();
+        match &self.accelerator
+        {
+            Some(a) => a.any_hit(objects, ray, blocks),
+            None => objects.iter().any(|o| o.intersect(ray).iter().any(blocks)),
+        }
+    }
 
-        color.multiply(comps.object.get_material().reflective)
+    pub fn reflected_color(&self, comps: Computations, remaining: i32) -> Tuple
+    {
+        match self.reflect_ray(&comps, remaining)
+        {
+            Some(ray) => self.color_at(ray, remaining - 1).multiply(comps.object.get_material().reflective),
+            None => create_color(0.0, 0.0, 0.0),
+        }
     }
 
     pub fn refracted_color(&self, comps: Computations, remaining: i32) -> Tuple
     {
-        if remaining <= 0
+        // multiply by the transparency value to account for any opacity
+        match self.refract_ray(&comps, remaining)
         {
-            return create_color(0.0, 0.0, 0.0);
+            Some(ray) => self.color_at(ray, remaining - 1).multiply(comps.object.get_material().transparency),
+            None => create_color(0.0, 0.0, 0.0),
         }
-        if fuzzy_equal(comps.object.get_material().transparency, 0.0)
+    }
+
+    // The ray reflected at a hit, or None if the surface isn't reflective
+    // or no more reflections are allowed
+    pub fn reflect_ray(&self, comps: &Computations, remaining: i32) -> Option<Ray>
+    {
+        if remaining <= 0 || fuzzy_equal(comps.object.get_material().reflective, 0.0)
         {
-            return create_color(0.0, 0.0, 0.0);
+            return None;
+        }
+        Some(Ray::new_with_kind(comps.over_point, comps.reflectv, RayKind::Reflection).with_time(comps.time))
+    }
+
+    // The ray refracted at a hit, or None if the surface isn't transparent,
+    // no more refractions are allowed, or there is total internal reflection
+    pub fn refract_ray(&self, comps: &Computations, remaining: i32) -> Option<Ray>
+    {
+        if remaining <= 0 || fuzzy_equal(comps.object.get_material().transparency, 0.0)
+        {
+            return None;
         }
 
         // Find the ratio of first index of refraction to the second.
@@ -150,7 +807,7 @@ impl World
         // Check for total internal reflection
         if sin2_t > 1.0
         {
-            return create_color(0.0, 0.0, 0.0);
+            return None;
         }
 
         // Find cos(theta_t) via trigonmetric identity
@@ -158,14 +815,102 @@ impl World
 
         // Compute the direction of the refracted ray
         let direction = comps.normalv.multiply(n_ratio * cos_i - cos_t).sub(comps.eyev.multiply(n_ratio));
+        Some(Ray::new_with_kind(comps.under_point, direction, RayKind::Refraction).with_time(comps.time))
+    }
+}
+
+// The render layers to draw: only those listed in include, when there is
+// a list, and never those in exclude. Shapes given no layer are in the
+// default layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderLayers
+{
+    pub include: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+impl RenderLayers
+{
+    pub const DEFAULT: &'static str = "default";
 
-        // Create the reflected ray
-        let refract_ray = Ray::new(comps.under_point, direction);
+    // Every layer
+    pub fn new() -> Self
+    {
+        RenderLayers{include: None, exclude: Vec::new()}
+    }
 
-        // Find the color of the refracted ray, making sure to multipy
-        // by the transparency value to account for any opacity
-        let color = self.color_at(refract_ray, remaining - 1).multiply(comps.object.get_material().transparency);
-        return color;
+    pub fn allows(&self, layer: Option<&str>) -> bool
+    {
+        let layer = layer.unwrap_or(Self::DEFAULT);
+        let included = match &self.include
+        {
+            Some(list) => list.iter().any(|l| l == layer),
+            None => true,
+        };
+        included && !self.exclude.iter().any(|l| l == layer)
+    }
+}
+
+// Where a named node is in a world: an object, or a shape inside one of
+// the groups
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NodeRef
+{
+    pub object: usize,
+    pub id: Option<ShapeId>,
+}
+
+// A ray waiting to be traced, with the share of its color that reaches
+// the eye and the number of reflections or refractions still allowed
+struct PendingRay
+{
+    ray: Ray,
+    throughput: Tuple,
+    remaining: i32,
+    // reflected or refracted, rather than the ray the trace began with
+    indirect: bool,
+    // refracted by a rough surface on the way, so not to be split again
+    scattered: bool,
+}
+
+// A seed from a point, so that the rays scattered from a hit are the same
+// however the image is split between threads
+fn point_seed(point: Tuple) -> u32
+{
+    point.to_array().iter().fold(0x811c9dc5, |seed: u32, v| (seed ^ v.to_bits() as u32).wrapping_mul(0x01000193))
+}
+
+// Limits on the light a sample can bring back, so that a rare very
+// bright path shows as a slightly dim pixel instead of a firefly. Colors
+// over a limit are scaled down, keeping their hue.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampleLimits
+{
+    // the brightest channel of a camera ray's color
+    pub max_value: Scalar,
+    // the brightest channel of the light from each reflected or
+    // refracted ray
+    pub max_indirect: Scalar,
+}
+
+impl SampleLimits
+{
+    // No limits
+    pub fn new() -> Self
+    {
+        SampleLimits{max_value: Scalar::INFINITY, max_indirect: Scalar::INFINITY}
+    }
+}
+
+// A color scaled down, if need be, so that no channel is over the limit
+pub fn limit_color(color: Tuple, limit: Scalar) -> Tuple
+{
+    let v = color.get_vec();
+    let brightest = v[0].max(v[1]).max(v[2]);
+    match brightest > limit
+    {
+        true => color.multiply(limit / brightest),
+        false => color,
     }
 }
 
@@ -197,7 +942,7 @@ mod tests
         // p.93 Scenario: Precomputing the state of an intersection
         let ray3 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let shape3 = Shape::new_sphere(3);
-        let intersection3 = Intersection::new(4.0, shape3.clone());
+        let intersection3 = Intersection::new(4.0, &shape3);
         let comps3 = intersection3.prepare_computations(ray3, Intersections::new(vec![intersection3.clone()]));
         assert!(fuzzy_equal(comps3.t, intersection3.t));
         assert_eq!(comps3.object, intersection3.object);
@@ -208,14 +953,14 @@ mod tests
         // p.94 Scenario: The hit, when an intersection occurs on the outside
         let ray4 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let shape4 = Shape::new_sphere(4);
-        let intersection4 = Intersection::new(4.0, shape4.clone());
+        let intersection4 = Intersection::new(4.0, &shape4);
         let comps4 = intersection4.prepare_computations(ray4, Intersections::new(vec![intersection4.clone()]));
         assert!(comps4.inside == false);
 
         // p.95 Scenario: The hit, when an intersection occurs on the inside
         let ray5 = Ray::new(create_point(0.0, 0.0, 0.0), create_vector(0.0, 0.0, 1.0));
         let shape5 = Shape::new_sphere(5);
-        let intersection5 = Intersection::new(1.0, shape5.clone());
+        let intersection5 = Intersection::new(1.0, &shape5);
         let comps5 = intersection5.prepare_computations(ray5, Intersections::new(vec![intersection5.clone()]));
         assert_eq!(comps5.point, create_point(0.0, 0.0, 1.0));
         assert_eq!(comps5.eyev, create_vector(0.0, 0.0, -1.0));
@@ -227,7 +972,7 @@ mod tests
         let world6 = World::default_world();
         let ray6 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
         let shape6 = world6.objects[0].clone();
-        let intersection6 = Intersection::new(4.0, shape6.clone());
+        let intersection6 = Intersection::new(4.0, &shape6);
         let comps6 = intersection6.prepare_computations(ray6, Intersections::new(vec![intersection6.clone()]));
         let color6 = world6.shade_hit(comps6, World::REFLECTION_RECURSION);
         assert_eq!(color6, create_color(0.38066, 0.47583, 0.2855));
@@ -237,7 +982,7 @@ mod tests
         world7.light = PointLight::new(create_point(0.0, 0.25, 0.0), create_color(1.0, 1.0, 1.0));
         let ray7 = Ray::new(create_point(0.0, 0.0, 0.0), create_vector(0.0, 0.0, 1.0));
         let shape7 = world7.objects[1].clone();
-        let intersection7 = Intersection::new(0.5, shape7.clone());
+        let intersection7 = Intersection::new(0.5, &shape7);
         let comps7 = intersection7.prepare_computations(ray7, Intersections::new(vec![intersection7.clone()]));
         let color7 = world7.shade_hit(comps7, World::REFLECTION_RECURSION);
         assert_eq!(color7, create_color(0.90498, 0.90498, 0.90498));
@@ -257,12 +1002,12 @@ mod tests
         // p.97 Scenario: The color with an intersection behind the ray
         let mut world10 = World::default_world();
         let mut outer10 = world10.objects[0].clone();
-        let mut outer_material10 = outer10.get_material();
+        let mut outer_material10 = outer10.get_material().clone();
         outer_material10.ambient = 1.0;
         outer10.set_material(outer_material10);
         world10.objects[0] = outer10;
         let mut inner10 = world10.objects[1].clone();
-        let mut inner_material10 = inner10.get_material();
+        let mut inner_material10 = inner10.get_material().clone();
         inner_material10.ambient = 1.0;
         inner10.set_material(inner_material10.clone());
         world10.objects[1] = inner10;
@@ -277,22 +1022,27 @@ mod tests
         // p.111 Scenario: There is no shadow when nothing collinear with point and light
         let world1 = World::default_world();
         let point1 = create_point(0.0, 10.0, 0.0);
-        assert!(world1.is_shadowed(point1) == false);
+        assert!(world1.is_shadowed(world1.light.position, point1) == false);
 
         // p.112 Scenario: The shadow when an object is between the point and light
         let world2 = World::default_world();
         let point2 = create_point(10.0, -10.0, 10.0);
-        assert!(world2.is_shadowed(point2));
+        assert!(world2.is_shadowed(world2.light.position, point2));
 
         // p.112 Scenario: There is no shadow when object is behind the light
         let world3 = World::default_world();
         let point3 = create_point(-20.0, 20.0, -20.0);
-        assert!(world3.is_shadowed(point3) == false);
+        assert!(world3.is_shadowed(world3.light.position, point3) == false);
 
         // p.112 Scenario: There is no shadow when object is behind the point
         let world4 = World::default_world();
         let point4 = create_point(-2.0, 2.0, -2.0);
-        assert!(world4.is_shadowed(point4) == false);
+        assert!(world4.is_shadowed(world4.light.position, point4) == false);
+
+        // Scenario: Occlusion is measured for each light
+        let light5 = PointLight::new(create_point(10.0, 10.0, 10.0), create_color(1.0, 1.0, 1.0));
+        assert_eq!(world2.occlusion(&world2.light, point2), 1.0);
+        assert_eq!(world2.occlusion(&light5, point2), 0.0);
 
         // p.114 Scenario: shade_hit() is given an intersection in shadow
         let mut world5 = World::default_world();
@@ -302,10 +1052,428 @@ mod tests
         sphere2.set_transform(Matrix::translation(10.0, 0.0, 0.0));
         world5.objects = vec![sphere1.clone(), sphere2.clone()];
         let ray5 = Ray::new(create_point(0.0, 0.0, 5.0), create_vector(0.0, 0.0, 1.0));
-        let intersection5 = Intersection::new(4.0, sphere2);
+        let intersection5 = Intersection::new(4.0, &sphere2);
         let comps5 = intersection5.prepare_computations(ray5, Intersections::new(vec![intersection5.clone()]));
-        let color5 = world5.shade_hit(comps5, World::REFLECTION_RECURSION);
+        let color5 = world5.shade_hit(comps5.clone(), World::REFLECTION_RECURSION);
         assert_eq!(color5, create_color(0.1, 0.1, 0.1));
+
+        // Scenario: The world's ambient light scales the ambient term
+        world5.ambient = create_color(0.5, 0.0, 1.0);
+        assert_eq!(world5.shade_hit(comps5.clone(), World::REFLECTION_RECURSION),
+            create_color(0.05, 0.0, 0.1));
+        world5.ambient = create_color(0.0, 0.0, 0.0);
+        assert_eq!(world5.shade_hit(comps5, World::REFLECTION_RECURSION),
+            create_color(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_world_visibility_feature()
+    {
+        // Scenario: A shape hidden from the camera is not seen by primary rays
+        let mut world1 = World::default_world();
+        let mut visibility1 = Visibility::new();
+        visibility1.camera = false;
+        world1.objects[0].set_visibility(visibility1);
+        world1.objects[1].set_visibility(visibility1);
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world1.color_at(ray1, World::REFLECTION_RECURSION), create_color(0.0, 0.0, 0.0));
+
+        // Scenario: A shape hidden from the camera still casts shadows
+        assert!(world1.is_shadowed(world1.light.position, create_point(10.0, -10.0, 10.0)));
+
+        // Scenario: A shape hidden from reflections is not seen in a mirror
+        let mut world2 = World::default_world();
+        let mut mirror2 = Shape::new_plane(3);
+        mirror2.set_transform(Matrix::translation(0.0, -1.0, 0.0));
+        let mut material2 = mirror2.get_material().clone();
+        material2.reflective = 0.5;
+        mirror2.set_material(material2);
+        world2.objects.push(mirror2.clone());
+        let sqrt2 = Scalar::sqrt(2.0);
+        let ray2 = Ray::new(create_point(0.0, 0.0, -3.0),
+            create_vector(0.0, -sqrt2 / 2.0, sqrt2 / 2.0));
+        let i2 = Intersection::new(sqrt2, &mirror2);
+        let comps2 = i2.prepare_computations(ray2, Intersections::new(vec![i2.clone()]));
+        let visible2 = world2.reflected_color(comps2.clone(), World::REFLECTION_RECURSION);
+        assert!(visible2.approx_equal(create_color(0.19032, 0.2379, 0.14274)));
+        let mut visibility2 = Visibility::new();
+        visibility2.reflection = false;
+        world2.objects[0].set_visibility(visibility2);
+        world2.objects[1].set_visibility(visibility2);
+        let hidden2 = world2.reflected_color(comps2, World::REFLECTION_RECURSION);
+        assert_eq!(hidden2, create_color(0.0, 0.0, 0.0));
+
+        // Scenario: A group's visibility is kept when the world is compiled
+        let mut group5 = Shape::new_group(6);
+        let mut visibility5 = Visibility::new();
+        visibility5.camera = false;
+        group5.set_visibility(visibility5);
+        group5.add_child(Shape::new_sphere(7));
+        let light5 = PointLight::new(create_point(-10.0, 10.0, -10.0), create_color(1.0, 1.0, 1.0));
+        let mut world5 = World::new(light5, vec![group5]);
+        world5.compile();
+        let ray5 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world5.intersect_world(ray5).count(), 0);
+        assert!(world5.get_render_objects().iter().all(|o| !o.is_visible_to(RayKind::Camera)));
+
+        // Scenario: A shape that casts no shadow lets the light through
+        let mut world3 = World::default_world();
+        let point3 = create_point(10.0, -10.0, 10.0);
+        assert!(world3.is_shadowed(world3.light.position, point3));
+        let mut visibility3 = Visibility::new();
+        visibility3.casts_shadow = false;
+        world3.objects[0].set_visibility(visibility3);
+        world3.objects[1].set_visibility(visibility3);
+        assert!(!world3.is_shadowed(world3.light.position, point3));
+        assert_eq!(world3.occlusion_at(&world3.light, point3, 0.0), 0.0);
+        // but it is still seen by the camera
+        let ray3 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world3.intersect_world(ray3).count(), 4);
+
+        // Scenario: Shapes in a group that casts no shadow cast none either
+        let mut group4 = Shape::new_group(4);
+        group4.set_visibility(visibility3);
+        group4.add_child(Shape::new_sphere(5));
+        let light4 = PointLight::new(create_point(0.0, 5.0, 0.0), create_color(1.0, 1.0, 1.0));
+        let mut world4 = World::new(light4, vec![group4]);
+        world4.set_acceleration(Acceleration::Bvh);
+        assert!(!world4.is_shadowed(create_point(0.0, 5.0, 0.0), create_point(0.0, -5.0, 0.0)));
+        // as they are once the world is compiled, as it is for rendering
+        world4.compile();
+        assert!(!world4.is_shadowed(create_point(0.0, 5.0, 0.0), create_point(0.0, -5.0, 0.0)));
+    }
+
+    #[test]
+    fn test_world_acceleration_feature()
+    {
+        // Scenario: Colors are the same with any acceleration structure
+        let mut world1 = World::default_world();
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world1.get_acceleration(), Acceleration::None);
+        for acceleration in [Acceleration::KdTree, Acceleration::Grid, Acceleration::Bvh]
+        {
+            world1.set_acceleration(acceleration);
+            assert_eq!(world1.get_acceleration(), acceleration);
+            assert_eq!(world1.intersect_world(ray1).count(), 4);
+            assert_eq!(world1.color_at(ray1, World::REFLECTION_RECURSION),
+                create_color(0.38066, 0.47583, 0.2855));
+        }
+
+        // Scenario: Shadows are the same with any acceleration structure
+        let mut world2 = World::default_world();
+        let mut group = Shape::new_group(3);
+        let mut inner = Shape::new_sphere(4);
+        inner.set_transform(Matrix::translation(0.0, 5.0, 0.0));
+        group.add_child(inner);
+        group.set_layer("props");
+        world2.objects.push(group);
+        world2.compile();
+        let light = create_point(-10.0, 10.0, -10.0);
+        let point1 = create_point(10.0, -10.0, 10.0);
+        let point2 = create_point(0.0, 5.0, 10.0);
+        let point3 = create_point(0.0, 10.0, 0.0);
+        for acceleration in [Acceleration::None, Acceleration::KdTree, Acceleration::Grid, Acceleration::Bvh]
+        {
+            world2.set_acceleration(acceleration);
+            world2.set_layers(RenderLayers::new());
+            assert!(world2.is_shadowed(light, point1));
+            assert!(!world2.is_shadowed_ignoring(light, point1, &[1, 2]));
+            assert!(world2.is_shadowed(create_point(0.0, 5.0, -10.0), point2));
+            assert!(!world2.is_shadowed_ignoring(create_point(0.0, 5.0, -10.0), point2, &[4]));
+            assert!(!world2.is_shadowed(light, point3));
+            world2.set_layers(RenderLayers{include: None, exclude: vec![String::from("props")]});
+            assert!(!world2.is_shadowed(create_point(0.0, 5.0, -10.0), point2));
+        }
+    }
+
+    #[test]
+    fn test_world_max_distance_feature()
+    {
+        // Scenario: Hits beyond a ray's max_t or the world's distance are ignored
+        let mut world1 = World::default_world();
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        for acceleration in [Acceleration::None, Acceleration::KdTree, Acceleration::Grid, Acceleration::Bvh]
+        {
+            world1.set_acceleration(acceleration);
+            world1.set_max_distance(Scalar::INFINITY);
+            assert_eq!(world1.intersect_world(ray1.with_max_t(5.0)).count(), 2);
+            world1.set_max_distance(4.2);
+            assert_eq!(world1.intersect_world(ray1).count(), 1);
+            assert_eq!(world1.intersect_world(ray1.with_max_t(10.0)).count(), 1);
+            world1.set_max_distance(3.0);
+            assert_eq!(world1.color_at(ray1, World::REFLECTION_RECURSION), create_color(0.0, 0.0, 0.0));
+        }
+
+        // Scenario: Shadow rays stop at the light
+        let world2 = World::default_world();
+        assert!(!world2.is_shadowed(create_point(0.0, 0.0, -2.0), create_point(0.0, 0.0, -5.0)));
+        assert!(world2.is_shadowed(create_point(0.0, 0.0, 5.0), create_point(0.0, 0.0, -5.0)));
+
+        // Scenario: Shadow rays towards a directional light never stop
+        let forwards = create_vector(0.0, 0.0, 1.0);
+        assert!(world2.is_shadowed_in_direction(forwards, create_point(0.0, 0.0, -500.0)));
+        assert!(!world2.is_shadowed_in_direction(forwards.negate(), create_point(0.0, 0.0, -5.0)));
+
+        // Scenario: A directional light casts parallel shadows however far off
+        let mut world3 = World::default_world();
+        world3.light = PointLight::new_directional(create_vector(0.0, -1.0, 0.0), create_color(1.0, 1.0, 1.0));
+        assert_eq!(world3.occlusion(&world3.light, create_point(0.5, -1000.0, 0.0)), 1.0);
+        assert_eq!(world3.occlusion(&world3.light, create_point(1.5, -1000.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn test_world_ignoring_feature()
+    {
+        // Scenario: A ray can ignore shapes by id
+        let mut world1 = World::default_world();
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let xs1 = world1.intersect_world_ignoring(ray1, &[2]);
+        assert_eq!(xs1.count(), 2);
+        assert!(fuzzy_equal(xs1.get_intersection(0).t, 4.0));
+        let xs2 = world1.intersect_world_ignoring(ray1, &[1]);
+        assert!(fuzzy_equal(xs2.get_intersection(0).t, 4.5));
+        assert_eq!(world1.intersect_world_ignoring(ray1, &[1, 2]).count(), 0);
+
+        // Scenario: Ignoring a group ignores the shapes inside it
+        let mut group = Shape::new_group(3);
+        let mut inner = Shape::new_sphere(4);
+        inner.set_transform(Matrix::translation(0.0, 5.0, 0.0));
+        group.add_child(inner);
+        world1.objects.push(group);
+        let ray2 = Ray::new(create_point(0.0, 5.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world1.intersect_world_ignoring(ray2, &[3]).count(), 0);
+        assert_eq!(world1.intersect_world_ignoring(ray2, &[4]).count(), 0);
+        assert_eq!(world1.intersect_world_ignoring(ray2, &[1]).count(), 2);
+
+        // Scenario: Ignored shapes cast no shadows
+        let light = create_point(-10.0, 10.0, -10.0);
+        let point = create_point(10.0, -10.0, 10.0);
+        assert!(world1.is_shadowed(light, point));
+        assert!(world1.is_shadowed_ignoring(light, point, &[2]));
+        assert!(!world1.is_shadowed_ignoring(light, point, &[1, 2]));
+    }
+
+    #[test]
+    fn test_world_compile_feature()
+    {
+        // Scenario: Compiling a world flattens its groups
+        let mut world1 = World::default_world();
+        let mut outer = Shape::new_group(3);
+        outer.set_transform(Matrix::translation(0.0, 0.0, 2.0));
+        let mut inner = Shape::new_group(4);
+        inner.set_transform(Matrix::scaling(2.0, 2.0, 2.0));
+        let mut sphere = Shape::new_sphere(5);
+        sphere.set_transform(Matrix::translation(1.0, 0.0, 0.0));
+        inner.add_child(sphere);
+        outer.add_child(inner);
+        world1.objects.push(outer);
+        let ray1 = Ray::new(create_point(2.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let before = world1.intersect_world(ray1).get_intersection(0);
+        let (t_before, normal_before) = (before.t, before.object.normal_at_in(&before.parents,
+            ray1.position(before.t), (0.0, 0.0), 0.0));
+        world1.compile();
+        assert!(world1.is_compiled());
+        assert_eq!(world1.get_render_objects().len(), 3);
+        assert!(world1.get_render_objects()[2].get_arena().is_none());
+
+        // Scenario: A compiled world is hit in the same places
+        assert_eq!(world1.intersect_world(ray1).count(), 2);
+        let after = world1.intersect_world(ray1).get_intersection(0);
+        assert!(fuzzy_equal(after.t, t_before));
+        assert!(after.parents.is_empty());
+        let normal_after = after.object.normal_at(ray1.position(after.t), (0.0, 0.0));
+        assert!(normal_after.approx_equal(normal_before));
+
+        // Scenario: Compiling keeps the acceleration structure
+        world1.set_acceleration(Acceleration::KdTree);
+        world1.compile();
+        assert_eq!(world1.get_acceleration(), Acceleration::KdTree);
+        assert_eq!(world1.intersect_world(ray1).count(), 2);
+    }
+
+    #[test]
+    fn test_world_motion_feature()
+    {
+        // Scenario: A moving group is hit where it is at the ray's time,
+        // however the world is compiled or accelerated
+        let mut world1 = World::default_world();
+        let mut group = Shape::new_group(3);
+        group.set_transform(Matrix::translation(0.0, 0.0, 2.0));
+        group.set_motion(create_vector(4.0, 0.0, 0.0));
+        let mut sphere = Shape::new_sphere(4);
+        sphere.set_transform(Matrix::translation(-2.0, 0.0, 0.0));
+        group.add_child(sphere);
+        world1.objects.push(group);
+        let ray1 = Ray::new(create_point(2.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        for compile in [false, true]
+        {
+            if compile
+            {
+                world1.compile();
+            }
+            for acceleration in [Acceleration::None, Acceleration::KdTree, Acceleration::Grid, Acceleration::Bvh]
+            {
+                world1.set_acceleration(acceleration);
+                assert_eq!(world1.intersect_world(ray1).count(), 0);
+                assert_eq!(world1.intersect_world(ray1.with_time(0.5)).count(), 0);
+                let xs = world1.intersect_world(ray1.with_time(1.0));
+                assert_eq!(xs.count(), 2);
+                assert!(fuzzy_equal(xs.get_intersection(0).t, 6.0));
+            }
+        }
+
+        // Scenario: Shadows are cast where shapes are at the time
+        let light = PointLight::new(create_point(2.0, 0.0, 10.0), create_color(1.0, 1.0, 1.0));
+        let point = create_point(2.0, 0.0, -5.0);
+        assert_eq!(world1.occlusion_at(&light, point, 0.0), 0.0);
+        assert_eq!(world1.occlusion_at(&light, point, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_world_update_feature()
+    {
+        // Scenario: Moving an object updates a compiled world without compiling it again
+        let mut world1 = World::default_world();
+        let mut group = Shape::new_group(3);
+        group.add_child(Shape::new_sphere(4));
+        group.add_child(Shape::new_cube(5));
+        group.set_transform(Matrix::translation(0.0, 0.0, 5.0));
+        world1.objects.push(group);
+        world1.set_acceleration(Acceleration::Grid);
+        world1.compile();
+        world1.get_object_mut(2).set_transform(Matrix::translation(3.0, 0.0, 5.0));
+        assert_eq!(world1.update(), vec![2]);
+        assert_eq!(world1.get_render_objects().len(), 4);
+        let ray1 = Ray::new(create_point(3.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let xs1 = world1.intersect_world(ray1);
+        assert_eq!(xs1.count(), 4);
+        assert!(fuzzy_equal(xs1.get_intersection(0).t, 9.0));
+        let ray2 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world1.intersect_world(ray2).count(), 4);
+
+        // Scenario: Adding objects compiles the world again
+        world1.objects.push(Shape::new_sphere(6));
+        assert!(world1.update().is_empty());
+        assert_eq!(world1.get_render_objects().len(), 5);
+        assert_eq!(world1.get_acceleration(), Acceleration::Grid);
+    }
+
+    #[test]
+    fn test_world_nodes_feature()
+    {
+        // Scenario: Finding named objects and shapes inside them
+        let mut world1 = World::new(PointLight::new(create_point(0.0, 10.0, -10.0),
+            create_color(1.0, 1.0, 1.0)), Vec::new());
+        let mut model = Shape::new_group(1);
+        model.set_transform(Matrix::translation(0.0, 0.0, 5.0));
+        let mut ball = Shape::new_sphere(2);
+        ball.set_transform(Matrix::translation(2.0, 0.0, 0.0));
+        let ball_id = model.add_child(ball);
+        let index = world1.add_named("model", model);
+        world1.set_name("model/ball", NodeRef{object: index, id: Some(ball_id)});
+        assert_eq!(world1.get_node("model").unwrap().get_id(), 1);
+        assert_eq!(world1.get_node("model/ball").unwrap().get_id(), 2);
+        assert!(world1.get_node("car").is_none());
+        assert_eq!(world1.get_node_world_transform("model/ball"), Some(Matrix::translation(2.0, 0.0, 5.0)));
+
+        // Scenario: Editing a node inside a group marks its object as changed
+        world1.get_node_mut("model/ball").unwrap().set_transform(Matrix::translation(-2.0, 0.0, 0.0));
+        assert_eq!(world1.take_changed(), vec![index]);
+        assert_eq!(world1.get_node_world_transform("model/ball"), Some(Matrix::translation(-2.0, 0.0, 5.0)));
+
+        // Scenario: The root transform moves every object
+        world1.set_transform(Matrix::translation(0.0, 3.0, 0.0));
+        assert_eq!(world1.get_node_world_transform("model/ball"), Some(Matrix::translation(-2.0, 3.0, 5.0)));
+        let ray1 = Ray::new(create_point(-2.0, 3.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        let xs1 = world1.intersect_world(ray1);
+        assert_eq!(xs1.count(), 2);
+        assert!(fuzzy_equal(xs1.get_intersection(0).t, 9.0));
+        let ray2 = Ray::new(create_point(-2.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world1.intersect_world(ray2).count(), 0);
+    }
+
+    #[test]
+    fn test_world_editing_feature()
+    {
+        // Scenario: Adding shapes gives each object its own id
+        let mut world1 = World::default_world();
+        assert_eq!(world1.add_shape(Shape::new_cube(7)), 7);
+        assert_eq!(world1.add_shape(Shape::new_cube(1)), 8);
+        assert_eq!(world1.get_shape(8).unwrap().get_id(), 8);
+        assert!(world1.get_shape(9).is_none());
+
+        // Scenario: Ids are unique among the shapes inside groups too
+        let mut world2 = World::default_world();
+        let mut group2 = Shape::new_group(9);
+        group2.add_child(Shape::new_sphere(10));
+        assert_eq!(world2.add_shape(group2), 9);
+        assert_eq!(world2.add_shape(Shape::new_cube(10)), 11);
+        let mut group2b = Shape::new_group(12);
+        group2b.add_child(Shape::new_sphere(1));
+        assert_eq!(world2.add_shape(group2b), 12);
+        let mut ids2 = HashSet::new();
+        world2.objects.last().unwrap().collect_ids(&mut ids2);
+        assert_eq!(ids2, HashSet::from([12, 13]));
+
+        // Scenario: Replacing and removing shapes by id
+        world1.compile();
+        let mut floor = Shape::new_plane(0);
+        floor.set_transform(Matrix::translation(0.0, -1.0, 0.0));
+        let old = world1.replace_shape(7, floor).unwrap();
+        assert_eq!(old.get_id(), 7);
+        assert_eq!(world1.update(), vec![2]);
+        let ray1 = Ray::new(create_point(0.0, 5.0, 0.0), create_vector(0.0, -1.0, 0.0));
+        assert!(fuzzy_equal(world1.intersect_world(ray1).hit().unwrap().t, 4.0));
+        let mut ball = Shape::new_sphere(20);
+        ball.set_transform(Matrix::translation(5.0, 0.0, 0.0));
+        world1.add_named("ball", ball);
+        world1.get_shape_mut(2);
+        assert!(world1.remove_shape(1).is_some());
+        assert!(world1.remove_shape(1).is_none());
+        assert!(world1.remove_shape(8).is_some());
+        assert_eq!(world1.objects.len(), 3);
+        assert_eq!(world1.get_node("ball").unwrap().get_id(), 20);
+        assert_eq!(world1.take_changed(), vec![0]);
+        assert!(fuzzy_equal(world1.intersect_world(ray1).hit().unwrap().t, 4.5));
+
+        // Scenario: Changing the light
+        let light = PointLight::new(create_point(0.0, 10.0, 0.0), create_color(0.5, 0.5, 0.5));
+        world1.set_light(light);
+        assert_eq!(world1.get_light().position, light.position);
+    }
+
+    #[test]
+    fn test_world_layers_feature()
+    {
+        // Scenario: Hiding a layer hides its shapes from every ray
+        let mut world1 = World::default_world();
+        world1.objects[1].set_layer("props");
+        let ray1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world1.intersect_world(ray1).count(), 4);
+        assert!(world1.is_shadowed(create_point(0.0, 0.0, 0.0), create_point(0.0, 0.0, 0.75)));
+        world1.set_layers(RenderLayers{include: None, exclude: vec![String::from("props")]});
+        assert_eq!(world1.intersect_world(ray1).count(), 2);
+        assert!(!world1.is_shadowed(create_point(0.0, 0.0, 0.0), create_point(0.0, 0.0, 0.75)));
+        world1.set_layers(RenderLayers{include: Some(vec![String::from("props")]), exclude: Vec::new()});
+        let xs1 = world1.intersect_world(ray1);
+        assert_eq!(xs1.count(), 2);
+        assert!(fuzzy_equal(xs1.get_intersection(0).t, 4.5));
+
+        // Scenario: Shapes in a group take the group's layer, compiled or not
+        let mut world2 = World::default_world();
+        let mut group = Shape::new_group(3);
+        let mut inner = Shape::new_sphere(4);
+        inner.set_transform(Matrix::translation(0.0, 5.0, 0.0));
+        group.add_child(inner);
+        group.set_layer("props");
+        world2.objects.push(group);
+        world2.set_layers(RenderLayers{include: None, exclude: vec![String::from("props")]});
+        let ray2 = Ray::new(create_point(0.0, 5.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world2.intersect_world(ray2).count(), 0);
+        world2.compile();
+        assert_eq!(world2.intersect_world(ray2).count(), 0);
+        world2.set_layers(RenderLayers::new());
+        assert_eq!(world2.intersect_world(ray2).count(), 2);
     }
 
     #[test]
@@ -316,12 +1484,12 @@ mod tests
         world1.light = PointLight::new(create_point(0.0, 0.0, 0.0),
             create_color(1.0, 1.0, 1.0));
         let mut lower = Shape::new_plane(1);
-        let mut lower_material = lower.get_material();
+        let mut lower_material = lower.get_material().clone();
         lower_material.reflective = 1.0;
         lower.set_material(lower_material);
         lower.set_transform(Matrix::translation(0.0, -1.0, 0.0));
         let mut upper = Shape::new_plane(2);
-        let mut upper_material = upper.get_material();
+        let mut upper_material = upper.get_material().clone();
         upper_material.reflective = 1.0;
         upper.set_material(upper_material);
         upper.set_transform(Matrix::translation(0.0, 1.0, 0.0));
@@ -329,6 +1497,50 @@ mod tests
         let ray1 = Ray::new(create_point(0.0, 0.0, 0.0), create_vector(0.0, 1.0, 0.0));
         // should terminate successfully
         world1.color_at(ray1, World::REFLECTION_RECURSION);
+
+        // Scenario: Many reflections between mirrors don't use up the stack
+        let shallow = world1.color_at(ray1, World::REFLECTION_RECURSION);
+        let deep = world1.color_at(ray1, 20000);
+        assert!(deep.get_vec()[0] > shallow.get_vec()[0]);
+
+        // Scenario: Rays that miss everything see the background
+        let mut world2 = World::default_world();
+        let ray2 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 1.0, 0.0));
+        assert_eq!(world2.color_at(ray2, World::REFLECTION_RECURSION), create_color(0.0, 0.0, 0.0));
+        world2.background = Background::Color(create_color(0.2, 0.3, 0.4));
+        assert_eq!(world2.color_at(ray2, World::REFLECTION_RECURSION), create_color(0.2, 0.3, 0.4));
+
+        // Scenario: Fog blends hits toward its color with distance, but not the background
+        world2.fog = Some(Fog::Linear{color: create_color(1.0, 1.0, 1.0), start: 0.0, end: 8.0});
+        let ray3 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        assert_eq!(world2.color_at(ray3, World::REFLECTION_RECURSION), create_color(0.69033, 0.73791, 0.64275));
+        assert_eq!(world2.color_at(ray2, World::REFLECTION_RECURSION), create_color(0.2, 0.3, 0.4));
+
+        // Scenario: The depth followed from camera rays can be changed
+        assert_eq!(world1.get_max_depth(), World::REFLECTION_RECURSION);
+        assert_eq!(world1.color_for_ray(ray1), shallow);
+        world1.set_max_depth(10);
+        assert_eq!(world1.color_for_ray(ray1), world1.color_at(ray1, 10));
+        world1.set_max_depth(1000);
+        assert_eq!(world1.get_max_depth(), World::MAX_DEPTH);
+
+        // Scenario: Sample limits scale bright colors down, keeping their hue
+        let mut world3 = World::default_world();
+        let ray3 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
+        world3.set_sample_limits(SampleLimits{max_value: 0.2, max_indirect: Scalar::INFINITY});
+        assert_eq!(world3.color_for_ray(ray3), create_color(0.16, 0.2, 0.12));
+        assert_eq!(limit_color(create_color(0.1, 0.2, 0.3), 1.0), create_color(0.1, 0.2, 0.3));
+
+        // Scenario: Limiting indirect light to nothing leaves only the direct light
+        let mut world4 = World::default_world();
+        let mut mirror = Shape::new_plane(3);
+        mirror.update_material(|m| m.reflective = 0.5);
+        mirror.set_transform(Matrix::translation(0.0, -1.0, 0.0));
+        world4.objects.push(mirror);
+        let ray4 = Ray::new(create_point(0.0, 0.0, -3.0), create_vector(0.0, -1.0, 1.0).normalize());
+        assert_ne!(world4.color_for_ray(ray4), world4.color_at(ray4, 0));
+        world4.set_sample_limits(SampleLimits{max_value: Scalar::INFINITY, max_indirect: 0.0});
+        assert_eq!(world4.color_for_ray(ray4), world4.color_at(ray4, 0));
     }
 
     #[test]
@@ -338,8 +1550,8 @@ mod tests
         let world1 = World::default_world();
         let shape1 = world1.objects[0].clone();
         let r1 = Ray::new(create_point(0.0, 0.0, -5.0), create_vector(0.0, 0.0, 1.0));
-        let i11 = Intersection::new(4.0, shape1.clone());
-        let i12 = Intersection::new(6.0, shape1.clone());
+        let i11 = Intersection::new(4.0, &shape1);
+        let i12 = Intersection::new(6.0, &shape1);
         let xs1 = Intersections::new(vec![i11.clone(), i12.clone()]);
         let comps1 = i11.prepare_computations(r1, xs1);
         let color1 = world1.refracted_color(comps1.clone(), 5);
@@ -352,14 +1564,14 @@ mod tests
         // p.157 Scenario: The refracted color under total internal reflection
         let world3 = World::default_world();
         let shape3 = world3.objects[0].clone();
-        let mut material3 = shape3.get_material();
+        let mut material3 = shape3.get_material().clone();
         material3.transparency = 1.0;
         material3.refractive_index = 1.5;
-        let sqrt2 = 2.0_f64.sqrt();
+        let sqrt2 = Scalar::sqrt(2.0);
         let r3 = Ray::new(create_point(0.0, 0.0, sqrt2 / 2.0),
             create_vector(0.0, 1.0, 0.0));
-        let i31 = Intersection::new(-sqrt2 / 2.0, shape3.clone());
-        let i32 = Intersection::new(sqrt2 / 2.0, shape3.clone());
+        let i31 = Intersection::new(-sqrt2 / 2.0, &shape3);
+        let i32 = Intersection::new(sqrt2 / 2.0, &shape3);
         let xs3 = Intersections::new(vec![i31.clone(), i32.clone()]);
         let comps3 = i32.prepare_computations(r3, xs3);
         let color3 = world3.refracted_color(comps3.clone(), 5);
@@ -368,22 +1580,22 @@ mod tests
         // p.158 Scenario: The refracted color with a refracted ray
         let mut world4 = World::default_world();
         let mut a4 = world4.objects[0].clone();
-        let mut material_a4 = a4.get_material();
+        let mut material_a4 = a4.get_material().clone();
         material_a4.ambient = 1.0;
         material_a4.pattern = Some(Pattern::test_pattern());
         a4.set_material(material_a4);
         let mut b4 = world4.objects[1].clone();
-        let mut material_b4 = b4.get_material();
+        let mut material_b4 = b4.get_material().clone();
         material_b4.transparency = 1.0;
         material_b4.refractive_index = 1.5;
         b4.set_material(material_b4);
         world4.objects = vec![a4.clone(), b4.clone()];
         let r4 = Ray::new(create_point(0.0, 0.0, 0.1),
             create_vector(0.0, 1.0, 0.0));
-        let i41 = Intersection::new(-0.9899, a4.clone());
-        let i42 = Intersection::new(-0.4899, b4.clone());
-        let i43 = Intersection::new(0.4899, b4.clone());
-        let i44 = Intersection::new(0.9899, a4.clone());
+        let i41 = Intersection::new(-0.9899, &a4);
+        let i42 = Intersection::new(-0.4899, &b4);
+        let i43 = Intersection::new(0.4899, &b4);
+        let i44 = Intersection::new(0.9899, &a4);
         let xs4 = Intersections::new(vec![i41.clone(),
             i42.clone(), i43.clone(), i44.clone()]);
         let comps4 = i43.prepare_computations(r4, xs4);
@@ -394,14 +1606,14 @@ mod tests
         // p.159 Scenario: shade_hit() with a transparent material
         let mut world5 = World::default_world();
         let mut floor5 = Shape::new_plane(3);
-        let mut material_floor5 = floor5.get_material();
+        let mut material_floor5 = floor5.get_material().clone();
         floor5.set_transform(Matrix::translation(0.0, -1.0, 0.0));
         material_floor5.transparency = 0.5;
         material_floor5.refractive_index = 1.5;
         floor5.set_material(material_floor5);
         world5.objects.push(floor5.clone());
         let mut ball5 = Shape::new_sphere(4);
-        let mut material_ball5 = ball5.get_material();
+        let mut material_ball5 = ball5.get_material().clone();
         material_ball5.color = create_color(1.0, 0.0, 0.0);
         material_ball5.ambient = 0.5;
         ball5.set_transform(Matrix::translation(0.0, -3.5, -0.5));
@@ -409,7 +1621,7 @@ mod tests
         world5.objects.push(ball5.clone());
         let r5 = Ray::new(create_point(0.0, 0.0, -3.0),
             create_vector(0.0, -sqrt2 / 2.0, sqrt2 / 2.0));
-        let i5 = Intersection::new(sqrt2, floor5.clone());
+        let i5 = Intersection::new(sqrt2, &floor5);
         let xs5 = Intersections::new(vec![i5.clone()]);
         let comps5 = i5.prepare_computations(r5, xs5);
         let color5 = world5.shade_hit(comps5, 5);
@@ -420,7 +1632,7 @@ mod tests
         let r6 = Ray::new(create_point(0.0, 0.0, -3.0),
             create_vector(0.0, -sqrt2 / 2.0, sqrt2 / 2.0));
         let mut floor6 = Shape::new_plane(6);
-        let mut material_floor6 = floor5.get_material();
+        let mut material_floor6 = floor5.get_material().clone();
         floor6.set_transform(Matrix::translation(0.0, -1.0, 0.0));
         material_floor6.reflective = 0.5;
         material_floor6.transparency = 0.5;
@@ -428,16 +1640,75 @@ mod tests
         floor6.set_material(material_floor6);
         world6.objects.push(floor6.clone());
         let mut ball6 = Shape::new_sphere(7);
-        let mut material_ball6 = ball6.get_material();
+        let mut material_ball6 = ball6.get_material().clone();
         material_ball6.color = create_color(1.0, 0.0, 0.0);
         material_ball6.ambient = 0.5;
         ball6.set_transform(Matrix::translation(0.0, -3.5, -0.5));
         ball6.set_material(material_ball6);
         world6.objects.push(ball6.clone());
-        let i6 = Intersection::new(sqrt2, floor6.clone());
+        let i6 = Intersection::new(sqrt2, &floor6);
         let xs6 = Intersections::new(vec![i6.clone()]);
         let comps6 = i6.prepare_computations(r6, xs6);
         let color6 = world6.shade_hit(comps6, 5);
         assert_eq!(color6, create_color(0.93391, 0.69643, 0.69243));
     }
+
+    #[test]
+    fn test_world_rough_refraction_feature()
+    {
+        // a pane of glass that doesn't bend light, over a striped floor that
+        // shows its own colors
+        let mut glass = Material::new();
+        glass.ambient = 0.0;
+        glass.diffuse = 0.0;
+        glass.specular = 0.0;
+        glass.transparency = 1.0;
+        let mut pane = Shape::new_plane(1);
+        pane.set_material(glass.clone());
+        let mut floor_material = Material::new();
+        floor_material.ambient = 1.0;
+        floor_material.diffuse = 0.0;
+        floor_material.specular = 0.0;
+        floor_material.pattern = Some(Pattern::new_stripe_pattern(create_color(1.0, 1.0, 1.0),
+            create_color(0.0, 0.0, 0.0)));
+        let mut floor = Shape::new_plane(2);
+        floor.set_material(floor_material);
+        floor.set_transform(Matrix::translation(0.0, -1.0, 0.0));
+        let light = PointLight::new(create_point(0.0, 10.0, 0.0), create_color(1.0, 1.0, 1.0));
+        let ray = Ray::new(create_point(0.9, 1.0, 0.0), create_vector(0.0, -1.0, 0.0));
+
+        // Scenario: Clear glass shows the stripe straight below
+        let clear = World::new(light, vec![pane.clone(), floor.clone()]);
+        assert_eq!(clear.color_at(ray, 5), create_color(1.0, 1.0, 1.0));
+
+        // Scenario: Frosted glass blurs the stripes together
+        glass.refraction_roughness = 1.0;
+        glass.refraction_samples = 16;
+        pane.set_material(glass.clone());
+        let frosted = World::new(light, vec![pane.clone(), floor.clone()]);
+        let blurred = frosted.color_at(ray, 5).get_vec()[0];
+        assert!(blurred > 0.1 && blurred < 0.9);
+        assert_eq!(frosted.color_at(ray, 5).get_vec()[0], blurred);
+
+        // Scenario: Scattered rays stay in the cone, through the surface
+        let xs = frosted.intersect_world(ray);
+        let comps = xs.hit().unwrap().prepare_computations(ray, xs.clone());
+        let refracted = frosted.refract_ray(&comps, 5).unwrap();
+        assert_eq!(frosted.scatter_refraction(&comps, refracted, (0.0, 0.3)).direction, refracted.direction);
+        for sample in [(1.0, 0.0), (0.5, 0.25), (0.9, 0.8)]
+        {
+            let scattered = frosted.scatter_refraction(&comps, refracted, sample);
+            let cos = scattered.direction.dot_product(refracted.direction.normalize());
+            assert!(cos >= (0.5 as Scalar).sqrt() - EPSILON);
+            assert!(scattered.direction.dot_product(comps.normalv) < 0.0);
+            assert_eq!(scattered.origin, refracted.origin);
+        }
+
+        // Scenario: One sample for rough glass is still a blur, of one ray
+        glass.refraction_samples = 1;
+        pane.set_material(glass);
+        let single = World::new(light, vec![pane, floor]);
+        let one = single.color_at(ray, 5).get_vec()[0];
+        assert!(one == 0.0 || one == 1.0);
+    }
 }
@@ -0,0 +1,594 @@
+use std::fmt;
+use std::error;
+use crate::arithmetic::*;
+
+// A value read from a YAML (or JSON) document
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value
+{
+    Null,
+    Number(f64),
+    Text(String),
+    List(Vec<Value>),
+    // entries are kept in the order they were written
+    Map(Vec<(String, Value)>),
+}
+
+impl Value
+{
+    // Looks up a key of a map
+    pub fn get(&self, key: &str) -> Option<&Value>
+    {
+        match self
+        {
+            Value::Map(entries) => entries.iter().find(|e| e.0 == key).map(|e| &e.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64>
+    {
+        match self
+        {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // A number at the precision of the rest of the crate
+    pub fn as_scalar(&self) -> Option<Scalar>
+    {
+        self.as_f64().map(|n| n as Scalar)
+    }
+
+    pub fn as_str(&self) -> Option<&str>
+    {
+        match self
+        {
+            Value::Text(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool>
+    {
+        match self.as_str()
+        {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&Vec<Value>>
+    {
+        match self
+        {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// A syntax error, with the line it was found on
+#[derive(Clone, Debug, PartialEq)]
+pub struct YamlError
+{
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for YamlError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for YamlError
+{
+}
+
+fn yaml_error(line: usize, message: &str) -> YamlError
+{
+    YamlError{line, message: String::from(message)}
+}
+
+#[derive(Clone, Debug)]
+struct Line
+{
+    number: usize,
+    indent: usize,
+    text: String,
+}
+
+// Removes a comment, which starts with # outside of quotes
+fn strip_comment(text: &str) -> &str
+{
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices()
+    {
+        match quote
+        {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &text[..i],
+            None => (),
+        }
+        previous = c;
+    }
+    text
+}
+
+fn is_item(text: &str) -> bool
+{
+    text == "-" || text.starts_with("- ")
+}
+
+// Splits "key: value" into its key and value, if the text is a map entry
+fn split_key(text: &str) -> Option<(String, &str)>
+{
+    if text.starts_with('[') || text.starts_with('{')
+    {
+        return None;
+    }
+    let mut quote = None;
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    for (n, &(i, c)) in chars.iter().enumerate()
+    {
+        match quote
+        {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ':' =>
+            {
+                let at_end = n + 1 == chars.len();
+                if at_end || chars[n + 1].1 == ' '
+                {
+                    let key = text[..i].trim();
+                    let key = key.trim_matches(|q| q == '"' || q == '\'');
+                    return Some((String::from(key), text[i + 1..].trim()));
+                }
+            },
+            None => (),
+        }
+    }
+    None
+}
+
+// Whether every [ and { in the text has been closed
+fn is_balanced(text: &str) -> bool
+{
+    let mut depth = 0;
+    let mut quote = None;
+    for c in text.chars()
+    {
+        match quote
+        {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None => match c
+            {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth = depth + 1,
+                ']' | '}' => depth = depth - 1,
+                _ => (),
+            },
+        }
+    }
+    depth <= 0
+}
+
+fn scalar(text: &str) -> Value
+{
+    match text
+    {
+        "" | "~" | "null" => return Value::Null,
+        ".inf" | "+.inf" => return Value::Number(f64::INFINITY),
+        "-.inf" => return Value::Number(f64::NEG_INFINITY),
+        _ => (),
+    }
+    // rust also parses words like "inf" and "nan", which YAML treats as text
+    let numeric = text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+' || c == '.');
+    match text.parse::<f64>()
+    {
+        Ok(n) if numeric => Value::Number(n),
+        _ => Value::Text(String::from(text)),
+    }
+}
+
+// Parses [lists], {maps}, quoted strings and plain values on one line
+struct FlowParser
+{
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl FlowParser
+{
+    fn skip_space(&mut self)
+    {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace()
+        {
+            self.pos = self.pos + 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char>
+    {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), YamlError>
+    {
+        self.skip_space();
+        match self.peek()
+        {
+            Some(d) if d == c =>
+            {
+                self.pos = self.pos + 1;
+                Ok(())
+            },
+            _ => Err(YamlError{line: self.line, message: format!("expected '{}'", c)}),
+        }
+    }
+
+    fn parse_value(&mut self, nested: bool) -> Result<Value, YamlError>
+    {
+        self.skip_space();
+        match self.peek()
+        {
+            Some('[') => self.parse_list(),
+            Some('{') => self.parse_map(),
+            Some('"') | Some('\'') => Ok(Value::Text(self.parse_quoted()?)),
+            _ => Ok(scalar(&self.parse_plain(nested, false))),
+        }
+    }
+
+    // Plain text runs to the end, or inside [] and {} up to the next
+    // separator. Keys also stop at a colon.
+    fn parse_plain(&mut self, nested: bool, key: bool) -> String
+    {
+        let start = self.pos;
+        while let Some(c) = self.peek()
+        {
+            if (nested && (c == ',' || c == ']' || c == '}')) || (key && c == ':')
+            {
+                break;
+            }
+            self.pos = self.pos + 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().trim().to_string()
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, YamlError>
+    {
+        let quote = self.chars[self.pos];
+        self.pos = self.pos + 1;
+        let mut text = String::new();
+        while let Some(c) = self.peek()
+        {
+            self.pos = self.pos + 1;
+            if c == quote
+            {
+                // a doubled single quote stands for one quote
+                if quote == '\'' && self.peek() == Some('\'')
+                {
+                    self.pos = self.pos + 1;
+                    text.push('\'');
+                    continue;
+                }
+                return Ok(text);
+            }
+            if c == '\\' && quote == '"'
+            {
+                match self.peek()
+                {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(e) => text.push(e),
+                    None => break,
+                }
+                self.pos = self.pos + 1;
+                continue;
+            }
+            text.push(c);
+        }
+        Err(yaml_error(self.line, "unterminated string"))
+    }
+
+    fn parse_list(&mut self) -> Result<Value, YamlError>
+    {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_space();
+        if self.peek() == Some(']')
+        {
+            self.pos = self.pos + 1;
+            return Ok(Value::List(items));
+        }
+        loop
+        {
+            items.push(self.parse_value(true)?);
+            self.skip_space();
+            match self.peek()
+            {
+                Some(',') => self.pos = self.pos + 1,
+                Some(']') =>
+                {
+                    self.pos = self.pos + 1;
+                    return Ok(Value::List(items));
+                },
+                _ => return Err(yaml_error(self.line, "expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_map(&mut self) -> Result<Value, YamlError>
+    {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+        self.skip_space();
+        if self.peek() == Some('}')
+        {
+            self.pos = self.pos + 1;
+            return Ok(Value::Map(entries));
+        }
+        loop
+        {
+            self.skip_space();
+            let key = match self.peek()
+            {
+                Some('"') | Some('\'') => self.parse_quoted()?,
+                _ => self.parse_plain(true, true),
+            };
+            self.expect(':')?;
+            let value = self.parse_value(true)?;
+            entries.push((key, value));
+            self.skip_space();
+            match self.peek()
+            {
+                Some(',') => self.pos = self.pos + 1,
+                Some('}') =>
+                {
+                    self.pos = self.pos + 1;
+                    return Ok(Value::Map(entries));
+                },
+                _ => return Err(yaml_error(self.line, "expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+// Parses the block (indentation based) structure of a document
+struct BlockParser
+{
+    lines: Vec<Line>,
+    pos: usize,
+}
+
+impl BlockParser
+{
+    fn parse_node(&mut self) -> Result<Value, YamlError>
+    {
+        let line = self.lines[self.pos].clone();
+        if is_item(&line.text)
+        {
+            self.parse_sequence(line.indent)
+        }
+        else if split_key(&line.text).is_some()
+        {
+            self.parse_mapping(line.indent)
+        }
+        else
+        {
+            self.pos = self.pos + 1;
+            self.parse_inline(&line.text, line.number)
+        }
+    }
+
+    // Parses a value written after a key or dash, which may be a [list] or
+    // {map} continuing onto the following lines
+    fn parse_inline(&mut self, text: &str, number: usize) -> Result<Value, YamlError>
+    {
+        let mut text = String::from(text);
+        while !is_balanced(&text) && self.pos < self.lines.len()
+        {
+            text.push(' ');
+            text.push_str(&self.lines[self.pos].text);
+            self.pos = self.pos + 1;
+        }
+        let mut flow = FlowParser{chars: text.chars().collect(), pos: 0, line: number};
+        let value = flow.parse_value(false)?;
+        flow.skip_space();
+        if flow.pos < flow.chars.len()
+        {
+            return Err(yaml_error(number, "unexpected text after value"));
+        }
+        Ok(value)
+    }
+
+    fn parse_sequence(&mut self, indent: usize) -> Result<Value, YamlError>
+    {
+        let mut items = Vec::new();
+        while self.pos < self.lines.len() && self.lines[self.pos].indent == indent
+            && is_item(&self.lines[self.pos].text)
+        {
+            let line = self.lines[self.pos].clone();
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty()
+            {
+                self.pos = self.pos + 1;
+                if self.pos < self.lines.len() && self.lines[self.pos].indent > indent
+                {
+                    items.push(self.parse_node()?);
+                }
+                else
+                {
+                    items.push(Value::Null);
+                }
+            }
+            else
+            {
+                // the text after the dash is treated as the first line of
+                // a block indented to where that text starts
+                let offset = line.text.len() - rest.len();
+                self.lines[self.pos] = Line{number: line.number,
+                    indent: indent + offset, text: String::from(rest)};
+                items.push(self.parse_node()?);
+            }
+        }
+        if self.pos < self.lines.len() && self.lines[self.pos].indent > indent
+        {
+            return Err(yaml_error(self.lines[self.pos].number, "unexpected indentation"));
+        }
+        Ok(Value::List(items))
+    }
+
+    fn parse_mapping(&mut self, indent: usize) -> Result<Value, YamlError>
+    {
+        let mut entries = Vec::new();
+        while self.pos < self.lines.len() && self.lines[self.pos].indent == indent
+            && !is_item(&self.lines[self.pos].text)
+        {
+            let line = self.lines[self.pos].clone();
+            let (key, rest) = match split_key(&line.text)
+            {
+                Some(kv) => kv,
+                None => return Err(yaml_error(line.number, "expected 'key: value'")),
+            };
+            self.pos = self.pos + 1;
+            let value;
+            if rest.is_empty()
+            {
+                // the value is a block on the following lines, which for
+                // a list may start at the same indentation as the key
+                let nested = self.pos < self.lines.len()
+                    && (self.lines[self.pos].indent > indent
+                        || (self.lines[self.pos].indent == indent && is_item(&self.lines[self.pos].text)));
+                value = match nested
+                {
+                    true => self.parse_node()?,
+                    false => Value::Null,
+                };
+            }
+            else
+            {
+                value = self.parse_inline(rest, line.number)?;
+            }
+            entries.push((key, value));
+        }
+        if self.pos < self.lines.len() && self.lines[self.pos].indent > indent
+        {
+            return Err(yaml_error(self.lines[self.pos].number, "unexpected indentation"));
+        }
+        Ok(Value::Map(entries))
+    }
+}
+
+// Parses the subset of YAML used by scene files: block maps and lists,
+// [flow lists], {flow maps}, quoted and plain scalars, and # comments.
+// JSON documents are also accepted, as JSON is written in flow style.
+pub fn parse_yaml(text: &str) -> Result<Value, YamlError>
+{
+    let mut lines = Vec::new();
+    for (index, raw) in text.lines().enumerate()
+    {
+        let content = strip_comment(raw).trim_end();
+        let text = content.trim_start();
+        if text.is_empty() || text == "---"
+        {
+            continue;
+        }
+        if content[..content.len() - text.len()].contains('\t')
+        {
+            return Err(yaml_error(index + 1, "tabs cannot be used for indentation"));
+        }
+        lines.push(Line{number: index + 1, indent: content.len() - text.len(),
+            text: String::from(text)});
+    }
+    if lines.is_empty()
+    {
+        return Ok(Value::Null);
+    }
+    let indent = lines[0].indent;
+    let mut parser = BlockParser{lines, pos: 0};
+    let value = parser.parse_node()?;
+    if parser.pos < parser.lines.len()
+    {
+        let line = &parser.lines[parser.pos];
+        let message = match line.indent == indent
+        {
+            true => "expected the document to continue as a list or map",
+            false => "unexpected indentation",
+        };
+        return Err(yaml_error(line.number, message));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_yaml_feature()
+    {
+        // Scenario: Parsing block lists and maps
+        let text1 = "
+# a camera and a sphere
+- add: camera
+  width: 100
+  from: [ 0, 1.5, -5 ]   # comment after a value
+- add: sphere
+  transform:
+    - [ translate, 1, 2, 3 ]
+    - [ 'rotate-y', .5 ]
+  material:
+    color: [1, 0, 0]
+    shininess: .inf
+  shadow: false
+";
+        let doc1 = parse_yaml(text1).unwrap();
+        let items1 = doc1.as_list().unwrap();
+        assert_eq!(items1.len(), 2);
+        assert_eq!(items1[0].get("add").unwrap().as_str(), Some("camera"));
+        assert_eq!(items1[0].get("width").unwrap().as_f64(), Some(100.0));
+        assert_eq!(items1[0].get("from").unwrap(), &Value::List(vec![Value::Number(0.0),
+            Value::Number(1.5), Value::Number(-5.0)]));
+        let transform1 = items1[1].get("transform").unwrap().as_list().unwrap();
+        assert_eq!(transform1.len(), 2);
+        assert_eq!(transform1[1].as_list().unwrap()[0].as_str(), Some("rotate-y"));
+        let material1 = items1[1].get("material").unwrap();
+        assert_eq!(material1.get("shininess").unwrap().as_f64(), Some(f64::INFINITY));
+        assert_eq!(items1[1].get("shadow").unwrap().as_bool(), Some(false));
+
+        // Scenario: A list may be written at the same indentation as its key
+        let doc2 = parse_yaml("value:\n- 1\n- 2\nname: two").unwrap();
+        assert_eq!(doc2.get("value").unwrap().as_list().unwrap().len(), 2);
+        assert_eq!(doc2.get("name").unwrap().as_str(), Some("two"));
+
+        // Scenario: Parsing a JSON document
+        let text3 = "[\n  {\"add\": \"light\", \"at\": [-10, 10, -10]},\n  {\"add\": \"plane\", \"name\": \"it's \\\"flat\\\"\"}\n]";
+        let doc3 = parse_yaml(text3).unwrap();
+        let items3 = doc3.as_list().unwrap();
+        assert_eq!(items3[0].get("at").unwrap().as_list().unwrap()[0].as_f64(), Some(-10.0));
+        assert_eq!(items3[1].get("name").unwrap().as_str(), Some("it's \"flat\""));
+
+        // Scenario: Syntax errors report their line
+        let error4 = parse_yaml("- add: sphere\n  material: [1, 2\n").unwrap_err();
+        assert_eq!(error4.line, 2);
+        let error5 = parse_yaml("- add: sphere\n      width: 3\n").unwrap_err();
+        assert_eq!(error5, yaml_error(2, "unexpected indentation"));
+        let error6 = parse_yaml("- add: sphere\n \tmaterial: shiny\n").unwrap_err();
+        assert_eq!(error6, yaml_error(2, "tabs cannot be used for indentation"));
+        // tabs after the indentation are fine
+        assert!(parse_yaml("- add: sphere\n  name: a\tb\n\t\n").is_ok());
+    }
+}